@@ -0,0 +1,33 @@
+use std::{
+    process::Command,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+/// Commit and time of the build, reported by `GET /version`
+fn main() {
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    // Reproducible builds pin the time
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Clock is before Unix epoch")
+                .as_secs()
+        });
+
+    println!("cargo:rustc-env=MICROPHONE_COMMIT={}", commit);
+    println!("cargo:rustc-env=MICROPHONE_BUILT_AT={}", built_at);
+}
@@ -0,0 +1,277 @@
+use std::{
+    net::IpAddr,
+    sync::Arc,
+};
+
+use actix_web::{
+    dev::Payload,
+    web,
+    FromRequest,
+    HttpRequest,
+};
+use futures::future::LocalBoxFuture;
+
+use crate::{
+    adapters::secrets_match,
+    api_keys::{
+        ApiKey,
+        ApiKeys,
+    },
+    client_ip::ClientIp,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    geoip::GeoIp,
+    hostname::Hostnames,
+    jwt::Jwt,
+    metrics::Metrics,
+    submit::SUBMIT_TOKEN_HEADER,
+    LiveTopics,
+    Topic,
+};
+
+/// Topic named by `{topic_name}` path segment that the client is allowed to post to,
+/// unknown and forbidden topics are indistinguishable for the client
+pub struct AllowedTopic {
+    pub name:    String,
+    pub info:    Topic,
+    /// Id of the API key of the request, usage is counted by it
+    pub api_key: Option<String>,
+}
+
+/// Who the client says it is besides its address, topics can allow it from anywhere then
+#[derive(Default)]
+pub struct Caller {
+    /// Groups of its identity provider token
+    pub groups:       Vec<String>,
+    pub api_key:      Option<ApiKey>,
+    /// `X-Submit-Token` of the submission form
+    pub submit_token: Option<String>,
+}
+
+impl Caller {
+    /// Caller of the request, a token or API key that doesn't verify is an error even if the
+    /// address would be allowed, so whoever sends it finds out
+    pub fn of(request: &HttpRequest) -> Result<Self, ApiError> {
+        let groups = match request.app_data::<web::Data<Option<Arc<Jwt>>>>() {
+            Some(jwt) => match (jwt.as_ref(), Jwt::bearer(request.headers())) {
+                (Some(jwt), Some(token)) =>
+                    jwt.verify(token)
+                        .map_err(|err| ApiError::new(ErrorCode::InvalidToken, err))?
+                        .groups,
+                _ => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+        let api_key = match request.app_data::<web::Data<Option<Arc<ApiKeys>>>>() {
+            Some(api_keys) => match (api_keys.as_ref(), ApiKeys::bearer(request.headers())) {
+                (Some(api_keys), Some(key)) => Some(api_keys.find(key).ok_or_else(|| {
+                    ApiError::new(ErrorCode::InvalidToken, "Unknown or revoked API key")
+                })?),
+                _ => None,
+            },
+            None => None,
+        };
+
+        let submit_token = request
+            .headers()
+            .get(SUBMIT_TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        Ok(Self {
+            groups,
+            api_key,
+            submit_token,
+        })
+    }
+
+    fn is_allowed(&self, name: &str, info: &Topic) -> bool {
+        info.allows_groups(&self.groups)
+            || self
+                .api_key
+                .as_ref()
+                .is_some_and(|api_key| api_key.scope.topics.iter().any(|topic| topic == name))
+            || self
+                .submit_token
+                .as_deref()
+                .zip(info.submit_token.as_deref())
+                .is_some_and(|(provided, expected)| secrets_match(expected, provided))
+    }
+}
+
+fn no_such_topic() -> ApiError {
+    ApiError::new(ErrorCode::TopicNotFound, "No such topic")
+}
+
+impl AllowedTopic {
+    fn is_country_allowed(&self, country: Option<&str>) -> bool {
+        self.info.allow_countries.is_empty()
+            || country.is_some_and(|country| {
+                self.info
+                    .allow_countries
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(country))
+            })
+    }
+}
+
+impl AllowedTopic {
+    /// Topic the client of the request is allowed to post to, for requests that name it
+    /// elsewhere than in the path, like frames of a WebSocket
+    pub fn of(
+        request: &HttpRequest,
+        name: String,
+    ) -> LocalBoxFuture<'static, Result<Self, actix_web::Error>> {
+        let client_ip = ClientIp::of(request);
+        let info = request
+            .app_data::<web::Data<Arc<LiveTopics>>>()
+            .and_then(|topics| topics.current().get(&name).cloned());
+        let hostnames = request
+            .app_data::<web::Data<Arc<Hostnames>>>()
+            .map(|hostnames| hostnames.get_ref().clone());
+        let geoip = request
+            .app_data::<web::Data<Arc<GeoIp>>>()
+            .map(|geoip| geoip.get_ref().clone());
+        let metrics = request
+            .app_data::<web::Data<Arc<Metrics>>>()
+            .map(|metrics| metrics.get_ref().clone());
+        let caller = Caller::of(request).and_then(|caller| {
+            if let Some(api_key) = &caller.api_key {
+                api_key.scope.check(request.headers())?;
+            }
+            Ok(caller)
+        });
+
+        Box::pin(async move {
+            let ClientIp(client_address) =
+                client_ip.map_err(|err| ApiError::new(ErrorCode::InvalidClientAddress, err))?;
+            let caller = caller?;
+
+            Ok(Self::for_address(
+                name,
+                info,
+                client_address,
+                &caller,
+                hostnames.as_deref(),
+                geoip.as_deref(),
+                metrics.as_deref(),
+            )
+            .await?)
+        })
+    }
+
+    /// Every topic the client of the request is allowed to post to, with the caller it's telling
+    /// them by. Scope of its API key isn't checked against the request, which posts nothing
+    pub async fn all_of(request: &HttpRequest) -> Result<(Vec<Self>, Caller), ApiError> {
+        let ClientIp(client_address) = ClientIp::of(request)
+            .map_err(|err| ApiError::new(ErrorCode::InvalidClientAddress, err))?;
+        let caller = Caller::of(request)?;
+        let topics = request
+            .app_data::<web::Data<Arc<LiveTopics>>>()
+            .map(|topics| topics.current())
+            .unwrap_or_default();
+        let hostnames = request
+            .app_data::<web::Data<Arc<Hostnames>>>()
+            .map(|hostnames| hostnames.get_ref().clone());
+        let geoip = request
+            .app_data::<web::Data<Arc<GeoIp>>>()
+            .map(|geoip| geoip.get_ref().clone());
+
+        let mut allowed = Vec::new();
+        for (name, info) in topics.iter() {
+            // Not counted by country, nothing is posted
+            if let Ok(topic) = Self::for_address(
+                name.clone(),
+                Some(info.clone()),
+                client_address,
+                &caller,
+                hostnames.as_deref(),
+                geoip.as_deref(),
+                None,
+            )
+            .await
+            {
+                allowed.push(topic);
+            }
+        }
+
+        Ok((allowed, caller))
+    }
+
+    /// Topic a client at the address or the caller is allowed to post to, for clients that
+    /// don't make HTTP requests too
+    pub async fn for_address(
+        name: String,
+        info: Option<Topic>,
+        client_address: IpAddr,
+        caller: &Caller,
+        hostnames: Option<&Hostnames>,
+        geoip: Option<&GeoIp>,
+        metrics: Option<&Metrics>,
+    ) -> Result<Self, ApiError> {
+        let info = info.ok_or_else(no_such_topic)?;
+
+        let allowed = caller.is_allowed(&name, &info)
+            || match hostnames {
+                Some(hostnames) => hostnames.is_allowed(&info, client_address).await,
+                None => info.is_allowed(client_address),
+            };
+        if !allowed {
+            return Err(no_such_topic());
+        }
+
+        let topic = Self {
+            name,
+            info,
+            api_key: caller.api_key.as_ref().map(|api_key| api_key.id.clone()),
+        };
+
+        if let Some(geoip) = geoip.filter(|geoip| geoip.is_enabled()) {
+            let country = geoip.country(client_address);
+            let country_label = country.as_deref().unwrap_or("unknown");
+
+            if let Some(metrics) = metrics {
+                metrics.increment(
+                    "microphone_requests_by_country_total",
+                    &[("topic", &topic.name), ("country", country_label)],
+                );
+            }
+
+            if !topic.is_country_allowed(country.as_deref()) {
+                log::warn!(
+                    "Denied request from {} ({}) to \"{}\"",
+                    client_address,
+                    country_label,
+                    topic.name
+                );
+                if let Some(metrics) = metrics {
+                    metrics.increment(
+                        "microphone_geoip_denied_total",
+                        &[("topic", &topic.name), ("country", country_label)],
+                    );
+                }
+
+                return Err(no_such_topic());
+            }
+        }
+
+        Ok(topic)
+    }
+}
+
+impl FromRequest for AllowedTopic {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let name = request
+            .match_info()
+            .get("topic_name")
+            .unwrap_or_default()
+            .to_owned();
+
+        Self::of(request, name)
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use actix_web::{
+    web,
+    HttpRequest,
+    HttpResponse,
+    Responder,
+};
+use serde::Deserialize;
+
+use super::{
+    deliver,
+    header_value,
+    render_push,
+    signature_matches,
+    Commit,
+    TopicPath,
+};
+use crate::{
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    maintenance::Maintenance,
+    metrics::Metrics,
+    quotas::Quotas,
+    stream::Streams,
+    LiveTopics,
+    TgClient,
+    TgMarkdownString,
+};
+
+#[derive(Deserialize)]
+struct Repository {
+    full_name: String,
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct PushEvent {
+    #[serde(rename = "ref")]
+    git_ref:       String,
+    #[serde(default)]
+    after:         String,
+    pusher:        User,
+    repository:    Repository,
+    #[serde(default)]
+    commits:       Vec<Commit>,
+    #[serde(default)]
+    total_commits: usize,
+}
+
+#[derive(Deserialize)]
+struct Branch {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct PullRequest {
+    number:   u64,
+    title:    String,
+    html_url: String,
+    #[serde(default)]
+    merged:   bool,
+    head:     Branch,
+    base:     Branch,
+}
+
+#[derive(Deserialize)]
+struct PullRequestEvent {
+    action:       String,
+    pull_request: PullRequest,
+    sender:       User,
+    repository:   Repository,
+}
+
+#[derive(Deserialize)]
+struct Issue {
+    number:   u64,
+    title:    String,
+    html_url: String,
+}
+
+#[derive(Deserialize)]
+struct IssuesEvent {
+    action:     String,
+    issue:      Issue,
+    sender:     User,
+    repository: Repository,
+}
+
+/// Renders supported event into sender and message text, unsupported events yield `None`
+fn render(event: &str, body: &[u8]) -> Result<Option<(String, String)>, serde_json::Error> {
+    let rendered = match event {
+        "push" => {
+            let push: PushEvent = serde_json::from_slice(body)?;
+            let total_commits = push.total_commits.max(push.commits.len());
+            let text = render_push(
+                &push.pusher.login,
+                &push.git_ref,
+                &push.after,
+                &push.commits,
+                total_commits,
+            );
+
+            Some((push.repository.full_name, text))
+        }
+        "pull_request" => {
+            let event: PullRequestEvent = serde_json::from_slice(body)?;
+            let pull_request = &event.pull_request;
+            let action = if event.action == "closed" && pull_request.merged {
+                "merged"
+            } else {
+                &event.action
+            };
+            let text = format!(
+                "*{}* {} pull request {}\n{} → {}",
+                *TgMarkdownString::new(&event.sender.login),
+                *TgMarkdownString::new(action),
+                *TgMarkdownString::link(
+                    &format!("#{} {}", pull_request.number, pull_request.title),
+                    &pull_request.html_url
+                ),
+                *TgMarkdownString::code(&pull_request.head.name),
+                *TgMarkdownString::code(&pull_request.base.name),
+            );
+
+            Some((event.repository.full_name, text))
+        }
+        "issues" => {
+            let event: IssuesEvent = serde_json::from_slice(body)?;
+            let text = format!(
+                "*{}* {} issue {}",
+                *TgMarkdownString::new(&event.sender.login),
+                *TgMarkdownString::new(&event.action),
+                *TgMarkdownString::link(
+                    &format!("#{} {}", event.issue.number, event.issue.title),
+                    &event.issue.html_url
+                ),
+            );
+
+            Some((event.repository.full_name, text))
+        }
+        _ => None,
+    };
+
+    Ok(rendered)
+}
+
+pub async fn handle(
+    request: HttpRequest,
+    topics: web::Data<Arc<LiveTopics>>,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    streams: web::Data<Arc<Streams>>,
+    metrics: web::Data<Arc<Metrics>>,
+    path_data: web::Path<TopicPath>,
+    body: web::Bytes,
+) -> impl Responder {
+    let topics = topics.current();
+    let topic_info = match topics.get(&path_data.topic_name) {
+        Some(topic_info) => topic_info,
+        None =>
+            return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic")),
+    };
+
+    let secret = match &topic_info.gitea_secret {
+        Some(secret) => secret,
+        None =>
+            return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic")),
+    };
+
+    if !signature_matches(
+        secret,
+        &body,
+        header_value(request.headers(), "X-Gitea-Signature"),
+    ) {
+        // Same answer as unknown topic, so topic names can't be guessed
+        return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic"));
+    }
+    metrics.count_ingress(&path_data.topic_name, body.len(), 0);
+
+    let event = header_value(request.headers(), "X-Gitea-Event");
+    match render(event, &body) {
+        Ok(Some((sender, text))) =>
+            deliver(
+                &tg_client,
+                &maintenance,
+                &quotas,
+                &streams,
+                topic_info,
+                &path_data.topic_name,
+                &sender,
+                &text,
+            )
+            .await,
+        Ok(None) => HttpResponse::NoContent().finish(),
+        Err(err) => HttpResponse::from(ApiError::new(
+            ErrorCode::MalformedPayload,
+            format!("Malformed {} event: {}", event, err),
+        )),
+    }
+}
@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use actix_web::{
+    web,
+    HttpRequest,
+    HttpResponse,
+    Responder,
+};
+use serde::Deserialize;
+
+use super::{
+    deliver,
+    header_value,
+    render_push,
+    secrets_match,
+    Commit,
+    TopicPath,
+};
+use crate::{
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    maintenance::Maintenance,
+    metrics::Metrics,
+    quotas::Quotas,
+    stream::Streams,
+    LiveTopics,
+    TgClient,
+    TgMarkdownString,
+};
+
+#[derive(Deserialize)]
+struct Project {
+    path_with_namespace: String,
+}
+
+#[derive(Deserialize)]
+struct User {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct PushEvent {
+    #[serde(rename = "ref")]
+    git_ref:             String,
+    #[serde(default)]
+    after:               String,
+    user_name:           String,
+    project:             Project,
+    #[serde(default)]
+    commits:             Vec<Commit>,
+    #[serde(default)]
+    total_commits_count: usize,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestAttributes {
+    iid:           u64,
+    title:         String,
+    url:           String,
+    action:        Option<String>,
+    state:         String,
+    source_branch: String,
+    target_branch: String,
+}
+
+#[derive(Deserialize)]
+struct MergeRequestEvent {
+    user:              User,
+    project:           Project,
+    object_attributes: MergeRequestAttributes,
+}
+
+#[derive(Deserialize)]
+struct IssueAttributes {
+    iid:    u64,
+    title:  String,
+    url:    String,
+    action: Option<String>,
+    state:  String,
+}
+
+#[derive(Deserialize)]
+struct IssueEvent {
+    user:              User,
+    project:           Project,
+    object_attributes: IssueAttributes,
+}
+
+fn past_tense(action: &str) -> &str {
+    match action {
+        "open" => "opened",
+        "close" => "closed",
+        "reopen" => "reopened",
+        "update" => "updated",
+        "merge" => "merged",
+        "approve" => "approved",
+        "unapprove" => "unapproved",
+        action => action,
+    }
+}
+
+/// Renders supported event into sender and message text, unsupported events yield `None`
+fn render(event: &str, body: &[u8]) -> Result<Option<(String, String)>, serde_json::Error> {
+    let rendered = match event {
+        "Push Hook" | "Tag Push Hook" => {
+            let push: PushEvent = serde_json::from_slice(body)?;
+            let total_commits = push.total_commits_count.max(push.commits.len());
+            let text = render_push(
+                &push.user_name,
+                &push.git_ref,
+                &push.after,
+                &push.commits,
+                total_commits,
+            );
+
+            Some((push.project.path_with_namespace, text))
+        }
+        "Merge Request Hook" => {
+            let merge_request: MergeRequestEvent = serde_json::from_slice(body)?;
+            let attributes = &merge_request.object_attributes;
+            let action = attributes.action.as_deref().unwrap_or(&attributes.state);
+            let text = format!(
+                "*{}* {} merge request {}\n{} → {}",
+                *TgMarkdownString::new(&merge_request.user.name),
+                *TgMarkdownString::new(past_tense(action)),
+                *TgMarkdownString::link(
+                    &format!("!{} {}", attributes.iid, attributes.title),
+                    &attributes.url
+                ),
+                *TgMarkdownString::code(&attributes.source_branch),
+                *TgMarkdownString::code(&attributes.target_branch),
+            );
+
+            Some((merge_request.project.path_with_namespace, text))
+        }
+        "Issue Hook" | "Confidential Issue Hook" => {
+            let issue: IssueEvent = serde_json::from_slice(body)?;
+            let attributes = &issue.object_attributes;
+            let action = attributes.action.as_deref().unwrap_or(&attributes.state);
+            let text = format!(
+                "*{}* {} issue {}",
+                *TgMarkdownString::new(&issue.user.name),
+                *TgMarkdownString::new(past_tense(action)),
+                *TgMarkdownString::link(
+                    &format!("#{} {}", attributes.iid, attributes.title),
+                    &attributes.url
+                ),
+            );
+
+            Some((issue.project.path_with_namespace, text))
+        }
+        _ => None,
+    };
+
+    Ok(rendered)
+}
+
+pub async fn handle(
+    request: HttpRequest,
+    topics: web::Data<Arc<LiveTopics>>,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    streams: web::Data<Arc<Streams>>,
+    metrics: web::Data<Arc<Metrics>>,
+    path_data: web::Path<TopicPath>,
+    body: web::Bytes,
+) -> impl Responder {
+    let topics = topics.current();
+    let topic_info = match topics.get(&path_data.topic_name) {
+        Some(topic_info) => topic_info,
+        None =>
+            return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic")),
+    };
+
+    let expected_token = match &topic_info.gitlab_token {
+        Some(expected_token) => expected_token,
+        None =>
+            return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic")),
+    };
+
+    // Wrong token gets the same answer as unknown topic, so topic names can't be guessed
+    if !secrets_match(
+        expected_token,
+        header_value(request.headers(), "X-Gitlab-Token"),
+    ) {
+        return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic"));
+    }
+    metrics.count_ingress(&path_data.topic_name, body.len(), 0);
+
+    let event = header_value(request.headers(), "X-Gitlab-Event");
+    match render(event, &body) {
+        Ok(Some((sender, text))) =>
+            deliver(
+                &tg_client,
+                &maintenance,
+                &quotas,
+                &streams,
+                topic_info,
+                &path_data.topic_name,
+                &sender,
+                &text,
+            )
+            .await,
+        // GitLab disables hooks that keep failing, so events we don't render are acknowledged
+        Ok(None) => HttpResponse::NoContent().finish(),
+        Err(err) => HttpResponse::from(ApiError::new(
+            ErrorCode::MalformedPayload,
+            format!("Malformed {}: {}", event, err),
+        )),
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+use serde::Deserialize;
+
+use super::TopicPath;
+use crate::{
+    access::AllowedTopic,
+    bot_http::Outbound,
+    delivery_response,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    format::Pipeline,
+    maintenance::Maintenance,
+    metrics::Metrics,
+    quotas::{
+        self,
+        Quotas,
+    },
+    send_options::SendOptions,
+    severity::Severity,
+    stream::Streams,
+    TgClient,
+    TgMarkdownString,
+};
+
+const SENDER: &str = "grafana";
+/// Grouped notifications can carry lots of alerts, keep the message under Telegram's limit
+const MAX_LISTED_ALERTS: usize = 10;
+/// Larger panel images are skipped, the rest is kept in memory while being sent
+const MAX_IMAGE_SIZE: usize = 20 * 1024 * 1024;
+const IMAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client panel images are downloaded with, in app data. Bot API client isn't used as its proxy
+/// and addresses are meant for Telegram
+pub struct ImageClient(reqwest::Client);
+
+impl ImageClient {
+    pub fn new(outbound: &Outbound) -> Self {
+        Self(
+            outbound
+                .client_builder()
+                .timeout(IMAGE_TIMEOUT)
+                .build()
+                .expect("Failed to build Grafana image client"),
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct Notification {
+    #[serde(default)]
+    alerts: Vec<Alert>,
+}
+
+#[derive(Deserialize)]
+struct Alert {
+    status:        String,
+    #[serde(default)]
+    labels:        BTreeMap<String, String>,
+    #[serde(default)]
+    annotations:   BTreeMap<String, String>,
+    #[serde(default)]
+    values:        Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(default, rename = "generatorURL")]
+    generator_url: String,
+    #[serde(default, rename = "silenceURL")]
+    silence_url:   String,
+    #[serde(default, rename = "dashboardURL")]
+    dashboard_url: String,
+    #[serde(default, rename = "panelURL")]
+    panel_url:     String,
+    #[serde(default, rename = "imageURL")]
+    image_url:     String,
+}
+
+impl Alert {
+    fn name(&self) -> &str {
+        self.labels
+            .get("alertname")
+            .map(String::as_str)
+            .unwrap_or("Unnamed alert")
+    }
+
+    fn title(&self) -> String {
+        let state = match self.status.as_str() {
+            "firing" => "🔥 *Firing:*",
+            "resolved" => "✅ *Resolved:*",
+            _ => "❔ *Unknown:*",
+        };
+
+        format!("{} {}", state, *TgMarkdownString::new(self.name()))
+    }
+
+    fn links(&self) -> String {
+        [
+            ("Dashboard", &self.dashboard_url),
+            ("Panel", &self.panel_url),
+            ("Source", &self.generator_url),
+            ("Silence", &self.silence_url),
+        ]
+        .iter()
+        .filter(|(_, url)| !url.is_empty())
+        .map(|(name, url)| TgMarkdownString::link(name, url).0)
+        .collect::<Vec<_>>()
+        .join(" · ")
+    }
+
+    fn render(&self) -> String {
+        let mut text = self.title();
+
+        for annotation in ["summary", "description"] {
+            if let Some(value) = self.annotations.get(annotation) {
+                text.push_str(&format!("\n{}", *TgMarkdownString::new(value)));
+            }
+        }
+
+        if let Some(values) = self.values.as_ref().filter(|values| !values.is_empty()) {
+            let values = values
+                .iter()
+                .map(|(name, value)| TgMarkdownString::code(&format!("{}={}", name, value)).0)
+                .collect::<Vec<_>>()
+                .join(", ");
+            text.push_str(&format!("\nValues: {}", values));
+        }
+
+        let links = self.links();
+        if !links.is_empty() {
+            text.push_str(&format!("\n{}", links));
+        }
+
+        text
+    }
+}
+
+fn render(notification: &Notification) -> String {
+    let mut text = notification
+        .alerts
+        .iter()
+        .take(MAX_LISTED_ALERTS)
+        .map(Alert::render)
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    if notification.alerts.len() > MAX_LISTED_ALERTS {
+        text.push_str(&format!(
+            "\n\n{}",
+            *TgMarkdownString::new(&format!(
+                "...and {} more",
+                notification.alerts.len() - MAX_LISTED_ALERTS
+            ))
+        ));
+    }
+
+    text
+}
+
+pub async fn handle(
+    topic: AllowedTopic,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    streams: web::Data<Arc<Streams>>,
+    metrics: web::Data<Arc<Metrics>>,
+    image_client: web::Data<ImageClient>,
+    path_data: web::Path<TopicPath>,
+    body: web::Bytes,
+) -> impl Responder {
+    let topic_info = &topic.info;
+    metrics.count_ingress(&topic.name, body.len(), 0);
+
+    let notification: Notification = match serde_json::from_slice(&body) {
+        Ok(notification) => notification,
+        Err(err) =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::MalformedPayload,
+                format!("Malformed alert: {}", err),
+            )),
+    };
+
+    if notification.alerts.is_empty() {
+        return HttpResponse::NoContent().finish();
+    }
+
+    let text = render(&notification);
+    if maintenance.intercept(&path_data.topic_name, topic_info, SENDER, &text) {
+        return HttpResponse::Accepted().body("Topic is under maintenance");
+    }
+
+    if let Some(response) =
+        quotas::enforce(&quotas, &tg_client, &topic.name, topic_info, text.len()).await
+    {
+        return response;
+    }
+    streams.publish(
+        &topic.name,
+        topic_info,
+        SENDER,
+        Severity::default(),
+        &text,
+        &[],
+    );
+
+    let mut responses = tg_client
+        .send_formatted_to_all(
+            &tg_client.recipients_of(&topic.name, topic_info),
+            &Pipeline::default(),
+            &path_data.topic_name,
+            SENDER,
+            &text,
+            SendOptions::of_topic(topic_info),
+        )
+        .await;
+
+    // Images of alerts the text doesn't list aren't sent either
+    for alert in notification
+        .alerts
+        .iter()
+        .take(MAX_LISTED_ALERTS)
+        .filter(|alert| !alert.image_url.is_empty())
+    {
+        // Image urls usually point to Grafana itself, which Telegram can't reach
+        let image = match download(&image_client.0, &alert.image_url).await {
+            Ok(image) => image,
+            Err(err) => {
+                log::warn!("Failed to download image {}: {}", alert.image_url, err);
+                continue;
+            }
+        };
+
+        responses.extend(
+            tg_client
+                .send_photo_to_all(
+                    &tg_client.recipients_of(&topic.name, topic_info),
+                    &path_data.topic_name,
+                    SENDER,
+                    &alert.title(),
+                    &image,
+                    SendOptions::of_topic(topic_info),
+                )
+                .await,
+        );
+    }
+
+    delivery_response(&responses)
+}
+
+/// Body of the image, read until it turns out to be over `MAX_IMAGE_SIZE`
+async fn download(http_client: &reqwest::Client, url: &str) -> Result<Vec<u8>, String> {
+    let too_large = || format!("image is larger than {} bytes", MAX_IMAGE_SIZE);
+
+    let mut response = http_client
+        .get(url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|err| err.to_string())?;
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_IMAGE_SIZE as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut image = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+        if image.len() + chunk.len() > MAX_IMAGE_SIZE {
+            return Err(too_large());
+        }
+        image.extend_from_slice(&chunk);
+    }
+
+    Ok(image)
+}
@@ -0,0 +1,205 @@
+//! Endpoints accepting webhooks of third party services and rendering them into messages
+
+pub mod gitea;
+pub mod gitlab;
+pub mod grafana;
+pub mod sentry;
+
+use actix_web::{
+    http::header::HeaderMap,
+    HttpResponse,
+};
+use hmac::{
+    Hmac,
+    Mac,
+};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{
+    delivery_response,
+    format::Pipeline,
+    maintenance::Maintenance,
+    quotas::{
+        self,
+        Quotas,
+    },
+    send_options::SendOptions,
+    severity::Severity,
+    stream::Streams,
+    TgClient,
+    TgMarkdownString,
+    Topic,
+};
+
+/// Push events can carry hundreds of commits, only the first ones are listed in the message
+const MAX_LISTED_COMMITS: usize = 10;
+
+#[derive(Deserialize)]
+pub struct TopicPath {
+    topic_name: String,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    id:      String,
+    message: String,
+    url:     String,
+    author:  CommitAuthor,
+}
+
+#[derive(Deserialize)]
+struct CommitAuthor {
+    name: String,
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// Compares secrets in constant time so their common prefix length doesn't leak through timing.
+/// Empty secret matches nothing, requests without one provide it empty
+pub fn secrets_match(expected: &str, provided: &str) -> bool {
+    !expected.is_empty()
+        && expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Checks hex encoded HMAC-SHA256 signature of the request body
+fn signature_matches(secret: &str, body: &[u8], hex_signature: &str) -> bool {
+    let signature = match decode_hex(hex_signature) {
+        Some(signature) => signature,
+        None => return false,
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body);
+
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn branch_name(git_ref: &str) -> &str {
+    git_ref
+        .strip_prefix("refs/heads/")
+        .or_else(|| git_ref.strip_prefix("refs/tags/"))
+        .unwrap_or(git_ref)
+}
+
+fn is_zero_sha(sha: &str) -> bool {
+    !sha.is_empty() && sha.chars().all(|ch| ch == '0')
+}
+
+fn render_push(
+    user: &str,
+    git_ref: &str,
+    after: &str,
+    commits: &[Commit],
+    total_commits: usize,
+) -> String {
+    let kind = if git_ref.starts_with("refs/tags/") {
+        "tag"
+    } else {
+        "branch"
+    };
+    let name = TgMarkdownString::code(branch_name(git_ref));
+    let user = TgMarkdownString::new(user);
+
+    if is_zero_sha(after) {
+        return format!("*{}* deleted {} {}", *user, kind, *name);
+    }
+
+    if commits.is_empty() {
+        return format!("*{}* pushed {} {}", *user, kind, *name);
+    }
+
+    let mut text = format!(
+        "*{}* pushed {} commit{} to {}\n",
+        *user,
+        total_commits,
+        if total_commits == 1 { "" } else { "s" },
+        *name
+    );
+
+    for commit in commits.iter().take(MAX_LISTED_COMMITS) {
+        let short_id = commit.id.get(..8).unwrap_or(&commit.id);
+        text.push_str(&format!(
+            "\n{} {} ― {}",
+            *TgMarkdownString::link(short_id, &commit.url),
+            *TgMarkdownString::new(commit.message.lines().next().unwrap_or_default()),
+            *TgMarkdownString::new(&commit.author.name),
+        ));
+    }
+
+    if total_commits > MAX_LISTED_COMMITS {
+        text.push_str(&format!(
+            "\n{}",
+            *TgMarkdownString::new(&format!(
+                "...and {} more",
+                total_commits - MAX_LISTED_COMMITS
+            ))
+        ));
+    }
+
+    text
+}
+
+async fn deliver(
+    tg_client: &TgClient,
+    maintenance: &Maintenance,
+    quotas: &Quotas,
+    streams: &Streams,
+    topic_info: &Topic,
+    topic_name: &str,
+    sender: &str,
+    text: &str,
+) -> HttpResponse {
+    if maintenance.intercept(topic_name, topic_info, sender, text) {
+        return HttpResponse::Accepted().body("Topic is under maintenance");
+    }
+
+    if let Some(response) =
+        quotas::enforce(quotas, tg_client, topic_name, topic_info, text.len()).await
+    {
+        return response;
+    }
+    streams.publish(
+        topic_name,
+        topic_info,
+        sender,
+        Severity::default(),
+        text,
+        &[],
+    );
+
+    let responses = tg_client
+        .send_formatted_to_all(
+            &tg_client.recipients_of(topic_name, topic_info),
+            &Pipeline::default(),
+            topic_name,
+            sender,
+            text,
+            SendOptions::of_topic(topic_info),
+        )
+        .await;
+
+    delivery_response(&responses)
+}
@@ -0,0 +1,279 @@
+use std::{
+    collections::{
+        hash_map::Entry,
+        HashMap,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use actix_web::{
+    web,
+    HttpRequest,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use super::{
+    deliver,
+    header_value,
+    signature_matches,
+    TopicPath,
+};
+use crate::{
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    maintenance::Maintenance,
+    metrics::Metrics,
+    quotas::Quotas,
+    state,
+    stream::Streams,
+    LiveTopics,
+    TgClient,
+    TgMarkdownString,
+};
+
+const SENDER: &str = "sentry";
+const DEFAULT_COLLAPSE_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Issues that didn't fire for this long are forgotten along with their suppressed count
+const RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Deserialize)]
+struct IssueAlert {
+    data: IssueAlertData,
+}
+
+#[derive(Deserialize)]
+struct IssueAlertData {
+    event:          Event,
+    triggered_rule: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    title:       String,
+    #[serde(default)]
+    culprit:     Option<String>,
+    #[serde(default)]
+    environment: Option<String>,
+    #[serde(default)]
+    level:       Option<String>,
+    #[serde(default)]
+    web_url:     Option<String>,
+    #[serde(default)]
+    issue_id:    Option<String>,
+}
+
+struct Collapsed {
+    last_sent:  Instant,
+    suppressed: usize,
+}
+
+/// Collapse window of an issue as it's exported
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Snapshot {
+    topic:      String,
+    issue_id:   String,
+    last_sent:  DateTime<Utc>,
+    suppressed: usize,
+}
+
+/// Remembers recently notified issues so repeated alerts for the same issue are not resent
+#[derive(Default)]
+pub struct RecentIssues {
+    issues: Mutex<HashMap<(String, String), Collapsed>>,
+}
+
+impl RecentIssues {
+    /// Returns number of notifications suppressed since the last one that went through,
+    /// or `None` if this one should be suppressed as well
+    fn admit(&self, topic: &str, issue_id: &str, window: Duration) -> Option<usize> {
+        let mut issues = self.issues.lock().expect("Recent issues lock is poisoned");
+        let now = Instant::now();
+
+        issues.retain(|_, collapsed| now.duration_since(collapsed.last_sent) < RETENTION);
+
+        match issues.entry((topic.to_owned(), issue_id.to_owned())) {
+            Entry::Vacant(entry) => {
+                entry.insert(Collapsed {
+                    last_sent:  now,
+                    suppressed: 0,
+                });
+                Some(0)
+            }
+            Entry::Occupied(mut entry) => {
+                let collapsed = entry.get_mut();
+                if now.duration_since(collapsed.last_sent) < window {
+                    collapsed.suppressed += 1;
+                    None
+                } else {
+                    collapsed.last_sent = now;
+                    Some(std::mem::take(&mut collapsed.suppressed))
+                }
+            }
+        }
+    }
+
+    pub fn export(&self) -> Vec<Snapshot> {
+        self.issues
+            .lock()
+            .expect("Recent issues lock is poisoned")
+            .iter()
+            .map(|((topic, issue_id), collapsed)| Snapshot {
+                topic:      topic.clone(),
+                issue_id:   issue_id.clone(),
+                last_sent:  state::wall_time(collapsed.last_sent),
+                suppressed: collapsed.suppressed,
+            })
+            .collect()
+    }
+
+    pub fn import(&self, snapshots: Vec<Snapshot>) {
+        let mut issues = self.issues.lock().expect("Recent issues lock is poisoned");
+        for snapshot in snapshots {
+            issues.insert(
+                (snapshot.topic, snapshot.issue_id),
+                Collapsed {
+                    last_sent:  state::instant_at(snapshot.last_sent),
+                    suppressed: snapshot.suppressed,
+                },
+            );
+        }
+    }
+}
+
+fn render(alert: &IssueAlert, suppressed: usize) -> String {
+    let event = &alert.data.event;
+    let mut text = format!("🐞 *{}*", *TgMarkdownString::new(&event.title));
+
+    if let Some(culprit) = event
+        .culprit
+        .as_deref()
+        .filter(|culprit| !culprit.is_empty())
+    {
+        text.push_str(&format!("\n{}", *TgMarkdownString::code(culprit)));
+    }
+
+    let details = [
+        ("Environment", event.environment.as_deref()),
+        ("Level", event.level.as_deref()),
+        ("Rule", alert.data.triggered_rule.as_deref()),
+    ];
+    for (name, value) in details {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            text.push_str(&format!("\n{}: {}", name, *TgMarkdownString::new(value)));
+        }
+    }
+
+    if suppressed > 0 {
+        text.push_str(&format!(
+            "\n_{}_",
+            *TgMarkdownString::new(&format!(
+                "{} more alerts for this issue were collapsed",
+                suppressed
+            ))
+        ));
+    }
+
+    if let Some(web_url) = &event.web_url {
+        text.push_str(&format!(
+            "\n{}",
+            *TgMarkdownString::link("Open in Sentry", web_url)
+        ));
+    }
+
+    text
+}
+
+pub async fn handle(
+    request: HttpRequest,
+    topics: web::Data<Arc<LiveTopics>>,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    streams: web::Data<Arc<Streams>>,
+    metrics: web::Data<Arc<Metrics>>,
+    recent_issues: web::Data<Arc<RecentIssues>>,
+    path_data: web::Path<TopicPath>,
+    body: web::Bytes,
+) -> impl Responder {
+    let topics = topics.current();
+    let topic_info = match topics.get(&path_data.topic_name) {
+        Some(topic_info) => topic_info,
+        None =>
+            return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic")),
+    };
+
+    let secret = match &topic_info.sentry_secret {
+        Some(secret) => secret,
+        None =>
+            return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic")),
+    };
+
+    if !signature_matches(
+        secret,
+        &body,
+        header_value(request.headers(), "Sentry-Hook-Signature"),
+    ) {
+        // Same answer as unknown topic, so topic names can't be guessed
+        return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic"));
+    }
+    metrics.count_ingress(&path_data.topic_name, body.len(), 0);
+
+    // Installation and other integration events are acknowledged and ignored
+    if header_value(request.headers(), "Sentry-Hook-Resource") != "event_alert" {
+        return HttpResponse::NoContent().finish();
+    }
+
+    let alert: IssueAlert = match serde_json::from_slice(&body) {
+        Ok(alert) => alert,
+        Err(err) =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::MalformedPayload,
+                format!("Malformed issue alert: {}", err),
+            )),
+    };
+
+    let suppressed = match &alert.data.event.issue_id {
+        Some(issue_id) => {
+            let window = topic_info
+                .sentry_collapse_window
+                .unwrap_or(DEFAULT_COLLAPSE_WINDOW);
+
+            match recent_issues.admit(&path_data.topic_name, issue_id, window) {
+                Some(suppressed) => suppressed,
+                None => return HttpResponse::NoContent().finish(),
+            }
+        }
+        None => 0,
+    };
+
+    deliver(
+        &tg_client,
+        &maintenance,
+        &quotas,
+        &streams,
+        topic_info,
+        &path_data.topic_name,
+        SENDER,
+        &render(&alert, suppressed),
+    )
+    .await
+}
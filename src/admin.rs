@@ -0,0 +1,176 @@
+use std::{
+    collections::BTreeMap,
+    future::{
+        ready,
+        Ready,
+    },
+    sync::Arc,
+};
+
+use actix_web::{
+    dev::{
+        Payload,
+        ServiceRequest,
+    },
+    http::header::{
+        self,
+        HeaderMap,
+    },
+    web,
+    FromRequest,
+    HttpRequest,
+};
+use serde::Deserialize;
+
+use crate::{
+    adapters::secrets_match,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    jwt::Jwt,
+};
+
+/// What a token lets its bearer do, every role can do what the ones before it can
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[derive(Eq)]
+#[derive(PartialOrd)]
+#[derive(Ord)]
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Lists chats, groups, bans and maintenance windows, reads metrics
+    Viewer,
+    /// Mutes topics with maintenance windows, lifts bans, reads and deletes messages and logs
+    Operator,
+    /// Edits recipient groups and moves runtime state
+    Admin,
+}
+
+/// Token of `[admin_tokens.<name>]`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminToken {
+    token: String,
+    role:  Role,
+}
+
+/// Admin API is enabled only when a token is configured
+pub struct AdminConfig {
+    /// `admin_token`, which has admin role
+    pub token:  Option<String>,
+    pub tokens: BTreeMap<String, AdminToken>,
+    /// Identity provider whose tokens get roles of `jwt.admin_roles`
+    pub jwt:    Option<Arc<Jwt>>,
+}
+
+impl AdminConfig {
+    fn is_enabled(&self) -> bool {
+        self.token.is_some()
+            || !self.tokens.is_empty()
+            || self.jwt.as_ref().is_some_and(|jwt| jwt.grants_roles())
+    }
+
+    /// Every token is compared, so the time taken doesn't tell which one matched
+    fn role_of(&self, provided_token: &str) -> Option<Role> {
+        let admin_token = self
+            .token
+            .as_deref()
+            .map(|token| (token, Role::Admin))
+            .into_iter();
+        let tokens = self
+            .tokens
+            .values()
+            .map(|admin_token| (admin_token.token.as_str(), admin_token.role));
+
+        admin_token
+            .chain(tokens)
+            .filter(|(token, _)| secrets_match(token, provided_token))
+            .map(|(_, role)| role)
+            .max()
+    }
+}
+
+fn verify(
+    admin_config: Option<&web::Data<AdminConfig>>,
+    headers: &HeaderMap,
+    required: Role,
+) -> Result<Role, actix_web::Error> {
+    let admin_config = match admin_config.filter(|admin_config| admin_config.is_enabled()) {
+        Some(admin_config) => admin_config,
+        None =>
+            return Err(ApiError::new(ErrorCode::AdminApiDisabled, "Admin API is disabled").into()),
+    };
+
+    let provided_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    let role = admin_config.role_of(provided_token).or_else(|| {
+        let jwt = admin_config.jwt.as_ref()?;
+        let claims = jwt
+            .verify(Jwt::bearer(headers)?)
+            .map_err(|err| log::debug!("Rejected token of admin API: {}", err))
+            .ok()?;
+        jwt.role_of(&claims)
+    });
+
+    match role {
+        Some(role) if role >= required => Ok(role),
+        Some(_) => Err(ApiError::new(
+            ErrorCode::InsufficientRole,
+            "Token's role doesn't allow this",
+        )
+        .into()),
+        None => Err(ApiError::new(ErrorCode::InvalidToken, "Invalid admin token").into()),
+    }
+}
+
+/// Extracting it succeeds only for requests carrying a token of admin role in
+/// `Authorization: Bearer` header
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(verify(request.app_data(), request.headers(), Role::Admin).map(|_| Admin))
+    }
+}
+
+/// Extracting it succeeds for tokens of operator role and above
+pub struct Operator;
+
+impl Operator {
+    /// Whether the request carries token that's at least operator's, for middleware that has no
+    /// extractors
+    pub fn is_present(request: &ServiceRequest) -> bool {
+        verify(request.app_data(), request.headers(), Role::Operator).is_ok()
+    }
+}
+
+impl FromRequest for Operator {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(verify(request.app_data(), request.headers(), Role::Operator).map(|_| Operator))
+    }
+}
+
+/// Extracting it succeeds for tokens of any role
+pub struct Viewer;
+
+impl FromRequest for Viewer {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(verify(request.app_data(), request.headers(), Role::Viewer).map(|_| Viewer))
+    }
+}
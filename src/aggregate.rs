@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use actix_web::rt::time::interval;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use humantime_serde::re::humantime::format_duration;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    deliver_to_all,
+    locale::Phrase,
+    state,
+    Delivery,
+    SendOptions,
+    SentMessage,
+    TgClient,
+    TgMarkdownString,
+};
+
+/// Follow-ups are sent or edited this often, not on every repeat
+const FLUSH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Message that was delivered and repeats since
+#[derive(Clone)]
+struct Group {
+    started:    Instant,
+    window:     Duration,
+    /// Times the message came again, and how many of those the follow-ups show
+    repeats:    u64,
+    reported:   u64,
+    /// Messages the follow-ups reply to, empty while the first one is being delivered
+    sent:       Vec<SentMessage>,
+    follow_ups: Vec<SentMessage>,
+}
+
+/// Group as it's exported
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Snapshot {
+    topic:      String,
+    sender:     String,
+    text:       String,
+    started:    DateTime<Utc>,
+    #[serde(with = "humantime_serde")]
+    window:     Duration,
+    repeats:    u64,
+    reported:   u64,
+    sent:       Vec<SentMessage>,
+    follow_ups: Vec<SentMessage>,
+}
+
+/// Identical messages of topics with `aggregate_window`, by topic, sender and text
+#[derive(Default)]
+pub struct Aggregates {
+    groups: Mutex<HashMap<(String, String, String), Group>>,
+}
+
+impl Aggregates {
+    /// Whether the message repeats one delivered within the window, it's counted then instead of
+    /// being sent. Otherwise it starts a new group
+    pub fn repeat(&self, topic: &str, sender: &str, text: &str, window: Duration) -> bool {
+        let mut groups = self.groups.lock().expect("Aggregates lock is poisoned");
+        let key = (topic.to_owned(), sender.to_owned(), text.to_owned());
+
+        if let Some(group) = groups.get_mut(&key) {
+            if group.started.elapsed() < group.window {
+                group.repeats += 1;
+                return true;
+            }
+        }
+
+        groups.insert(
+            key,
+            Group {
+                started: Instant::now(),
+                window,
+                repeats: 0,
+                reported: 0,
+                sent: Vec::new(),
+                follow_ups: Vec::new(),
+            },
+        );
+        false
+    }
+
+    /// Remembers where the first message went, a message nobody got isn't aggregated
+    pub fn attach(&self, topic: &str, sender: &str, text: &str, deliveries: &[Delivery]) {
+        let mut groups = self.groups.lock().expect("Aggregates lock is poisoned");
+        let key = (topic.to_owned(), sender.to_owned(), text.to_owned());
+
+        let sent = deliveries
+            .iter()
+            .filter_map(Delivery::sent_message)
+            .cloned()
+            .collect::<Vec<_>>();
+        if sent.is_empty() {
+            groups.remove(&key);
+        } else if let Some(group) = groups.get_mut(&key) {
+            group.sent = sent;
+        }
+    }
+
+    /// Groups with repeats the follow-ups don't show yet. Groups past their window are taken
+    /// out, they get the last update
+    fn take_unreported(&self) -> Vec<((String, String, String), Group)> {
+        let mut groups = self.groups.lock().expect("Aggregates lock is poisoned");
+        let mut unreported = Vec::new();
+
+        let expired = groups
+            .iter()
+            .filter(|(_, group)| group.started.elapsed() >= group.window)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in expired {
+            let group = groups.remove(&key).expect("Key is taken from the map");
+            if group.repeats > group.reported && !group.sent.is_empty() {
+                unreported.push((key, group));
+            }
+        }
+
+        for (key, group) in groups.iter_mut() {
+            if group.repeats > group.reported && !group.sent.is_empty() {
+                unreported.push((key.clone(), group.clone()));
+                group.reported = group.repeats;
+            }
+        }
+
+        unreported
+    }
+
+    pub fn export(&self) -> Vec<Snapshot> {
+        self.groups
+            .lock()
+            .expect("Aggregates lock is poisoned")
+            .iter()
+            .map(|((topic, sender, text), group)| Snapshot {
+                topic:      topic.clone(),
+                sender:     sender.clone(),
+                text:       text.clone(),
+                started:    state::wall_time(group.started),
+                window:     group.window,
+                repeats:    group.repeats,
+                reported:   group.reported,
+                sent:       group.sent.clone(),
+                follow_ups: group.follow_ups.clone(),
+            })
+            .collect()
+    }
+
+    pub fn import(&self, snapshots: Vec<Snapshot>) {
+        let mut groups = self.groups.lock().expect("Aggregates lock is poisoned");
+        for snapshot in snapshots {
+            groups.insert(
+                (snapshot.topic, snapshot.sender, snapshot.text),
+                Group {
+                    started:    state::instant_at(snapshot.started),
+                    window:     snapshot.window,
+                    repeats:    snapshot.repeats,
+                    reported:   snapshot.reported,
+                    sent:       snapshot.sent,
+                    follow_ups: snapshot.follow_ups,
+                },
+            );
+        }
+    }
+
+    /// Follow-ups of groups still in their window are kept to be edited next time
+    fn keep_follow_ups(
+        &self,
+        key: &(String, String, String),
+        started: Instant,
+        follow_ups: Vec<SentMessage>,
+    ) {
+        let mut groups = self.groups.lock().expect("Aggregates lock is poisoned");
+        if let Some(group) = groups.get_mut(key) {
+            // A new group of the same message may have started once the window was over
+            if group.started == started {
+                group.follow_ups = follow_ups;
+            }
+        }
+    }
+}
+
+/// Whole minutes once it's over a minute, so follow-ups read "in the last 10m"
+fn rounded(duration: Duration) -> Duration {
+    match duration.as_secs() {
+        seconds if seconds >= 60 => Duration::from_secs(seconds / 60 * 60),
+        seconds => Duration::from_secs(seconds.max(1)),
+    }
+}
+
+/// Replies to delivered messages with the number of times they were seen, editing the reply as
+/// more repeats come
+pub fn spawn_flusher(aggregates: Arc<Aggregates>, tg_client: Arc<TgClient>) {
+    actix_web::rt::spawn(async move {
+        let mut ticks = interval(FLUSH_INTERVAL);
+
+        loop {
+            ticks.tick().await;
+
+            for (key, group) in aggregates.take_unreported() {
+                let (topic, _, _) = &key;
+                let elapsed = rounded(group.started.elapsed().min(group.window));
+                let text = format!(
+                    "🔁 _{}_",
+                    *TgMarkdownString::new(&tg_client.locale(topic).fill(
+                        Phrase::SeenTimes,
+                        &[
+                            &(group.repeats + 1).to_string(),
+                            &format_duration(elapsed).to_string()
+                        ],
+                    ))
+                );
+
+                let follow_ups = if group.follow_ups.is_empty() {
+                    let recipients = group
+                        .sent
+                        .iter()
+                        .map(|sent| sent.recipient.clone())
+                        .collect::<Vec<_>>();
+                    let chunks = [text];
+                    deliver_to_all(&recipients, |recipient| {
+                        let sent = group
+                            .sent
+                            .iter()
+                            .find(|sent| sent.recipient == recipient)
+                            .expect("Recipients are taken from sent messages");
+                        let options = SendOptions {
+                            reply_to: Some(sent.message_id),
+                            silent: true,
+                            ..SendOptions::default()
+                        };
+                        tg_client.send_message(recipient, topic, &chunks, options)
+                    })
+                    .await
+                    .iter()
+                    .filter_map(Delivery::sent_message)
+                    .cloned()
+                    .collect()
+                } else {
+                    for follow_up in &group.follow_ups {
+                        match tg_client.edit_text(topic, follow_up, text.clone()).await {
+                            Ok(response) if response.status().is_success() => (),
+                            Ok(response) => log::warn!(
+                                "Telegram responded with {} to follow-up edit for {}",
+                                response.status(),
+                                follow_up.recipient
+                            ),
+                            Err(err) => log::warn!(
+                                "Failed to edit follow-up for {}: {}",
+                                follow_up.recipient,
+                                err.without_url()
+                            ),
+                        }
+                    }
+                    group.follow_ups
+                };
+
+                aggregates.keep_follow_ups(&key, group.started, follow_ups);
+            }
+        }
+    });
+}
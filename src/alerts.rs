@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    future::{
+        ready,
+        Ready,
+    },
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use actix_web::{
+    dev::Payload,
+    FromRequest,
+    HttpRequest,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    deliver_to_all,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    escalation::Escalations,
+    format::Pipeline,
+    locale::Phrase,
+    send_options::SendOptions,
+    state,
+    Delivery,
+    SentMessage,
+    TgClient,
+};
+
+/// Alerts that never got resolved are forgotten eventually, their resolve is sent as usual
+const FIRING_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// What happens to the message of firing alert once it's resolved
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolveMode {
+    /// Message gets `✅ Resolved` prefix
+    #[default]
+    Edit,
+    /// Resolve is sent as reply to the message
+    Reply,
+}
+
+/// Identity of the alert a message is about, taken from `X-Alert-Id` and `X-Alert-Status`
+/// headers
+#[derive(Default)]
+pub struct AlertUpdate {
+    id:       Option<String>,
+    resolved: bool,
+}
+
+impl AlertUpdate {
+    pub fn new(id: Option<String>, resolved: bool) -> Self {
+        Self { id, resolved }
+    }
+
+    fn parse(request: &HttpRequest) -> Result<Self, ApiError> {
+        let header = |name| {
+            request.headers().get(name).map(|value| {
+                value.to_str().map(str::trim).map_err(|_| {
+                    ApiError::new(
+                        ErrorCode::InvalidHeader,
+                        format!("{} has to be visible ASCII", name),
+                    )
+                })
+            })
+        };
+
+        let id = header("X-Alert-Id").transpose()?;
+        let resolved = match header("X-Alert-Status").transpose()? {
+            None | Some("firing") => false,
+            Some("resolved") => true,
+            Some(_) =>
+                return Err(ApiError::new(
+                    ErrorCode::InvalidHeader,
+                    "X-Alert-Status has to be firing or resolved",
+                )),
+        };
+        if resolved && id.is_none() {
+            return Err(ApiError::new(
+                ErrorCode::InvalidHeader,
+                "X-Alert-Status: resolved requires X-Alert-Id",
+            ));
+        }
+
+        Ok(Self {
+            id: id.map(str::to_owned),
+            resolved,
+        })
+    }
+
+    /// Id of the alert the message fires
+    pub fn firing(&self) -> Option<&str> {
+        self.id.as_deref().filter(|_| !self.resolved)
+    }
+
+    /// Id of the alert the message resolves
+    pub fn resolving(&self) -> Option<&str> {
+        self.id.as_deref().filter(|_| self.resolved)
+    }
+}
+
+impl FromRequest for AlertUpdate {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::parse(request).map_err(Into::into))
+    }
+}
+
+struct FiringAlert {
+    sent:     Vec<SentMessage>,
+    /// Escalation waiting for Ack, stopped by the resolve
+    ack_id:   Option<u64>,
+    pinned:   bool,
+    fired_at: Instant,
+}
+
+/// Firing alert as it's exported
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Snapshot {
+    topic:    String,
+    id:       String,
+    sent:     Vec<SentMessage>,
+    ack_id:   Option<u64>,
+    pinned:   bool,
+    fired_at: DateTime<Utc>,
+}
+
+/// Messages of alerts that fired and weren't resolved yet, by topic and alert id
+#[derive(Default)]
+pub struct Alerts {
+    firing: Mutex<HashMap<(String, String), FiringAlert>>,
+}
+
+impl Alerts {
+    /// Remembers delivered messages of the alert, replacing ones it fired with before
+    pub fn fire(
+        &self,
+        topic: &str,
+        id: &str,
+        deliveries: &[Delivery],
+        ack_id: Option<u64>,
+        pinned: bool,
+    ) {
+        let mut firing = self.firing.lock().expect("Alerts lock is poisoned");
+        firing.retain(|_, alert| alert.fired_at.elapsed() < FIRING_RETENTION);
+        firing.insert(
+            (topic.to_owned(), id.to_owned()),
+            FiringAlert {
+                sent: deliveries
+                    .iter()
+                    .filter_map(Delivery::sent_message)
+                    .cloned()
+                    .collect(),
+                ack_id,
+                pinned,
+                fired_at: Instant::now(),
+            },
+        );
+    }
+
+    pub fn is_firing(&self, topic: &str, id: &str) -> bool {
+        self.firing
+            .lock()
+            .expect("Alerts lock is poisoned")
+            .contains_key(&(topic.to_owned(), id.to_owned()))
+    }
+
+    pub fn export(&self) -> Vec<Snapshot> {
+        self.firing
+            .lock()
+            .expect("Alerts lock is poisoned")
+            .iter()
+            .map(|((topic, id), alert)| Snapshot {
+                topic:    topic.clone(),
+                id:       id.clone(),
+                sent:     alert.sent.clone(),
+                ack_id:   alert.ack_id,
+                pinned:   alert.pinned,
+                fired_at: state::wall_time(alert.fired_at),
+            })
+            .collect()
+    }
+
+    pub fn import(&self, snapshots: Vec<Snapshot>) {
+        let mut firing = self.firing.lock().expect("Alerts lock is poisoned");
+        for snapshot in snapshots {
+            firing.insert(
+                (snapshot.topic, snapshot.id),
+                FiringAlert {
+                    sent:     snapshot.sent,
+                    ack_id:   snapshot.ack_id,
+                    pinned:   snapshot.pinned,
+                    fired_at: state::instant_at(snapshot.fired_at),
+                },
+            );
+        }
+    }
+
+    fn take(&self, topic: &str, id: &str) -> Option<FiringAlert> {
+        self.firing
+            .lock()
+            .expect("Alerts lock is poisoned")
+            .remove(&(topic.to_owned(), id.to_owned()))
+    }
+}
+
+/// Message that resolves an alert
+pub struct Resolve<'a> {
+    pub topic:    &'a str,
+    pub sender:   &'a str,
+    pub id:       &'a str,
+    pub text:     &'a str,
+    pub mode:     ResolveMode,
+    pub pipeline: &'a Pipeline,
+    pub options:  SendOptions<'a>,
+}
+
+/// Edits or replies to messages of the firing alert, stops its escalation and unpins it.
+/// Returns `None` if the alert isn't known, the resolve is then sent as usual
+pub async fn resolve(
+    alerts: &Alerts,
+    escalations: &Escalations,
+    tg_client: &TgClient,
+    resolve: Resolve<'_>,
+) -> Option<Vec<Delivery>> {
+    let alert = alerts.take(resolve.topic, resolve.id)?;
+
+    if let Some(ack_id) = alert.ack_id {
+        escalations.acknowledge(ack_id);
+    }
+    if alert.pinned {
+        for sent in &alert.sent {
+            tg_client.unpin(sent).await;
+        }
+    }
+    if alert.sent.is_empty() {
+        return None;
+    }
+
+    let recipients = alert
+        .sent
+        .iter()
+        .map(|sent| sent.recipient.clone())
+        .collect::<Vec<_>>();
+    let sent_to = |recipient: &str| {
+        alert
+            .sent
+            .iter()
+            .find(|sent| sent.recipient == recipient)
+            .expect("Recipients are taken from sent messages")
+    };
+
+    let deliveries = match resolve.mode {
+        ResolveMode::Edit =>
+            deliver_to_all(&recipients, |recipient| {
+                let sent = sent_to(recipient);
+                let resolved = tg_client.locale(resolve.topic).text(Phrase::Resolved);
+                tg_client.edit_text(
+                    resolve.topic,
+                    sent,
+                    format!("✅ *{}*\n{}", resolved, sent.text),
+                )
+            })
+            .await,
+        ResolveMode::Reply => {
+            let chunks = tg_client.render(
+                resolve.pipeline,
+                resolve.topic,
+                resolve.sender,
+                resolve.text,
+                &resolve.options,
+            );
+            deliver_to_all(&recipients, |recipient| {
+                tg_client.send_message(
+                    recipient,
+                    resolve.topic,
+                    &chunks,
+                    SendOptions {
+                        reply_to: Some(sent_to(recipient).message_id),
+                        ..resolve.options
+                    },
+                )
+            })
+            .await
+        }
+    };
+
+    Some(deliveries)
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+};
+
+use actix_web::rt::time::sleep;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use cron::Schedule;
+use serde::Deserialize;
+
+use crate::{
+    coordination::Coordinator,
+    maintenance::deserialize_schedule,
+    send_options::SendOptions,
+    LiveTopics,
+    TgClient,
+    Topics,
+};
+
+/// Message microphone posts to a topic by itself on schedule
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Announcement {
+    /// Cron expression with seconds field in UTC, e.g. "0 45 1 * * *"
+    #[serde(deserialize_with = "deserialize_schedule")]
+    cron:   Box<Schedule>,
+    topic:  String,
+    /// `{date}` and `{time}` are substituted with UTC date and time it's sent at
+    text:   String,
+    /// Name of the announcement by default
+    sender: Option<String>,
+}
+
+pub type Announcements = BTreeMap<String, Announcement>;
+
+impl Announcement {
+    fn render(&self, at: DateTime<Utc>) -> String {
+        self.text
+            .replace("{date}", &at.format("%Y-%m-%d").to_string())
+            .replace("{time}", &at.format("%H:%M").to_string())
+    }
+}
+
+/// Announcements have to be posted to topics there are
+pub fn check(announcements: &Announcements, topics: &Topics) -> Result<(), String> {
+    match announcements
+        .iter()
+        .find(|(_, announcement)| !topics.contains_key(&announcement.topic))
+    {
+        Some((name, announcement)) => Err(format!(
+            "Announcement \"{}\" is posted to \"{}\", which is not a topic",
+            name, announcement.topic
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Every announcement waits for its next occurrence, only the leader posts it
+pub fn spawn(
+    announcements: Announcements,
+    topics: Arc<LiveTopics>,
+    tg_client: Arc<TgClient>,
+    coordinator: Arc<Coordinator>,
+) {
+    for (name, announcement) in announcements {
+        let topics = topics.clone();
+        let tg_client = tg_client.clone();
+        let coordinator = coordinator.clone();
+
+        actix_web::rt::spawn(async move {
+            let sender = announcement.sender.clone().unwrap_or_else(|| name.clone());
+
+            while let Some(occurrence) = announcement.cron.upcoming(Utc).next() {
+                sleep((occurrence - Utc::now()).to_std().unwrap_or_default()).await;
+
+                if !coordinator.is_leader() {
+                    continue;
+                }
+                // Topics can be gone since config was reloaded
+                let topics = topics.current();
+                let topic_info = match topics.get(&announcement.topic) {
+                    Some(topic_info) => topic_info,
+                    None => {
+                        log::warn!(
+                            "Skipped announcement \"{}\", topic \"{}\" is gone",
+                            name,
+                            announcement.topic
+                        );
+                        continue;
+                    }
+                };
+
+                let responses = tg_client
+                    .send_formatted_to_all(
+                        &tg_client.recipients_of(&announcement.topic, topic_info),
+                        &topic_info.format,
+                        &announcement.topic,
+                        &sender,
+                        &announcement.render(occurrence),
+                        SendOptions::of_topic(topic_info),
+                    )
+                    .await;
+                if responses.iter().any(|delivery| !delivery.is_delivered()) {
+                    log::warn!("Failed to send announcement \"{}\"", name);
+                }
+            }
+
+            log::warn!("Announcement \"{}\" has no more occurrences", name);
+        });
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    io,
+    path::PathBuf,
+    sync::{
+        Arc,
+        RwLock,
+    },
+};
+
+use actix_web::{
+    http::header::{
+        self,
+        HeaderMap,
+    },
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sha2::{
+    Digest,
+    Sha256,
+};
+use uuid::Uuid;
+
+use crate::{
+    adapters::secrets_match,
+    admin::{
+        Admin,
+        Viewer,
+    },
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    threshold::write_atomically,
+};
+
+/// Every key starts with it, so keys are told apart from other bearer tokens
+const PREFIX: &str = "mic_";
+/// Random bytes of the secret part of a key
+const SECRET_BYTES: usize = 32;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeysConfig {
+    /// Hashes of keys created with admin API are kept here, a missing file is no keys
+    file: PathBuf,
+}
+
+/// What a key lets its bearer post
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Scope {
+    pub topics:      Vec<String>,
+    /// Files can be sent too, keys without it can send text only
+    #[serde(default)]
+    pub attachments: bool,
+    /// Largest request body in bytes, requests have to have `Content-Length` then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size:    Option<u64>,
+}
+
+/// Key a request has
+pub struct ApiKey {
+    pub id:    String,
+    pub scope: Scope,
+}
+
+#[derive(Serialize)]
+#[derive(Deserialize)]
+struct StoredKey {
+    id:         String,
+    name:       String,
+    /// Hex SHA-256 of the whole key, the key itself is shown only once when it's created
+    hash:       String,
+    created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    scope:      Scope,
+}
+
+#[derive(Default)]
+#[derive(Deserialize)]
+struct Stored {
+    #[serde(default)]
+    keys: Vec<StoredKey>,
+}
+
+/// Key as admin API shows it, with the key itself only in response to creating it
+#[derive(Serialize)]
+pub struct Described<'a> {
+    id:         &'a str,
+    name:       &'a str,
+    created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    scope:      &'a Scope,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key:        Option<String>,
+}
+
+/// Keys of producers, created and revoked at runtime
+pub struct ApiKeys {
+    file: PathBuf,
+    keys: RwLock<Vec<StoredKey>>,
+}
+
+fn hash(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+impl StoredKey {
+    fn describe(&self, key: Option<String>) -> Described<'_> {
+        Described {
+            id: &self.id,
+            name: &self.name,
+            created_at: self.created_at,
+            scope: &self.scope,
+            key,
+        }
+    }
+}
+
+impl Scope {
+    /// Rejects requests with files or bodies the key isn't allowed to send
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        let is_multipart = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("multipart/form-data"));
+        if is_multipart && !self.attachments {
+            return Err(ApiError::new(
+                ErrorCode::OutOfScope,
+                "API key can send text only",
+            ));
+        }
+
+        if let Some(max_size) = self.max_size {
+            let size = headers
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok());
+            match size {
+                Some(size) if size <= max_size => {}
+                Some(_) =>
+                    return Err(ApiError::new(
+                        ErrorCode::OutOfScope,
+                        format!("API key allows bodies of up to {} bytes", max_size),
+                    )),
+                None =>
+                    return Err(ApiError::new(
+                        ErrorCode::OutOfScope,
+                        "API key with max_size requires Content-Length",
+                    )),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ApiKeys {
+    pub fn load(config: ApiKeysConfig) -> io::Result<Self> {
+        let stored = match std::fs::read(&config.file) {
+            Ok(contents) => serde_json::from_slice::<Stored>(&contents)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Stored::default(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            file: config.file,
+            keys: RwLock::new(stored.keys),
+        })
+    }
+
+    /// API key of `Authorization: Bearer` header, if there is one
+    pub fn bearer(headers: &HeaderMap) -> Option<&str> {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .filter(|token| token.starts_with(PREFIX))
+    }
+
+    /// The key unless it's unknown or revoked
+    pub fn find(&self, key: &str) -> Option<ApiKey> {
+        let (id, _) = key.strip_prefix(PREFIX)?.split_once('_')?;
+        let provided_hash = hash(key);
+
+        self.keys
+            .read()
+            .expect("API keys lock is poisoned")
+            .iter()
+            .find(|stored| stored.id == id && secrets_match(&stored.hash, &provided_hash))
+            .map(|stored| ApiKey {
+                id:    stored.id.clone(),
+                scope: stored.scope.clone(),
+            })
+    }
+
+    fn save(&self, keys: &[StoredKey]) -> io::Result<()> {
+        write_atomically(
+            &self.file,
+            &serde_json::json!({
+                "keys": keys,
+            }),
+        )
+    }
+}
+
+fn disabled() -> HttpResponse {
+    HttpResponse::from(ApiError::new(
+        ErrorCode::ApiKeysDisabled,
+        "API keys are not configured",
+    ))
+}
+
+fn not_saved(err: io::Error) -> HttpResponse {
+    log::error!("Failed to save API keys: {}", err);
+    HttpResponse::from(ApiError::new(
+        ErrorCode::InternalError,
+        "Failed to save API keys",
+    ))
+}
+
+pub async fn list_keys(_: Viewer, api_keys: web::Data<Option<Arc<ApiKeys>>>) -> impl Responder {
+    let api_keys = match api_keys.as_ref() {
+        Some(api_keys) => api_keys,
+        None => return disabled(),
+    };
+
+    let keys = api_keys.keys.read().expect("API keys lock is poisoned");
+    HttpResponse::Ok().json(
+        keys.iter()
+            .map(|stored| stored.describe(None))
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewKey {
+    /// Who the key is for, shown in logs and listings
+    name:        String,
+    topics:      Vec<String>,
+    #[serde(default)]
+    attachments: bool,
+    max_size:    Option<u64>,
+}
+
+/// Responds with the key, which can't be seen again
+pub async fn create_key(
+    _: Admin,
+    api_keys: web::Data<Option<Arc<ApiKeys>>>,
+    new_key: web::Json<NewKey>,
+) -> impl Responder {
+    let api_keys = match api_keys.as_ref() {
+        Some(api_keys) => api_keys,
+        None => return disabled(),
+    };
+    let new_key = new_key.into_inner();
+
+    let id = Uuid::new_v4().simple().to_string()[..12].to_owned();
+    let mut secret = [0; SECRET_BYTES];
+    if let Err(err) = openssl::rand::rand_bytes(&mut secret) {
+        log::error!("Failed to generate API key: {}", err);
+        return HttpResponse::from(ApiError::new(
+            ErrorCode::InternalError,
+            "Failed to generate API key",
+        ));
+    }
+    let key = format!(
+        "{}{}_{}",
+        PREFIX,
+        id,
+        base64::encode_config(secret, base64::URL_SAFE_NO_PAD)
+    );
+
+    let stored = StoredKey {
+        id,
+        name: new_key.name,
+        hash: hash(&key),
+        created_at: Utc::now(),
+        scope: Scope {
+            topics:      new_key.topics,
+            attachments: new_key.attachments,
+            max_size:    new_key.max_size,
+        },
+    };
+
+    let mut keys = api_keys.keys.write().expect("API keys lock is poisoned");
+    keys.push(stored);
+    if let Err(err) = api_keys.save(&keys) {
+        keys.pop();
+        return not_saved(err);
+    }
+
+    let stored = keys.last().expect("Key was just pushed");
+    log::info!("Created API key {} for {}", stored.id, stored.name);
+    HttpResponse::Created().json(stored.describe(Some(key)))
+}
+
+#[derive(Deserialize)]
+pub struct KeyPath {
+    id: String,
+}
+
+/// Requests with the key are rejected right away
+pub async fn revoke_key(
+    _: Admin,
+    api_keys: web::Data<Option<Arc<ApiKeys>>>,
+    path_data: web::Path<KeyPath>,
+) -> impl Responder {
+    let api_keys = match api_keys.as_ref() {
+        Some(api_keys) => api_keys,
+        None => return disabled(),
+    };
+
+    let mut keys = api_keys.keys.write().expect("API keys lock is poisoned");
+    let position = match keys.iter().position(|stored| stored.id == path_data.id) {
+        Some(position) => position,
+        None =>
+            return HttpResponse::from(ApiError::new(ErrorCode::ApiKeyNotFound, "No such API key")),
+    };
+
+    let revoked = keys.remove(position);
+    if let Err(err) = api_keys.save(&keys) {
+        keys.insert(position, revoked);
+        return not_saved(err);
+    }
+
+    log::info!("Revoked API key {} of {}", revoked.id, revoked.name);
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    net::IpAddr,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use actix_web::{
+    http::StatusCode,
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    admin::{
+        Operator,
+        Viewer,
+    },
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    metrics::Metrics,
+};
+
+#[derive(Debug)]
+#[derive(Deserialize)]
+#[derive(Clone)]
+#[serde(deny_unknown_fields)]
+pub struct BanConfig {
+    /// Rejected requests allowed from one address within `within` before it's banned
+    pub max_rejections: usize,
+    #[serde(with = "humantime_serde")]
+    pub within:         Duration,
+    /// How long banned address stays banned
+    #[serde(with = "humantime_serde")]
+    pub duration:       Duration,
+}
+
+#[derive(Default)]
+struct Offender {
+    rejections:   VecDeque<Instant>,
+    banned_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Ban {
+    address:      IpAddr,
+    banned_until: DateTime<Utc>,
+}
+
+/// Addresses that keep getting 401, 403 or 404 are banned for a while
+pub struct Bans {
+    config:    Option<BanConfig>,
+    metrics:   Arc<Metrics>,
+    offenders: Mutex<HashMap<IpAddr, Offender>>,
+}
+
+impl Bans {
+    pub fn new(config: Option<BanConfig>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            metrics,
+            offenders: Mutex::default(),
+        }
+    }
+
+    pub fn is_banned(&self, address: IpAddr) -> bool {
+        self.offenders
+            .lock()
+            .expect("Bans lock is poisoned")
+            .get(&address)
+            .and_then(|offender| offender.banned_until)
+            .is_some_and(|banned_until| banned_until > Utc::now())
+    }
+
+    /// Counts the response against the address if it's a rejection
+    pub fn observe(&self, address: IpAddr, status: StatusCode) {
+        let config = match &self.config {
+            Some(config) => config,
+            None => return,
+        };
+
+        if !matches!(
+            status,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND
+        ) {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut offenders = self.offenders.lock().expect("Bans lock is poisoned");
+        offenders.retain(|_, offender| {
+            while offender
+                .rejections
+                .front()
+                .is_some_and(|rejected_at| now.duration_since(*rejected_at) > config.within)
+            {
+                offender.rejections.pop_front();
+            }
+
+            !offender.rejections.is_empty()
+                || offender
+                    .banned_until
+                    .is_some_and(|banned_until| banned_until > Utc::now())
+        });
+
+        let offender = offenders.entry(address).or_default();
+        offender.rejections.push_back(now);
+
+        if offender.rejections.len() >= config.max_rejections {
+            let duration =
+                chrono::Duration::from_std(config.duration).unwrap_or(chrono::Duration::MAX);
+
+            offender.rejections.clear();
+            offender.banned_until = Some(Utc::now() + duration);
+
+            log::warn!(
+                "Banned {} for {}",
+                address,
+                humantime_serde::re::humantime::format_duration(config.duration)
+            );
+            self.metrics.increment("microphone_bans_total", &[]);
+        }
+    }
+
+    /// Bans in effect, rejections that didn't lead to one yet aren't kept
+    pub fn export(&self) -> Vec<Ban> {
+        let now = Utc::now();
+        self.offenders
+            .lock()
+            .expect("Bans lock is poisoned")
+            .iter()
+            .filter_map(|(address, offender)| {
+                offender
+                    .banned_until
+                    .filter(|banned_until| *banned_until > now)
+                    .map(|banned_until| Ban {
+                        address: *address,
+                        banned_until,
+                    })
+            })
+            .collect()
+    }
+
+    pub fn import(&self, bans: Vec<Ban>) {
+        let mut offenders = self.offenders.lock().expect("Bans lock is poisoned");
+        for ban in bans {
+            offenders.entry(ban.address).or_default().banned_until = Some(ban.banned_until);
+        }
+    }
+}
+
+pub async fn list_bans(_: Viewer, bans: web::Data<Arc<Bans>>) -> impl Responder {
+    HttpResponse::Ok().json(bans.export())
+}
+
+#[derive(Deserialize)]
+pub struct BanPath {
+    address: IpAddr,
+}
+
+pub async fn delete_ban(
+    _: Operator,
+    bans: web::Data<Arc<Bans>>,
+    path_data: web::Path<BanPath>,
+) -> impl Responder {
+    match bans
+        .offenders
+        .lock()
+        .expect("Bans lock is poisoned")
+        .remove(&path_data.address)
+    {
+        Some(_) => HttpResponse::NoContent().finish(),
+        None => HttpResponse::from(ApiError::new(ErrorCode::BanNotFound, "No such ban")),
+    }
+}
+
+pub async fn delete_bans(_: Operator, bans: web::Data<Arc<Bans>>) -> impl Responder {
+    bans.offenders
+        .lock()
+        .expect("Bans lock is poisoned")
+        .clear();
+
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,458 @@
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque,
+    },
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+    sync::{
+        atomic::{
+            AtomicU32,
+            Ordering,
+        },
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use hyper::client::connect::HttpInfo;
+use reqwest::{
+    header::{
+        HeaderMap,
+        HeaderName,
+        HeaderValue,
+    },
+    ClientBuilder,
+    Proxy,
+    RequestBuilder,
+    Url,
+};
+use serde::{
+    Deserialize,
+    Deserializer,
+};
+use tokio::net::lookup_host;
+
+use crate::{
+    internal::{
+        Incident,
+        InternalAlerts,
+    },
+    socks,
+};
+
+/// Local addresses of this many latest connections are remembered to tell new ones
+const REMEMBERED_CONNECTIONS: usize = 256;
+/// Failed resolution is tried again after this, meanwhile previous addresses are used
+const RESOLVE_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Identification of requests microphone makes, to Bot API and callback URLs, in `[outbound]`
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Outbound {
+    /// "reqwest" by default
+    user_agent: Option<String>,
+    /// Sent with every request, e.g. for egress proxies that require identification
+    #[serde(default, deserialize_with = "deserialize_headers")]
+    headers:    HeaderMap,
+}
+
+fn deserialize_headers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HeaderMap, D::Error> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in BTreeMap::<String, String>::deserialize(deserializer)? {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| serde::de::Error::custom(format!("invalid header name \"{}\"", name)))?;
+        let value = HeaderValue::from_str(&value).map_err(|_| {
+            serde::de::Error::custom(format!("invalid value of header \"{}\"", name))
+        })?;
+        headers.insert(name, value);
+    }
+
+    Ok(headers)
+}
+
+impl Outbound {
+    pub fn client_builder(&self) -> ClientBuilder {
+        ClientBuilder::new()
+            .user_agent(self.user_agent.as_deref().unwrap_or("reqwest"))
+            .default_headers(self.headers.clone())
+    }
+}
+
+/// How connections to Bot API are made and pooled, in `[telegram.http]`
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Requests share one multiplexed connection instead of one connection each
+    #[serde(default)]
+    http2:             bool,
+    /// Idle connections are closed after this
+    #[serde(default = "default_pool_idle_timeout", with = "humantime_serde")]
+    pool_idle_timeout: Duration,
+    /// Idle connections kept open at most, unlimited by default
+    pool_max_idle:     Option<usize>,
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    timeout:           Duration,
+    /// Fixed addresses of Bot API host tried in order, DNS isn't asked then
+    #[serde(default)]
+    addresses:         Vec<IpAddr>,
+    /// Resolved addresses of Bot API host are used for this long, the system resolver is
+    /// asked for every new connection without it
+    #[serde(default, with = "humantime_serde")]
+    dns_cache_ttl:     Option<Duration>,
+    /// Connection failures in a row after which the next address is tried and cached
+    /// addresses are resolved again
+    #[serde(default = "default_switch_after")]
+    switch_after:      u32,
+    /// `http://`, `https://`, `socks5://` or `socks5h://` proxy Bot API is reached through,
+    /// `proxy` of the root table if not set
+    #[serde(default, deserialize_with = "deserialize_proxy")]
+    proxy:             Option<Url>,
+    /// Taken from the root table
+    #[serde(skip)]
+    outbound:          Outbound,
+}
+
+pub fn deserialize_proxy<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Url>, D::Error> {
+    let url = match Option::<String>::deserialize(deserializer)? {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let url = Url::parse(&url).map_err(serde::de::Error::custom)?;
+    if !["http", "https", "socks5", "socks5h"].contains(&url.scheme()) {
+        return Err(serde::de::Error::custom(format!(
+            "unsupported proxy scheme \"{}\"",
+            url.scheme()
+        )));
+    }
+    if url.host_str().is_none() {
+        return Err(serde::de::Error::custom("proxy URL has no host"));
+    }
+
+    Ok(Some(url))
+}
+
+/// `http://` or `https://` URL microphone posts to
+pub fn deserialize_url<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Url>, D::Error> {
+    let url = match Option::<String>::deserialize(deserializer)? {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+    let url = Url::parse(&url).map_err(serde::de::Error::custom)?;
+    if !["http", "https"].contains(&url.scheme()) {
+        return Err(serde::de::Error::custom(format!(
+            "URL must be http:// or https://, got {}://",
+            url.scheme()
+        )));
+    }
+
+    Ok(Some(url))
+}
+
+fn default_pool_idle_timeout() -> Duration {
+    Duration::from_secs(90)
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_switch_after() -> u32 {
+    3
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            http2:             false,
+            pool_idle_timeout: default_pool_idle_timeout(),
+            pool_max_idle:     None,
+            timeout:           default_timeout(),
+            addresses:         Vec::new(),
+            dns_cache_ttl:     None,
+            switch_after:      default_switch_after(),
+            proxy:             None,
+            outbound:          Outbound::default(),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Same config, with the global proxy unless it has its own
+    pub fn or_proxy(mut self, proxy: Option<Url>) -> Self {
+        self.proxy = self.proxy.or(proxy);
+        self
+    }
+
+    pub fn with_outbound(mut self, outbound: Outbound) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    /// Client connecting to the address instead of resolving the host, through the proxy
+    /// if there is one
+    fn client(&self, host: &str, address: Option<IpAddr>, proxy: Option<&Url>) -> reqwest::Client {
+        let mut builder = self
+            .outbound
+            .client_builder()
+            .timeout(self.timeout)
+            .pool_idle_timeout(self.pool_idle_timeout);
+        if let Some(pool_max_idle) = self.pool_max_idle {
+            builder = builder.pool_max_idle_per_host(pool_max_idle);
+        }
+        // Only HTTP/2 tells the pool up front that connections can be shared, otherwise
+        // requests of a fan-out racing for the first connection would open one each
+        builder = if self.http2 {
+            builder.http2_prior_knowledge()
+        } else {
+            builder.http1_only()
+        };
+        if let Some(proxy) = proxy {
+            // Only Bot API is proxied, images are also downloaded from internal hosts
+            let (host, proxy) = (host.to_owned(), proxy.clone());
+            builder = builder.proxy(Proxy::custom(move |url| {
+                (url.host_str() == Some(host.as_str())).then(|| proxy.clone())
+            }));
+        }
+        if let Some(address) = address {
+            // Port of the URL is used, not the one given here
+            builder = builder.resolve(host, SocketAddr::new(address, 0));
+        }
+
+        builder.build().expect("Failed to build http client")
+    }
+}
+
+/// Client of Bot API that moves on to other addresses of its host when connections fail
+pub struct BotHttp {
+    config:        HttpConfig,
+    host:          String,
+    /// Configured proxy, or local bridge to it for SOCKS proxies
+    proxy:         Option<Url>,
+    resolution:    Mutex<Resolution>,
+    /// Connection failures in a row
+    failures:      AtomicU32,
+    /// When failures reached `switch_after`, if Bot API didn't respond since
+    failing_since: Mutex<Option<Instant>>,
+    alerts:        InternalAlerts,
+}
+
+struct Resolution {
+    client:     reqwest::Client,
+    /// Pinned or cached addresses, empty if the system resolver is asked
+    addresses:  Vec<IpAddr>,
+    /// Index of the address connections go to
+    current:    usize,
+    /// Cached addresses are resolved again once it passes
+    expires_at: Option<Instant>,
+}
+
+impl BotHttp {
+    pub fn new(config: &HttpConfig, base_url: &str) -> Self {
+        let host = Url::parse(base_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_default();
+        let proxy = config.proxy.as_ref().map(|proxy| {
+            if proxy.scheme().starts_with("socks") {
+                socks::bridge(proxy).expect("Failed to set up SOCKS proxy bridge")
+            } else {
+                proxy.clone()
+            }
+        });
+        let resolution = Resolution {
+            client:     config.client(&host, config.addresses.first().copied(), proxy.as_ref()),
+            addresses:  config.addresses.clone(),
+            current:    0,
+            // Resolved before the first request
+            expires_at: match config.dns_cache_ttl {
+                Some(_) if config.addresses.is_empty() => Some(Instant::now()),
+                _ => None,
+            },
+        };
+
+        Self {
+            config: config.clone(),
+            host,
+            proxy,
+            resolution: Mutex::new(resolution),
+            failures: AtomicU32::new(0),
+            failing_since: Mutex::new(None),
+            alerts: InternalAlerts::default(),
+        }
+    }
+
+    /// Connections failing and recovering are raised to the internal topic
+    pub fn with_alerts(mut self, alerts: InternalAlerts) -> Self {
+        self.alerts = alerts;
+        self
+    }
+
+    fn resolution(&self) -> std::sync::MutexGuard<'_, Resolution> {
+        self.resolution.lock().expect("Resolution lock is poisoned")
+    }
+
+    fn failing_since(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.failing_since.lock().expect("Failure lock is poisoned")
+    }
+
+    /// Client for requests that don't need failures to be noticed
+    pub fn current(&self) -> reqwest::Client {
+        self.resolution().client.clone()
+    }
+
+    pub fn post(&self, url: String) -> RequestBuilder {
+        self.current().post(url)
+    }
+
+    /// Sends the request through the current address, resolving the host first if cached
+    /// addresses expired
+    pub async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response, reqwest::Error> {
+        let request = request.build()?;
+        self.refresh().await;
+
+        let result = self.current().execute(request).await;
+        match &result {
+            Err(err) if err.is_connect() || err.is_timeout() => {
+                let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= self.config.switch_after {
+                    self.failures.store(0, Ordering::Relaxed);
+                    let is_outage_start = {
+                        let mut failing_since = self.failing_since();
+                        let is_start = failing_since.is_none();
+                        failing_since.get_or_insert_with(Instant::now);
+                        is_start
+                    };
+                    if is_outage_start {
+                        // Display of the error itself has the URL with bot token
+                        let error = std::error::Error::source(err)
+                            .map_or_else(|| "connection failed".to_owned(), ToString::to_string);
+                        self.alerts
+                            .raise(Incident::BotApiFailing { failures, error });
+                    }
+                    self.switch();
+                }
+            }
+            Ok(_) => {
+                self.failures.store(0, Ordering::Relaxed);
+                if let Some(failing_since) = self.failing_since().take() {
+                    self.alerts.raise(Incident::BotApiBack {
+                        after: failing_since.elapsed(),
+                    });
+                }
+            }
+            Err(_) => self.failures.store(0, Ordering::Relaxed),
+        }
+
+        result
+    }
+
+    async fn refresh(&self) {
+        let ttl = match self.config.dns_cache_ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        {
+            let mut resolution = self.resolution();
+            if resolution
+                .expires_at
+                .is_none_or(|expires_at| expires_at > Instant::now())
+            {
+                return;
+            }
+            // Concurrent requests keep using previous addresses instead of resolving too
+            resolution.expires_at = Some(Instant::now() + RESOLVE_RETRY_INTERVAL);
+        }
+
+        let mut addresses = match lookup_host((self.host.as_str(), 443)).await {
+            Ok(addresses) => addresses.map(|address| address.ip()).collect::<Vec<_>>(),
+            Err(err) => {
+                log::warn!("Failed to resolve {}: {}", self.host, err);
+                return;
+            }
+        };
+        addresses.dedup();
+        if addresses.is_empty() {
+            log::warn!("{} resolved to no addresses", self.host);
+            return;
+        }
+
+        let mut resolution = self.resolution();
+        resolution.expires_at = Some(Instant::now() + ttl);
+        if resolution.addresses != addresses {
+            log::info!("Resolved {} to {:?}", self.host, addresses);
+            resolution.client =
+                self.config
+                    .client(&self.host, Some(addresses[0]), self.proxy.as_ref());
+            resolution.addresses = addresses;
+            resolution.current = 0;
+        }
+    }
+
+    /// Moves on to the next address, cached addresses are also resolved again before the
+    /// next request
+    fn switch(&self) {
+        let mut resolution = self.resolution();
+        if self.config.dns_cache_ttl.is_some() && self.config.addresses.is_empty() {
+            resolution.expires_at = Some(Instant::now());
+        }
+        if resolution.addresses.len() < 2 {
+            return;
+        }
+
+        resolution.current = (resolution.current + 1) % resolution.addresses.len();
+        let address = resolution.addresses[resolution.current];
+        log::warn!(
+            "Connections to {} keep failing, switching to {}",
+            self.host,
+            address
+        );
+        resolution.client = self
+            .config
+            .client(&self.host, Some(address), self.proxy.as_ref());
+    }
+}
+
+/// Tells connections responses came through apart by their local address
+#[derive(Default)]
+pub struct ConnectionTracker {
+    seen: Mutex<VecDeque<SocketAddr>>,
+}
+
+impl ConnectionTracker {
+    /// Whether the response came through a connection no earlier response used
+    pub fn is_new(&self, response: &reqwest::Response) -> bool {
+        let local_address = match response.extensions().get::<HttpInfo>() {
+            Some(info) => info.local_addr(),
+            None => return false,
+        };
+
+        let mut seen = self
+            .seen
+            .lock()
+            .expect("Connection tracker lock is poisoned");
+        if seen.contains(&local_address) {
+            return false;
+        }
+        if seen.len() == REMEMBERED_CONNECTIONS {
+            seen.pop_front();
+        }
+        seen.push_back(local_address);
+
+        true
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+use serde::Deserialize;
+
+use crate::{
+    admin::Operator,
+    delivery_response,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    format::Pipeline,
+    send_options::SendOptions,
+    LiveTopics,
+    TgClient,
+    Topics,
+};
+
+/// Shown in the header instead of a topic
+const TOPIC: &str = "broadcast";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BroadcastConfig {
+    /// Recipients of these topics get broadcasts, each chat once
+    topics: Vec<String>,
+}
+
+impl BroadcastConfig {
+    pub fn check(&self, topics: &Topics) -> Result<(), String> {
+        match self
+            .topics
+            .iter()
+            .find(|topic| !topics.contains_key(*topic))
+        {
+            Some(topic) => Err(format!("Broadcast topic \"{}\" is not a topic", topic)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BroadcastPath {
+    sender: String,
+}
+
+/// Sends the message to every recipient of the broadcast topics, topics that are gone since
+/// config was reloaded are skipped
+pub async fn post_broadcast(
+    _: Operator,
+    config: web::Data<Option<BroadcastConfig>>,
+    topics: web::Data<Arc<LiveTopics>>,
+    tg_client: web::Data<Arc<TgClient>>,
+    path_data: web::Path<BroadcastPath>,
+    text: String,
+) -> impl Responder {
+    let config = match config.as_ref() {
+        Some(config) => config,
+        None =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::BroadcastDisabled,
+                "Broadcast is not configured",
+            )),
+    };
+    if text.is_empty() {
+        return HttpResponse::from(ApiError::new(
+            ErrorCode::InvalidMessage,
+            "Text of broadcast is empty",
+        ));
+    }
+
+    let topics = topics.current();
+    let mut recipients = Vec::new();
+    for (topic, topic_info) in config
+        .topics
+        .iter()
+        .filter_map(|topic| topics.get(topic).map(|topic_info| (topic, topic_info)))
+    {
+        for recipient in tg_client.recipients_of(topic, topic_info) {
+            if !recipients.contains(&recipient) {
+                recipients.push(recipient);
+            }
+        }
+    }
+
+    log::info!(
+        "Broadcasting message of {} to {} chats",
+        path_data.sender,
+        recipients.len()
+    );
+    let responses = tg_client
+        .send_formatted_to_all(
+            &recipients,
+            &Pipeline::default(),
+            TOPIC,
+            &path_data.sender,
+            &text,
+            SendOptions::default(),
+        )
+        .await;
+
+    delivery_response(&responses)
+}
@@ -0,0 +1,120 @@
+use std::{
+    future::{
+        ready,
+        Ready,
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    dev::Payload,
+    rt::time::sleep,
+    web,
+    FromRequest,
+    HttpRequest,
+};
+use reqwest::Url;
+
+use crate::{
+    bot_http::Outbound,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    messages::MessageRecord,
+};
+
+const CALLBACK_ATTEMPTS: usize = 3;
+const CALLBACK_RETRY_DELAY: Duration = Duration::from_secs(5);
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Client callbacks are posted with, in app data
+pub struct CallbackClient(reqwest::Client);
+
+impl CallbackClient {
+    pub fn new(outbound: &Outbound) -> Self {
+        Self(
+            outbound
+                .client_builder()
+                .build()
+                .expect("Failed to build callback client"),
+        )
+    }
+}
+
+/// URL delivery outcome is posted to, taken from `X-Callback-Url` header
+pub struct Callback(Option<Target>);
+
+pub struct Target {
+    url:    Url,
+    client: reqwest::Client,
+}
+
+impl FromRequest for Callback {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let value = match request.headers().get("X-Callback-Url") {
+            Some(value) => value,
+            None => return ready(Ok(Self(None))),
+        };
+
+        match value
+            .to_str()
+            .ok()
+            .and_then(|value| Url::parse(value).ok())
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+        {
+            Some(url) => {
+                let client = request
+                    .app_data::<web::Data<CallbackClient>>()
+                    .map(|client| client.0.clone())
+                    .unwrap_or_default();
+                ready(Ok(Self(Some(Target { url, client }))))
+            }
+            None => ready(Err(ApiError::new(
+                ErrorCode::InvalidCallbackUrl,
+                "X-Callback-Url has to be an absolute http or https URL",
+            )
+            .into())),
+        }
+    }
+}
+
+impl Callback {
+    pub fn into_target(self) -> Option<Target> {
+        self.0
+    }
+}
+
+impl Target {
+    /// Posts record of the delivered message to the callback URL
+    pub async fn report(&self, record: &MessageRecord) {
+        let url = &self.url;
+        for attempt in 1..=CALLBACK_ATTEMPTS {
+            match self
+                .client
+                .post(url.clone())
+                .timeout(CALLBACK_TIMEOUT)
+                .json(record)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+            {
+                Ok(_) => return,
+                Err(err) => log::warn!(
+                    "Callback {} failed, attempt {} of {}: {}",
+                    url,
+                    attempt,
+                    CALLBACK_ATTEMPTS,
+                    err
+                ),
+            }
+
+            if attempt < CALLBACK_ATTEMPTS {
+                sleep(CALLBACK_RETRY_DELAY).await;
+            }
+        }
+    }
+}
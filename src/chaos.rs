@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+
+/// Shares of Bot API requests that fail, each from 0 to 1
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// Answered with 500 Internal Server Error
+    #[serde(default)]
+    failure_rate:    f64,
+    /// Answered with 429 Too Many Requests
+    #[serde(default)]
+    rate_limit_rate: f64,
+    /// Fail with timeout without reaching Telegram
+    #[serde(default)]
+    timeout_rate:    f64,
+    /// `retry_after` of injected 429 responses in seconds
+    #[serde(default = "default_retry_after")]
+    retry_after:     u64,
+}
+
+fn default_retry_after() -> u64 {
+    1
+}
+
+pub enum Fault {
+    ServerError,
+    RateLimited { retry_after: u64 },
+    TimedOut,
+}
+
+impl ChaosConfig {
+    /// Fault to inject into the next request, if any
+    pub fn fault(&self) -> Option<Fault> {
+        let roll = rand::random::<f64>();
+
+        if roll < self.failure_rate {
+            Some(Fault::ServerError)
+        } else if roll < self.failure_rate + self.rate_limit_rate {
+            Some(Fault::RateLimited {
+                retry_after: self.retry_after,
+            })
+        } else if roll < self.failure_rate + self.rate_limit_rate + self.timeout_rate {
+            Some(Fault::TimedOut)
+        } else {
+            None
+        }
+    }
+}
+
+/// Bot API error response as Telegram would send it
+fn response(status: u16, body: serde_json::Value) -> reqwest::Response {
+    http::Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .expect("Injected response is valid")
+        .into()
+}
+
+impl Fault {
+    pub async fn inject(
+        self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        match self {
+            Self::ServerError => Ok(response(
+                500,
+                json!({
+                    "ok": false,
+                    "error_code": 500,
+                    "description": "Internal Server Error: injected by chaos mode",
+                }),
+            )),
+            Self::RateLimited { retry_after } => Ok(response(
+                429,
+                json!({
+                    "ok": false,
+                    "error_code": 429,
+                    "description": format!("Too Many Requests: retry after {}", retry_after),
+                    "parameters": { "retry_after": retry_after },
+                }),
+            )),
+            // reqwest errors can't be made up, a request that is out of time before it starts
+            // fails with the real thing
+            Self::TimedOut => request.timeout(Duration::ZERO).send().await,
+        }
+    }
+}
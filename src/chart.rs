@@ -0,0 +1,323 @@
+use std::sync::Arc;
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::Deserialize;
+
+use crate::{
+    access::AllowedTopic,
+    callbacks::Callback,
+    errors::{
+        ApiError,
+        ErrorCode,
+        Problems,
+    },
+    locale::{
+        Locale,
+        Phrase,
+    },
+    maintenance::Maintenance,
+    messages::{
+        self,
+        Messages,
+    },
+    metrics::Metrics,
+    photo,
+    quotas::{
+        self,
+        Quotas,
+    },
+    send_options::SendOptions,
+    usage::Usage,
+    PostPathData,
+    TgClient,
+    TgMarkdownString,
+};
+
+const WIDTH: usize = 800;
+const HEIGHT: usize = 400;
+const MARGIN: usize = 16;
+const GRID_LINES: usize = 4;
+const BACKGROUND: [u8; 3] = [0xFF, 0xFF, 0xFF];
+const GRID: [u8; 3] = [0xE0, 0xE0, 0xE0];
+const FRAME: [u8; 3] = [0x90, 0x90, 0x90];
+/// Colors of series and squares showing them in the caption, repeated past the last one
+const PALETTE: [([u8; 3], &str); 5] = [
+    ([0x1E, 0x88, 0xE5], "🟦"),
+    ([0xE5, 0x39, 0x35], "🟥"),
+    ([0x43, 0xA0, 0x47], "🟩"),
+    ([0xFB, 0x8C, 0x00], "🟧"),
+    ([0x8E, 0x24, 0xAA], "🟪"),
+];
+
+/// Unix seconds or RFC 3339
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Timestamp {
+    Unix(i64),
+    Rfc3339(DateTime<Utc>),
+}
+
+impl Timestamp {
+    fn seconds(&self) -> i64 {
+        match self {
+            Self::Unix(seconds) => *seconds,
+            Self::Rfc3339(time) => time.timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Series {
+    #[serde(default)]
+    name:   String,
+    points: Vec<(Timestamp, f64)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChartRequest {
+    #[serde(default)]
+    title:  String,
+    series: Vec<Series>,
+}
+
+/// Points of a series in time order
+struct Line<'a> {
+    name:   &'a str,
+    points: Vec<(i64, f64)>,
+}
+
+/// Line chart of the series, axes span their points
+fn draw(lines: &[Line]) -> Vec<u8> {
+    let points = || lines.iter().flat_map(|line| line.points.iter());
+    let (mut first, mut last) = (i64::MAX, i64::MIN);
+    let (mut low, mut high) = (f64::INFINITY, f64::NEG_INFINITY);
+    for (time, value) in points() {
+        first = first.min(*time);
+        last = last.max(*time);
+        low = low.min(*value);
+        high = high.max(*value);
+    }
+    if first == last {
+        (first, last) = (first - 1, last + 1);
+    }
+    if low == high {
+        let padding = (low.abs() / 10.0).max(1.0);
+        (low, high) = (low - padding, high + padding);
+    }
+
+    let mut pixels = [BACKGROUND].repeat(WIDTH * HEIGHT);
+    let (left, top) = (MARGIN as f64, MARGIN as f64);
+    let (width, height) = ((WIDTH - 2 * MARGIN) as f64, (HEIGHT - 2 * MARGIN) as f64);
+    let x_of = |time: i64| left + (time - first) as f64 / (last - first) as f64 * width;
+    let y_of = |value: f64| top + (high - value) / (high - low) * height;
+
+    for i in 0..=GRID_LINES {
+        let share = i as f64 / GRID_LINES as f64;
+        let color = if i == 0 || i == GRID_LINES {
+            FRAME
+        } else {
+            GRID
+        };
+        let (x, y) = (left + share * width, top + share * height);
+        draw_segment(&mut pixels, (left, y), (left + width, y), color);
+        draw_segment(&mut pixels, (x, top), (x, top + height), color);
+    }
+
+    for (index, line) in lines.iter().enumerate() {
+        let (color, _) = PALETTE[index % PALETTE.len()];
+        let coordinates = line
+            .points
+            .iter()
+            .map(|(time, value)| (x_of(*time), y_of(*value)))
+            .collect::<Vec<_>>();
+        if let [point] = coordinates.as_slice() {
+            draw_segment(&mut pixels, *point, *point, color);
+        }
+        for segment in coordinates.windows(2) {
+            draw_segment(&mut pixels, segment[0], segment[1], color);
+        }
+    }
+
+    photo::png(WIDTH as u32, HEIGHT as u32, &pixels.concat())
+}
+
+/// Two pixels thick segment between the points
+fn draw_segment(pixels: &mut [[u8; 3]], from: (f64, f64), to: (f64, f64), color: [u8; 3]) {
+    let steps = (to.0 - from.0)
+        .abs()
+        .max((to.1 - from.1).abs())
+        .ceil()
+        .max(1.0) as usize;
+    for step in 0..=steps {
+        let share = step as f64 / steps as f64;
+        let x = (from.0 + (to.0 - from.0) * share).round() as usize;
+        let y = (from.1 + (to.1 - from.1) * share).round() as usize;
+        for (x, y) in [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)] {
+            if x < WIDTH && y < HEIGHT {
+                pixels[y * WIDTH + x] = color;
+            }
+        }
+    }
+}
+
+/// Title, legend with first, last, lowest and highest values of each series, and time span
+fn caption(title: &str, lines: &[Line], locale: Locale) -> String {
+    let mut caption = String::new();
+    if !title.is_empty() {
+        caption.push_str(&format!("*{}*\n", *TgMarkdownString::new(title)));
+    }
+
+    for (index, line) in lines.iter().enumerate() {
+        let (_, square) = PALETTE[index % PALETTE.len()];
+        let values = || line.points.iter().map(|(_, value)| *value);
+        let low = values().fold(f64::INFINITY, f64::min);
+        let high = values().fold(f64::NEG_INFINITY, f64::max);
+        let (_, first) = line.points[0];
+        let (_, last) = line.points[line.points.len() - 1];
+        let name = if line.name.is_empty() {
+            String::new()
+        } else {
+            format!("{}: ", line.name)
+        };
+        caption.push_str(&format!(
+            "{} {}\n",
+            square,
+            *TgMarkdownString::new(&format!(
+                "{}{} → {} ({})",
+                name,
+                first,
+                last,
+                locale.fill(Phrase::MinMax, &[&low.to_string(), &high.to_string()])
+            ))
+        ));
+    }
+
+    let times = lines
+        .iter()
+        .flat_map(|line| line.points.iter().map(|(time, _)| *time));
+    let format_time = |seconds: i64| {
+        DateTime::from_timestamp(seconds, 0)
+            .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default()
+    };
+    if let (Some(first), Some(last)) = (times.clone().min(), times.max()) {
+        caption.push_str(&TgMarkdownString::new(&format!(
+            "{} – {} UTC",
+            format_time(first),
+            format_time(last)
+        )));
+    }
+
+    caption
+}
+
+/// Renders posted series as line chart and sends it as photo
+pub async fn post_chart(
+    topic: AllowedTopic,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    metrics: web::Data<Arc<Metrics>>,
+    messages: web::Data<Arc<Messages>>,
+    usage: web::Data<Arc<Usage>>,
+    callback: Callback,
+    path_data: web::Path<PostPathData>,
+    body: web::Bytes,
+) -> impl Responder {
+    let topic_info = &topic.info;
+    metrics.count_ingress(&topic.name, body.len(), 0);
+    let meter = usage.meter(&topic, body.len());
+
+    let request: ChartRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(err) =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::MalformedPayload,
+                format!("Malformed chart: {}", err),
+            )),
+    };
+
+    let mut problems = Problems::default();
+    let mut lines = Vec::with_capacity(request.series.len());
+    for (index, series) in request.series.iter().enumerate() {
+        let mut points = series
+            .points
+            .iter()
+            .map(|(time, value)| (time.seconds(), *value))
+            .collect::<Vec<_>>();
+        if points.is_empty() || points.iter().any(|(_, value)| !value.is_finite()) {
+            problems.push(
+                format!("series[{}].points", index),
+                ApiError::new(
+                    ErrorCode::MalformedPayload,
+                    "Every series needs points with finite values",
+                ),
+            );
+            continue;
+        }
+        points.sort_by_key(|(time, _)| *time);
+        lines.push(Line {
+            name: &series.name,
+            points,
+        });
+    }
+    if request.series.is_empty() {
+        problems.push(
+            "series",
+            ApiError::new(
+                ErrorCode::MalformedPayload,
+                "Chart needs at least one series",
+            ),
+        );
+    }
+    if let Err(err) = problems.check(ErrorCode::MalformedPayload) {
+        return HttpResponse::from(err);
+    }
+
+    let caption = caption(&request.title, &lines, tg_client.locale(&topic.name));
+    if maintenance.intercept(
+        &path_data.topic_name,
+        topic_info,
+        &path_data.sender,
+        &caption,
+    ) {
+        return HttpResponse::Accepted().body("Topic is under maintenance");
+    }
+
+    if let Some(response) =
+        quotas::enforce(&quotas, &tg_client, &topic.name, topic_info, body.len()).await
+    {
+        return response;
+    }
+
+    let image = draw(&lines);
+    let tg_client = tg_client.get_ref().clone();
+    let recipients = tg_client.recipients_of(&topic.name, topic_info);
+    let options = SendOptions::of_topic(topic_info);
+    let topic_name = path_data.topic_name.clone();
+    let sender = path_data.sender.clone();
+    messages::dispatch(
+        &messages,
+        &path_data.topic_name,
+        &path_data.sender,
+        topic_info.accept_async,
+        callback,
+        meter,
+        async move {
+            tg_client
+                .send_photo_to_all(&recipients, &topic_name, &sender, &caption, &image, options)
+                .await
+        },
+    )
+    .await
+}
@@ -0,0 +1,436 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::PathBuf,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    rt::time::sleep,
+    web,
+    HttpResponse,
+    Responder,
+};
+use serde::{
+    de::DeserializeOwned,
+    Deserialize,
+    Serialize,
+};
+use serde_json::json;
+
+use crate::{
+    admin::Viewer,
+    config,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    threshold::write_atomically,
+    updates::{
+        self,
+        UpdatesConfig,
+    },
+    Config,
+    LiveTopics,
+    TgClient,
+    Topics,
+};
+
+const TELEGRAM_GET_CHAT_METHOD: &str = "getChat";
+const TELEGRAM_GET_UPDATES_METHOD: &str = "getUpdates";
+const DEFAULT_REFRESH: Duration = Duration::from_secs(24 * 60 * 60);
+
+const USAGE: &str = "\
+Usage: microphone chats --config <path>
+
+Lists chats the bot knows about: recipients from the config and chats that recently sent
+updates to the bot. Recipients can be written as @username in the config.";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+pub struct KnownChat {
+    pub id:         i64,
+    #[serde(rename = "type")]
+    pub kind:       String,
+    pub title:      Option<String>,
+    pub username:   Option<String>,
+    pub first_name: Option<String>,
+}
+
+impl KnownChat {
+    fn name(&self) -> String {
+        match (&self.username, &self.title, &self.first_name) {
+            (Some(username), _, _) => format!("@{}", username),
+            (None, Some(title), _) => title.clone(),
+            (None, None, Some(first_name)) => first_name.clone(),
+            (None, None, None) => String::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TgResponse<T> {
+    ok:          bool,
+    result:      Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatHolder {
+    chat: KnownChat,
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    message: Option<ChatHolder>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    message:        Option<ChatHolder>,
+    channel_post:   Option<ChatHolder>,
+    my_chat_member: Option<ChatHolder>,
+    callback_query: Option<CallbackQuery>,
+}
+
+impl Update {
+    fn into_chat(self) -> Option<KnownChat> {
+        self.message
+            .or(self.channel_post)
+            .or(self.my_chat_member)
+            .or_else(|| self.callback_query.and_then(|query| query.message))
+            .map(|holder| holder.chat)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatsConfig {
+    /// Ids `@username` recipients were resolved to are kept here, so they are known right after
+    /// restart. A missing file is no ids
+    file:    Option<PathBuf>,
+    /// Usernames are resolved again this often, in case they moved to other chats
+    #[serde(default = "default_refresh", with = "humantime_serde")]
+    refresh: Duration,
+    /// Names recipients can be written as, for chat ids or `@username`
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+}
+
+impl Default for ChatsConfig {
+    fn default() -> Self {
+        Self {
+            file:    None,
+            refresh: DEFAULT_REFRESH,
+            aliases: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_refresh() -> Duration {
+    DEFAULT_REFRESH
+}
+
+#[derive(Default)]
+#[derive(Deserialize)]
+struct Stored {
+    #[serde(default)]
+    chats: BTreeMap<String, i64>,
+}
+
+/// Numeric ids of chats recipients refer to by `@username` or alias
+#[derive(Default)]
+pub struct Chats {
+    ids:     Mutex<BTreeMap<String, i64>>,
+    file:    Option<PathBuf>,
+    refresh: Duration,
+    aliases: BTreeMap<String, String>,
+}
+
+impl Chats {
+    pub fn load(config: ChatsConfig) -> io::Result<Self> {
+        let stored = match &config.file {
+            Some(file) => match std::fs::read(file) {
+                Ok(contents) => serde_json::from_slice::<Stored>(&contents)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Stored::default(),
+                Err(err) => return Err(err),
+            },
+            None => Stored::default(),
+        };
+
+        Ok(Self {
+            ids:     Mutex::new(stored.chats),
+            file:    config.file,
+            refresh: config.refresh,
+            aliases: config.aliases,
+        })
+    }
+
+    /// Chat id or `@username` the recipient stands for
+    fn target<'a>(&'a self, recipient: &'a str) -> &'a str {
+        self.aliases
+            .get(recipient)
+            .map_or(recipient, String::as_str)
+    }
+
+    /// False if the id was known already
+    fn remember(&self, chat: &KnownChat) -> bool {
+        match &chat.username {
+            Some(username) =>
+                self.ids
+                    .lock()
+                    .expect("Chats lock is poisoned")
+                    .insert(username.to_lowercase(), chat.id)
+                    != Some(chat.id),
+            None => false,
+        }
+    }
+
+    fn save(&self) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+
+        let ids = self.ids.lock().expect("Chats lock is poisoned").clone();
+        if let Err(err) = write_atomically(file, &json!({ "chats": ids })) {
+            log::error!("Failed to save chat ids: {}", err);
+        }
+    }
+
+    /// Chat id to send to, `@username` is looked up with getChat once and cached. Recipient is
+    /// used as is if the lookup fails, Telegram accepts usernames of public channels anyway
+    pub async fn resolve(&self, tg_client: &TgClient, recipient: &str) -> String {
+        let recipient = self.target(recipient);
+        let username = match recipient.strip_prefix('@') {
+            Some(username) => username.to_lowercase(),
+            None => return recipient.to_owned(),
+        };
+
+        let cached = self
+            .ids
+            .lock()
+            .expect("Chats lock is poisoned")
+            .get(&username)
+            .copied();
+        if let Some(id) = cached {
+            return id.to_string();
+        }
+
+        match get_chat(tg_client, recipient).await {
+            Ok(chat) => {
+                if self.remember(&chat) {
+                    self.save();
+                }
+                chat.id.to_string()
+            }
+            Err(err) => {
+                log::warn!("Failed to resolve {}: {}", recipient, err);
+                recipient.to_owned()
+            }
+        }
+    }
+}
+
+async fn call<T: DeserializeOwned>(
+    tg_client: &TgClient,
+    method: &str,
+    payload: &serde_json::Value,
+) -> Result<T, String> {
+    let response = tg_client
+        .call_method(method, payload)
+        .await
+        .map_err(|err| err.to_string())?
+        .json::<TgResponse<T>>()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    match (response.ok, response.result) {
+        (true, Some(result)) => Ok(result),
+        _ => Err(response
+            .description
+            .unwrap_or_else(|| format!("Telegram refused to {}", method))),
+    }
+}
+
+async fn get_chat(tg_client: &TgClient, chat_id: &str) -> Result<KnownChat, String> {
+    call(
+        tg_client,
+        TELEGRAM_GET_CHAT_METHOD,
+        &json!({ "chat_id": chat_id }),
+    )
+    .await
+}
+
+/// Chat of every recipient along with chats from pending updates, if bot doesn't consume
+/// updates itself
+async fn discover(
+    tg_client: &TgClient,
+    recipients: Vec<String>,
+    with_updates: bool,
+) -> (Vec<KnownChat>, Vec<String>) {
+    let mut chats = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    if with_updates {
+        // Updates aren't confirmed without offset, so this doesn't take them from anyone
+        match call::<Vec<Update>>(
+            tg_client,
+            TELEGRAM_GET_UPDATES_METHOD,
+            &json!({ "limit": 100, "timeout": 0 }),
+        )
+        .await
+        {
+            Ok(updates) =>
+                for chat in updates.into_iter().filter_map(Update::into_chat) {
+                    chats.insert(chat.id, chat);
+                },
+            Err(err) => errors.push(format!("Failed to get updates: {}", err)),
+        }
+    }
+
+    let mut recipients = recipients;
+    recipients.sort_unstable();
+    recipients.dedup();
+    for recipient in recipients {
+        match get_chat(tg_client, tg_client.chats.target(&recipient)).await {
+            Ok(chat) => {
+                chats.insert(chat.id, chat);
+            }
+            Err(err) => errors.push(format!("Failed to get chat {}: {}", recipient, err)),
+        }
+    }
+
+    let chats = chats.into_values().collect::<Vec<_>>();
+    let mut changed = false;
+    for chat in &chats {
+        changed |= tg_client.chats.remember(chat);
+    }
+    if changed {
+        tg_client.chats.save();
+    }
+
+    (chats, errors)
+}
+
+/// Resolves `@username` recipients at startup and every `refresh` after, ids of usernames that
+/// fail to resolve are kept until they succeed again
+pub fn spawn_refresher(tg_client: Arc<TgClient>, topics: Arc<LiveTopics>) {
+    actix_web::rt::spawn(async move {
+        loop {
+            let mut usernames = recipients(&tg_client, &topics.current())
+                .iter()
+                .chain(tg_client.chats.aliases.keys())
+                .map(|recipient| tg_client.chats.target(recipient).to_owned())
+                .filter(|target| target.starts_with('@'))
+                .collect::<Vec<_>>();
+            usernames.sort_unstable();
+            usernames.dedup();
+
+            let mut changed = false;
+            for username in &usernames {
+                match get_chat(&tg_client, username).await {
+                    Ok(chat) => changed |= tg_client.chats.remember(&chat),
+                    Err(err) => log::warn!("Failed to resolve {}: {}", username, err),
+                }
+            }
+            if changed {
+                tg_client.chats.save();
+            }
+
+            sleep(tg_client.chats.refresh).await;
+        }
+    });
+}
+
+fn recipients(tg_client: &TgClient, topics: &Topics) -> Vec<String> {
+    topics
+        .iter()
+        .flat_map(|(topic, topic_info)| {
+            tg_client
+                .recipients_of(topic, topic_info)
+                .into_iter()
+                .chain(
+                    topic_info
+                        .escalation
+                        .iter()
+                        .flat_map(|escalation| escalation.recipients.clone()),
+                )
+        })
+        .collect()
+}
+
+pub async fn list_chats(
+    _: Viewer,
+    tg_client: web::Data<Arc<TgClient>>,
+    topics: web::Data<Arc<LiveTopics>>,
+    updates_config: web::Data<UpdatesConfig>,
+) -> impl Responder {
+    let topics = topics.current();
+    // getUpdates would interrupt long polling of the instance
+    let with_updates = !updates::poller_runs(&topics, &updates_config);
+    let (chats, errors) = discover(&tg_client, recipients(&tg_client, &topics), with_updates).await;
+
+    if chats.is_empty() && !errors.is_empty() {
+        return HttpResponse::from(
+            ApiError::new(
+                ErrorCode::TelegramUnavailable,
+                "Failed to get chats from Telegram",
+            )
+            .with_details(json!({ "errors": errors })),
+        );
+    }
+
+    HttpResponse::Ok().json(json!({
+        "chats": chats,
+        "errors": errors,
+    }))
+}
+
+pub async fn run(args: Vec<String>) -> Result<(), std::io::Error> {
+    let config_path = match args.as_slice() {
+        [flag, config_path] if flag == "--config" => config_path,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let config: Config = match config::load_source(config_path).await {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let tg_client = TgClient::new(
+        config.telegram.secret,
+        &config
+            .telegram
+            .http
+            .or_proxy(config.proxy)
+            .with_outbound(config.outbound.clone()),
+        Arc::default(),
+    )
+    .with_chats(Chats::load(config.chats)?)
+    .with_recipient_groups(config.recipient_groups);
+    let recipients = recipients(&tg_client, &config.topics);
+    let (chats, errors) = discover(&tg_client, recipients, true).await;
+
+    for err in errors {
+        eprintln!("{}", err);
+    }
+    for chat in chats {
+        println!("{:<16} {:<12} {}", chat.id, chat.kind, chat.name());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,347 @@
+use std::{
+    future::{
+        ready,
+        Ready,
+    },
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+};
+
+use actix_web::{
+    dev::{
+        Payload,
+        ServiceRequest,
+    },
+    http::header::HeaderMap,
+    web,
+    FromRequest,
+    HttpRequest,
+};
+use ipnet::IpNet;
+
+use crate::errors::{
+    ApiError,
+    ErrorCode,
+};
+
+/// Proxies whose forwarding headers are trusted
+#[derive(Default)]
+pub struct ClientIpConfig {
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+/// Address of the client, forwarding headers are taken into account only when the request
+/// comes from a trusted proxy
+pub struct ClientIp(pub IpAddr);
+
+impl ClientIp {
+    fn from_parts(
+        peer: Option<SocketAddr>,
+        headers: &HeaderMap,
+        config: Option<&web::Data<ClientIpConfig>>,
+    ) -> Result<Self, &'static str> {
+        // With PROXY protocol enabled this is the address reported by the proxy
+        let peer = peer.ok_or("Cannot get client address")?.ip();
+        let trusted_proxies = config
+            .map(|config| config.trusted_proxies.as_slice())
+            .unwrap_or_default();
+
+        resolve(peer, headers, trusted_proxies).map(Self)
+    }
+
+    pub fn of(request: &HttpRequest) -> Result<Self, &'static str> {
+        Self::from_parts(request.peer_addr(), request.headers(), request.app_data())
+    }
+
+    pub fn of_service_request(request: &ServiceRequest) -> Result<Self, &'static str> {
+        Self::from_parts(request.peer_addr(), request.headers(), request.app_data())
+    }
+}
+
+impl FromRequest for ClientIp {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            Self::of(request)
+                .map_err(|err| ApiError::new(ErrorCode::InvalidClientAddress, err).into()),
+        )
+    }
+}
+
+fn is_trusted(address: IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies
+        .iter()
+        .any(|trusted| trusted.contains(&address))
+}
+
+/// Parses node of `Forwarded` or `X-Forwarded-For` header, which may carry port and quotes
+fn parse_node(node: &str) -> Result<IpAddr, &'static str> {
+    let node = node.trim().trim_matches('"');
+
+    if let Ok(address) = node.parse::<IpAddr>() {
+        return Ok(address);
+    }
+    if let Ok(address) = node.parse::<SocketAddr>() {
+        return Ok(address.ip());
+    }
+
+    node.strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .and_then(|node| node.parse().ok())
+        .ok_or("Cannot parse forwarded client address")
+}
+
+fn header_nodes<'a>(headers: &'a HeaderMap, name: &str) -> Result<Vec<&'a str>, &'static str> {
+    let mut nodes = Vec::new();
+
+    for value in headers.get_all(name) {
+        let value = value
+            .to_str()
+            .map_err(|_| "Forwarding header is not valid ASCII")?;
+        nodes.extend(value.split(',').filter(|node| !node.trim().is_empty()));
+    }
+
+    Ok(nodes)
+}
+
+/// Addresses from `Forwarded` header, or from `X-Forwarded-For` if the former is absent,
+/// the closest proxy goes last
+fn forwarded_chain(headers: &HeaderMap) -> Result<Vec<IpAddr>, &'static str> {
+    let forwarded = header_nodes(headers, "Forwarded")?;
+
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .ok_or("Forwarded header element has no \"for\" parameter")
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect();
+    }
+
+    header_nodes(headers, "X-Forwarded-For")?
+        .into_iter()
+        .map(parse_node)
+        .collect()
+}
+
+/// Walks the forwarding chain from the closest hop and stops at the first untrusted address
+pub fn resolve(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpNet],
+) -> Result<IpAddr, &'static str> {
+    if !is_trusted(peer, trusted_proxies) {
+        return Ok(peer);
+    }
+
+    let chain = forwarded_chain(headers)?;
+    if chain.is_empty() {
+        if let Some(real_ip) = headers.get("X-Real-IP") {
+            return parse_node(
+                real_ip
+                    .to_str()
+                    .map_err(|_| "X-Real-IP is not valid ASCII")?,
+            );
+        }
+    }
+
+    let mut client = peer;
+    for address in chain.into_iter().rev() {
+        client = address;
+        if !is_trusted(address, trusted_proxies) {
+            break;
+        }
+    }
+
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{
+        HeaderName,
+        HeaderValue,
+    };
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap()]
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer_headers_are_ignored() {
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1"), ("x-real-ip", "2.2.2.2")]);
+
+        assert_eq!(
+            resolve(ip("192.0.2.1"), &headers, &trusted()),
+            Ok(ip("192.0.2.1"))
+        );
+    }
+
+    #[test]
+    fn no_trusted_proxies_means_peer_address() {
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1")]);
+
+        assert_eq!(resolve(ip("10.0.0.1"), &headers, &[]), Ok(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn trusted_peer_without_headers() {
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &HeaderMap::new(), &trusted()),
+            Ok(ip("10.0.0.1"))
+        );
+    }
+
+    #[test]
+    fn x_forwarded_for_single() {
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1")]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("1.1.1.1"))
+        );
+    }
+
+    #[test]
+    fn x_forwarded_for_skips_trusted_hops() {
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1, 10.0.0.3, 10.0.0.2")]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("1.1.1.1"))
+        );
+    }
+
+    #[test]
+    fn x_forwarded_for_spoofed_prefix_is_not_trusted() {
+        let headers = headers(&[("x-forwarded-for", "6.6.6.6, 1.1.1.1")]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("1.1.1.1"))
+        );
+    }
+
+    #[test]
+    fn x_forwarded_for_multiple_headers() {
+        let headers = headers(&[
+            ("x-forwarded-for", "1.1.1.1"),
+            ("x-forwarded-for", "10.0.0.2"),
+        ]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("1.1.1.1"))
+        );
+    }
+
+    #[test]
+    fn x_forwarded_for_all_trusted_yields_farthest() {
+        let headers = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("10.0.0.3"))
+        );
+    }
+
+    #[test]
+    fn x_forwarded_for_with_port() {
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1:4711")]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("1.1.1.1"))
+        );
+    }
+
+    #[test]
+    fn forwarded_takes_precedence() {
+        let headers = headers(&[
+            ("forwarded", "for=1.1.1.1;proto=https;by=10.0.0.1"),
+            ("x-forwarded-for", "2.2.2.2"),
+        ]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("1.1.1.1"))
+        );
+    }
+
+    #[test]
+    fn forwarded_ipv6_with_port() {
+        let headers = headers(&[("forwarded", "for=\"[2001:db8:cafe::17]:4711\"")]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("2001:db8:cafe::17"))
+        );
+    }
+
+    #[test]
+    fn forwarded_ipv6_without_port() {
+        let headers = headers(&[("forwarded", "For=\"[2001:db8::1]\", for=10.0.0.2")]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("2001:db8::1"))
+        );
+    }
+
+    #[test]
+    fn forwarded_obfuscated_node_is_rejected() {
+        let headers = headers(&[("forwarded", "for=_hidden")]);
+
+        assert!(resolve(ip("10.0.0.1"), &headers, &trusted()).is_err());
+    }
+
+    #[test]
+    fn forwarded_without_for_is_rejected() {
+        let headers = headers(&[("forwarded", "proto=https")]);
+
+        assert!(resolve(ip("10.0.0.1"), &headers, &trusted()).is_err());
+    }
+
+    #[test]
+    fn x_real_ip_is_used_without_chain() {
+        let headers = headers(&[("x-real-ip", "1.1.1.1")]);
+
+        assert_eq!(
+            resolve(ip("10.0.0.1"), &headers, &trusted()),
+            Ok(ip("1.1.1.1"))
+        );
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        let headers = headers(&[("x-forwarded-for", "not an address")]);
+
+        assert!(resolve(ip("10.0.0.1"), &headers, &trusted()).is_err());
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicU16,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+
+use crate::lines::{
+    self,
+    Pipeline,
+    Rejection,
+};
+
+/// Largest datagram CoAP over UDP carries without block-wise transfer, with room to spare
+const MAX_DATAGRAM: usize = 2048;
+/// How long a device retransmits a confirmable message (RFC 7252 EXCHANGE_LIFETIME), every
+/// retransmission within it gets the reply to the first one instead of another delivery
+const EXCHANGE_LIFETIME: Duration = Duration::from_secs(247);
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+const TYPE_CONFIRMABLE: u8 = 0;
+const TYPE_NON_CONFIRMABLE: u8 = 1;
+const TYPE_ACKNOWLEDGEMENT: u8 = 2;
+const TYPE_RESET: u8 = 3;
+
+const OPTION_URI_HOST: u16 = 3;
+const OPTION_URI_PORT: u16 = 7;
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const OPTION_ACCEPT: u16 = 17;
+const OPTION_SIZE1: u16 = 60;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CoapConfig {
+    /// Address datagrams are received on, CoAP port is 5683
+    listen: SocketAddr,
+}
+
+/// Codes are `class.detail`, packed as `class << 5 | detail`
+#[derive(Clone)]
+#[derive(Copy)]
+enum Code {
+    Empty,
+    Post,
+    Changed,
+    BadRequest,
+    Unauthorized,
+    BadOption,
+    NotFound,
+    MethodNotAllowed,
+    TooManyRequests,
+    BadGateway,
+}
+
+impl Code {
+    fn byte(self) -> u8 {
+        let (class, detail) = match self {
+            Self::Empty => (0, 0),
+            Self::Post => (0, 2),
+            Self::Changed => (2, 4),
+            Self::BadRequest => (4, 0),
+            Self::Unauthorized => (4, 1),
+            Self::BadOption => (4, 2),
+            Self::NotFound => (4, 4),
+            Self::MethodNotAllowed => (4, 5),
+            Self::TooManyRequests => (4, 29),
+            Self::BadGateway => (5, 2),
+        };
+        class << 5 | detail
+    }
+}
+
+struct Request {
+    kind:       u8,
+    code:       u8,
+    message_id: u16,
+    token:      Vec<u8>,
+    path:       Vec<String>,
+    payload:    Vec<u8>,
+    /// Critical option the endpoint doesn't know, fails the request like RFC says
+    bad_option: Option<u16>,
+}
+
+/// Why a datagram can't be handled, the ones that aren't CoAP messages get no reply at all
+enum Invalid {
+    NotCoap,
+    Malformed(&'static str),
+}
+
+/// Parses a message of RFC 7252
+fn parse(datagram: &[u8]) -> Result<Request, Invalid> {
+    let (header, rest) = match datagram {
+        [first, code, id_high, id_low, rest @ ..] => ([*first, *code, *id_high, *id_low], rest),
+        _ => return Err(Invalid::NotCoap),
+    };
+    let token_length = usize::from(header[0] & 0x0f);
+    if header[0] >> 6 != VERSION || token_length > 8 || rest.len() < token_length {
+        return Err(Invalid::NotCoap);
+    }
+
+    let (token, mut rest) = rest.split_at(token_length);
+    let mut request = Request {
+        kind:       header[0] >> 4 & 0x03,
+        code:       header[1],
+        message_id: u16::from_be_bytes([header[2], header[3]]),
+        token:      token.to_vec(),
+        path:       Vec::new(),
+        payload:    Vec::new(),
+        bad_option: None,
+    };
+
+    let mut number = 0u16;
+    while let Some((&first, after)) = rest.split_first() {
+        if first == PAYLOAD_MARKER {
+            if after.is_empty() {
+                return Err(Invalid::Malformed("Payload marker without payload"));
+            }
+            request.payload = after.to_vec();
+            break;
+        }
+
+        let (delta, after) = extended(first >> 4, after)?;
+        let (length, after) = extended(first & 0x0f, after)?;
+        if after.len() < usize::from(length) {
+            return Err(Invalid::Malformed("Option is longer than the message"));
+        }
+        let (value, after) = after.split_at(usize::from(length));
+        number = number
+            .checked_add(delta)
+            .ok_or(Invalid::Malformed("Option number is too large"))?;
+        rest = after;
+
+        match number {
+            OPTION_URI_PATH => request.path.push(
+                String::from_utf8(value.to_vec())
+                    .map_err(|_| Invalid::Malformed("Uri-Path has to be UTF-8"))?,
+            ),
+            OPTION_URI_HOST
+            | OPTION_URI_PORT
+            | OPTION_CONTENT_FORMAT
+            | OPTION_ACCEPT
+            | OPTION_SIZE1 => {}
+            critical if critical % 2 == 1 => {
+                request.bad_option = request.bad_option.or(Some(critical));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(request)
+}
+
+/// Option delta or length with its extended bytes
+fn extended(nibble: u8, bytes: &[u8]) -> Result<(u16, &[u8]), Invalid> {
+    match (nibble, bytes) {
+        (0..=12, _) => Ok((u16::from(nibble), bytes)),
+        (13, [extra, rest @ ..]) => Ok((u16::from(*extra) + 13, rest)),
+        (14, [high, low, rest @ ..]) => u16::from_be_bytes([*high, *low])
+            .checked_add(269)
+            .map(|value| (value, rest))
+            .ok_or(Invalid::Malformed("Option number is too large")),
+        _ => Err(Invalid::Malformed("Malformed option")),
+    }
+}
+
+/// Reply to the request, acknowledgement of a confirmable one or a message of its own
+fn encode(request: &Request, message_id: u16, code: Code, diagnostic: &str) -> Vec<u8> {
+    let kind = match request.kind {
+        TYPE_CONFIRMABLE => TYPE_ACKNOWLEDGEMENT,
+        _ => TYPE_NON_CONFIRMABLE,
+    };
+
+    let mut reply = vec![
+        VERSION << 6 | kind << 4 | request.token.len() as u8,
+        code.byte(),
+    ];
+    reply.extend_from_slice(&message_id.to_be_bytes());
+    reply.extend_from_slice(&request.token);
+    if !diagnostic.is_empty() {
+        reply.push(PAYLOAD_MARKER);
+        reply.extend_from_slice(diagnostic.as_bytes());
+    }
+    reply
+}
+
+/// Replies to confirmable messages recently received, so retransmissions aren't delivered twice
+#[derive(Default)]
+struct Exchanges {
+    exchanges: Mutex<HashMap<(SocketAddr, u16), Exchange>>,
+}
+
+struct Exchange {
+    received_at: Instant,
+    /// Missing while the message is being delivered
+    reply:       Option<Vec<u8>>,
+}
+
+impl Exchanges {
+    /// Whether the message is new, otherwise the reply to it if it's ready
+    fn begin(&self, peer: SocketAddr, message_id: u16) -> Result<(), Option<Vec<u8>>> {
+        let mut exchanges = self.exchanges.lock().expect("Exchanges lock is poisoned");
+        exchanges.retain(|_, exchange| exchange.received_at.elapsed() < EXCHANGE_LIFETIME);
+
+        match exchanges.get(&(peer, message_id)) {
+            Some(exchange) => Err(exchange.reply.clone()),
+            None => {
+                exchanges.insert(
+                    (peer, message_id),
+                    Exchange {
+                        received_at: Instant::now(),
+                        reply:       None,
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&self, peer: SocketAddr, message_id: u16, reply: &[u8]) {
+        let mut exchanges = self.exchanges.lock().expect("Exchanges lock is poisoned");
+        if let Some(exchange) = exchanges.get_mut(&(peer, message_id)) {
+            exchange.reply = Some(reply.to_vec());
+        }
+    }
+}
+
+struct Endpoint {
+    socket:          UdpSocket,
+    pipeline:        Arc<Pipeline>,
+    exchanges:       Exchanges,
+    next_message_id: AtomicU16,
+}
+
+pub fn spawn(config: CoapConfig, pipeline: Arc<Pipeline>) {
+    let address = config.listen;
+
+    actix_web::rt::spawn(async move {
+        if let Err(err) = run(address, pipeline).await {
+            log::error!("CoAP endpoint on {} failed: {}", address, err);
+        }
+    });
+}
+
+async fn run(address: SocketAddr, pipeline: Arc<Pipeline>) -> std::io::Result<()> {
+    let endpoint = Arc::new(Endpoint {
+        socket: UdpSocket::bind(address).await?,
+        pipeline,
+        exchanges: Exchanges::default(),
+        next_message_id: AtomicU16::new(initial_message_id()),
+    });
+    log::info!("Accepting CoAP messages on coap://{}", address);
+
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    loop {
+        let (size, peer) = endpoint.socket.recv_from(&mut buffer).await?;
+        let datagram = buffer[..size].to_vec();
+        let endpoint = endpoint.clone();
+
+        actix_web::rt::spawn(async move {
+            endpoint.handle(peer, &datagram).await;
+        });
+    }
+}
+
+/// Message ids only have to differ from recent ones, starting from the clock is enough
+fn initial_message_id() -> u16 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos() as u16)
+        .unwrap_or_default()
+}
+
+impl Endpoint {
+    async fn handle(&self, peer: SocketAddr, datagram: &[u8]) {
+        let request = match parse(datagram) {
+            Ok(request) => request,
+            Err(Invalid::NotCoap) => return,
+            Err(Invalid::Malformed(message)) => {
+                log::debug!("Malformed CoAP message from {}: {}", peer, message);
+                return self.reset(peer, datagram).await;
+            }
+        };
+
+        match (request.kind, request.code) {
+            (TYPE_ACKNOWLEDGEMENT | TYPE_RESET, _) => return,
+            // Empty confirmable message is a ping, answered with reset
+            (TYPE_CONFIRMABLE, 0) => return self.reset(peer, datagram).await,
+            (_, 0) => return,
+            _ => {}
+        }
+
+        if request.kind == TYPE_CONFIRMABLE {
+            match self.exchanges.begin(peer, request.message_id) {
+                Ok(()) => {}
+                Err(Some(reply)) => {
+                    let _ = self.socket.send_to(&reply, peer).await;
+                    return;
+                }
+                // Still being delivered, the device retransmits again later
+                Err(None) => return,
+            }
+        }
+
+        if let Some(number) = request.bad_option {
+            let diagnostic = format!("Option {} is not supported", number);
+            return self
+                .respond(peer, &request, Code::BadOption, &diagnostic)
+                .await;
+        }
+
+        let (code, diagnostic) = self.deliver(peer, &request).await;
+        self.respond(peer, &request, code, diagnostic).await;
+    }
+
+    async fn deliver(&self, peer: SocketAddr, request: &Request) -> (Code, &'static str) {
+        if request.code != Code::Post.byte() {
+            return (Code::MethodNotAllowed, "Messages are posted with POST");
+        }
+        let (topic, sender) = match request.path.as_slice() {
+            [topic, sender] if !topic.is_empty() && !sender.is_empty() => (topic, sender),
+            _ => return (Code::NotFound, "Messages are posted to /{topic}/{sender}"),
+        };
+        let text = match std::str::from_utf8(&request.payload) {
+            Ok(text) if !text.is_empty() => text,
+            _ => return (Code::BadRequest, "Payload has to be UTF-8 text"),
+        };
+
+        match lines::deliver(&self.pipeline, peer, topic, sender, text).await {
+            Ok(()) => (Code::Changed, ""),
+            Err(rejection) => {
+                self.pipeline.metrics.increment(
+                    "microphone_coap_rejected_total",
+                    &[("reason", rejection.reason())],
+                );
+                let code = match rejection {
+                    Rejection::Malformed => Code::BadRequest,
+                    Rejection::Secret => Code::Unauthorized,
+                    Rejection::Topic => Code::NotFound,
+                    Rejection::Quota => Code::TooManyRequests,
+                    Rejection::Delivery => Code::BadGateway,
+                };
+                (code, rejection.reason())
+            }
+        }
+    }
+
+    async fn respond(&self, peer: SocketAddr, request: &Request, code: Code, diagnostic: &str) {
+        let message_id = match request.kind {
+            TYPE_CONFIRMABLE => request.message_id,
+            _ => self.next_message_id.fetch_add(1, Ordering::Relaxed),
+        };
+        let reply = encode(request, message_id, code, diagnostic);
+
+        if request.kind == TYPE_CONFIRMABLE {
+            self.exchanges.finish(peer, request.message_id, &reply);
+        }
+        if let Err(err) = self.socket.send_to(&reply, peer).await {
+            log::debug!("Failed to reply to CoAP message from {}: {}", peer, err);
+        }
+    }
+
+    /// Reset of a message that can't be handled, carries its id and nothing else
+    async fn reset(&self, peer: SocketAddr, datagram: &[u8]) {
+        let reset = [
+            VERSION << 6 | TYPE_RESET << 4,
+            Code::Empty.byte(),
+            datagram[2],
+            datagram[3],
+        ];
+        let _ = self.socket.send_to(&reset, peer).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Confirmable POST of token 0xCAFE, with the options and payload given already encoded
+    fn post(options: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut datagram = vec![0x42, 0x02, 0x12, 0x34, 0xCA, 0xFE];
+        datagram.extend(options);
+        if !payload.is_empty() {
+            datagram.push(PAYLOAD_MARKER);
+            datagram.extend(payload);
+        }
+
+        datagram
+    }
+
+    fn parsed(datagram: &[u8]) -> Request {
+        match parse(datagram) {
+            Ok(request) => request,
+            Err(_) => panic!("Datagram isn't parsed"),
+        }
+    }
+
+    fn malformed(datagram: &[u8]) -> &'static str {
+        match parse(datagram) {
+            Err(Invalid::Malformed(reason)) => reason,
+            Err(Invalid::NotCoap) => panic!("Datagram isn't taken for CoAP"),
+            Ok(_) => panic!("Datagram is parsed"),
+        }
+    }
+
+    #[test]
+    fn parses_messages_with_path_and_payload() {
+        // Uri-Path "ops", Uri-Path "disk", Content-Format text/plain
+        let request = parsed(&post(b"\xB3ops\x04disk\x10", b"Disk is full"));
+
+        assert_eq!(request.kind, TYPE_CONFIRMABLE);
+        assert_eq!(request.code, Code::Post.byte());
+        assert_eq!(request.message_id, 0x1234);
+        assert_eq!(request.token, [0xCA, 0xFE]);
+        assert_eq!(request.path, ["ops", "disk"]);
+        assert_eq!(request.payload, b"Disk is full");
+        assert_eq!(request.bad_option, None);
+    }
+
+    #[test]
+    fn decodes_extended_deltas_and_lengths() {
+        let long = "a".repeat(20);
+        let longer = "b".repeat(300);
+        let mut options = vec![0xBD, 20 - 13];
+        options.extend(long.as_bytes());
+        // Next Uri-Path, length 300 is 269 and 2 bytes
+        options.extend([0x0E, 0x00, 31]);
+        options.extend(longer.as_bytes());
+        // Size1 at 60 is 13 and 1 byte of delta, then unknown critical option is 14 and 2 bytes
+        options.extend([0xD0, 60 - 11 - 13]);
+        options.extend([0xE0, 0x07, 0x34]);
+
+        let request = parsed(&post(&options, b""));
+
+        assert_eq!(request.path, [long, longer]);
+        assert_eq!(request.bad_option, Some(60 + 269 + 0x0734));
+    }
+
+    #[test]
+    fn unknown_elective_options_are_ignored() {
+        // Option 2054 is elective, 2053 critical
+        assert_eq!(parsed(&post(b"\xE0\x06\xF9", b"")).bad_option, None);
+        assert_eq!(parsed(&post(b"\xE0\x06\xF8", b"")).bad_option, Some(2053));
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        assert!(matches!(parse(&[0x40, 0x02, 0x12]), Err(Invalid::NotCoap)));
+        assert!(matches!(
+            parse(&[0x48, 0x02, 0x12, 0x34, 1, 2]),
+            Err(Invalid::NotCoap)
+        ));
+
+        assert_eq!(malformed(&post(b"\xD0", b"")), "Malformed option");
+        assert_eq!(malformed(&post(b"\xBE\x00", b"")), "Malformed option");
+        assert_eq!(
+            malformed(&post(b"\xB5ops", b"")),
+            "Option is longer than the message"
+        );
+        assert_eq!(
+            malformed(&post(&[PAYLOAD_MARKER], b"")),
+            "Payload marker without payload"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert!(matches!(
+            parse(&[0x80, 0x02, 0x12, 0x34]),
+            Err(Invalid::NotCoap)
+        ));
+        assert!(matches!(
+            parse(&[0x49, 0x02, 0x12, 0x34]),
+            Err(Invalid::NotCoap)
+        ));
+
+        // Delta nibble 15 is reserved for the payload marker
+        assert_eq!(malformed(&post(b"\xF1a", b"")), "Malformed option");
+        assert_eq!(
+            malformed(&post(b"\xB2\xFF\xFE", b"")),
+            "Uri-Path has to be UTF-8"
+        );
+        assert_eq!(
+            malformed(&post(b"\xE0\xFF\xFF", b"")),
+            "Option number is too large"
+        );
+        assert_eq!(
+            malformed(&post(b"\xE0\xFE\xF2\xE0\x00\x01", b"")),
+            "Option number is too large"
+        );
+    }
+
+    #[test]
+    fn acknowledges_confirmable_messages_with_their_token() {
+        let request = parsed(&post(b"\xB3ops", b"Disk is full"));
+
+        assert_eq!(
+            encode(&request, request.message_id, Code::Changed, ""),
+            [0x62, 0x44, 0x12, 0x34, 0xCA, 0xFE]
+        );
+        assert_eq!(
+            encode(
+                &request,
+                request.message_id,
+                Code::NotFound,
+                "No such topic"
+            ),
+            [
+                &[0x62, 0x84, 0x12, 0x34, 0xCA, 0xFE, 0xFF][..],
+                b"No such topic"
+            ]
+            .concat()
+        );
+    }
+}
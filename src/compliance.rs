@@ -0,0 +1,501 @@
+use std::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        Write,
+    },
+    path::PathBuf,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use hmac::{
+    Hmac,
+    Mac,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sha2::{
+    Digest,
+    Sha256,
+};
+
+use crate::{
+    admin::Viewer,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+};
+
+/// `previous` of the first entry
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComplianceConfig {
+    /// File entries are appended to, one JSON object per line
+    log:         PathBuf,
+    /// Entries are chained with HMAC-SHA256 instead of plain SHA-256, so whoever can edit the
+    /// log can't write a chain of their own without the key too
+    signing_key: Option<String>,
+}
+
+/// What one Bot API request carried, without the text itself
+#[derive(Serialize)]
+#[derive(Deserialize)]
+struct Record {
+    seq:              u64,
+    time:             DateTime<Utc>,
+    topic:            String,
+    chat_id:          String,
+    method:           String,
+    /// Hex SHA-256 of the text or caption as it was sent
+    digest:           String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attachment_bytes: Option<usize>,
+    /// `hash` of the entry before
+    previous:         String,
+}
+
+#[derive(Serialize)]
+#[derive(Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    record: Record,
+    /// Over the JSON of the record, which has `previous` in it
+    hash:   String,
+}
+
+struct Chain {
+    file:      File,
+    seq:       u64,
+    last_hash: String,
+}
+
+/// Hash-chained log of messages sent, an entry is appended before its request is made
+pub struct AuditLog {
+    path:        PathBuf,
+    signing_key: Option<String>,
+    chain:       Mutex<Chain>,
+}
+
+#[derive(Serialize)]
+pub struct Verification {
+    entries:   u64,
+    valid:     bool,
+    /// Entry the chain breaks at, the one after the last entry if the end is cut off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    broken_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason:    Option<String>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl AuditLog {
+    /// Continues the chain of the log that's already there, a broken one is continued too but
+    /// verification keeps telling where it's broken
+    pub fn open(config: ComplianceConfig) -> io::Result<Self> {
+        let audit_log = Self {
+            chain:       Mutex::new(Chain {
+                file:      OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&config.log)?,
+                seq:       0,
+                last_hash: GENESIS.to_owned(),
+            }),
+            path:        config.log,
+            signing_key: config.signing_key,
+        };
+
+        let (verification, last) = audit_log.walk()?;
+        if !verification.valid {
+            log::error!(
+                "Audit log {} is broken at entry {}: {}",
+                audit_log.path.display(),
+                verification.broken_at.unwrap_or_default(),
+                verification.reason.unwrap_or_default()
+            );
+        }
+        if let Some((seq, hash)) = last {
+            let mut chain = audit_log.chain.lock().expect("Audit log lock is poisoned");
+            chain.seq = seq;
+            chain.last_hash = hash;
+        }
+
+        Ok(audit_log)
+    }
+
+    fn hash(&self, record: &Record) -> String {
+        let json = serde_json::to_vec(record).expect("Failed to serialize audit record");
+        match &self.signing_key {
+            Some(key) => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+                    .expect("HMAC can take key of any size");
+                mac.update(&json);
+                hex(&mac.finalize().into_bytes())
+            }
+            None => hex(&Sha256::digest(&json)),
+        }
+    }
+
+    /// Appends the entry and flushes it to disk, the message mustn't be sent if this fails
+    pub fn record(
+        &self,
+        topic: &str,
+        chat_id: &str,
+        method: &str,
+        text: &str,
+        attachment_bytes: Option<usize>,
+    ) -> io::Result<()> {
+        let mut chain = self.chain.lock().expect("Audit log lock is poisoned");
+        let record = Record {
+            seq: chain.seq + 1,
+            time: Utc::now(),
+            topic: topic.to_owned(),
+            chat_id: chat_id.to_owned(),
+            method: method.to_owned(),
+            digest: hex(&Sha256::digest(text.as_bytes())),
+            attachment_bytes,
+            previous: chain.last_hash.clone(),
+        };
+        let hash = self.hash(&record);
+        let entry = Entry { record, hash };
+
+        let mut line = serde_json::to_vec(&entry).expect("Failed to serialize audit entry");
+        line.push(b'\n');
+        chain.file.write_all(&line)?;
+        chain.file.sync_data()?;
+
+        chain.seq = entry.record.seq;
+        chain.last_hash = entry.hash;
+        Ok(())
+    }
+
+    /// Checks every entry of the log on disk against the one before, and the last one against
+    /// what was appended last
+    pub fn verify(&self) -> io::Result<Verification> {
+        // Holding the lock keeps entries from being appended halfway through
+        let chain = self.chain.lock().expect("Audit log lock is poisoned");
+        let (mut verification, last) = self.walk()?;
+
+        let last_seq = last.as_ref().map_or(0, |(seq, _)| *seq);
+        let last_hash = last.as_ref().map_or(GENESIS, |(_, hash)| hash.as_str());
+        if verification.valid && (last_seq != chain.seq || last_hash != chain.last_hash) {
+            verification.valid = false;
+            verification.broken_at = Some(last_seq + 1);
+            verification.reason = Some(format!(
+                "Log ends at entry {}, {} were appended",
+                last_seq, chain.seq
+            ));
+        }
+
+        Ok(verification)
+    }
+
+    /// Verification of the file, with seq and hash of its last entry
+    fn walk(&self) -> io::Result<(Verification, Option<(u64, String)>)> {
+        let mut verification = Verification {
+            entries:   0,
+            valid:     true,
+            broken_at: None,
+            reason:    None,
+        };
+        let mut last: Option<(u64, String)> = None;
+
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            let expected_seq = last.as_ref().map_or(1, |(seq, _)| seq + 1);
+            let expected_previous = last.as_ref().map_or(GENESIS, |(_, hash)| hash.as_str());
+
+            let (entry, problem) = match serde_json::from_str::<Entry>(&line) {
+                Ok(entry) if entry.record.seq != expected_seq => {
+                    let problem = format!(
+                        "Entry has seq {} instead of {}",
+                        entry.record.seq, expected_seq
+                    );
+                    (Some(entry), Some(problem))
+                }
+                Ok(entry) if entry.record.previous != expected_previous => (
+                    Some(entry),
+                    Some("Entry doesn't follow the one before".to_owned()),
+                ),
+                Ok(entry) if self.hash(&entry.record) != entry.hash =>
+                    (Some(entry), Some("Hash doesn't match the entry".to_owned())),
+                Ok(entry) => (Some(entry), None),
+                Err(err) => (None, Some(format!("Entry can't be parsed: {}", err))),
+            };
+
+            // Entries after a broken one can't be trusted either, only the first break is told
+            if let Some(problem) = problem.filter(|_| verification.valid) {
+                verification.valid = false;
+                verification.broken_at = Some(expected_seq);
+                verification.reason = Some(problem);
+            }
+            if let Some(entry) = entry {
+                verification.entries += 1;
+                last = Some((entry.record.seq, entry.hash));
+            }
+        }
+
+        Ok((verification, last))
+    }
+}
+
+/// Bot API error response for messages that weren't sent because the audit log couldn't be
+/// appended to, it's retried like other server errors
+pub fn unaudited() -> reqwest::Response {
+    http::Response::builder()
+        .status(503)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(
+            serde_json::json!({
+                "ok": false,
+                "error_code": 503,
+                "description": "Message wasn't sent, audit log is unavailable",
+            })
+            .to_string(),
+        )
+        .expect("Unaudited response is valid")
+        .into()
+}
+
+/// Chain of `[compliance]` log checked from the first entry to the last one
+pub async fn verify_log(_: Viewer, audit_log: web::Data<Option<Arc<AuditLog>>>) -> impl Responder {
+    let audit_log = match audit_log.as_ref() {
+        Some(audit_log) => audit_log,
+        None =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::ComplianceDisabled,
+                "Compliance mode is not configured",
+            )),
+    };
+
+    match audit_log.verify() {
+        Ok(verification) => HttpResponse::Ok().json(verification),
+        Err(err) => {
+            log::error!("Failed to read audit log: {}", err);
+            HttpResponse::from(ApiError::new(
+                ErrorCode::InternalError,
+                "Failed to read audit log",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::Path,
+    };
+
+    use super::*;
+
+    /// Log of the test, apart from logs of other tests and test runs
+    fn log_path(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "microphone-audit-{}-{}.jsonl",
+            std::process::id(),
+            test
+        ));
+        let _ = fs::remove_file(&path);
+
+        path
+    }
+
+    fn open(path: &Path, signing_key: Option<&str>) -> AuditLog {
+        AuditLog::open(ComplianceConfig {
+            log:         path.to_path_buf(),
+            signing_key: signing_key.map(str::to_owned),
+        })
+        .unwrap()
+    }
+
+    fn recorded(path: &Path, signing_key: Option<&str>) -> AuditLog {
+        let audit_log = open(path, signing_key);
+        for (chat_id, text) in [
+            ("111", "Disk is full"),
+            ("222", "Disk is full"),
+            ("111", "Fixed"),
+        ] {
+            audit_log
+                .record("ops", chat_id, "sendMessage", text, None)
+                .unwrap();
+        }
+
+        audit_log
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn rewrite(path: &Path, lines: &[String]) {
+        fs::write(
+            path,
+            lines
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect::<String>(),
+        )
+        .unwrap();
+    }
+
+    fn broken(verification: &Verification) -> (Option<u64>, &str) {
+        assert!(!verification.valid);
+        (
+            verification.broken_at,
+            verification.reason.as_deref().unwrap(),
+        )
+    }
+
+    #[test]
+    fn entries_chain_to_the_one_before() {
+        let path = log_path("chain");
+        let audit_log = recorded(&path, None);
+
+        let entries = lines(&path)
+            .iter()
+            .map(|line| serde_json::from_str::<Entry>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries[0].record.previous, GENESIS);
+        for (before, entry) in entries.iter().zip(&entries[1..]) {
+            assert_eq!(entry.record.seq, before.record.seq + 1);
+            assert_eq!(entry.record.previous, before.hash);
+        }
+        assert_eq!(
+            entries[0].record.digest,
+            hex(&Sha256::digest(b"Disk is full"))
+        );
+
+        let verification = audit_log.verify().unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.entries, 3);
+
+        // Reopened log continues the chain
+        drop(audit_log);
+        let audit_log = open(&path, None);
+        audit_log
+            .record("ops", "111", "sendPhoto", "", Some(1024))
+            .unwrap();
+        let verification = audit_log.verify().unwrap();
+        assert!(verification.valid);
+        assert_eq!(verification.entries, 4);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cut_off_log_is_broken_at_the_missing_entry() {
+        let path = log_path("cut");
+        let audit_log = recorded(&path, None);
+        let mut lines = lines(&path);
+
+        lines.pop();
+        rewrite(&path, &lines);
+        assert_eq!(
+            broken(&audit_log.verify().unwrap()),
+            (Some(3), "Log ends at entry 2, 3 were appended")
+        );
+
+        // Entry cut in the middle of the line
+        let half = lines[1].len() / 2;
+        lines[1].truncate(half);
+        rewrite(&path, &lines);
+        let verification = audit_log.verify().unwrap();
+        let (broken_at, reason) = broken(&verification);
+        assert_eq!(broken_at, Some(2));
+        assert!(reason.starts_with("Entry can't be parsed"), "{}", reason);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn edited_entries_break_the_chain() {
+        let path = log_path("edited");
+        let audit_log = recorded(&path, None);
+        let original = lines(&path);
+
+        let mut lines = original.clone();
+        lines[1] = lines[1].replace("\"chat_id\":\"222\"", "\"chat_id\":\"333\"");
+        rewrite(&path, &lines);
+        assert_eq!(
+            broken(&audit_log.verify().unwrap()),
+            (Some(2), "Hash doesn't match the entry")
+        );
+
+        let mut lines = original.clone();
+        lines.remove(1);
+        rewrite(&path, &lines);
+        assert_eq!(
+            broken(&audit_log.verify().unwrap()),
+            (Some(2), "Entry has seq 3 instead of 2")
+        );
+
+        // Entry rehashed after the edit doesn't fit the next one
+        let mut lines = original.clone();
+        let mut entry = serde_json::from_str::<Entry>(&lines[1]).unwrap();
+        entry.record.chat_id = "333".to_owned();
+        entry.hash = audit_log.hash(&entry.record);
+        lines[1] = serde_json::to_string(&entry).unwrap();
+        rewrite(&path, &lines);
+        assert_eq!(
+            broken(&audit_log.verify().unwrap()),
+            (Some(3), "Entry doesn't follow the one before")
+        );
+
+        let mut lines = original;
+        lines.insert(0, "not json".to_owned());
+        rewrite(&path, &lines);
+        let verification = audit_log.verify().unwrap();
+        assert_eq!(verification.broken_at, Some(1));
+        assert_eq!(verification.entries, 3);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn signed_chains_verify_only_with_their_key() {
+        let path = log_path("signed");
+        drop(recorded(&path, Some("secret")));
+
+        assert!(open(&path, Some("secret")).verify().unwrap().valid);
+        assert_eq!(
+            broken(&open(&path, Some("other")).verify().unwrap()),
+            (Some(1), "Hash doesn't match the entry")
+        );
+        assert_eq!(
+            broken(&open(&path, None).verify().unwrap()),
+            (Some(1), "Hash doesn't match the entry")
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
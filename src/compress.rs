@@ -0,0 +1,764 @@
+use chrono::{
+    Datelike,
+    Timelike,
+    Utc,
+};
+
+/// Matches can't reach further back than the deflate window
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Earlier occurrences checked per position, trades ratio for speed
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const END_OF_BLOCK: u32 = 256;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+
+    table
+}
+
+/// CRC-32 as gzip and zip checksum their content
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, byte| {
+        CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Gzip member with the content deflated, readable by `gunzip` and `zcat`
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // No name or modification time, OS unknown
+    let mut gzip = vec![0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF];
+    gzip.extend(deflate(data));
+    gzip.extend(crc32(data).to_le_bytes());
+    gzip.extend((data.len() as u32).to_le_bytes());
+
+    gzip
+}
+
+/// Zip archive of the files, each deflated unless that makes it larger
+pub fn zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    // Names are UTF-8
+    const FLAGS: u16 = 1 << 11;
+    const VERSION: u16 = 20;
+
+    let now = Utc::now();
+    let time = (now.hour() << 11 | now.minute() << 5 | (now.second() / 2)) as u16;
+    let date = ((now.year().max(1980) as u32 - 1980) << 9 | now.month() << 5 | now.day()) as u16;
+
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    for (name, content) in files {
+        let deflated = deflate(content);
+        let (method, data): (u16, &[u8]) = if deflated.len() < content.len() {
+            (8, &deflated)
+        } else {
+            (0, content)
+        };
+        let offset = archive.len() as u32;
+
+        let mut header = Vec::new();
+        header.extend(VERSION.to_le_bytes());
+        header.extend(FLAGS.to_le_bytes());
+        header.extend(method.to_le_bytes());
+        header.extend(time.to_le_bytes());
+        header.extend(date.to_le_bytes());
+        header.extend(crc32(content).to_le_bytes());
+        header.extend((data.len() as u32).to_le_bytes());
+        header.extend((content.len() as u32).to_le_bytes());
+        header.extend((name.len() as u16).to_le_bytes());
+        // No extra field
+        header.extend(0u16.to_le_bytes());
+
+        archive.extend(0x0403_4B50u32.to_le_bytes());
+        archive.extend(&header);
+        archive.extend(name.as_bytes());
+        archive.extend(data);
+
+        directory.extend(0x0201_4B50u32.to_le_bytes());
+        directory.extend(VERSION.to_le_bytes());
+        directory.extend(&header);
+        // No comment, first disk, no attributes
+        directory.extend([0; 10]);
+        directory.extend(offset.to_le_bytes());
+        directory.extend(name.as_bytes());
+    }
+
+    let directory_offset = archive.len() as u32;
+    archive.extend(&directory);
+    archive.extend(0x0605_4B50u32.to_le_bytes());
+    archive.extend([0; 4]);
+    archive.extend((files.len() as u16).to_le_bytes());
+    archive.extend((files.len() as u16).to_le_bytes());
+    archive.extend((directory.len() as u32).to_le_bytes());
+    archive.extend(directory_offset.to_le_bytes());
+    archive.extend([0; 2]);
+
+    archive
+}
+
+/// Zlib stream of the content deflated, as PNG stores image data
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    // Deflate with 32K window, no dictionary
+    let mut zlib = vec![0x78, 0x01];
+    zlib.extend(deflate(data));
+    zlib.extend(adler32(data).to_be_bytes());
+
+    zlib
+}
+
+/// Content of zlib stream, as PNG stores image data. None if the stream is broken or inflates to
+/// more than `max_length` bytes
+pub fn unzlib(data: &[u8], max_length: usize) -> Option<Vec<u8>> {
+    let (header, rest) = (data.get(..2)?, &data[2..]);
+    // Deflate without preset dictionary
+    if header[0] & 0x0F != 8
+        || header[1] & 0x20 != 0
+        || u16::from_be_bytes([header[0], header[1]]) % 31 != 0
+    {
+        return None;
+    }
+
+    let mut reader = BitReader::new(rest);
+    let inflated = inflate(&mut reader, max_length)?;
+    let trailer = rest.get(reader.byte_position()..reader.byte_position() + 4)?;
+    if u32::from_be_bytes(trailer.try_into().ok()?) != adler32(&inflated) {
+        return None;
+    }
+
+    Some(inflated)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+
+    let (mut a, mut b) = (1, 0);
+    for byte in data {
+        a = (a + *byte as u32) % MODULUS;
+        b = (b + a) % MODULUS;
+    }
+
+    b << 16 | a
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes:  Vec<u8>,
+    buffer: u64,
+    count:  u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: usize, bits: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit
+    fn write_code(&mut self, code: u32, bits: u32) {
+        self.write((code.reverse_bits() >> (32 - bits)) as usize, bits);
+    }
+
+    /// Literal, length or end of block symbol in fixed Huffman codes
+    fn write_symbol(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let index = LENGTH_BASE
+            .iter()
+            .rposition(|base| *base <= length)
+            .expect("Matches are at least 3 bytes long");
+        self.write_symbol(257 + index as u32);
+        self.write(length - LENGTH_BASE[index], LENGTH_EXTRA_BITS[index]);
+
+        let index = DISTANCE_BASE
+            .iter()
+            .rposition(|base| *base <= distance)
+            .expect("Distances start at 1");
+        self.write_code(index as u32, 5);
+        self.write(distance - DISTANCE_BASE[index], DISTANCE_EXTRA_BITS[index]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+
+        self.bytes
+    }
+}
+
+/// Reads bits of deflate stream, least significant first
+struct BitReader<'a> {
+    bytes:    &'a [u8],
+    /// In bits
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn read(&mut self, bits: u32) -> Option<usize> {
+        let mut value = 0;
+        for bit in 0..bits as usize {
+            let byte = *self.bytes.get(self.position / 8)?;
+            value |= ((byte >> (self.position % 8)) as usize & 1) << bit;
+            self.position += 1;
+        }
+
+        Some(value)
+    }
+
+    /// Stored blocks start and streams end at byte boundary
+    fn byte_position(&self) -> usize {
+        self.position.div_ceil(8)
+    }
+
+    fn align(&mut self) {
+        self.position = self.byte_position() * 8;
+    }
+}
+
+/// Canonical Huffman code, as deflate describes them by code lengths of symbols
+struct Huffman {
+    /// Number of codes of each length
+    counts:  [usize; 16],
+    /// Ordered by their codes
+    symbols: Vec<usize>,
+}
+
+impl Huffman {
+    /// None if lengths describe more codes than there are of some length
+    fn new(lengths: &[usize]) -> Option<Self> {
+        let mut counts = [0; 16];
+        for length in lengths {
+            counts[*length] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1;
+        for count in &counts[1..] {
+            left = left * 2 - *count as isize;
+            if left < 0 {
+                return None;
+            }
+        }
+
+        let mut offsets = [0; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length]] = symbol;
+                offsets[*length] += 1;
+            }
+        }
+
+        Some(Self { counts, symbols })
+    }
+
+    /// Codes are packed starting from their most significant bit, so one bit is read at a time
+    fn decode(&self, reader: &mut BitReader) -> Option<usize> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for count in &self.counts[1..] {
+            code |= reader.read(1)?;
+            if code < first + count {
+                return self.symbols.get(index + code - first).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        None
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+
+    (
+        Huffman::new(&lengths).expect("Fixed codes are complete"),
+        Huffman::new(&[5; 30]).expect("Fixed codes are complete"),
+    )
+}
+
+/// Codes of literals and lengths, and of distances, as dynamic block starts with
+fn dynamic_codes(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    let literals = reader.read(5)? + 257;
+    let distances = reader.read(5)? + 1;
+    let length_codes = reader.read(4)? + 4;
+    if literals > 286 || distances > 30 {
+        return None;
+    }
+
+    let mut lengths = [0; 19];
+    for symbol in &ORDER[..length_codes] {
+        lengths[*symbol] = reader.read(3)?;
+    }
+    let length_code = Huffman::new(&lengths)?;
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match length_code.decode(reader)? {
+            length @ 0..=15 => (length, 1),
+            16 => (*lengths.last()?, 3 + reader.read(2)?),
+            17 => (0, 3 + reader.read(3)?),
+            _ => (0, 11 + reader.read(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat));
+    }
+    if lengths.len() > literals + distances || lengths[END_OF_BLOCK as usize] == 0 {
+        return None;
+    }
+
+    Some((
+        Huffman::new(&lengths[..literals])?,
+        Huffman::new(&lengths[literals..])?,
+    ))
+}
+
+/// Content of deflate stream, None if it's broken or longer than `max_length`
+fn inflate(reader: &mut BitReader, max_length: usize) -> Option<Vec<u8>> {
+    let mut inflated = Vec::new();
+    loop {
+        let last = reader.read(1)? == 1;
+        let (literals, distances) = match reader.read(2)? {
+            0 => {
+                reader.align();
+                let at = reader.byte_position();
+                let header = reader.bytes.get(at..at + 4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return None;
+                }
+                let stored = reader.bytes.get(at + 4..at + 4 + length as usize)?;
+                if inflated.len() + stored.len() > max_length {
+                    return None;
+                }
+                inflated.extend(stored);
+                reader.position = (at + 4 + length as usize) * 8;
+
+                if last {
+                    return Some(inflated);
+                }
+                continue;
+            }
+            1 => fixed_codes(),
+            2 => dynamic_codes(reader)?,
+            _ => return None,
+        };
+
+        loop {
+            let symbol = literals.decode(reader)?;
+            if symbol < END_OF_BLOCK as usize {
+                if inflated.len() == max_length {
+                    return None;
+                }
+                inflated.push(symbol as u8);
+                continue;
+            }
+            if symbol == END_OF_BLOCK as usize {
+                break;
+            }
+
+            let index = symbol - 257;
+            let length = LENGTH_BASE.get(index)? + reader.read(LENGTH_EXTRA_BITS[index])?;
+            let index = distances.decode(reader)?;
+            let distance = DISTANCE_BASE.get(index)? + reader.read(DISTANCE_EXTRA_BITS[index])?;
+            if distance > inflated.len() || inflated.len() + length > max_length {
+                return None;
+            }
+            // Matches can overlap bytes they produce
+            let start = inflated.len() - distance;
+            for at in start..start + length {
+                inflated.push(inflated[at]);
+            }
+        }
+
+        if last {
+            return Some(inflated);
+        }
+    }
+}
+
+/// Positions of earlier occurrences of 3 byte sequences, newest first
+struct Matcher<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            prev: vec![usize::MAX; WINDOW],
+        }
+    }
+
+    fn hash(&self, position: usize) -> usize {
+        let bytes = &self.data[position..position + MIN_MATCH];
+        let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+
+        (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + MIN_MATCH > self.data.len() {
+            return;
+        }
+
+        let hash = self.hash(position);
+        self.prev[position % WINDOW] = self.head[hash];
+        self.head[hash] = position;
+    }
+
+    /// Length and distance of the longest earlier match of bytes at the position
+    fn longest(&self, position: usize) -> (usize, usize) {
+        if position + MIN_MATCH > self.data.len() {
+            return (0, 0);
+        }
+
+        let ahead = &self.data[position..self.data.len().min(position + MAX_MATCH)];
+        let mut candidate = self.head[self.hash(position)];
+        let mut best = (0, 0);
+        for _ in 0..MAX_CHAIN {
+            if candidate == usize::MAX || position - candidate > WINDOW {
+                break;
+            }
+
+            let length = self.data[candidate..]
+                .iter()
+                .zip(ahead)
+                .take_while(|(earlier, byte)| earlier == byte)
+                .count();
+            if length > best.0 {
+                best = (length, position - candidate);
+                if length == ahead.len() {
+                    break;
+                }
+            }
+
+            // Slots of positions that left the window are reused by newer ones
+            let next = self.prev[candidate % WINDOW];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+
+        best
+    }
+}
+
+/// Single deflate block with fixed Huffman codes, good enough for text
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // Last block, fixed Huffman codes
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let mut matcher = Matcher::new(data);
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = matcher.longest(position);
+        if length >= MIN_MATCH {
+            writer.write_match(length, distance);
+            for matched in position..position + length {
+                matcher.insert(matched);
+            }
+            position += length;
+        } else {
+            writer.write_symbol(data[position] as u32);
+            matcher.insert(position);
+            position += 1;
+        }
+    }
+    writer.write_symbol(END_OF_BLOCK);
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&hex[at..at + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn le_u16(data: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([data[at], data[at + 1]]) as usize
+    }
+
+    fn le_u32(data: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize
+    }
+
+    /// Text the dynamic Huffman stream of Python's zlib is of
+    fn numbers() -> Vec<u8> {
+        let words = [
+            "severity",
+            "critical",
+            "warning",
+            "info",
+            "recipient",
+            "topic",
+            "message",
+        ];
+        let numbers = (0..60).map(|number| (number * 37 % 101).to_string());
+
+        words
+            .map(str::to_owned)
+            .into_iter()
+            .chain(numbers)
+            .collect::<Vec<_>>()
+            .join(" ")
+            .into_bytes()
+    }
+
+    fn text() -> Vec<u8> {
+        let mut text = b"Disk usage is high on ".repeat(20);
+        text.extend(b"backup-01, backup-02 and backup-03 of the lab");
+
+        text
+    }
+
+    #[test]
+    fn crc32_of_check_input() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn adler32_of_known_inputs() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn fixed_huffman_blocks_of_known_inputs() {
+        assert_eq!(deflate(b""), [0x03, 0x00]);
+        assert_eq!(deflate(b"a"), [0x4B, 0x04, 0x00]);
+        // Literal, then a match of 4 bytes 1 back
+        assert_eq!(deflate(b"aaaaa"), [0x4B, 0x04, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn fixed_huffman_blocks_inflate_back() {
+        let text = text();
+        let deflated = deflate(&text);
+
+        assert!(deflated.len() < text.len() / 4);
+        assert_eq!(
+            inflate(&mut BitReader::new(&deflated), usize::MAX),
+            Some(text)
+        );
+    }
+
+    #[test]
+    fn matches_reach_back_as_far_as_window() {
+        // Repeat of the first block is a window away, the second is too far
+        let mut data = (0..WINDOW as u32 + 300)
+            .map(|value| value.wrapping_mul(2_654_435_761).to_be_bytes()[0])
+            .collect::<Vec<_>>();
+        data.extend_from_within(300..600);
+        data.extend_from_within(..300);
+        let deflated = deflate(&data);
+
+        assert_eq!(
+            inflate(&mut BitReader::new(&deflated), usize::MAX),
+            Some(data)
+        );
+    }
+
+    #[test]
+    fn gzip_has_header_and_trailer_of_content() {
+        let text = text();
+        let gzip = gzip(&text);
+
+        assert_eq!(gzip[..10], [0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF]);
+        assert_eq!(gzip[10..gzip.len() - 8], deflate(&text));
+        assert_eq!(
+            gzip[gzip.len() - 8..gzip.len() - 4],
+            crc32(&text).to_le_bytes()
+        );
+        assert_eq!(gzip[gzip.len() - 4..], (text.len() as u32).to_le_bytes());
+    }
+
+    #[test]
+    fn zip_directory_points_at_local_headers() {
+        let text = text();
+        let noise = (0..200u32)
+            .map(|value| value.wrapping_mul(2_654_435_761).to_be_bytes()[0])
+            .collect::<Vec<_>>();
+        let files = [
+            ("disk.txt".to_owned(), text.clone()),
+            ("noise.bin".to_owned(), noise.clone()),
+        ];
+        let archive = zip(&files);
+
+        // End of central directory is the last record, without comment
+        let end = archive.len() - 22;
+        assert_eq!(le_u32(&archive, end), 0x0605_4B50);
+        assert_eq!(le_u16(&archive, end + 8), 2);
+        assert_eq!(le_u16(&archive, end + 10), 2);
+        let (size, offset) = (le_u32(&archive, end + 12), le_u32(&archive, end + 16));
+        assert_eq!(offset + size, end);
+
+        let mut entry = offset;
+        let mut local = 0;
+        for ((name, content), method) in files.iter().zip([8, 0]) {
+            assert_eq!(le_u32(&archive, entry), 0x0201_4B50);
+            assert_eq!(le_u16(&archive, entry + 10), method);
+            assert_eq!(le_u32(&archive, entry + 16), crc32(content) as usize);
+            assert_eq!(le_u32(&archive, entry + 24), content.len());
+            assert_eq!(le_u32(&archive, entry + 42), local);
+            let name_length = le_u16(&archive, entry + 28);
+            assert_eq!(
+                &archive[entry + 46..entry + 46 + name_length],
+                name.as_bytes()
+            );
+
+            // Local header repeats the directory entry
+            assert_eq!(le_u32(&archive, local), 0x0403_4B50);
+            assert_eq!(
+                archive[local + 4..local + 30],
+                archive[entry + 6..entry + 32]
+            );
+            let compressed = le_u32(&archive, local + 18);
+            let data = &archive[local + 30 + name_length..][..compressed];
+            match method {
+                8 => assert_eq!(
+                    inflate(&mut BitReader::new(data), usize::MAX).as_ref(),
+                    Some(content)
+                ),
+                _ => assert_eq!(data, content),
+            }
+
+            entry += 46 + name_length;
+            local += 30 + name_length + compressed;
+        }
+        assert_eq!(entry, end);
+        assert_eq!(local, offset);
+    }
+
+    #[test]
+    fn zlib_streams_round_trip() {
+        let text = text();
+        let zlib = zlib(&text);
+
+        assert_eq!(zlib[..2], [0x78, 0x01]);
+        assert_eq!(zlib[zlib.len() - 4..], adler32(&text).to_be_bytes());
+        assert_eq!(unzlib(&zlib, text.len()), Some(text));
+    }
+
+    #[test]
+    fn unzlib_reads_streams_of_other_encoders() {
+        // Stored, fixed and dynamic Huffman blocks made by Python's zlib
+        assert_eq!(
+            unzlib(
+                &unhex("7801010c00f3ff68656c6c6f2c2068656c6c6f1cda0475"),
+                100
+            ),
+            Some(b"hello, hello".to_vec())
+        );
+        assert_eq!(
+            unzlib(&unhex("78da4b040000620062"), 100),
+            Some(b"a".to_vec())
+        );
+        let dynamic = unhex(concat!(
+            "78da0d8c518ac3300c05af324790255bb28e1382b718dab424a1cbde7efdf33ee60d738def38e7fdc7",
+            "be76eedb93dfed3ce6f1601e3f6fceb1cfcf1cc7cdfdfecc9dd7b8aeed31102c884a116ad02b2ab420",
+            "2b2678605421826234a1076ab890eb31622954a30bc569460aeab8addc0a3bb110d5e98b24cd494313",
+            "77144bc2294a4dfa024a4bd231c5974b5522298da6f4441baeff37a6340b",
+        ));
+        assert_eq!(dynamic[2] >> 1 & 3, 2);
+        assert_eq!(unzlib(&dynamic, 1000), Some(numbers()));
+    }
+
+    #[test]
+    fn unzlib_rejects_broken_and_too_long_streams() {
+        let zlib = zlib(&text());
+
+        assert_eq!(unzlib(&zlib[..zlib.len() - 1], usize::MAX), None);
+        assert_eq!(unzlib(&zlib[..zlib.len() / 2], usize::MAX), None);
+        assert_eq!(unzlib(&zlib, text().len() - 1), None);
+
+        let mut corrupted = zlib.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        assert_eq!(unzlib(&corrupted, usize::MAX), None);
+
+        // Preset dictionary, and a header that isn't a multiple of 31
+        assert_eq!(unzlib(&[0x78, 0xBB, 0x03, 0x00], usize::MAX), None);
+        assert_eq!(
+            unzlib(&[0x78, 0x02, 0x03, 0x00, 0, 0, 0, 1], usize::MAX),
+            None
+        );
+        assert_eq!(
+            unzlib(&[0x78, 0x01, 0x03, 0x00, 0, 0, 0, 1], usize::MAX),
+            Some(Vec::new())
+        );
+    }
+}
@@ -0,0 +1,663 @@
+use std::{
+    fmt,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+
+use serde::de::DeserializeOwned;
+use toml::{
+    Table,
+    Value,
+};
+
+/// Layout of config files written for this release
+pub const CURRENT_VERSION: i64 = 2;
+
+/// Files without `version` key predate versioning
+const UNVERSIONED: i64 = 1;
+
+/// Each migration upgrades the layout of the previous version by one and describes what it changed
+const MIGRATIONS: &[fn(&mut Table) -> Vec<String>] = &[migrate_flat_layout];
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Sent as bearer token when fetching remote config, Consul accepts ACL tokens this way
+const CONFIG_TOKEN_VARIABLE: &str = "MICROPHONE_CONFIG_TOKEN";
+
+/// Keys of topics that can be read from files with `<key>_file` as well as `admin_token`,
+/// `token` of admin tokens, `telegram.secret`, `lines.secret` and `compliance.signing_key`
+const TOPIC_SECRETS: [&str; 4] = [
+    "gitlab_token",
+    "gitea_secret",
+    "sentry_secret",
+    "submit_token",
+];
+/// Set by systemd for units with `LoadCredential`
+const CREDENTIALS_DIRECTORY_VARIABLE: &str = "CREDENTIALS_DIRECTORY";
+
+/// Format of config file, told by its extension
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("json") => Self::Json,
+            _ => Self::Toml,
+        }
+    }
+}
+
+/// Why config file couldn't be loaded, parse errors point at the offending key
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path:   PathBuf,
+        source: io::Error,
+    },
+    Fetch {
+        url:     String,
+        message: String,
+    },
+    Parse {
+        path:     PathBuf,
+        /// Dotted path of the key, e.g. `topics.ci.escalation.after`
+        key:      Option<String>,
+        position: Option<(usize, usize)>,
+        message:  String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read { path, source } => write!(
+                f,
+                "Failed to read config file {}: {}",
+                path.display(),
+                source
+            ),
+            Self::Fetch { url, message } =>
+                write!(f, "Failed to fetch config {}: {}", url, message),
+            Self::Parse {
+                path,
+                key,
+                position,
+                message,
+            } => {
+                write!(f, "{}", path.display())?;
+                if let Some((line, column)) = position {
+                    write!(f, ":{}:{}", line, column)?;
+                }
+                if let Some(key) = key {
+                    write!(f, ": `{}`", key)?;
+                }
+                write!(f, ": {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Read { source, .. } => Some(source),
+            Self::Fetch { .. } | Self::Parse { .. } => None,
+        }
+    }
+}
+
+/// One-based line and column of the byte offset
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count())
+        + 1;
+
+    (line, column)
+}
+
+/// Moves key between tables, path is split by dots
+fn move_key(table: &mut Table, from: &str, to: &str, changes: &mut Vec<String>) {
+    let value = match table.remove(from) {
+        Some(value) => value,
+        None => return,
+    };
+
+    let (parents, key) = match to.rsplit_once('.') {
+        Some((parents, key)) => (parents.split('.').collect::<Vec<_>>(), key),
+        None => (Vec::new(), to),
+    };
+
+    let mut destination = &mut *table;
+    for parent in parents {
+        destination = match destination
+            .entry(parent)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(parent) => parent,
+            // Left for deserialization to complain about
+            _ => return,
+        };
+    }
+
+    destination.insert(key.to_owned(), value);
+    changes.push(format!("moved `{}` to `{}`", from, to));
+}
+
+/// Version 1 had everything in the root table
+fn migrate_flat_layout(table: &mut Table) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    move_key(table, "port", "server.port", &mut changes);
+    move_key(
+        table,
+        "trusted_proxies",
+        "server.trusted_proxies",
+        &mut changes,
+    );
+    move_key(
+        table,
+        "proxy_protocol",
+        "server.proxy_protocol",
+        &mut changes,
+    );
+    move_key(table, "secret", "telegram.secret", &mut changes);
+    move_key(table, "telegram_updates", "telegram.updates", &mut changes);
+
+    changes
+}
+
+fn version_error(path: &Path, message: String) -> ConfigError {
+    ConfigError::Parse {
+        path: path.to_owned(),
+        key: Some("version".to_owned()),
+        position: None,
+        message,
+    }
+}
+
+/// Upgrades table of older layout in place, returns descriptions of the changes
+fn migrate(path: &Path, table: &mut Table) -> Result<Vec<String>, ConfigError> {
+    let version = match table.get("version") {
+        None => UNVERSIONED,
+        Some(Value::Integer(version)) => *version,
+        Some(_) =>
+            return Err(version_error(
+                path,
+                "version has to be an integer".to_owned(),
+            )),
+    };
+
+    if !(UNVERSIONED..=CURRENT_VERSION).contains(&version) {
+        return Err(version_error(
+            path,
+            format!(
+                "unsupported version {}, this release understands versions {} to {}",
+                version, UNVERSIONED, CURRENT_VERSION
+            ),
+        ));
+    }
+
+    let changes = MIGRATIONS[(version - UNVERSIONED) as usize..]
+        .iter()
+        .flat_map(|migration| migration(table))
+        .collect();
+    table.insert("version".to_owned(), Value::Integer(CURRENT_VERSION));
+
+    Ok(changes)
+}
+
+fn parse<T: DeserializeOwned>(path: &Path, text: &str) -> Result<T, ConfigError> {
+    serde_path_to_error::deserialize(toml::Deserializer::new(text)).map_err(|err| {
+        let key = Some(err.path().to_string()).filter(|key| key != ".");
+        let err = err.into_inner();
+
+        ConfigError::Parse {
+            path: path.to_owned(),
+            key,
+            position: err.span().map(|span| line_column(text, span.start)),
+            message: err.message().to_owned(),
+        }
+    })
+}
+
+/// Nulls mean absent keys, TOML has no such value
+fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => serde_json::Value::Object(
+            object
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect(),
+        ),
+        serde_json::Value::Array(array) =>
+            serde_json::Value::Array(array.into_iter().map(without_nulls).collect()),
+        value => value,
+    }
+}
+
+/// YAML and JSON are converted to TOML table, so migrations and defaults work the same for them
+fn parse_table(path: &Path, text: &str) -> Result<Table, ConfigError> {
+    let parse_error = |position, message: String| ConfigError::Parse {
+        path: path.to_owned(),
+        key: None,
+        position,
+        message,
+    };
+
+    let value = match Format::of(path) {
+        Format::Toml => return parse(path, text),
+        Format::Yaml => serde_yaml::from_str::<serde_json::Value>(text).map_err(|err| {
+            parse_error(
+                err.location()
+                    .map(|location| (location.line(), location.column())),
+                err.to_string(),
+            )
+        })?,
+        Format::Json => serde_json::from_str::<serde_json::Value>(text)
+            .map_err(|err| parse_error(Some((err.line(), err.column())), err.to_string()))?,
+    };
+
+    match Value::try_from(without_nulls(value)) {
+        Ok(Value::Table(table)) => Ok(table),
+        Ok(_) => Err(parse_error(None, "config has to be a mapping".to_owned())),
+        Err(err) => Err(parse_error(None, err.to_string())),
+    }
+}
+
+/// Config can be taken from HTTP server, e.g. Consul KV with `?raw`
+pub fn is_remote(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Path part of the URL, its extension tells the format
+pub fn remote_path(url: &str) -> &Path {
+    Path::new(url.split(['?', '#']).next().unwrap_or(url))
+}
+
+pub async fn fetch(url: &str) -> Result<String, ConfigError> {
+    let fetch_error = |message: String| ConfigError::Fetch {
+        url: url.to_owned(),
+        message,
+    };
+
+    let mut request = reqwest::Client::new().get(url).timeout(FETCH_TIMEOUT);
+    if let Ok(token) = std::env::var(CONFIG_TOKEN_VARIABLE) {
+        request = request.bearer_auth(token);
+    }
+
+    request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| fetch_error(err.to_string()))?
+        .text()
+        .await
+        .map_err(|err| fetch_error(err.to_string()))
+}
+
+/// Loads config from file or from URL
+pub async fn load_source<T: DeserializeOwned>(source: &str) -> Result<T, ConfigError> {
+    if is_remote(source) {
+        from_text(remote_path(source), &fetch(source).await?)
+    } else {
+        load(source.as_ref())
+    }
+}
+
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_owned(),
+        source,
+    })?;
+
+    from_text(path, &text)
+}
+
+/// Copies keys of `[defaults]` into every topic that doesn't set them, returns whether there
+/// were defaults at all
+fn apply_defaults(path: &Path, table: &mut Table) -> Result<bool, ConfigError> {
+    let defaults = match table.remove("defaults") {
+        Some(Value::Table(defaults)) => defaults,
+        Some(_) =>
+            return Err(ConfigError::Parse {
+                path:     path.to_owned(),
+                key:      Some("defaults".to_owned()),
+                position: None,
+                message:  "defaults has to be a table".to_owned(),
+            }),
+        None => return Ok(false),
+    };
+
+    if let Some(Value::Table(topics)) = table.get_mut("topics") {
+        for (_, topic_info) in topics.iter_mut() {
+            if let Value::Table(topic_info) = topic_info {
+                for (key, value) in &defaults {
+                    if !topic_info.contains_key(key) {
+                        topic_info.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Location of a secret value relative to the root table followed by its key
+fn secret_locations(table: &Table) -> Vec<(Vec<String>, &'static str)> {
+    let mut locations = vec![
+        (Vec::new(), "admin_token"),
+        (vec!["telegram".to_owned()], "secret"),
+        (vec!["lines".to_owned()], "secret"),
+        (vec!["compliance".to_owned()], "signing_key"),
+        // Proxy URLs can carry credentials
+        (Vec::new(), "proxy"),
+        (vec!["telegram".to_owned(), "http".to_owned()], "proxy"),
+    ];
+
+    if let Some(Value::Table(admin_tokens)) = table.get("admin_tokens") {
+        for name in admin_tokens.keys() {
+            locations.push((vec!["admin_tokens".to_owned(), name.clone()], "token"));
+        }
+    }
+
+    if let Some(Value::Table(topics)) = table.get("topics") {
+        for topic_name in topics.keys() {
+            let topic = vec!["topics".to_owned(), topic_name.clone()];
+            for key in TOPIC_SECRETS {
+                locations.push((topic.clone(), key));
+            }
+        }
+    }
+
+    locations
+}
+
+/// Relative paths point into credentials directory when running as systemd unit with
+/// `LoadCredential`
+fn secret_path(file: &str) -> PathBuf {
+    match std::env::var_os(CREDENTIALS_DIRECTORY_VARIABLE) {
+        Some(directory) if Path::new(file).is_relative() => Path::new(&directory).join(file),
+        _ => PathBuf::from(file),
+    }
+}
+
+/// Replaces `<key>_file` of secrets with contents of the file, returns whether there were any
+fn read_secret_files(path: &Path, table: &mut Table) -> Result<bool, ConfigError> {
+    let mut has_files = false;
+
+    for (parent, key) in secret_locations(table) {
+        let secret_error = |message: String| ConfigError::Parse {
+            path: path.to_owned(),
+            key: Some(
+                parent
+                    .iter()
+                    .map(String::as_str)
+                    .chain([format!("{}_file", key).as_str()])
+                    .collect::<Vec<_>>()
+                    .join("."),
+            ),
+            position: None,
+            message,
+        };
+
+        let parent_table = match parent.iter().try_fold(&mut *table, |table, segment| {
+            match table.get_mut(segment) {
+                Some(Value::Table(child)) => Some(child),
+                _ => None,
+            }
+        }) {
+            Some(parent_table) => parent_table,
+            None => continue,
+        };
+
+        let file = match parent_table.remove(&format!("{}_file", key)) {
+            Some(Value::String(file)) => file,
+            Some(_) => return Err(secret_error("has to be a path".to_owned())),
+            None => continue,
+        };
+        if parent_table.contains_key(key) {
+            return Err(secret_error(format!("can't be used along with `{}`", key)));
+        }
+
+        let file = secret_path(&file);
+        let secret = std::fs::read_to_string(&file)
+            .map_err(|err| secret_error(format!("failed to read {}: {}", file.display(), err)))?;
+        // Files written by editors and `kubectl create secret --from-file` end with newline
+        parent_table.insert(
+            key.to_owned(),
+            Value::String(secret.trim_end_matches(['\n', '\r']).to_owned()),
+        );
+        has_files = true;
+    }
+
+    Ok(has_files)
+}
+
+/// Empty secret would be what requests without one provide
+fn reject_empty_secrets(path: &Path, table: &Table) -> Result<(), ConfigError> {
+    for (parent, key) in secret_locations(table) {
+        // Proxy URLs are secret for credentials they may carry, not to authenticate anyone
+        if key == "proxy" {
+            continue;
+        }
+
+        let value = parent
+            .iter()
+            .try_fold(table, |table, segment| match table.get(segment) {
+                Some(Value::Table(child)) => Some(child),
+                _ => None,
+            })
+            .and_then(|parent_table| parent_table.get(key));
+        if matches!(value, Some(Value::String(secret)) if secret.trim().is_empty()) {
+            return Err(ConfigError::Parse {
+                path:     path.to_owned(),
+                key:      Some(
+                    parent
+                        .iter()
+                        .map(String::as_str)
+                        .chain([key])
+                        .collect::<Vec<_>>()
+                        .join("."),
+                ),
+                position: None,
+                message:  "can't be empty".to_owned(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses text of config file at the path in the format told by its extension, migrating older
+/// layouts and applying defaults
+pub fn from_text<T: DeserializeOwned>(path: &Path, text: &str) -> Result<T, ConfigError> {
+    let mut table = parse_table(path, text)?;
+    let changes = migrate(path, &mut table)?;
+    let has_defaults = apply_defaults(path, &mut table)?;
+    let has_secret_files = read_secret_files(path, &mut table)?;
+    reject_empty_secrets(path, &table)?;
+    if Format::of(path) == Format::Toml && changes.is_empty() && !has_defaults && !has_secret_files
+    {
+        // Parsing the text again keeps positions in error messages
+        return parse(path, text);
+    }
+
+    if !changes.is_empty() {
+        log_migration(path, &changes);
+    }
+
+    serde_path_to_error::deserialize(Value::Table(table)).map_err(|err| ConfigError::Parse {
+        path:     path.to_owned(),
+        key:      Some(err.path().to_string()).filter(|key| key != "."),
+        position: None,
+        message:  err.into_inner().message().to_owned(),
+    })
+}
+
+fn log_migration(path: &Path, changes: &[String]) {
+    for change in changes {
+        log::warn!("{}: {}", path.display(), change);
+    }
+    log::warn!(
+        "{} has older layout and was migrated at load time, set `version = {}` and apply the \
+         changes above to the file",
+        path.display(),
+        CURRENT_VERSION
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(text: &str) -> Table {
+        parse_table(Path::new("microphone.toml"), text).unwrap()
+    }
+
+    fn migrated(text: &str) -> Result<(Table, Vec<String>), String> {
+        let mut table = table(text);
+        let changes =
+            migrate(Path::new("microphone.toml"), &mut table).map_err(|err| err.to_string())?;
+
+        Ok((table, changes))
+    }
+
+    #[test]
+    fn flat_layout_moves_to_tables() {
+        let (table, changes) = migrated(
+            r#"
+            port = 8080
+            trusted_proxies = ["10.0.0.0/8"]
+            proxy_protocol = true
+            secret = "123:abc"
+            telegram_updates = "polling"
+
+            [topics.ops]
+            recipients = ["111"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            table,
+            self::table(
+                r#"
+                version = 2
+                [server]
+                port = 8080
+                trusted_proxies = ["10.0.0.0/8"]
+                proxy_protocol = true
+                [telegram]
+                secret = "123:abc"
+                updates = "polling"
+                [topics.ops]
+                recipients = ["111"]
+                "#
+            )
+        );
+        assert_eq!(
+            changes,
+            [
+                "moved `port` to `server.port`",
+                "moved `trusted_proxies` to `server.trusted_proxies`",
+                "moved `proxy_protocol` to `server.proxy_protocol`",
+                "moved `secret` to `telegram.secret`",
+                "moved `telegram_updates` to `telegram.updates`",
+            ]
+        );
+    }
+
+    #[test]
+    fn flat_keys_join_tables_already_there() {
+        let (table, changes) = migrated(
+            r#"
+            port = 8080
+            [telegram]
+            secret = "123:abc"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            table,
+            self::table("version = 2\n[server]\nport = 8080\n[telegram]\nsecret = \"123:abc\"")
+        );
+        assert_eq!(changes, ["moved `port` to `server.port`"]);
+    }
+
+    #[test]
+    fn current_layout_is_left_as_it_is() {
+        let text = "version = 2\n[server]\nport = 8080\n[telegram]\nsecret = \"123:abc\"";
+
+        assert_eq!(migrated(text).unwrap(), (table(text), Vec::new()));
+        // Partial files of the old layout have nothing to move
+        assert_eq!(
+            migrated("[topics.ops]\nrecipients = [\"111\"]").unwrap(),
+            (
+                table("version = 2\n[topics.ops]\nrecipients = [\"111\"]"),
+                Vec::new()
+            )
+        );
+    }
+
+    #[test]
+    fn flat_keys_conflicting_with_tables_are_left_to_deserialization() {
+        let (table, _) = migrated("secret = \"123:abc\"\ntelegram = \"yes\"").unwrap();
+
+        assert_eq!(table["telegram"].as_str(), Some("yes"));
+    }
+
+    #[test]
+    fn rejects_unknown_and_malformed_versions() {
+        assert_eq!(
+            migrated("version = 3").unwrap_err(),
+            "microphone.toml: `version`: unsupported version 3, this release understands \
+             versions 1 to 2"
+        );
+        assert_eq!(
+            migrated("version = 0").unwrap_err(),
+            "microphone.toml: `version`: unsupported version 0, this release understands \
+             versions 1 to 2"
+        );
+        assert_eq!(
+            migrated("version = \"2\"").unwrap_err(),
+            "microphone.toml: `version`: version has to be an integer"
+        );
+    }
+
+    #[test]
+    fn truncated_files_fail_to_parse_before_migration() {
+        let err = parse_table(Path::new("microphone.toml"), "port = 8080\n[server")
+            .err()
+            .unwrap();
+
+        assert!(
+            matches!(
+                err,
+                ConfigError::Parse {
+                    position: Some((2, _)),
+                    ..
+                }
+            ),
+            "{}",
+            err
+        );
+    }
+}
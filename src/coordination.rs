@@ -0,0 +1,222 @@
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use actix_web::rt::time::sleep;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// Unknown keys are rejected by the backend, `deny_unknown_fields` doesn't work with `flatten`
+#[derive(Deserialize)]
+pub struct CoordinationConfig {
+    #[serde(flatten)]
+    backend:       LeaseBackend,
+    /// Lease is renewed every third of this period, another replica takes over once it expires
+    #[serde(default = "default_lease_seconds")]
+    lease_seconds: u64,
+}
+
+fn default_lease_seconds() -> u64 {
+    15
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase", deny_unknown_fields)]
+enum LeaseBackend {
+    /// Lease file on storage shared between replicas
+    File { path: PathBuf },
+    #[cfg(feature = "redis")]
+    Redis {
+        url: String,
+        #[serde(default = "default_redis_key")]
+        key: String,
+    },
+}
+
+#[cfg(feature = "redis")]
+fn default_redis_key() -> String {
+    "microphone:leader".to_owned()
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileLease {
+    holder:     String,
+    expires_at: u64,
+}
+
+/// Decides which replica runs background work (heartbeat checks, digests, scheduled sends, retries)
+pub struct Coordinator {
+    is_leader: AtomicBool,
+}
+
+impl Coordinator {
+    /// Single instance deployment, always the leader
+    pub fn standalone() -> Arc<Self> {
+        Arc::new(Self {
+            is_leader: AtomicBool::new(true),
+        })
+    }
+
+    pub fn spawn(config: CoordinationConfig) -> Arc<Self> {
+        let coordinator = Arc::new(Self {
+            is_leader: AtomicBool::new(false),
+        });
+
+        let holder = holder_id();
+        let lease = Duration::from_secs(config.lease_seconds);
+        log::info!("Competing for leadership as \"{}\"", holder);
+
+        let task_coordinator = coordinator.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                let acquired = match config.backend.try_acquire(&holder, lease).await {
+                    Ok(acquired) => acquired,
+                    Err(err) => {
+                        log::error!("Failed to renew leadership lease: {}", err);
+                        false
+                    }
+                };
+
+                let was_leader = task_coordinator.is_leader.swap(acquired, Ordering::SeqCst);
+                match (was_leader, acquired) {
+                    (false, true) => log::info!("Became leader, running background work"),
+                    (true, false) => log::warn!("Lost leadership, background work paused"),
+                    _ => (),
+                }
+
+                sleep(lease / 3).await;
+            }
+        });
+
+        coordinator
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::SeqCst)
+    }
+}
+
+/// Name of the machine microphone runs on
+pub fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|hostname| hostname.trim().to_owned())
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
+
+fn holder_id() -> String {
+    format!("{}-{}", hostname(), std::process::id())
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before unix epoch")
+        .as_millis() as u64
+}
+
+impl LeaseBackend {
+    async fn try_acquire(&self, holder: &str, lease: Duration) -> Result<bool, String> {
+        match self {
+            Self::File { path } => {
+                let path = path.clone();
+                let holder = holder.to_owned();
+                actix_web::rt::task::spawn_blocking(move || {
+                    try_acquire_file_lease(&path, &holder, lease)
+                })
+                .await
+                .map_err(|err| err.to_string())?
+            }
+            #[cfg(feature = "redis")]
+            Self::Redis { url, key } => try_acquire_redis_lease(url, key, holder, lease)
+                .await
+                .map_err(|err| err.to_string()),
+        }
+    }
+}
+
+fn try_acquire_file_lease(path: &Path, holder: &str, lease: Duration) -> Result<bool, String> {
+    let now = now_millis();
+
+    let current = match std::fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str::<FileLease>(&content).ok(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.to_string()),
+    };
+
+    if let Some(current) = current {
+        if current.holder != holder && current.expires_at > now {
+            return Ok(false);
+        }
+    }
+
+    let new_lease = FileLease {
+        holder:     holder.to_owned(),
+        expires_at: now + lease.as_millis() as u64,
+    };
+    let temporary_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(
+        &temporary_path,
+        serde_json::to_string(&new_lease).expect("Failed to serialize lease"),
+    )
+    .map_err(|err| err.to_string())?;
+    std::fs::rename(&temporary_path, path).map_err(|err| err.to_string())?;
+
+    // Another replica could have replaced the file between our read and rename
+    let written = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    Ok(serde_json::from_str::<FileLease>(&written)
+        .map(|lease| lease.holder == holder)
+        .unwrap_or(false))
+}
+
+#[cfg(feature = "redis")]
+async fn try_acquire_redis_lease(
+    url: &str,
+    key: &str,
+    holder: &str,
+    lease: Duration,
+) -> redis::RedisResult<bool> {
+    const ACQUIRE_OR_RENEW: &str = r#"
+        local holder = redis.call('GET', KEYS[1])
+        if holder == false then
+            redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+            return 1
+        end
+        if holder == ARGV[1] then
+            redis.call('PEXPIRE', KEYS[1], ARGV[2])
+            return 1
+        end
+        return 0
+    "#;
+
+    let client = redis::Client::open(url)?;
+    let mut connection = client.get_async_connection().await?;
+
+    let acquired: i64 = redis::cmd("EVAL")
+        .arg(ACQUIRE_OR_RENEW)
+        .arg(1)
+        .arg(key)
+        .arg(holder)
+        .arg(lease.as_millis() as u64)
+        .query_async(&mut connection)
+        .await?;
+
+    Ok(acquired == 1)
+}
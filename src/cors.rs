@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use actix_web::{
+    dev::{
+        ServiceRequest,
+        ServiceResponse,
+    },
+    http::{
+        header::{
+            self,
+            HeaderMap,
+            HeaderValue,
+        },
+        Method,
+    },
+    web,
+    HttpResponse,
+};
+
+use crate::LiveTopics;
+
+/// Methods topics are posted to with
+const ALLOWED_METHODS: &str = "POST, GET";
+/// Browsers ask again after this many seconds
+const MAX_AGE: &str = "600";
+/// Allowed by `cors_origins` of a topic, any origin is allowed
+const ANY_ORIGIN: &str = "*";
+
+/// Origin of the request if the topic it's posted to allows pages of it to post, requests to
+/// `/{topic}/...` are the ones browsers make
+pub fn allowed_origin(request: &ServiceRequest) -> Option<HeaderValue> {
+    let origin = request.headers().get(header::ORIGIN)?;
+    let topic_name = request.path().trim_start_matches('/').split('/').next()?;
+    let topics = request.app_data::<web::Data<Arc<LiveTopics>>>()?.current();
+    let topic_info = topics.get(topic_name)?;
+
+    let origin_str = origin.to_str().ok()?;
+    topic_info
+        .cors_origins
+        .iter()
+        .any(|allowed| allowed == ANY_ORIGIN || allowed.eq_ignore_ascii_case(origin_str))
+        .then(|| origin.clone())
+}
+
+pub fn is_preflight(request: &ServiceRequest) -> bool {
+    request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Preflight is answered without reaching handlers, access is checked on the request itself
+pub fn preflight(request: ServiceRequest, origin: HeaderValue) -> ServiceResponse {
+    let mut response = HttpResponse::NoContent();
+    response
+        .insert_header((
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static(ALLOWED_METHODS),
+        ))
+        .insert_header((
+            header::ACCESS_CONTROL_MAX_AGE,
+            HeaderValue::from_static(MAX_AGE),
+        ));
+    // Headers the page asked for, like `Authorization` or `X-Severity`
+    if let Some(headers) = request
+        .headers()
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+    {
+        response.insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, headers.clone()));
+    }
+
+    let mut response = request.into_response(response.finish());
+    allow(response.headers_mut(), origin);
+    response
+}
+
+/// Lets the page read the response, with its request id
+pub fn allow(headers: &mut HeaderMap, origin: HeaderValue) {
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static("x-request-id"),
+    );
+    headers.append(header::VARY, HeaderValue::from_static("origin"));
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    admin::{
+        Operator,
+        Viewer,
+    },
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    TgClient,
+};
+
+/// Parts of descriptions Telegram rejects requests with when the bot can't write to the chat
+/// anymore, e.g. "Forbidden: bot was kicked from the group chat" or "Bad Request: chat not found"
+const TELEGRAM_DEFUNCT_ERRORS: [&str; 5] = [
+    "bot was kicked",
+    "chat not found",
+    "bot was blocked by the user",
+    "bot is not a member",
+    "user is deactivated",
+];
+
+/// Recipient the bot can't write to anymore
+#[derive(Clone)]
+#[derive(Serialize)]
+pub struct Defunct {
+    recipient: String,
+    chat_id:   String,
+    /// Description Telegram rejected the delivery with
+    reason:    String,
+    since:     DateTime<Utc>,
+}
+
+/// Recipients left out of deliveries since Telegram said their chats are gone for the bot, until
+/// the bot is added back or they are restored with admin API
+#[derive(Default)]
+pub struct DefunctRecipients {
+    recipients: Mutex<BTreeMap<String, Defunct>>,
+}
+
+impl DefunctRecipients {
+    pub fn contains(&self, recipient: &str) -> bool {
+        self.recipients
+            .lock()
+            .expect("Defunct recipients lock is poisoned")
+            .contains_key(recipient)
+    }
+
+    /// False if the recipient is defunct already
+    pub fn mark(&self, recipient: &str, chat_id: &str, reason: &str) -> bool {
+        let mut recipients = self
+            .recipients
+            .lock()
+            .expect("Defunct recipients lock is poisoned");
+        if recipients.contains_key(recipient) {
+            return false;
+        }
+
+        recipients.insert(
+            recipient.to_owned(),
+            Defunct {
+                recipient: recipient.to_owned(),
+                chat_id:   chat_id.to_owned(),
+                reason:    reason.to_owned(),
+                since:     Utc::now(),
+            },
+        );
+        true
+    }
+
+    /// Telegram told the bot about the chat again, so it's back in it. Restored recipients are
+    /// returned
+    pub fn restore_chat(&self, chat_id: i64) -> Vec<String> {
+        let chat_id = chat_id.to_string();
+        let mut recipients = self
+            .recipients
+            .lock()
+            .expect("Defunct recipients lock is poisoned");
+        let restored = recipients
+            .values()
+            .filter(|defunct| defunct.chat_id == chat_id)
+            .map(|defunct| defunct.recipient.clone())
+            .collect::<Vec<_>>();
+        for recipient in &restored {
+            recipients.remove(recipient);
+        }
+
+        restored
+    }
+
+    fn list(&self) -> Vec<Defunct> {
+        self.recipients
+            .lock()
+            .expect("Defunct recipients lock is poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Description of the rejection if it means the bot won't get into the chat by retrying
+pub fn reason(body: &[u8]) -> Option<String> {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|body| body["description"].as_str().map(str::to_owned))
+        .filter(|description| {
+            let description = description.to_lowercase();
+            TELEGRAM_DEFUNCT_ERRORS
+                .iter()
+                .any(|error| description.contains(error))
+        })
+}
+
+pub async fn list_defunct(_: Viewer, tg_client: web::Data<Arc<TgClient>>) -> impl Responder {
+    HttpResponse::Ok().json(tg_client.defunct.list())
+}
+
+#[derive(Deserialize)]
+pub struct DefunctPath {
+    recipient: String,
+}
+
+/// Recipient is delivered to again, e.g. after the bot was added back to a chat it doesn't get
+/// updates of
+pub async fn restore(
+    _: Operator,
+    tg_client: web::Data<Arc<TgClient>>,
+    path_data: web::Path<DefunctPath>,
+) -> impl Responder {
+    match tg_client
+        .defunct
+        .recipients
+        .lock()
+        .expect("Defunct recipients lock is poisoned")
+        .remove(&path_data.recipient)
+    {
+        Some(_) => {
+            log::info!("Recipient {} was restored", path_data.recipient);
+            HttpResponse::NoContent().finish()
+        }
+        None => HttpResponse::from(ApiError::new(
+            ErrorCode::RecipientNotFound,
+            "No such defunct recipient",
+        )),
+    }
+}
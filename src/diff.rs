@@ -0,0 +1,314 @@
+/// Unchanged lines shown around changes
+const CONTEXT_LINES: usize = 3;
+/// Past this many edits files are shown as removed and added as a whole, tracing the shortest
+/// edit script takes memory quadratic in it
+const MAX_EDIT_DISTANCE: usize = 2000;
+
+enum Edit<'a> {
+    Keep(&'a str),
+    Remove(&'a str),
+    Add(&'a str),
+}
+
+impl Edit<'_> {
+    fn is_change(&self) -> bool {
+        !matches!(self, Self::Keep(_))
+    }
+}
+
+/// Diff in unified format like `diff -u` produces, empty if texts have the same lines
+pub fn unified(before_name: &str, before: &str, after_name: &str, after: &str) -> String {
+    let before = before.lines().collect::<Vec<_>>();
+    let after = after.lines().collect::<Vec<_>>();
+    let edits = edits(&before, &after);
+
+    let changes = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, edit)| edit.is_change())
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Line numbers in both texts where each edit starts
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut before_line, mut after_line) = (0, 0);
+    for edit in &edits {
+        positions.push((before_line, after_line));
+        match edit {
+            Edit::Keep(_) => {
+                before_line += 1;
+                after_line += 1;
+            }
+            Edit::Remove(_) => before_line += 1,
+            Edit::Add(_) => after_line += 1,
+        }
+    }
+    positions.push((before_line, after_line));
+
+    let mut diff = format!("--- {}\n+++ {}\n", before_name, after_name);
+    let mut group_start = changes[0];
+    for (index, &change) in changes.iter().enumerate() {
+        let is_last = match changes.get(index + 1) {
+            Some(&next) => next - change > 2 * CONTEXT_LINES,
+            None => true,
+        };
+        if !is_last {
+            continue;
+        }
+
+        let start = group_start.saturating_sub(CONTEXT_LINES);
+        let end = (change + CONTEXT_LINES + 1).min(edits.len());
+        let (before_start, after_start) = positions[start];
+        let (before_end, after_end) = positions[end];
+        diff.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(before_start, before_end - before_start),
+            range(after_start, after_end - after_start)
+        ));
+        for edit in &edits[start..end] {
+            let (prefix, line) = match edit {
+                Edit::Keep(line) => (' ', line),
+                Edit::Remove(line) => ('-', line),
+                Edit::Add(line) => ('+', line),
+            };
+            diff.push(prefix);
+            diff.push_str(line);
+            diff.push('\n');
+        }
+
+        if let Some(&next) = changes.get(index + 1) {
+            group_start = next;
+        }
+    }
+
+    diff
+}
+
+/// Hunk range, empty ranges point at the line before them
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// Shortest edit script by Myers' algorithm
+fn edits<'a>(before: &[&'a str], after: &[&'a str]) -> Vec<Edit<'a>> {
+    let (n, m) = (before.len() as isize, after.len() as isize);
+    let offset = n + m + 1;
+    let mut furthest = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+    let at = |k: isize| (offset + k) as usize;
+
+    'search: for d in 0..=(n + m) {
+        if d as usize > MAX_EDIT_DISTANCE {
+            return before
+                .iter()
+                .map(|line| Edit::Remove(line))
+                .chain(after.iter().map(|line| Edit::Add(line)))
+                .collect();
+        }
+        trace.push(furthest[at(-d)..=at(d)].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && furthest[at(k - 1)] < furthest[at(k + 1)]) {
+                furthest[at(k + 1)]
+            } else {
+                furthest[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && before[x as usize] == after[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[at(k)] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let (mut x, mut y) = (n, m);
+    let mut edits = Vec::new();
+    for (d, furthest) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| furthest[(k + d) as usize];
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = if d == 0 { 0 } else { get(previous_k) };
+        let previous_y = previous_x - previous_k;
+
+        while x > previous_x && y > previous_y {
+            edits.push(Edit::Keep(before[x as usize - 1]));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == previous_x {
+                edits.push(Edit::Add(after[y as usize - 1]));
+                y -= 1;
+            } else {
+                edits.push(Edit::Remove(before[x as usize - 1]));
+                x -= 1;
+            }
+        }
+    }
+    edits.reverse();
+
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines numbered from 1, ones given replaced
+    fn numbered(count: usize, replaced: &[(usize, &str)]) -> String {
+        (1..=count)
+            .map(|number| {
+                let line = replaced
+                    .iter()
+                    .find(|(at, _)| *at == number)
+                    .map_or_else(|| number.to_string(), |(_, line)| line.to_string());
+                format!("{}\n", line)
+            })
+            .collect()
+    }
+
+    /// Hunks as `diff -u` of GNU diffutils prints them
+    fn hunks(lines: &[&str]) -> String {
+        let mut diff = "--- before.txt\n+++ after.txt\n".to_owned();
+        for line in lines {
+            diff.push_str(line);
+            diff.push('\n');
+        }
+
+        diff
+    }
+
+    fn diff(before: &str, after: &str) -> String {
+        unified("before.txt", before, "after.txt", after)
+    }
+
+    #[test]
+    fn same_lines_give_no_diff() {
+        assert_eq!(diff("", ""), "");
+        assert_eq!(diff(&numbered(5, &[]), &numbered(5, &[])), "");
+    }
+
+    #[test]
+    fn distant_changes_go_to_hunks_of_their_own() {
+        let after = numbered(20, &[(5, "five"), (17, "seventeen")]);
+
+        assert_eq!(
+            diff(&numbered(20, &[]), &after),
+            hunks(&[
+                "@@ -2,7 +2,7 @@",
+                " 2",
+                " 3",
+                " 4",
+                "-5",
+                "+five",
+                " 6",
+                " 7",
+                " 8",
+                "@@ -14,7 +14,7 @@",
+                " 14",
+                " 15",
+                " 16",
+                "-17",
+                "+seventeen",
+                " 18",
+                " 19",
+                " 20",
+            ])
+        );
+    }
+
+    #[test]
+    fn close_changes_share_hunk() {
+        let after = numbered(10, &[(3, "three"), (9, "nine")]);
+
+        assert_eq!(
+            diff(&numbered(10, &[]), &after),
+            hunks(&[
+                "@@ -1,10 +1,10 @@",
+                " 1",
+                " 2",
+                "-3",
+                "+three",
+                " 4",
+                " 5",
+                " 6",
+                " 7",
+                " 8",
+                "-9",
+                "+nine",
+                " 10",
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_texts_are_added_and_removed_whole() {
+        assert_eq!(
+            diff("", "a\nb\nc\n"),
+            hunks(&["@@ -0,0 +1,3 @@", "+a", "+b", "+c"])
+        );
+        assert_eq!(
+            diff("a\nb\nc\n", ""),
+            hunks(&["@@ -1,3 +0,0 @@", "-a", "-b", "-c"])
+        );
+    }
+
+    #[test]
+    fn truncated_text_loses_its_tail() {
+        assert_eq!(
+            diff(&numbered(10, &[]), &numbered(6, &[])),
+            hunks(&["@@ -4,7 +4,3 @@", " 4", " 5", " 6", "-7", "-8", "-9", "-10"])
+        );
+    }
+
+    #[test]
+    fn line_endings_and_missing_last_newline_are_not_changes() {
+        assert_eq!(diff("a\nb\nc\n", "a\r\nb\r\nc"), "");
+        assert_eq!(
+            diff("a\nb\nc", "a\r\nB\r\nc\r\n"),
+            hunks(&["@@ -1,3 +1,3 @@", " a", "-b", "+B", " c"])
+        );
+    }
+
+    #[test]
+    fn too_different_texts_are_replaced_whole() {
+        // Common first line is kept only while edits are within the limit
+        let lines = |prefix: &str, count: usize| {
+            (0..count)
+                .map(|line| format!("{}{}\n", prefix, line))
+                .collect::<String>()
+        };
+        let count = MAX_EDIT_DISTANCE / 2;
+
+        let within = diff(
+            &format!("same\n{}", lines("a", count)),
+            &format!("same\n{}", lines("b", count)),
+        );
+        assert!(within.starts_with(&hunks(&["@@ -1,1001 +1,1001 @@", " same", "-a0"])));
+
+        let count = count + 1;
+        let over = diff(
+            &format!("same\n{}", lines("a", count)),
+            &format!("same\n{}", lines("b", count)),
+        );
+        assert!(over.starts_with(&hunks(&["@@ -1,1002 +1,1002 @@", "-same", "-a0"])));
+        assert!(over.contains(&format!("-a{}\n+same\n+b0\n", count - 1)));
+    }
+}
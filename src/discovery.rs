@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use actix_web::{
+    web,
+    HttpRequest,
+    HttpResponse,
+    Responder,
+};
+use serde::Serialize;
+
+use crate::{
+    access::AllowedTopic,
+    upload::Uploads,
+    MAX_BODY_SIZE,
+};
+
+/// What a producer may send to a topic, recipients and secrets are left out
+#[derive(Serialize)]
+struct Capabilities<'a> {
+    name:                 &'a str,
+    content_types:        Vec<&'static str>,
+    attachments:          bool,
+    /// Bytes of a request body, of the API key if it allows less
+    max_body_size:        u64,
+    /// Bounds of multipart requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_fields:           Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_field_size:       Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_messages_per_day: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_bytes_per_day:    Option<u64>,
+}
+
+/// Topics the client is allowed to post to. Without credentials it's the topics its address is
+/// allowed to, with a token or API key the ones they allow too
+pub async fn list_topics(request: HttpRequest, uploads: web::Data<Arc<Uploads>>) -> impl Responder {
+    let (topics, caller) = match AllowedTopic::all_of(&request).await {
+        Ok(allowed) => allowed,
+        Err(err) => return HttpResponse::from(err),
+    };
+    let scope = caller.api_key.as_ref().map(|api_key| &api_key.scope);
+    let attachments = scope.is_none_or(|scope| scope.attachments);
+    let max_body_size = scope
+        .and_then(|scope| scope.max_size)
+        .map_or(MAX_BODY_SIZE as u64, |max_size| {
+            max_size.min(MAX_BODY_SIZE as u64)
+        });
+
+    let mut capabilities = topics
+        .iter()
+        .map(|topic| {
+            let limits = attachments.then(|| uploads.limits_of(&topic.info.upload_limits));
+            let mut content_types = vec!["text/plain", "application/x-www-form-urlencoded"];
+            if attachments {
+                content_types.push("multipart/form-data");
+            }
+
+            Capabilities {
+                name: &topic.name,
+                content_types,
+                attachments,
+                max_body_size,
+                max_fields: limits.map(|limits| limits.max_fields),
+                max_field_size: limits.map(|limits| limits.max_field_size),
+                max_messages_per_day: topic.info.max_messages_per_day,
+                max_bytes_per_day: topic.info.max_bytes_per_day,
+            }
+        })
+        .collect::<Vec<_>>();
+    capabilities.sort_by_key(|capabilities| capabilities.name);
+
+    HttpResponse::Ok().json(capabilities)
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+
+use actix_web::{
+    body::{
+        BodySize,
+        BoxBody,
+        MessageBody,
+    },
+    dev::{
+        ServiceRequest,
+        ServiceResponse,
+    },
+    http::{
+        header::{
+            self,
+            HeaderName,
+            HeaderValue,
+        },
+        StatusCode,
+    },
+    HttpResponse,
+    ResponseError,
+};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Stable identifiers of errors, clients are supposed to branch on these rather than messages
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    TopicNotFound,
+    BanNotFound,
+    RecipientGroupNotFound,
+    RecipientNotFound,
+    MessageNotFound,
+    MaintenanceWindowNotFound,
+    MessageQueued,
+    AdminApiDisabled,
+    WebhookDisabled,
+    ComplianceDisabled,
+    ApiKeysDisabled,
+    ApiKeyNotFound,
+    RemindersDisabled,
+    ReminderNotFound,
+    BroadcastDisabled,
+    InvalidToken,
+    InsufficientRole,
+    OutOfScope,
+    AddressBanned,
+    InvalidClientAddress,
+    InvalidSeverity,
+    InvalidMessage,
+    InvalidMultipart,
+    InvalidDuration,
+    InvalidCallbackUrl,
+    InvalidHeader,
+    MalformedPayload,
+    QuotaExceeded,
+    MultipartTooLarge,
+    UploadTimedOut,
+    HeadersTooLarge,
+    DeliveryFailed,
+    TelegramUnavailable,
+    TransformFailed,
+    // Errors that don't come from microphone itself, e.g. unknown routes or oversized bodies
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    InternalError,
+}
+
+impl ErrorCode {
+    fn status(self) -> StatusCode {
+        match self {
+            Self::TopicNotFound
+            | Self::BanNotFound
+            | Self::RecipientGroupNotFound
+            | Self::RecipientNotFound
+            | Self::MessageNotFound
+            | Self::MaintenanceWindowNotFound
+            | Self::AdminApiDisabled
+            | Self::WebhookDisabled
+            | Self::ComplianceDisabled
+            | Self::ApiKeysDisabled
+            | Self::ApiKeyNotFound
+            | Self::RemindersDisabled
+            | Self::ReminderNotFound
+            | Self::BroadcastDisabled
+            | Self::NotFound => StatusCode::NOT_FOUND,
+            Self::InvalidToken | Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::InsufficientRole | Self::OutOfScope | Self::AddressBanned | Self::Forbidden =>
+                StatusCode::FORBIDDEN,
+            Self::InvalidClientAddress
+            | Self::InvalidSeverity
+            | Self::InvalidMessage
+            | Self::InvalidMultipart
+            | Self::InvalidDuration
+            | Self::InvalidCallbackUrl
+            | Self::InvalidHeader
+            | Self::MalformedPayload
+            | Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::MessageQueued => StatusCode::CONFLICT,
+            Self::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            Self::MultipartTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UploadTimedOut => StatusCode::REQUEST_TIMEOUT,
+            Self::HeadersTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::DeliveryFailed | Self::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::TelegramUnavailable | Self::TransformFailed => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn of_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST => Self::BadRequest,
+            StatusCode::UNAUTHORIZED => Self::Unauthorized,
+            StatusCode::FORBIDDEN => Self::Forbidden,
+            StatusCode::NOT_FOUND => Self::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => Self::MethodNotAllowed,
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => Self::UnsupportedMediaType,
+            status if status.is_client_error() => Self::BadRequest,
+            _ => Self::InternalError,
+        }
+    }
+}
+
+/// Error replied with `{code, message, details, request_id}` JSON body
+#[derive(Debug)]
+pub struct ApiError {
+    code:    ErrorCode,
+    message: String,
+    details: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    code:       ErrorCode,
+    message:    &'a str,
+    details:    Option<&'a Value>,
+    request_id: Option<&'a str>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    fn body(&self, request_id: Option<&str>) -> String {
+        serde_json::to_string(&Envelope {
+            code: self.code,
+            message: &self.message,
+            details: self.details.as_ref(),
+            request_id,
+        })
+        .expect("Error envelope is always serializable")
+    }
+}
+
+/// Problem with one part of a request
+#[derive(Serialize)]
+struct Problem {
+    field:   String,
+    code:    ErrorCode,
+    message: String,
+}
+
+/// Problems found while validating a request, reported all at once so clients don't have to fix
+/// them one round-trip at a time
+#[derive(Default)]
+pub struct Problems(Vec<Problem>);
+
+impl Problems {
+    pub fn push(&mut self, field: impl Into<String>, error: ApiError) {
+        self.0.push(Problem {
+            field:   field.into(),
+            code:    error.code,
+            message: error.message,
+        });
+    }
+
+    /// Some problem is with the field
+    pub fn concern(&self, field: &str) -> bool {
+        self.0.iter().any(|problem| problem.field == field)
+    }
+
+    /// A single problem keeps its code, several are reported with the given one
+    pub fn check(self, code: ErrorCode) -> Result<(), ApiError> {
+        let (code, message) = match self.0.as_slice() {
+            [] => return Ok(()),
+            [problem] => (problem.code, problem.message.clone()),
+            problems => (code, format!("Request has {} problems", problems.len())),
+        };
+
+        Err(
+            ApiError::new(code, message).with_details(serde_json::json!({
+                "problems": self.0,
+            })),
+        )
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .content_type("application/json")
+            .body(self.body(None))
+    }
+}
+
+impl From<ApiError> for HttpResponse {
+    fn from(error: ApiError) -> Self {
+        HttpResponse::from_error(error)
+    }
+}
+
+/// Id of the request, taken from `X-Request-Id` header set by a reverse proxy or generated
+pub fn request_id(request: &ServiceRequest) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| {
+            !value.is_empty()
+                && value.len() <= 128
+                && value.bytes().all(|byte| byte.is_ascii_graphic())
+        })
+        .map(str::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// Echoes request id back and turns every error, including ones raised by actix itself,
+/// into the JSON envelope carrying that id. Error statuses without body, like 405 of the
+/// router, get the envelope too
+pub fn envelope(response: ServiceResponse, request_id: &str) -> ServiceResponse {
+    let status = response.status();
+    let reason = status.canonical_reason().unwrap_or("Unknown error");
+    let error = match response.response().error() {
+        Some(error) => Some(match error.as_error::<ApiError>() {
+            Some(api_error) => api_error.body(Some(request_id)),
+            None => {
+                let message = match error.to_string() {
+                    message if message.is_empty() => reason.to_owned(),
+                    message => message,
+                };
+
+                ApiError::new(ErrorCode::of_status(status), message).body(Some(request_id))
+            }
+        }),
+        None => ((status.is_client_error() || status.is_server_error())
+            && matches!(
+                response.response().body().size(),
+                BodySize::None | BodySize::Sized(0)
+            ))
+        .then(|| ApiError::new(ErrorCode::of_status(status), reason).body(Some(request_id))),
+    };
+
+    let mut response = match error {
+        Some(body) => response.map_body(|head, _| {
+            head.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            );
+            BoxBody::new(body)
+        }),
+        None => response,
+    };
+
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    response
+}
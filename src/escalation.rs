@@ -0,0 +1,411 @@
+use std::{
+    collections::HashMap,
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        self,
+        Write,
+    },
+    path::Path,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use actix_web::{
+    rt::time::interval,
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use humantime_serde::re::humantime::format_duration;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::json;
+
+use crate::{
+    admin::Viewer,
+    locale::{
+        Locale,
+        Phrase,
+    },
+    state,
+    LiveTopics,
+    SentMessage,
+    TgClient,
+    TgMarkdownString,
+    Topics,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Alerts nobody acknowledged even after escalation are forgotten eventually
+const PENDING_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+pub const ACK_CALLBACK_PREFIX: &str = "ack:";
+
+#[derive(Debug)]
+#[derive(Deserialize)]
+#[derive(Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EscalationConfig {
+    /// Critical messages nobody acknowledged within this period are sent again
+    #[serde(with = "humantime_serde")]
+    pub after:      Duration,
+    /// Notified on escalation in addition to topic recipients
+    #[serde(default)]
+    pub recipients: Vec<String>,
+}
+
+struct PendingAlert {
+    topic:     String,
+    sender:    String,
+    text:      String,
+    sent_at:   Instant,
+    escalated: bool,
+    pinned:    Vec<SentMessage>,
+}
+
+/// Pending alert as it's exported, with the id its Ack button carries
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Snapshot {
+    id:        u64,
+    topic:     String,
+    sender:    String,
+    text:      String,
+    sent_at:   DateTime<Utc>,
+    escalated: bool,
+    pinned:    Vec<SentMessage>,
+}
+
+/// Alert that stopped waiting for acknowledgement
+pub struct Acknowledged {
+    pub topic:  String,
+    sender:     String,
+    text:       String,
+    sent_at:    Instant,
+    escalated:  bool,
+    pub pinned: Vec<SentMessage>,
+}
+
+/// Line of `ack_log`
+#[derive(Serialize)]
+struct AckRecord<'a> {
+    id:              u64,
+    topic:           &'a str,
+    sender:          &'a str,
+    text:            &'a str,
+    sent_at:         DateTime<Utc>,
+    acknowledged_at: DateTime<Utc>,
+    acknowledged_by: &'a str,
+    latency_seconds: u64,
+    escalated:       bool,
+}
+
+/// Pending alert as `GET /admin/alerts/unacked` shows it
+#[derive(Serialize)]
+struct Unacknowledged<'a> {
+    id:                     u64,
+    topic:                  &'a str,
+    sender:                 &'a str,
+    text:                   &'a str,
+    sent_at:                DateTime<Utc>,
+    unacknowledged_seconds: u64,
+    escalated:              bool,
+}
+
+/// Critical messages waiting for someone to press Ack
+#[derive(Default)]
+pub struct Escalations {
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, PendingAlert>>,
+    /// Who pressed Ack of which alert and how long it took, one JSON object per line
+    ack_log: Option<Mutex<File>>,
+}
+
+impl Acknowledged {
+    /// Time from sending the alert to its acknowledgement
+    pub fn latency(&self) -> Duration {
+        self.sent_at.elapsed()
+    }
+}
+
+impl Escalations {
+    pub fn with_ack_log(mut self, path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.ack_log = Some(Mutex::new(file));
+
+        Ok(self)
+    }
+
+    /// Starts waiting for acknowledgement, returns id to put into Ack button
+    pub fn register(&self, topic: &str, sender: &str, text: &str) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        self.pending
+            .lock()
+            .expect("Escalations lock is poisoned")
+            .insert(
+                id,
+                PendingAlert {
+                    topic:     topic.to_owned(),
+                    sender:    sender.to_owned(),
+                    text:      text.to_owned(),
+                    sent_at:   Instant::now(),
+                    escalated: false,
+                    pinned:    Vec::new(),
+                },
+            );
+
+        id
+    }
+
+    pub fn attach_pinned(&self, id: u64, pinned: Vec<SentMessage>) {
+        if let Some(alert) = self
+            .pending
+            .lock()
+            .expect("Escalations lock is poisoned")
+            .get_mut(&id)
+        {
+            alert.pinned = pinned;
+        }
+    }
+
+    /// Returns the alert if it was still waiting for acknowledgement
+    pub fn acknowledge(&self, id: u64) -> Option<Acknowledged> {
+        self.pending
+            .lock()
+            .expect("Escalations lock is poisoned")
+            .remove(&id)
+            .map(|alert| Acknowledged {
+                topic:     alert.topic,
+                sender:    alert.sender,
+                text:      alert.text,
+                sent_at:   alert.sent_at,
+                escalated: alert.escalated,
+                pinned:    alert.pinned,
+            })
+    }
+
+    /// Appends the acknowledgement to `ack_log`, if there is one
+    pub fn log_ack(&self, id: u64, acknowledged: &Acknowledged, acknowledged_by: &str) {
+        let ack_log = match &self.ack_log {
+            Some(ack_log) => ack_log,
+            None => return,
+        };
+
+        let record = AckRecord {
+            id,
+            topic: &acknowledged.topic,
+            sender: &acknowledged.sender,
+            text: &acknowledged.text,
+            sent_at: state::wall_time(acknowledged.sent_at),
+            acknowledged_at: Utc::now(),
+            acknowledged_by,
+            latency_seconds: acknowledged.latency().as_secs(),
+            escalated: acknowledged.escalated,
+        };
+        let mut line = serde_json::to_string(&record).expect("Failed to serialize ack record");
+        line.push('\n');
+
+        let written = ack_log
+            .lock()
+            .expect("Ack log lock is poisoned")
+            .write_all(line.as_bytes());
+        if let Err(err) = written {
+            log::error!("Failed to write ack log: {}", err);
+        }
+    }
+
+    pub fn export(&self) -> Vec<Snapshot> {
+        self.pending
+            .lock()
+            .expect("Escalations lock is poisoned")
+            .iter()
+            .map(|(id, alert)| Snapshot {
+                id:        *id,
+                topic:     alert.topic.clone(),
+                sender:    alert.sender.clone(),
+                text:      alert.text.clone(),
+                sent_at:   state::wall_time(alert.sent_at),
+                escalated: alert.escalated,
+                pinned:    alert.pinned.clone(),
+            })
+            .collect()
+    }
+
+    /// Alerts keep their ids, so Ack buttons sent by the other instance keep working
+    pub fn import(&self, snapshots: Vec<Snapshot>) {
+        let mut pending = self.pending.lock().expect("Escalations lock is poisoned");
+        for snapshot in snapshots {
+            self.next_id.fetch_max(snapshot.id + 1, Ordering::SeqCst);
+            pending.insert(
+                snapshot.id,
+                PendingAlert {
+                    topic:     snapshot.topic,
+                    sender:    snapshot.sender,
+                    text:      snapshot.text,
+                    sent_at:   state::instant_at(snapshot.sent_at),
+                    escalated: snapshot.escalated,
+                    pinned:    snapshot.pinned,
+                },
+            );
+        }
+    }
+
+    /// Marks alerts that are past their topic's deadline as escalated and returns them
+    fn take_overdue(&self, topics: &Topics) -> Vec<(u64, String, String, String, Duration)> {
+        let mut pending = self.pending.lock().expect("Escalations lock is poisoned");
+        let now = Instant::now();
+        let mut overdue = Vec::new();
+
+        pending.retain(|_, alert| now.duration_since(alert.sent_at) < PENDING_RETENTION);
+
+        for (id, alert) in pending.iter_mut() {
+            let after = match topics
+                .get(&alert.topic)
+                .and_then(|topic_info| topic_info.escalation.as_ref())
+            {
+                Some(escalation) => escalation.after,
+                None => continue,
+            };
+
+            let unacknowledged_for = now.duration_since(alert.sent_at);
+            if !alert.escalated && unacknowledged_for > after {
+                alert.escalated = true;
+                overdue.push((
+                    *id,
+                    alert.topic.clone(),
+                    alert.sender.clone(),
+                    alert.text.clone(),
+                    unacknowledged_for,
+                ));
+            }
+        }
+
+        overdue
+    }
+}
+
+/// Alerts still waiting for someone to press Ack, the oldest first
+pub async fn list_unacknowledged(
+    _: Viewer,
+    escalations: web::Data<Arc<Escalations>>,
+) -> impl Responder {
+    let pending = escalations
+        .pending
+        .lock()
+        .expect("Escalations lock is poisoned");
+
+    let mut unacknowledged = pending
+        .iter()
+        .map(|(id, alert)| Unacknowledged {
+            id:                     *id,
+            topic:                  &alert.topic,
+            sender:                 &alert.sender,
+            text:                   &alert.text,
+            sent_at:                state::wall_time(alert.sent_at),
+            unacknowledged_seconds: alert.sent_at.elapsed().as_secs(),
+            escalated:              alert.escalated,
+        })
+        .collect::<Vec<_>>();
+    unacknowledged.sort_by_key(|alert| alert.sent_at);
+
+    HttpResponse::Ok().json(unacknowledged)
+}
+
+pub fn ack_markup(id: u64, locale: Locale) -> serde_json::Value {
+    json!({
+        "inline_keyboard": [[{
+            "text": format!("✅ {}", locale.text(Phrase::Ack)),
+            "callback_data": format!("{}{}", ACK_CALLBACK_PREFIX, id),
+        }]]
+    })
+}
+
+/// Replaces Ack button once it was pressed
+pub fn acknowledged_markup(id: u64, acknowledged_by: &str, locale: Locale) -> serde_json::Value {
+    json!({
+        "inline_keyboard": [[{
+            "text": format!("✅ {}", locale.fill(Phrase::AcknowledgedBy, &[acknowledged_by])),
+            "callback_data": format!("{}{}", ACK_CALLBACK_PREFIX, id),
+        }]]
+    })
+}
+
+/// Pending alerts stay in memory of the instance that received them, so every instance
+/// escalates its own
+pub fn spawn_checker(
+    escalations: Arc<Escalations>,
+    topics: Arc<LiveTopics>,
+    tg_client: Arc<TgClient>,
+) {
+    actix_web::rt::spawn(async move {
+        let mut ticks = interval(CHECK_INTERVAL);
+
+        loop {
+            ticks.tick().await;
+
+            let topics = topics.current();
+            for (id, topic, sender, text, unacknowledged_for) in escalations.take_overdue(&topics) {
+                let topic_info = match topics.get(&topic) {
+                    Some(topic_info) => topic_info,
+                    None => continue,
+                };
+                let escalation_recipients = topic_info
+                    .escalation
+                    .as_ref()
+                    .map(|escalation| escalation.recipients.as_slice())
+                    .unwrap_or_default();
+
+                let mut recipients = tg_client.recipients_of(&topic, topic_info);
+                for recipient in escalation_recipients {
+                    if !recipients.contains(recipient) && !tg_client.defunct.contains(recipient) {
+                        recipients.push(recipient.clone());
+                    }
+                }
+
+                let locale = tg_client.locale(&topic);
+                let duration = format_duration(Duration::from_secs(unacknowledged_for.as_secs()));
+                let text = format!(
+                    "🚨🚨🚨 *{}* 🚨🚨🚨\n\n{}",
+                    *TgMarkdownString::new(&locale.fill(
+                        Phrase::UnacknowledgedFor,
+                        &[&duration.to_string().to_uppercase()]
+                    )),
+                    text
+                );
+
+                let responses = tg_client
+                    .send_message_with_markup_to_all(
+                        &recipients,
+                        &topic,
+                        &sender,
+                        &text,
+                        &ack_markup(id, locale),
+                    )
+                    .await;
+
+                if responses.iter().any(|delivery| !delivery.is_delivered()) {
+                    log::warn!("Failed to escalate alert of {}@{}", sender, topic);
+                }
+            }
+        }
+    });
+}
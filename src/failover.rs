@@ -0,0 +1,204 @@
+use serde::Deserialize;
+
+#[cfg(feature = "smtp")]
+use crate::{
+    locale::Phrase,
+    smtp::{
+        Email,
+        Mailer,
+    },
+};
+use crate::{
+    Delivery,
+    TgClient,
+    Topic,
+};
+
+/// Where messages of a topic can be delivered, in `try` of the topic
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Telegram,
+    /// Emails to `email_recipients` of the topic through `[smtp]` server
+    #[cfg(feature = "smtp")]
+    Smtp,
+}
+
+impl Backend {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Telegram => "telegram",
+            #[cfg(feature = "smtp")]
+            Self::Smtp => "smtp",
+        }
+    }
+}
+
+pub fn default_backends() -> Vec<Backend> {
+    vec![Backend::Telegram]
+}
+
+/// Fallback backends that are compiled in, only configured ones are instantiated
+#[derive(Default)]
+pub struct Registry {
+    #[cfg(feature = "smtp")]
+    mailer: Option<Mailer>,
+}
+
+impl Registry {
+    #[cfg(feature = "smtp")]
+    pub fn with_mailer(mut self, mailer: Option<Mailer>) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    /// Whether the backend can deliver, it's configured
+    fn is_configured(&self, backend: Backend) -> bool {
+        match backend {
+            Backend::Telegram => true,
+            #[cfg(feature = "smtp")]
+            Backend::Smtp => self.mailer.is_some(),
+        }
+    }
+}
+
+/// Telegram is always the primary, fallbacks are tried in order if it failed for anyone
+pub fn check(topic_name: &str, topic_info: &Topic, registry: &Registry) -> Result<(), String> {
+    let backends = &topic_info.backends;
+    if backends.first() != Some(&Backend::Telegram) {
+        return Err(format!(
+            "Topic \"{}\" has to try telegram first",
+            topic_name
+        ));
+    }
+    if let Some(backend) = backends
+        .iter()
+        .find(|backend| !registry.is_configured(**backend))
+    {
+        return Err(format!(
+            "Topic \"{}\" tries {}, but [{}] is not configured",
+            topic_name,
+            backend.name(),
+            backend.name()
+        ));
+    }
+    #[cfg(feature = "smtp")]
+    if backends.contains(&Backend::Smtp) && topic_info.email_recipients.is_empty() {
+        return Err(format!(
+            "Topic \"{}\" tries smtp, but has no email_recipients",
+            topic_name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fallbacks of a topic, taken along into delivery of a message
+pub struct Fallbacks {
+    backends:         Vec<Backend>,
+    #[cfg(feature = "smtp")]
+    email_recipients: Vec<String>,
+}
+
+impl Fallbacks {
+    pub fn of_topic(topic_info: &Topic) -> Self {
+        Self {
+            backends: topic_info.backends.iter().skip(1).copied().collect(),
+            #[cfg(feature = "smtp")]
+            email_recipients: topic_info.email_recipients.clone(),
+        }
+    }
+
+    /// Delivers the message by the first fallback that accepts it if Telegram failed for any
+    /// recipient, the note says what went wrong. Deliveries by the fallback are added to the
+    /// Telegram ones
+    #[cfg_attr(not(feature = "smtp"), allow(unused_variables, unused_mut))]
+    pub async fn deliver(
+        &self,
+        tg_client: &TgClient,
+        topic: &str,
+        sender: &str,
+        text: &str,
+        mut deliveries: Vec<Delivery>,
+    ) -> Vec<Delivery> {
+        let failures = deliveries
+            .iter()
+            .filter_map(|delivery| {
+                let failure = delivery.failure()?;
+                Some(format!("{}: {}", delivery.recipient, failure))
+            })
+            .collect::<Vec<_>>();
+        if failures.is_empty() {
+            return deliveries;
+        }
+
+        for backend in &self.backends {
+            match backend {
+                Backend::Telegram => continue,
+                #[cfg(feature = "smtp")]
+                Backend::Smtp => {
+                    if let Some(emailed) =
+                        self.email(tg_client, topic, sender, text, &failures).await
+                    {
+                        deliveries.extend(emailed);
+                        break;
+                    }
+                }
+            }
+        }
+
+        deliveries
+    }
+
+    /// Deliveries to email recipients if the mail server accepted the message
+    #[cfg(feature = "smtp")]
+    async fn email(
+        &self,
+        tg_client: &TgClient,
+        topic: &str,
+        sender: &str,
+        text: &str,
+        failures: &[String],
+    ) -> Option<Vec<Delivery>> {
+        let mailer = tg_client
+            .backends
+            .mailer
+            .as_ref()
+            .expect("Topics trying smtp are checked to have mailer");
+
+        let locale = tg_client.locale(topic);
+        let body = format!(
+            "{}\n\n{}\n\n{}",
+            locale.text(Phrase::FailedOver),
+            failures.join("\n"),
+            text
+        );
+        let subject = locale.fill(Phrase::FailedOverSubject, &[sender, topic]);
+        let email = Email {
+            to:      &self.email_recipients,
+            subject: &subject,
+            body:    &body,
+        };
+        if let Err(err) = mailer.send(&email).await {
+            log::warn!("Failed to fall back to email for \"{}\": {}", topic, err);
+            return None;
+        }
+
+        log::warn!("Message of \"{}\" fell back to email", topic);
+        Some(
+            self.email_recipients
+                .iter()
+                .map(|recipient| Delivery {
+                    recipient:       format!("mailto:{}", recipient),
+                    result:          Ok(http::Response::new(Vec::new()).into()),
+                    attempts:        1,
+                    last_attempt_at: chrono::Utc::now(),
+                })
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,174 @@
+use chrono::{
+    DateTime,
+    NaiveTime,
+    Utc,
+};
+use regex::Regex;
+use serde::{
+    Deserialize,
+    Deserializer,
+};
+
+use crate::{
+    severity::Severity,
+    Topics,
+};
+
+/// Rule of `filters` of a topic, it matches messages that meet all of its conditions
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    #[serde(default, deserialize_with = "deserialize_pattern")]
+    sender:       Option<Regex>,
+    /// With `drop_lines` it's matched against each line instead of the whole message
+    #[serde(default, deserialize_with = "deserialize_pattern")]
+    message:      Option<Regex>,
+    /// Messages at most this severe
+    max_severity: Option<Severity>,
+    /// UTC time of day, `until` earlier than `from` spans midnight
+    from:         Option<NaiveTime>,
+    until:        Option<NaiveTime>,
+    action:       Action,
+}
+
+fn deserialize_pattern<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Regex>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|pattern| Regex::new(&pattern).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Drop,
+    /// Removes lines matching `message`, the message is dropped if none are left
+    DropLines,
+    /// Sent without notification sound
+    Silent,
+    /// Delivered as a message of this topic instead
+    Reroute(String),
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Drop => "drop",
+            Self::DropLines => "drop_lines",
+            Self::Silent => "silent",
+            Self::Reroute(_) => "reroute",
+        }
+    }
+}
+
+/// Message left after all matching rules, `None` from `apply` if it was dropped
+pub struct Filtered {
+    pub message: String,
+    pub silent:  bool,
+    pub reroute: Option<String>,
+}
+
+impl Filter {
+    fn is_in_hours(&self, now: DateTime<Utc>) -> bool {
+        let time = now.time();
+        match (self.from, self.until) {
+            (None, None) => true,
+            (Some(from), None) => from <= time,
+            (None, Some(until)) => time < until,
+            (Some(from), Some(until)) if from <= until => from <= time && time < until,
+            (Some(from), Some(until)) => from <= time || time < until,
+        }
+    }
+
+    fn matches(&self, sender: &str, severity: Severity, message: &str, now: DateTime<Utc>) -> bool {
+        let message_matches = match (&self.message, &self.action) {
+            (Some(pattern), Action::DropLines) =>
+                message.lines().any(|line| pattern.is_match(line)),
+            (Some(pattern), _) => pattern.is_match(message),
+            (None, _) => true,
+        };
+
+        message_matches
+            && self
+                .sender
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(sender))
+            && self
+                .max_severity
+                .is_none_or(|max_severity| severity <= max_severity)
+            && self.is_in_hours(now)
+    }
+}
+
+/// Applies every matching rule in order, until one drops or reroutes the message. Actions taken
+/// are passed to `taken` by name
+pub fn apply(
+    filters: &[Filter],
+    sender: &str,
+    severity: Severity,
+    message: String,
+    mut taken: impl FnMut(&str),
+) -> Option<Filtered> {
+    let now = Utc::now();
+    let mut filtered = Filtered {
+        message,
+        silent: false,
+        reroute: None,
+    };
+
+    for filter in filters {
+        if !filter.matches(sender, severity, &filtered.message, now) {
+            continue;
+        }
+        taken(filter.action.name());
+
+        match &filter.action {
+            Action::Drop => return None,
+            Action::DropLines => {
+                let pattern = filter.message.as_ref().expect("Checked to have message");
+                filtered.message = filtered
+                    .message
+                    .lines()
+                    .filter(|line| !pattern.is_match(line))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if filtered.message.trim().is_empty() {
+                    return None;
+                }
+            }
+            Action::Silent => filtered.silent = true,
+            Action::Reroute(topic) => {
+                filtered.reroute = Some(topic.clone());
+                break;
+            }
+        }
+    }
+
+    Some(filtered)
+}
+
+/// Rules that drop lines need a pattern, rerouted messages need a topic to go to
+pub fn check(topic: &str, filters: &[Filter], topics: &Topics) -> Result<(), String> {
+    for filter in filters {
+        match &filter.action {
+            Action::DropLines if filter.message.is_none() =>
+                return Err(format!(
+                    "Filter of topic \"{}\" drops lines, but has no message pattern",
+                    topic
+                )),
+            Action::Reroute(target) if target == topic || !topics.contains_key(target) =>
+                return Err(format!(
+                    "Filter of topic \"{}\" reroutes to \"{}\", which is not another topic",
+                    topic, target
+                )),
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,373 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::{
+    Deserialize,
+    Deserializer,
+};
+
+use crate::{
+    locale::{
+        Locale,
+        Phrase,
+    },
+    TgMarkdownString,
+};
+
+/// Longest text Telegram accepts in one message
+pub const TELEGRAM_MESSAGE_LIMIT: usize = 4096;
+const DEFAULT_REDACTION: &str = "[REDACTED]";
+/// Ends before whitespace, quotes and brackets, so links in Markdown and prose are found whole
+const URL_PATTERN: &str = r#"https?://[^\s<>"'()\[\]]+"#;
+
+/// What the message is about, used by stages that mention topic or sender
+pub struct Context<'a> {
+    pub topic:       &'a str,
+    pub sender:      &'a str,
+    /// Shown in the header instead of sender, if configured
+    pub sender_name: Option<&'a DisplayName>,
+    pub locale:      Locale,
+}
+
+/// Friendly name of a sender, senders in URLs stay machine-friendly
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisplayName {
+    name:  String,
+    emoji: Option<String>,
+}
+
+impl Context<'_> {
+    fn header(&self) -> String {
+        let (emoji, sender) = match self.sender_name {
+            Some(DisplayName { name, emoji }) => (emoji.as_deref(), name.as_str()),
+            None => (None, self.sender),
+        };
+        let emoji = emoji
+            .map(|emoji| format!("{} ", *TgMarkdownString::new(emoji)))
+            .unwrap_or_default();
+
+        format!(
+            "{}: {}*{}@{}*",
+            self.locale.text(Phrase::From),
+            emoji,
+            *TgMarkdownString::new(sender),
+            self.topic
+        )
+    }
+}
+
+/// Step of turning a posted message into Telegram messages
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Stage {
+    /// Replaces matches of the patterns, e.g. tokens that leaked into logs
+    Redact {
+        #[serde(deserialize_with = "deserialize_patterns")]
+        patterns:    Vec<Regex>,
+        #[serde(default = "default_redaction")]
+        replacement: String,
+    },
+    /// Wraps the message, `{text}`, `{topic}` and `{sender}` are substituted
+    Template { template: String },
+    /// Rewrites links of the message by the first rule matching each of them
+    RewriteUrls { rules: Vec<UrlRewrite> },
+    /// Adds `From: sender@topic` header, with display name of the sender if there is one
+    Decorate,
+    /// Escapes MarkdownV2 in the message, so it's shown as is
+    Escape,
+    /// Splits messages that are too long for Telegram
+    Chunk {
+        #[serde(default = "default_max_length")]
+        max_length: usize,
+    },
+}
+
+/// Rule of `rewrite_urls` stage, e.g. internal hostname to the one reachable from phones
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UrlRewrite {
+    /// Links starting with it are rewritten
+    prefix:      String,
+    /// Put in place of the prefix
+    replacement: Option<String>,
+    /// Link, after replacement of the prefix, is put in place of `{url}` percent-encoded, e.g.
+    /// to go through a redirector
+    wrap:        Option<String>,
+}
+
+impl UrlRewrite {
+    fn apply(&self, url: &str) -> Option<String> {
+        let rest = url.strip_prefix(&self.prefix)?;
+        let url = match &self.replacement {
+            Some(replacement) => format!("{}{}", replacement, rest),
+            None => url.to_owned(),
+        };
+
+        Some(match &self.wrap {
+            Some(wrap) => wrap.replace("{url}", &percent_encode(&url)),
+            None => url,
+        })
+    }
+}
+
+/// Everything but unreserved characters of RFC 3986
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' =>
+                encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+fn rewrite_urls(text: &str, rules: &[UrlRewrite]) -> String {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    URL.get_or_init(|| Regex::new(URL_PATTERN).expect("URL pattern is valid"))
+        .replace_all(text, |captures: &regex::Captures| {
+            // Punctuation after a link ends the sentence rather than the link
+            let url = captures[0].trim_end_matches(['.', ',', ';', ':', '!', '?']);
+            let punctuation = &captures[0][url.len()..];
+            let url = rules
+                .iter()
+                .find_map(|rule| rule.apply(url))
+                .unwrap_or_else(|| url.to_owned());
+
+            format!("{}{}", url, punctuation)
+        })
+        .into_owned()
+}
+
+fn default_redaction() -> String {
+    DEFAULT_REDACTION.to_owned()
+}
+
+fn default_max_length() -> usize {
+    TELEGRAM_MESSAGE_LIMIT
+}
+
+fn deserialize_patterns<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Regex>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| Regex::new(pattern).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Ordered stages messages of a topic go through. Without configuration only the header is
+/// added and the message is sent as is
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(transparent)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            stages: vec![Stage::Decorate],
+        }
+    }
+}
+
+/// Message between stages, header is kept apart so it isn't escaped or templated
+struct Draft {
+    header: Option<String>,
+    body:   String,
+    chunks: Option<usize>,
+}
+
+impl Pipeline {
+    /// Texts of Telegram messages to send, there is always at least one
+    pub fn render(&self, context: &Context, text: &str) -> Vec<String> {
+        self.run(context, text, None)
+    }
+
+    /// Same as `render`, but the text is a code block in every message. Escaping stage is
+    /// skipped, code is escaped by its own rules
+    pub fn render_code(&self, context: &Context, text: &str, language: &str) -> Vec<String> {
+        self.run(context, text, Some(language))
+    }
+
+    fn run(&self, context: &Context, text: &str, language: Option<&str>) -> Vec<String> {
+        let mut draft = Draft {
+            header: None,
+            body:   text.to_owned(),
+            chunks: None,
+        };
+
+        for stage in &self.stages {
+            match stage {
+                Stage::Redact {
+                    patterns,
+                    replacement,
+                } =>
+                    for pattern in patterns {
+                        draft.body = pattern
+                            .replace_all(&draft.body, replacement.as_str())
+                            .into_owned();
+                    },
+                Stage::Template { template } =>
+                    draft.body = template
+                        .replace("{topic}", context.topic)
+                        .replace("{sender}", context.sender)
+                        .replace("{text}", &draft.body),
+                Stage::RewriteUrls { rules } => draft.body = rewrite_urls(&draft.body, rules),
+                Stage::Decorate => draft.header = Some(context.header()),
+                Stage::Escape if language.is_some() => (),
+                Stage::Escape => draft.body = TgMarkdownString::new(&draft.body).to_string(),
+                Stage::Chunk { max_length } => draft.chunks = Some(*max_length),
+            }
+        }
+
+        let header = draft
+            .header
+            .map(|header| format!("{}\n\n", header))
+            .unwrap_or_default();
+
+        // Fences of code blocks are added after splitting, room is left for them
+        let (body, fence_length) = match language {
+            Some(language) => (
+                TgMarkdownString::escape_code(&draft.body),
+                TgMarkdownString::fenced("", Some(language)).chars().count(),
+            ),
+            None => (draft.body, 0),
+        };
+
+        // Only the body is split, so markup of the header never ends up in two messages
+        let mut chunks = match draft.chunks {
+            Some(max_length) => chunk(
+                &body,
+                max_length.saturating_sub(fence_length),
+                header.chars().count(),
+            ),
+            None => vec![body],
+        };
+        if language.is_some() {
+            for chunk in &mut chunks {
+                *chunk = TgMarkdownString::fenced(chunk, language).to_string();
+            }
+        }
+        chunks[0].insert_str(0, &header);
+
+        chunks
+    }
+}
+
+/// Language of code blocks for files with well known extensions
+pub fn language_of(filename: &str) -> Option<&'static str> {
+    let extension = filename.rsplit_once('.')?.1.to_ascii_lowercase();
+    let language = match extension.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "go" => "go",
+        "js" | "mjs" => "javascript",
+        "ts" => "typescript",
+        "java" => "java",
+        "kt" => "kotlin",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "sh" | "bash" => "bash",
+        "sql" => "sql",
+        "json" => "json",
+        "yml" | "yaml" => "yaml",
+        "toml" => "toml",
+        "xml" => "xml",
+        "html" => "html",
+        "css" => "css",
+        "diff" | "patch" => "diff",
+        _ => return None,
+    };
+
+    Some(language)
+}
+
+/// MarkdownV2 as it reads, for sending without parse mode. Escapes and formatting characters
+/// are dropped, links are followed by their URL
+pub fn plain_text(markdown: &str) -> String {
+    let mut plain = String::with_capacity(markdown.len());
+    let mut chars = markdown.chars().peekable();
+    let mut line_start = true;
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => plain.extend(chars.next()),
+            '*' | '_' | '~' | '|' | '`' | '[' => (),
+            ']' if chars.peek() == Some(&'(') => {
+                chars.next();
+                plain.push_str(" (");
+            }
+            ']' => (),
+            '>' if line_start => (),
+            _ => plain.push(ch),
+        }
+        line_start = ch == '\n';
+    }
+
+    plain
+}
+
+/// Letters and digits masked, so markup that broke parsing can be logged without the message
+pub fn masked(text: &str) -> String {
+    text.chars()
+        .map(|ch| if ch.is_alphanumeric() { 'x' } else { ch })
+        .collect()
+}
+
+/// Splits at line breaks where possible, never right after an escaping backslash. First chunk
+/// leaves room for `reserved` characters
+fn chunk(text: &str, max_length: usize, reserved: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    loop {
+        let max_length = if chunks.is_empty() {
+            max_length.saturating_sub(reserved).max(2)
+        } else {
+            max_length.max(2)
+        };
+        if rest.chars().count() <= max_length {
+            break;
+        }
+
+        let limit = rest
+            .char_indices()
+            .nth(max_length)
+            .map_or(rest.len(), |(index, _)| index);
+        let mut split = match rest[..limit].rfind('\n') {
+            Some(index) if index > 0 => index + 1,
+            _ => limit,
+        };
+
+        let backslashes = rest[..split]
+            .chars()
+            .rev()
+            .take_while(|ch| *ch == '\\')
+            .count();
+        if backslashes % 2 == 1 {
+            split -= 1;
+        }
+
+        chunks.push(rest[..split].to_owned());
+        rest = &rest[split..];
+    }
+    chunks.push(rest.to_owned());
+
+    chunks
+}
@@ -0,0 +1,37 @@
+use std::{
+    net::IpAddr,
+    path::Path,
+};
+
+use maxminddb::{
+    geoip2,
+    Reader,
+};
+
+/// Country lookups, available only when MaxMind database is configured
+#[derive(Default)]
+pub struct GeoIp {
+    reader: Option<Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    pub fn open(database: &Path) -> Self {
+        Self {
+            reader: Some(Reader::open_readfile(database).expect("Failed to open GeoIP database")),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.reader.is_some()
+    }
+
+    /// ISO 3166-1 alpha-2 code of the country the address belongs to
+    pub fn country(&self, address: IpAddr) -> Option<String> {
+        let country: geoip2::Country = self.reader.as_ref()?.lookup(address).ok()?;
+
+        country
+            .country
+            .and_then(|country| country.iso_code)
+            .map(str::to_owned)
+    }
+}
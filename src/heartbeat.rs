@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use actix_web::{
+    rt::time::interval,
+    web,
+    HttpResponse,
+    Responder,
+};
+use humantime_serde::re::humantime::format_duration;
+
+use crate::{
+    access::AllowedTopic,
+    coordination::Coordinator,
+    delivery_response,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    locale::Phrase,
+    maintenance::Maintenance,
+    LiveTopics,
+    PostPathData,
+    TgClient,
+    TgMarkdownString,
+    Topics,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Beat {
+    last_seen: Instant,
+    missed:    bool,
+}
+
+/// Last heartbeat of every sender that has sent at least one
+#[derive(Default)]
+pub struct Heartbeats {
+    beats: Mutex<HashMap<(String, String), Beat>>,
+}
+
+impl Heartbeats {
+    /// Returns for how long the sender has been missing if it was already reported as such
+    fn beat(&self, topic: &str, sender: &str) -> Option<Duration> {
+        let mut beats = self.beats.lock().expect("Heartbeats lock is poisoned");
+        let now = Instant::now();
+
+        let previous = beats.insert(
+            (topic.to_owned(), sender.to_owned()),
+            Beat {
+                last_seen: now,
+                missed:    false,
+            },
+        );
+
+        previous
+            .filter(|beat| beat.missed)
+            .map(|beat| now.duration_since(beat.last_seen))
+    }
+
+    /// Marks senders that are past their topic's deadline as missed and returns them
+    fn take_overdue(&self, topics: &Topics) -> Vec<(String, String, Duration)> {
+        let mut beats = self.beats.lock().expect("Heartbeats lock is poisoned");
+        let now = Instant::now();
+        let mut overdue = Vec::new();
+
+        for ((topic, sender), beat) in beats.iter_mut() {
+            let expected_every = match topics
+                .get(topic)
+                .and_then(|topic_info| topic_info.expect_heartbeat_every)
+            {
+                Some(expected_every) => expected_every,
+                None => continue,
+            };
+
+            let since_last_seen = now.duration_since(beat.last_seen);
+            if !beat.missed && since_last_seen > expected_every {
+                beat.missed = true;
+                overdue.push((topic.clone(), sender.clone(), since_last_seen));
+            }
+        }
+
+        overdue
+    }
+}
+
+fn rounded(duration: Duration) -> Duration {
+    Duration::from_secs(duration.as_secs())
+}
+
+pub fn spawn_checker(
+    heartbeats: Arc<Heartbeats>,
+    topics: Arc<LiveTopics>,
+    tg_client: Arc<TgClient>,
+    coordinator: Arc<Coordinator>,
+    maintenance: Arc<Maintenance>,
+) {
+    actix_web::rt::spawn(async move {
+        let mut ticks = interval(CHECK_INTERVAL);
+
+        loop {
+            ticks.tick().await;
+
+            if !coordinator.is_leader() {
+                continue;
+            }
+
+            let topics = topics.current();
+            for (topic, sender, since_last_seen) in heartbeats.take_overdue(&topics) {
+                let topic_info = match topics.get(&topic) {
+                    Some(topic_info) => topic_info,
+                    None => continue,
+                };
+
+                let locale = tg_client.locale(&topic);
+                let text = format!(
+                    "💔 *{}*\n{}",
+                    locale.text(Phrase::HeartbeatMissed),
+                    *TgMarkdownString::new(&locale.fill(
+                        Phrase::LastHeartbeatAgo,
+                        &[&format_duration(rounded(since_last_seen)).to_string()]
+                    ))
+                );
+
+                // Planned downtime is the usual reason for missing heartbeats
+                if maintenance.intercept(&topic, topic_info, &sender, &text) {
+                    continue;
+                }
+
+                let responses = tg_client
+                    .send_message_to_all(
+                        &tg_client.recipients_of(&topic, topic_info),
+                        &topic,
+                        &sender,
+                        &text,
+                    )
+                    .await;
+
+                if responses.iter().any(|delivery| !delivery.is_delivered()) {
+                    log::warn!(
+                        "Failed to notify about missed heartbeat of {}@{}",
+                        sender,
+                        topic
+                    );
+                }
+            }
+        }
+    });
+}
+
+pub async fn post_heartbeat(
+    topic: AllowedTopic,
+    tg_client: web::Data<Arc<TgClient>>,
+    heartbeats: web::Data<Arc<Heartbeats>>,
+    path_data: web::Path<PostPathData>,
+) -> impl Responder {
+    let topic_info = &topic.info;
+    if topic_info.expect_heartbeat_every.is_none() {
+        return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic"));
+    }
+
+    match heartbeats.beat(&path_data.topic_name, &path_data.sender) {
+        Some(missing_for) => {
+            let locale = tg_client.locale(&path_data.topic_name);
+            let text = format!(
+                "💚 *{}*\n{}",
+                locale.text(Phrase::HeartbeatBack),
+                *TgMarkdownString::new(&locale.fill(
+                    Phrase::MissingFor,
+                    &[&format_duration(rounded(missing_for)).to_string()]
+                ))
+            );
+
+            let responses = tg_client
+                .send_message_to_all(
+                    &tg_client.recipients_of(&path_data.topic_name, topic_info),
+                    &path_data.topic_name,
+                    &path_data.sender,
+                    &text,
+                )
+                .await;
+
+            delivery_response(&responses)
+        }
+        None => HttpResponse::NoContent().finish(),
+    }
+}
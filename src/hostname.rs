@@ -0,0 +1,85 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::Topic;
+
+/// Lookups are cached, in both outcomes, so DNS is queried once in a while per client
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Forward-confirmed hostnames of client addresses
+#[derive(Default)]
+pub struct Hostnames {
+    cache: Mutex<HashMap<IpAddr, (Instant, Option<String>)>>,
+}
+
+/// Hostname from PTR record, provided it resolves back to the same address
+fn confirmed_hostname(address: IpAddr) -> Option<String> {
+    let hostname = dns_lookup::lookup_addr(&address).ok()?;
+    let addresses = dns_lookup::lookup_host(&hostname).ok()?;
+
+    addresses
+        .contains(&address)
+        .then(|| hostname.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// `*.example.com` matches any subdomain of example.com, other patterns match exactly
+fn matches(pattern: &str, hostname: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+
+    match pattern.strip_prefix('*') {
+        Some(suffix) => suffix.starts_with('.') && hostname.ends_with(suffix),
+        None => pattern == hostname,
+    }
+}
+
+impl Hostnames {
+    async fn lookup(&self, address: IpAddr) -> Option<String> {
+        if let Some((resolved_at, hostname)) = self
+            .cache
+            .lock()
+            .expect("Hostnames lock is poisoned")
+            .get(&address)
+        {
+            if resolved_at.elapsed() < CACHE_TTL {
+                return hostname.clone();
+            }
+        }
+
+        let hostname = actix_web::rt::task::spawn_blocking(move || confirmed_hostname(address))
+            .await
+            .ok()
+            .flatten();
+
+        let mut cache = self.cache.lock().expect("Hostnames lock is poisoned");
+        cache.retain(|_, (resolved_at, _)| resolved_at.elapsed() < CACHE_TTL);
+        cache.insert(address, (Instant::now(), hostname.clone()));
+
+        hostname
+    }
+
+    /// Checks `allow_list` first and resolves hostname only if `allow_hosts` is needed
+    pub async fn is_allowed(&self, topic_info: &Topic, address: IpAddr) -> bool {
+        if topic_info.is_allowed(address) {
+            return true;
+        }
+
+        if topic_info.allow_hosts.is_empty() {
+            return false;
+        }
+
+        match self.lookup(address).await {
+            Some(hostname) => topic_info
+                .allow_hosts
+                .iter()
+                .any(|pattern| matches(pattern, &hostname)),
+            None => false,
+        }
+    }
+}
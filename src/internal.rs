@@ -0,0 +1,199 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{
+    channel::mpsc,
+    StreamExt,
+};
+use humantime_serde::re::humantime::format_duration;
+
+use crate::{
+    coordination,
+    failover::Fallbacks,
+    format,
+    locale::{
+        Locale,
+        Phrase,
+    },
+    LiveTopics,
+    TgClient,
+    TgMarkdownString,
+};
+
+const SENDER: &str = "microphone";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Trouble of microphone itself, reported to `internal_topic`
+pub enum Incident {
+    /// Changed config given by URL is invalid, the running one is kept
+    ConfigRejected {
+        error: String,
+    },
+    /// Connections to Bot API failed this many times in a row
+    BotApiFailing {
+        failures: u32,
+        error:    String,
+    },
+    /// Bot API responded again after it was failing for this long
+    BotApiBack {
+        after: Duration,
+    },
+    /// Files in the spool that can't be delivered
+    SpoolStuck {
+        files: usize,
+    },
+    QuotaTripped {
+        topic: String,
+    },
+    /// Bot was kicked from the chat of the recipient, or the chat is gone
+    RecipientDefunct {
+        recipient: String,
+        reason:    String,
+    },
+    /// Announced with `announce_restarts`
+    Started {
+        topics: usize,
+    },
+    Stopping,
+}
+
+impl Incident {
+    fn text(&self, locale: Locale) -> String {
+        let (icon, title, details) = match self {
+            Self::ConfigRejected { error } => (
+                "⚠️",
+                locale.text(Phrase::ConfigRejected).to_owned(),
+                Some(error),
+            ),
+            Self::BotApiFailing { failures, error } => (
+                "🔴",
+                locale.fill(Phrase::BotApiFailing, &[&failures.to_string()]),
+                Some(error),
+            ),
+            Self::BotApiBack { after } => (
+                "✅",
+                locale.fill(
+                    Phrase::BotApiBack,
+                    &[&format_duration(Duration::from_secs(after.as_secs())).to_string()],
+                ),
+                None,
+            ),
+            Self::SpoolStuck { files } => (
+                "📥",
+                locale.fill(Phrase::SpoolStuck, &[&files.to_string()]),
+                None,
+            ),
+            Self::QuotaTripped { topic } =>
+                ("⛔", locale.fill(Phrase::QuotaTripped, &[topic]), None),
+            Self::RecipientDefunct { recipient, reason } => (
+                "🚪",
+                locale.fill(Phrase::RecipientDefunct, &[recipient]),
+                Some(reason),
+            ),
+            Self::Started { topics } => (
+                "🟢",
+                locale.fill(
+                    Phrase::Started,
+                    &[VERSION, &coordination::hostname(), &topics.to_string()],
+                ),
+                None,
+            ),
+            Self::Stopping => (
+                "🔻",
+                locale.fill(Phrase::Stopping, &[VERSION, &coordination::hostname()]),
+                None,
+            ),
+        };
+
+        let title = format!("{} *{}*", icon, *TgMarkdownString::new(&title));
+        match details {
+            Some(details) => format!("{}\n{}", title, *TgMarkdownString::new(details)),
+            None => title,
+        }
+    }
+}
+
+/// Incidents are queued and delivered in background, so they can be raised from anywhere,
+/// Bot API client included. Nothing is raised without `internal_topic`
+#[derive(Clone)]
+#[derive(Default)]
+pub struct InternalAlerts {
+    topic:     Option<String>,
+    incidents: Option<mpsc::UnboundedSender<Incident>>,
+}
+
+impl InternalAlerts {
+    pub fn new(topic: Option<String>) -> (Self, mpsc::UnboundedReceiver<Incident>) {
+        let (sender, receiver) = mpsc::unbounded();
+        let alerts = Self {
+            incidents: topic.is_some().then_some(sender),
+            topic,
+        };
+
+        (alerts, receiver)
+    }
+
+    pub fn topic(&self) -> Option<&str> {
+        self.topic.as_deref()
+    }
+
+    pub fn raise(&self, incident: Incident) {
+        if let Some(incidents) = &self.incidents {
+            let _ = incidents.unbounded_send(incident);
+        }
+    }
+}
+
+/// Delivers raised incidents to the internal topic as they come
+pub fn spawn_sender(
+    mut incidents: mpsc::UnboundedReceiver<Incident>,
+    topics: Arc<LiveTopics>,
+    tg_client: Arc<TgClient>,
+) {
+    actix_web::rt::spawn(async move {
+        while let Some(incident) = incidents.next().await {
+            deliver(&incident, &topics, &tg_client).await;
+        }
+    });
+}
+
+/// Sends the incident to recipients of the internal topic, falling back like messages of the
+/// topic do
+pub async fn deliver(incident: &Incident, topics: &LiveTopics, tg_client: &TgClient) {
+    let topic = match tg_client.alerts.topic() {
+        Some(topic) => topic,
+        None => return,
+    };
+    let topics = topics.current();
+    let topic_info = match topics.get(topic) {
+        Some(topic_info) => topic_info,
+        None => {
+            log::warn!("Internal topic \"{}\" is gone, dropping alert", topic);
+            return;
+        }
+    };
+
+    let text = incident.text(tg_client.locale(topic));
+    let deliveries = tg_client
+        .send_message_to_all(
+            &tg_client.recipients_of(topic, topic_info),
+            topic,
+            SENDER,
+            &text,
+        )
+        .await;
+    let deliveries = Fallbacks::of_topic(topic_info)
+        .deliver(
+            tg_client,
+            topic,
+            SENDER,
+            &format::plain_text(&text),
+            deliveries,
+        )
+        .await;
+    if !deliveries.iter().any(|delivery| delivery.is_delivered()) {
+        log::warn!("Failed to deliver internal alert to \"{}\"", topic);
+    }
+}
@@ -0,0 +1,609 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    http::header::{
+        self,
+        HeaderMap,
+    },
+    rt::time::{
+        sleep,
+        timeout,
+    },
+};
+use openssl::{
+    bn::BigNum,
+    ec::{
+        EcGroup,
+        EcKey,
+    },
+    ecdsa::EcdsaSig,
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{
+        PKey,
+        Public,
+    },
+    rsa::Rsa,
+    sign::Verifier,
+};
+use serde::Deserialize;
+use tokio::sync::Notify;
+
+use crate::{
+    admin::Role,
+    bot_http::Outbound,
+};
+
+/// Clock difference between the identity provider and microphone that tokens are forgiven
+const LEEWAY_SECONDS: i64 = 60;
+/// Unknown key ids make keys be fetched again, but not more often than this
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    /// `iss` tokens have to have
+    issuer:       String,
+    /// One of `aud` of tokens has to be this
+    audience:     String,
+    /// Keys tokens are signed with, usually `jwks_uri` of the provider's discovery document
+    jwks_url:     String,
+    /// How often keys are fetched again, to pick up rotated ones
+    #[serde(default = "default_jwks_refresh", with = "humantime_serde")]
+    jwks_refresh: Duration,
+    /// Claim with groups of the caller, matched with `allow_groups` of topics
+    #[serde(default = "default_groups_claim")]
+    groups_claim: String,
+    /// Admin API role members of the group get
+    #[serde(default)]
+    admin_roles:  BTreeMap<String, Role>,
+}
+
+fn default_jwks_refresh() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_owned()
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+/// Keys of other types and curves are skipped
+#[derive(Deserialize)]
+struct Jwk {
+    kty:     String,
+    kid:     Option<String>,
+    #[serde(rename = "use")]
+    key_use: Option<String>,
+    crv:     Option<String>,
+    n:       Option<String>,
+    e:       Option<String>,
+    x:       Option<String>,
+    y:       Option<String>,
+}
+
+struct Key {
+    id:      Option<String>,
+    public:  PKey<Public>,
+    /// Coordinate size of EC keys, signatures are `r || s` of it each
+    ec_size: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Self::One(value) => vec![value],
+            Self::Many(values) => values,
+        }
+    }
+}
+
+/// Caller a verified token is of
+pub struct Claims {
+    pub groups: Vec<String>,
+}
+
+/// Verifies tokens of the identity provider with its keys, which are kept fetched in background
+pub struct Jwt {
+    config:  JwtConfig,
+    keys:    RwLock<Vec<Key>>,
+    refresh: Notify,
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, String> {
+    base64::decode_config(part, base64::URL_SAFE_NO_PAD)
+        .map_err(|err| format!("Token isn't base64url: {}", err))
+}
+
+fn big_number(value: Option<&String>) -> Option<BigNum> {
+    BigNum::from_slice(&decode(value?).ok()?).ok()
+}
+
+impl Key {
+    fn of(jwk: &Jwk) -> Option<Self> {
+        if jwk
+            .key_use
+            .as_deref()
+            .is_some_and(|key_use| key_use != "sig")
+        {
+            return None;
+        }
+
+        let (public, ec_size) = match jwk.kty.as_str() {
+            "RSA" => {
+                let rsa = Rsa::from_public_components(
+                    big_number(jwk.n.as_ref())?,
+                    big_number(jwk.e.as_ref())?,
+                )
+                .ok()?;
+                (PKey::from_rsa(rsa).ok()?, None)
+            }
+            "EC" => {
+                let (curve, size) = match jwk.crv.as_deref()? {
+                    "P-256" => (Nid::X9_62_PRIME256V1, 32),
+                    "P-384" => (Nid::SECP384R1, 48),
+                    _ => return None,
+                };
+                let group = EcGroup::from_curve_name(curve).ok()?;
+                let x = big_number(jwk.x.as_ref())?;
+                let y = big_number(jwk.y.as_ref())?;
+                let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y).ok()?;
+                (PKey::from_ec_key(key).ok()?, Some(size))
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            id: jwk.kid.clone(),
+            public,
+            ec_size,
+        })
+    }
+
+    fn verifies(&self, algorithm: &str, signed: &[u8], signature: &[u8]) -> bool {
+        let digest = match (algorithm, self.ec_size) {
+            ("RS256", None) => MessageDigest::sha256(),
+            ("RS384", None) => MessageDigest::sha384(),
+            ("RS512", None) => MessageDigest::sha512(),
+            ("ES256", Some(32)) => MessageDigest::sha256(),
+            ("ES384", Some(48)) => MessageDigest::sha384(),
+            _ => return false,
+        };
+
+        match self.ec_size {
+            None => Verifier::new(digest, &self.public)
+                .and_then(|mut verifier| verifier.verify_oneshot(signature, signed))
+                .unwrap_or(false),
+            // JWS has raw `r || s`, OpenSSL checks signatures of it
+            Some(size) => {
+                if signature.len() != size * 2 {
+                    return false;
+                }
+                let verified = || -> Result<bool, openssl::error::ErrorStack> {
+                    let (r, s) = signature.split_at(size);
+                    let signature = EcdsaSig::from_private_components(
+                        BigNum::from_slice(r)?,
+                        BigNum::from_slice(s)?,
+                    )?;
+                    let hash = openssl::hash::hash(digest, signed)?;
+                    signature.verify(&hash, &*self.public.ec_key()?)
+                };
+                verified().unwrap_or(false)
+            }
+        }
+    }
+}
+
+impl Jwt {
+    pub fn spawn(config: JwtConfig, outbound: &Outbound) -> Arc<Self> {
+        let jwt = Arc::new(Self {
+            config,
+            keys: RwLock::new(Vec::new()),
+            refresh: Notify::new(),
+        });
+        let client = outbound
+            .client_builder()
+            .build()
+            .expect("Failed to build JWKS client");
+
+        let fetcher = jwt.clone();
+        actix_web::rt::spawn(async move {
+            let refresh = fetcher
+                .config
+                .jwks_refresh
+                .saturating_sub(MIN_REFRESH_INTERVAL);
+            loop {
+                fetcher.fetch_keys(&client).await;
+                sleep(MIN_REFRESH_INTERVAL).await;
+                let _ = timeout(refresh, fetcher.refresh.notified()).await;
+            }
+        });
+
+        jwt
+    }
+
+    async fn fetch_keys(&self, client: &reqwest::Client) {
+        let url = &self.config.jwks_url;
+        let jwks = match client.get(url).send().await {
+            Ok(response) if response.status().is_success() => response.json::<Jwks>().await,
+            Ok(response) => {
+                log::warn!("JWKS {} responded with {}", url, response.status());
+                return;
+            }
+            Err(err) => Err(err),
+        };
+
+        match jwks {
+            Ok(jwks) => {
+                let keys = jwks.keys.iter().filter_map(Key::of).collect::<Vec<_>>();
+                log::info!("Fetched {} signing keys from {}", keys.len(), url);
+                *self.keys.write().expect("JWKS lock is poisoned") = keys;
+            }
+            Err(err) => log::warn!("Failed to fetch JWKS from {}: {}", url, err),
+        }
+    }
+
+    /// Token of `Authorization: Bearer` header, if there is one that looks like a JWT
+    pub fn bearer(headers: &HeaderMap) -> Option<&str> {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .filter(|token| token.split('.').count() == 3)
+    }
+
+    /// Claims of the token if it's signed by the identity provider, meant for microphone and
+    /// not expired
+    pub fn verify(&self, token: &str) -> Result<Claims, String> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature)) => (header, payload, signature),
+            _ => return Err("Token isn't a JWT".to_owned()),
+        };
+        let signed = &token[..header.len() + 1 + payload.len()];
+
+        let header = serde_json::from_slice::<Header>(&decode(header)?)
+            .map_err(|err| format!("Token header is malformed: {}", err))?;
+        let signature = decode(signature)?;
+
+        let verified = {
+            let keys = self.keys.read().expect("JWKS lock is poisoned");
+            let mut candidates = keys
+                .iter()
+                .filter(|key| header.kid.is_none() || key.id == header.kid)
+                .peekable();
+            if candidates.peek().is_none() {
+                // Provider might have rotated keys since they were fetched
+                self.refresh.notify_one();
+                return Err("Token is signed with unknown key".to_owned());
+            }
+            candidates.any(|key| key.verifies(&header.alg, signed.as_bytes(), &signature))
+        };
+        if !verified {
+            return Err("Token signature is invalid".to_owned());
+        }
+
+        let claims = serde_json::from_slice::<serde_json::Value>(&decode(payload)?)
+            .map_err(|err| format!("Token claims are malformed: {}", err))?;
+        self.check(&claims, chrono::Utc::now().timestamp())?;
+
+        let groups = claims
+            .get(&self.config.groups_claim)
+            .cloned()
+            .and_then(|groups| serde_json::from_value::<OneOrMany>(groups).ok())
+            .map(OneOrMany::into_vec)
+            .unwrap_or_default();
+
+        Ok(Claims { groups })
+    }
+
+    fn check(&self, claims: &serde_json::Value, now: i64) -> Result<(), String> {
+        if claims["iss"].as_str() != Some(self.config.issuer.as_str()) {
+            return Err("Token is issued by someone else".to_owned());
+        }
+        let audiences = serde_json::from_value::<OneOrMany>(claims["aud"].clone())
+            .map(OneOrMany::into_vec)
+            .unwrap_or_default();
+        if !audiences.contains(&self.config.audience) {
+            return Err("Token is meant for someone else".to_owned());
+        }
+        match claims["exp"].as_i64() {
+            Some(expires_at) if expires_at + LEEWAY_SECONDS > now => {}
+            Some(_) => return Err("Token is expired".to_owned()),
+            None => return Err("Token has no expiration".to_owned()),
+        }
+        if claims["nbf"]
+            .as_i64()
+            .is_some_and(|not_before| not_before - LEEWAY_SECONDS > now)
+        {
+            return Err("Token isn't valid yet".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Highest admin API role of the groups
+    pub fn role_of(&self, claims: &Claims) -> Option<Role> {
+        claims
+            .groups
+            .iter()
+            .filter_map(|group| self.config.admin_roles.get(group).copied())
+            .max()
+    }
+
+    pub fn grants_roles(&self) -> bool {
+        !self.config.admin_roles.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::{
+        ec::EcKey,
+        pkey::Private,
+        sign::Signer,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    const ISSUER: &str = "https://id.example.com";
+    const AUDIENCE: &str = "microphone";
+
+    fn encode(data: &[u8]) -> String {
+        base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn jwt(jwks: serde_json::Value) -> Jwt {
+        let jwks = serde_json::from_value::<Jwks>(jwks).unwrap();
+
+        Jwt {
+            config:  JwtConfig {
+                issuer:       ISSUER.to_owned(),
+                audience:     AUDIENCE.to_owned(),
+                jwks_url:     "https://id.example.com/jwks".to_owned(),
+                jwks_refresh: default_jwks_refresh(),
+                groups_claim: default_groups_claim(),
+                admin_roles:  BTreeMap::new(),
+            },
+            keys:    RwLock::new(jwks.keys.iter().filter_map(Key::of).collect()),
+            refresh: Notify::new(),
+        }
+    }
+
+    fn rsa_key() -> (Rsa<Private>, serde_json::Value) {
+        let key = Rsa::generate(2048).unwrap();
+        let jwk = json!({
+            "kty": "RSA",
+            "kid": "rsa",
+            "use": "sig",
+            "n": encode(&key.n().to_vec()),
+            "e": encode(&key.e().to_vec()),
+        });
+
+        (key, jwk)
+    }
+
+    fn ec_key() -> (EcKey<Private>, serde_json::Value) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+        key.public_key()
+            .affine_coordinates(
+                &group,
+                &mut x,
+                &mut y,
+                &mut openssl::bn::BigNumContext::new().unwrap(),
+            )
+            .unwrap();
+        let jwk = json!({
+            "kty": "EC",
+            "kid": "ec",
+            "crv": "P-256",
+            "x": encode(&x.to_vec_padded(32).unwrap()),
+            "y": encode(&y.to_vec_padded(32).unwrap()),
+        });
+
+        (key, jwk)
+    }
+
+    fn valid_claims() -> serde_json::Value {
+        let now = chrono::Utc::now().timestamp();
+
+        json!({
+            "iss": ISSUER,
+            "aud": [AUDIENCE, "someone-else"],
+            "exp": now + 300,
+            "nbf": now,
+            "groups": ["ops"],
+        })
+    }
+
+    fn unsigned(header: serde_json::Value, claims: &serde_json::Value) -> String {
+        format!(
+            "{}.{}",
+            encode(header.to_string().as_bytes()),
+            encode(claims.to_string().as_bytes())
+        )
+    }
+
+    fn rs256(key: &Rsa<Private>, kid: &str, claims: &serde_json::Value) -> String {
+        let signed = unsigned(json!({"alg": "RS256", "kid": kid}), claims);
+        let key = PKey::from_rsa(key.clone()).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        let signature = signer.sign_oneshot_to_vec(signed.as_bytes()).unwrap();
+
+        format!("{}.{}", signed, encode(&signature))
+    }
+
+    fn es256(key: &EcKey<Private>, alg: &str, claims: &serde_json::Value) -> String {
+        let signed = unsigned(json!({"alg": alg, "kid": "ec"}), claims);
+        let hash = openssl::hash::hash(MessageDigest::sha256(), signed.as_bytes()).unwrap();
+        let signature = EcdsaSig::sign(&hash, key).unwrap();
+        let mut raw = signature.r().to_vec_padded(32).unwrap();
+        raw.extend(signature.s().to_vec_padded(32).unwrap());
+
+        format!("{}.{}", signed, encode(&raw))
+    }
+
+    #[test]
+    fn verifies_rs256_tokens() {
+        let (key, jwk) = rsa_key();
+        let jwt = jwt(json!({ "keys": [jwk] }));
+
+        let claims = jwt.verify(&rs256(&key, "rsa", &valid_claims())).unwrap();
+
+        assert_eq!(claims.groups, ["ops"]);
+    }
+
+    #[test]
+    fn verifies_es256_tokens() {
+        let (key, jwk) = ec_key();
+        let jwt = jwt(json!({ "keys": [jwk] }));
+
+        assert!(jwt.verify(&es256(&key, "ES256", &valid_claims())).is_ok());
+    }
+
+    #[test]
+    fn rejects_tokens_of_other_keys() {
+        let (key, jwk) = rsa_key();
+        let (other, _) = rsa_key();
+        let jwt = jwt(json!({ "keys": [jwk] }));
+
+        assert_eq!(
+            jwt.verify(&rs256(&other, "rsa", &valid_claims()))
+                .err()
+                .unwrap(),
+            "Token signature is invalid"
+        );
+        assert_eq!(
+            jwt.verify(&rs256(&key, "rotated", &valid_claims()))
+                .err()
+                .unwrap(),
+            "Token is signed with unknown key"
+        );
+    }
+
+    #[test]
+    fn rejects_algorithms_not_of_the_key() {
+        let (rsa, rsa_jwk) = rsa_key();
+        let (ec, ec_jwk) = ec_key();
+        let jwt = jwt(json!({ "keys": [rsa_jwk, ec_jwk] }));
+
+        // Signature is fine, but EC keys don't do ES384 of P-256 or RSA
+        let token = es256(&ec, "ES384", &valid_claims());
+        assert_eq!(
+            jwt.verify(&token).err().unwrap(),
+            "Token signature is invalid"
+        );
+        let token = es256(&ec, "RS256", &valid_claims());
+        assert_eq!(
+            jwt.verify(&token).err().unwrap(),
+            "Token signature is invalid"
+        );
+
+        // Public key used as HMAC secret
+        let signed = unsigned(json!({"alg": "HS256", "kid": "rsa"}), &valid_claims());
+        let secret = PKey::hmac(&rsa.public_key_to_pem().unwrap()).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &secret).unwrap();
+        let signature = signer.sign_oneshot_to_vec(signed.as_bytes()).unwrap();
+        let token = format!("{}.{}", signed, encode(&signature));
+        assert_eq!(
+            jwt.verify(&token).err().unwrap(),
+            "Token signature is invalid"
+        );
+    }
+
+    #[test]
+    fn rejects_unsigned_tokens() {
+        let (_, jwk) = rsa_key();
+        let jwt = jwt(json!({ "keys": [jwk] }));
+
+        let token = format!("{}.", unsigned(json!({"alg": "none"}), &valid_claims()));
+
+        assert_eq!(
+            jwt.verify(&token).err().unwrap(),
+            "Token signature is invalid"
+        );
+    }
+
+    #[test]
+    fn rejects_tokens_of_other_issuers_and_audiences() {
+        let (key, jwk) = ec_key();
+        let jwt = jwt(json!({ "keys": [jwk] }));
+
+        let mut claims = valid_claims();
+        claims["iss"] = json!("https://evil.example.com");
+        assert_eq!(
+            jwt.verify(&es256(&key, "ES256", &claims)).err().unwrap(),
+            "Token is issued by someone else"
+        );
+
+        let mut claims = valid_claims();
+        claims["aud"] = json!("someone-else");
+        assert_eq!(
+            jwt.verify(&es256(&key, "ES256", &claims)).err().unwrap(),
+            "Token is meant for someone else"
+        );
+    }
+
+    #[test]
+    fn forgives_expiration_within_leeway() {
+        let jwt = jwt(json!({ "keys": [] }));
+        let now = 1_700_000_000;
+        let expiring_at = |exp: i64| {
+            let mut claims = valid_claims();
+            claims["exp"] = json!(exp);
+            claims["nbf"] = json!(now - 600);
+            jwt.check(&claims, now)
+        };
+
+        assert!(expiring_at(now - LEEWAY_SECONDS + 1).is_ok());
+        assert_eq!(
+            expiring_at(now - LEEWAY_SECONDS).err().unwrap(),
+            "Token is expired"
+        );
+    }
+
+    #[test]
+    fn forgives_not_before_within_leeway() {
+        let jwt = jwt(json!({ "keys": [] }));
+        let now = 1_700_000_000;
+        let valid_from = |nbf: i64| {
+            let mut claims = valid_claims();
+            claims["exp"] = json!(now + 600);
+            claims["nbf"] = json!(nbf);
+            jwt.check(&claims, now)
+        };
+
+        assert!(valid_from(now + LEEWAY_SECONDS).is_ok());
+        assert_eq!(
+            valid_from(now + LEEWAY_SECONDS + 1).err().unwrap(),
+            "Token isn't valid yet"
+        );
+    }
+}
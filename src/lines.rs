@@ -0,0 +1,284 @@
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::rt::time::timeout;
+use futures::{
+    SinkExt,
+    StreamExt,
+};
+use serde::Deserialize;
+use tokio::net::{
+    TcpListener,
+    TcpStream,
+    UdpSocket,
+};
+use tokio_util::codec::{
+    Framed,
+    LinesCodec,
+};
+
+use crate::{
+    access::{
+        AllowedTopic,
+        Caller,
+    },
+    adapters::secrets_match,
+    geoip::GeoIp,
+    hostname::Hostnames,
+    maintenance::Maintenance,
+    metrics::Metrics,
+    quotas::{
+        self,
+        Quotas,
+    },
+    send_options::SendOptions,
+    severity::Severity,
+    stream::Streams,
+    LiveTopics,
+    TgClient,
+};
+
+/// Longer lines close the connection, longer datagrams are truncated
+const MAX_LINE: usize = 16 * 1024;
+/// Connections that send nothing for this long are closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinesConfig {
+    /// Address TCP connections are accepted on, every line gets `ok` or `error: ...` back
+    tcp:    Option<SocketAddr>,
+    /// Address datagrams are received on, nothing is sent back
+    udp:    Option<SocketAddr>,
+    /// Shared by senders, the first field of every line
+    secret: String,
+}
+
+/// What messages of devices go through, the same as messages posted by webhooks
+pub struct Pipeline {
+    pub topics:      Arc<LiveTopics>,
+    pub tg_client:   Arc<TgClient>,
+    pub maintenance: Arc<Maintenance>,
+    pub quotas:      Arc<Quotas>,
+    pub streams:     Arc<Streams>,
+    pub metrics:     Arc<Metrics>,
+    pub hostnames:   Arc<Hostnames>,
+    pub geoip:       Arc<GeoIp>,
+}
+
+/// Why a message of a device wasn't delivered
+#[derive(Clone)]
+#[derive(Copy)]
+pub enum Rejection {
+    Malformed,
+    Secret,
+    Topic,
+    Quota,
+    Delivery,
+}
+
+impl Rejection {
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::Malformed => "malformed",
+            Self::Secret => "wrong secret",
+            Self::Topic => "no such topic",
+            Self::Quota => "quota exceeded",
+            Self::Delivery => "delivery failed",
+        }
+    }
+}
+
+pub fn spawn(config: LinesConfig, pipeline: Arc<Pipeline>) {
+    let secret: Arc<str> = config.secret.into();
+
+    if let Some(address) = config.tcp {
+        let secret = secret.clone();
+        let pipeline = pipeline.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(err) = run_tcp(address, secret, pipeline).await {
+                log::error!("Line listener on tcp://{} failed: {}", address, err);
+            }
+        });
+    }
+
+    if let Some(address) = config.udp {
+        actix_web::rt::spawn(async move {
+            if let Err(err) = run_udp(address, secret, pipeline).await {
+                log::error!("Line listener on udp://{} failed: {}", address, err);
+            }
+        });
+    }
+}
+
+async fn run_tcp(
+    address: SocketAddr,
+    secret: Arc<str>,
+    pipeline: Arc<Pipeline>,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    log::info!("Accepting lines on tcp://{}", address);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let secret = secret.clone();
+        let pipeline = pipeline.clone();
+        actix_web::rt::spawn(async move {
+            handle_connection(stream, peer, &secret, &pipeline).await;
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, peer: SocketAddr, secret: &str, pipeline: &Pipeline) {
+    let mut lines = Framed::new(stream, LinesCodec::new_with_max_length(MAX_LINE));
+
+    while let Ok(Some(line)) = timeout(IDLE_TIMEOUT, lines.next()).await {
+        let reply = match line {
+            Ok(line) => match deliver_line(pipeline, secret, peer, &line).await {
+                Ok(()) => "ok".to_owned(),
+                Err(rejection) => format!("error: {}", rejection.reason()),
+            },
+            Err(err) => {
+                log::debug!("Closing line connection from {}: {}", peer, err);
+                let _ = lines.send("error: line is too long").await;
+                return;
+            }
+        };
+
+        if lines.send(reply).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn run_udp(
+    address: SocketAddr,
+    secret: Arc<str>,
+    pipeline: Arc<Pipeline>,
+) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(address).await?;
+    log::info!("Accepting lines on udp://{}", address);
+
+    let mut buffer = vec![0; MAX_LINE];
+    loop {
+        let (size, peer) = socket.recv_from(&mut buffer).await?;
+        let datagram = String::from_utf8_lossy(&buffer[..size]).into_owned();
+        let secret = secret.clone();
+        let pipeline = pipeline.clone();
+
+        // Datagrams are answered by nobody, a slow delivery shouldn't hold the next ones
+        actix_web::rt::spawn(async move {
+            for line in datagram.lines().filter(|line| !line.is_empty()) {
+                let _ = deliver_line(&pipeline, &secret, peer, line).await;
+            }
+        });
+    }
+}
+
+/// Delivers `secret|topic|sender|message` line, the message is the rest of the line and can
+/// have `|` in it
+async fn deliver_line(
+    pipeline: &Pipeline,
+    secret: &str,
+    peer: SocketAddr,
+    line: &str,
+) -> Result<(), Rejection> {
+    let mut fields = line.splitn(4, '|');
+    let result = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+        (Some(provided_secret), Some(topic), Some(sender), Some(text))
+            if !topic.is_empty() && !sender.is_empty() && !text.is_empty() =>
+        {
+            if secrets_match(secret, provided_secret) {
+                deliver(pipeline, peer, topic, sender, text).await
+            } else {
+                Err(Rejection::Secret)
+            }
+        }
+        _ => Err(Rejection::Malformed),
+    };
+
+    if let Err(rejection) = result {
+        log::warn!("Rejected line from {}: {}", peer, rejection.reason());
+        pipeline.metrics.increment(
+            "microphone_lines_rejected_total",
+            &[("reason", rejection.reason())],
+        );
+    }
+    result
+}
+
+/// Delivers message of a device at the address, provided it's allowed to post to the topic
+pub async fn deliver(
+    pipeline: &Pipeline,
+    peer: SocketAddr,
+    topic_name: &str,
+    sender: &str,
+    text: &str,
+) -> Result<(), Rejection> {
+    let topic = AllowedTopic::for_address(
+        topic_name.to_owned(),
+        pipeline.topics.current().get(topic_name).cloned(),
+        peer.ip(),
+        &Caller::default(),
+        Some(&pipeline.hostnames),
+        Some(&pipeline.geoip),
+        Some(&pipeline.metrics),
+    )
+    .await
+    .map_err(|_| Rejection::Topic)?;
+    let topic_info = &topic.info;
+    pipeline.metrics.count_ingress(topic_name, text.len(), 0);
+
+    if pipeline
+        .maintenance
+        .intercept(topic_name, topic_info, sender, text)
+    {
+        return Ok(());
+    }
+    if quotas::enforce(
+        &pipeline.quotas,
+        &pipeline.tg_client,
+        topic_name,
+        topic_info,
+        text.len(),
+    )
+    .await
+    .is_some()
+    {
+        return Err(Rejection::Quota);
+    }
+    pipeline.streams.publish(
+        topic_name,
+        topic_info,
+        sender,
+        Severity::default(),
+        text,
+        &[],
+    );
+
+    let tg_client = &pipeline.tg_client;
+    let responses = tg_client
+        .send_formatted_to_all(
+            &tg_client.recipients_of(topic_name, topic_info),
+            &topic_info.format,
+            topic_name,
+            sender,
+            text,
+            SendOptions::of_topic(topic_info),
+        )
+        .await;
+
+    if responses.iter().all(|delivery| delivery.is_delivered()) {
+        Ok(())
+    } else {
+        log::warn!(
+            "Failed to deliver message from {} to some recipients of \"{}\"",
+            peer,
+            topic_name
+        );
+        Err(Rejection::Delivery)
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+};
+
+use serde::Deserialize;
+
+use crate::Topics;
+
+/// Language of texts microphone adds to messages or sends itself, posted texts are never
+/// translated
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Ru,
+}
+
+/// Texts generated by microphone, `{}` are filled in order
+#[derive(Clone)]
+#[derive(Copy)]
+pub enum Phrase {
+    /// Header of a message, before the sender
+    From,
+    Resolved,
+    UnacknowledgedFor,
+    Ack,
+    AcknowledgedBy,
+    Acknowledged,
+    AlreadyAcknowledged,
+    HeartbeatMissed,
+    LastHeartbeatAgo,
+    HeartbeatBack,
+    MissingFor,
+    MaintenanceOver,
+    HeldMessage,
+    HeldMessages,
+    LatestMessages,
+    QuotaExceeded,
+    QuotaRejects,
+    /// Prefix of deliveries redirected to the sandbox chat, followed by recipient
+    Staging,
+    MeantFor,
+    Identical,
+    NoDifferences,
+    MinMax,
+    /// Note of messages delivered by fallback, before the failures
+    #[cfg_attr(not(feature = "smtp"), allow(dead_code))]
+    FailedOver,
+    #[cfg_attr(not(feature = "smtp"), allow(dead_code))]
+    FailedOverSubject,
+    /// Footer of messages sent as plain text as Telegram couldn't parse their formatting
+    FormattingDropped,
+    /// Follow-up of aggregated messages, with number of times and period
+    SeenTimes,
+    /// Periodic summary of a topic, with period, counts by severity and top senders
+    SummaryOf,
+    SummaryCounts,
+    SummaryPrevious,
+    TopSenders,
+    /// Alerts of the internal topic
+    ConfigRejected,
+    BotApiFailing,
+    BotApiBack,
+    SpoolStuck,
+    QuotaTripped,
+    RecipientDefunct,
+    /// Answers to `/subscribe` and `/unsubscribe`, with the topic
+    Subscribed,
+    AlreadySubscribed,
+    Unsubscribed,
+    NotSubscribed,
+    SubscriptionClosed,
+    SubscribeUsage,
+    /// Button of downscaled photos and the answer once their original is gone
+    Original,
+    OriginalGone,
+    /// Version, host and number of topics
+    Started,
+    Stopping,
+}
+
+impl Locale {
+    pub fn text(self, phrase: Phrase) -> &'static str {
+        use Phrase::*;
+
+        match self {
+            Self::En => match phrase {
+                From => "From",
+                Resolved => "Resolved",
+                UnacknowledgedFor => "UNACKNOWLEDGED FOR {}",
+                Ack => "Ack",
+                AcknowledgedBy => "Acknowledged by {}",
+                Acknowledged => "Acknowledged",
+                AlreadyAcknowledged => "Already acknowledged",
+                HeartbeatMissed => "Heartbeat missed",
+                LastHeartbeatAgo => "Last one was received {} ago",
+                HeartbeatBack => "Heartbeat is back",
+                MissingFor => "It was missing for {}",
+                MaintenanceOver => "Maintenance is over",
+                HeldMessage => "{} message was held during maintenance",
+                HeldMessages => "{} messages were held during maintenance",
+                LatestMessages => "Latest messages:",
+                QuotaExceeded => "Daily quota exceeded",
+                QuotaRejects =>
+                    "Further messages are rejected until 00:00 UTC, senders get 429 Too Many \
+                     Requests",
+                Staging => "Staging",
+                MeantFor => "to {}",
+                Identical => "{} and {} are identical",
+                NoDifferences => "No differences",
+                MinMax => "min {}, max {}",
+                FailedOver => "Telegram didn't deliver this message to everyone:",
+                FailedOverSubject => "Message from {}@{}",
+                FormattingDropped => "⚠️ Sent without formatting, Telegram couldn't parse it",
+                SeenTimes => "Seen {} times in the last {}",
+                SummaryOf => "Summary of the last {}",
+                SummaryCounts => "{} messages, {} critical, {} warnings",
+                SummaryPrevious => "The {} before: {} messages",
+                TopSenders => "Top senders: {}",
+                ConfigRejected => "Changed config is invalid, the running one is kept",
+                BotApiFailing => "Connections to Bot API failed {} times in a row",
+                BotApiBack => "Bot API is reachable again after failing for {}",
+                SpoolStuck => "Spooled files can't be delivered: {}",
+                QuotaTripped => "Topic {} exceeded its daily quota",
+                RecipientDefunct => "Messages aren't delivered to {} anymore",
+                Subscribed => "Subscribed to {}",
+                AlreadySubscribed => "Already subscribed to {}",
+                Unsubscribed => "Unsubscribed from {}",
+                NotSubscribed => "Not subscribed to {}",
+                SubscriptionClosed => "{} can't be subscribed to",
+                SubscribeUsage => "Send /subscribe <topic> or /unsubscribe <topic>",
+                Original => "Original",
+                OriginalGone => "Original is no longer kept",
+                Started => "microphone v{} started on {} with {} topics",
+                Stopping => "microphone v{} on {} is shutting down",
+            },
+            Self::Ru => match phrase {
+                From => "От",
+                Resolved => "Решено",
+                UnacknowledgedFor => "НЕ ПОДТВЕРЖДЕНО {}",
+                Ack => "Принято",
+                AcknowledgedBy => "Принял {}",
+                Acknowledged => "Принято",
+                AlreadyAcknowledged => "Уже принято",
+                HeartbeatMissed => "Heartbeat пропал",
+                LastHeartbeatAgo => "Последний получен {} назад",
+                HeartbeatBack => "Heartbeat вернулся",
+                MissingFor => "Его не было {}",
+                MaintenanceOver => "Обслуживание завершено",
+                HeldMessage | HeldMessages => "Задержано на время обслуживания: {}",
+                LatestMessages => "Последние сообщения:",
+                QuotaExceeded => "Дневная квота исчерпана",
+                QuotaRejects =>
+                    "Следующие сообщения отклоняются до 00:00 UTC, отправители получают 429 \
+                     Too Many Requests",
+                Staging => "Тестовый стенд",
+                MeantFor => "для {}",
+                Identical => "{} и {} совпадают",
+                NoDifferences => "Различий нет",
+                MinMax => "мин {}, макс {}",
+                FailedOver => "Telegram доставил это сообщение не всем:",
+                FailedOverSubject => "Сообщение от {}@{}",
+                FormattingDropped =>
+                    "⚠️ Отправлено без форматирования, Telegram не смог его разобрать",
+                SeenTimes => "Повторилось раз: {}, за последние {}",
+                SummaryOf => "Сводка за последние {}",
+                SummaryCounts => "Сообщений: {}, критических: {}, предупреждений: {}",
+                SummaryPrevious => "За предыдущие {}: {}",
+                TopSenders => "Самые активные отправители: {}",
+                ConfigRejected => "Измененный конфиг некорректен, работает прежний",
+                BotApiFailing => "Подключения к Bot API не удались {} раз подряд",
+                BotApiBack => "Bot API снова доступен, сбои длились {}",
+                SpoolStuck => "Не удается доставить файлы из спула: {}",
+                QuotaTripped => "Топик {} исчерпал дневную квоту",
+                RecipientDefunct => "Сообщения больше не доставляются {}",
+                Subscribed => "Подписка на {} оформлена",
+                AlreadySubscribed => "Подписка на {} уже есть",
+                Unsubscribed => "Подписка на {} отменена",
+                NotSubscribed => "Подписки на {} нет",
+                SubscriptionClosed => "На {} нельзя подписаться",
+                SubscribeUsage => "Отправьте /subscribe <топик> или /unsubscribe <топик>",
+                Original => "Оригинал",
+                OriginalGone => "Оригинал больше не хранится",
+                Started => "microphone v{} запущен на {}, топиков: {}",
+                Stopping => "microphone v{} на {} останавливается",
+            },
+        }
+    }
+
+    /// Phrase with its `{}` replaced by the arguments
+    pub fn fill(self, phrase: Phrase, arguments: &[&str]) -> String {
+        arguments
+            .iter()
+            .fold(self.text(phrase).to_owned(), |text, argument| {
+                text.replacen("{}", argument, 1)
+            })
+    }
+}
+
+/// Locales of topics, kept next to recipient groups so they follow config changes
+#[derive(Default)]
+pub struct Locales {
+    topics: Mutex<HashMap<String, Locale>>,
+}
+
+impl Locales {
+    pub fn of_topics(topics: &Topics) -> Self {
+        let locales = Self::default();
+        locales.replace(topics);
+
+        locales
+    }
+
+    pub fn replace(&self, topics: &Topics) {
+        *self.topics.lock().expect("Locales lock is poisoned") = topics
+            .iter()
+            .map(|(name, topic_info)| (name.clone(), topic_info.locale))
+            .collect();
+    }
+
+    /// Unknown topics, e.g. of messages sent by pipe mode without config, get English
+    pub fn of(&self, topic: &str) -> Locale {
+        self.topics
+            .lock()
+            .expect("Locales lock is poisoned")
+            .get(topic)
+            .copied()
+            .unwrap_or_default()
+    }
+}
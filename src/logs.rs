@@ -0,0 +1,144 @@
+use std::{
+    str::FromStr,
+    sync::{
+        Arc,
+        OnceLock,
+    },
+};
+
+use actix_web::{
+    web,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use log::{
+    Level,
+    Log,
+    Metadata,
+    Record,
+};
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+};
+use tokio::sync::broadcast;
+
+use crate::{
+    admin::Operator,
+    stream,
+};
+
+/// Entries a slow client falls behind by before it's told some were skipped
+const CAPACITY: usize = 1024;
+
+static ENTRIES: OnceLock<broadcast::Sender<Arc<Entry>>> = OnceLock::new();
+
+#[derive(Serialize)]
+struct Entry {
+    time:    DateTime<Utc>,
+    #[serde(serialize_with = "serialize_level")]
+    level:   Level,
+    target:  String,
+    message: String,
+}
+
+fn serialize_level<S: serde::Serializer>(level: &Level, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&level.as_str().to_ascii_lowercase())
+}
+
+impl Entry {
+    /// Topics are named in logs quoted, after `@` of `sender@topic` or as the first segment of
+    /// a request path
+    fn mentions(&self, topic: &str) -> bool {
+        let message = &self.message;
+        message.contains(&format!("\"{}\"", topic))
+            || message
+                .match_indices(&format!("@{}", topic))
+                .any(|(at, part)| {
+                    !message[at + part.len()..]
+                        .starts_with(|c: char| c.is_alphanumeric() || c == '_')
+                })
+            || message.contains(&format!(" /{}/", topic))
+            || message.contains(&format!(" /{} ", topic))
+    }
+}
+
+/// Writes records like env_logger, and passes them to `GET /admin/logs/stream` clients too
+struct Tap {
+    inner: env_logger::Logger,
+}
+
+impl Log for Tap {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        self.inner.log(record);
+        #[cfg(windows)]
+        crate::service::report(record);
+
+        let entries = match ENTRIES.get() {
+            Some(entries) if entries.receiver_count() > 0 => entries,
+            _ => return,
+        };
+        let _ = entries.send(Arc::new(Entry {
+            time:    Utc::now(),
+            level:   record.level(),
+            target:  record.target().to_owned(),
+            message: record.args().to_string(),
+        }));
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Installs the logger, `RUST_LOG` filters it as usual with `info` by default
+pub fn init() {
+    let inner =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).build();
+    let max_level = inner.filter();
+
+    ENTRIES.get_or_init(|| broadcast::channel(CAPACITY).0);
+    log::set_boxed_logger(Box::new(Tap { inner })).expect("Logger is installed once");
+    log::set_max_level(max_level);
+}
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    topic: Option<String>,
+    /// Entries at least this severe, every one that's logged by default
+    #[serde(default, deserialize_with = "deserialize_level")]
+    level: Option<Level>,
+}
+
+fn deserialize_level<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Level>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|level| Level::from_str(&level).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Server-sent events with JSON entries logged from now on
+pub async fn stream_logs(_: Operator, query: web::Query<StreamQuery>) -> impl Responder {
+    let StreamQuery { topic, level } = query.into_inner();
+    let receiver = ENTRIES
+        .get_or_init(|| broadcast::channel(CAPACITY).0)
+        .subscribe();
+
+    stream::sse(stream::events(receiver, move |entry: Arc<Entry>| {
+        let is_wanted = level.is_none_or(|level| entry.level <= level)
+            && topic.as_deref().is_none_or(|topic| entry.mentions(topic));
+        is_wanted.then(|| serde_json::to_string(&*entry).expect("Failed to serialize log entry"))
+    }))
+}
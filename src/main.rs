@@ -1,427 +1,3325 @@
 use std::{
-    collections::HashMap,
+    cell::Cell,
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    future::Future,
     net::IpAddr,
-    sync::Arc,
+    path::PathBuf,
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
 };
 
+use access::AllowedTopic;
 use actix_web::{
-    dev::ConnectionInfo,
+    dev::Service,
     guard,
     http::header,
     middleware::Logger,
+    rt::time::{
+        sleep,
+        timeout,
+    },
     web::{
         self,
         PayloadConfig,
     },
     App,
+    HttpRequest,
     HttpResponse,
-    HttpServer,
     Responder,
 };
-use futures::StreamExt;
+use aggregate::Aggregates;
+use alerts::{
+    AlertUpdate,
+    Alerts,
+    ResolveMode,
+};
+use bans::Bans;
+use callbacks::Callback;
+use client_ip::ClientIp;
+use coordination::Coordinator;
+use errors::{
+    ApiError,
+    ErrorCode,
+    Problems,
+};
+use escalation::{
+    EscalationConfig,
+    Escalations,
+};
+use format::{
+    DisplayName,
+    Pipeline,
+    TELEGRAM_MESSAGE_LIMIT,
+};
+use futures::{
+    future::{
+        ready,
+        LocalBoxFuture,
+    },
+    StreamExt,
+};
+use geoip::GeoIp;
+use hostname::Hostnames;
+use humantime_serde::re::humantime::format_duration;
+use internal::{
+    Incident,
+    InternalAlerts,
+};
 use ipnet::IpNet;
+use locale::{
+    Locale,
+    Locales,
+    Phrase,
+};
+use maintenance::{
+    Maintenance,
+    MaintenanceMode,
+    MaintenanceWindow,
+};
+use messages::Messages;
+use metrics::Metrics;
+use mirror::Mirror;
+use quotas::Quotas;
+use recipient_groups::RecipientGroups;
 use reqwest::{
     multipart::{
         Form,
         Part,
     },
-    ClientBuilder,
     StatusCode,
 };
+use send_options::{
+    LinkPreviewOptions,
+    RequestOptions,
+    SendOptions,
+};
 use serde::{
     Deserialize,
     Serialize,
 };
+use severity::{
+    Severity,
+    Style,
+};
+#[cfg(feature = "smtp")]
+use smtp::Mailer;
+use stream::Streams;
+use summary::Summaries;
+use threshold::Thresholds;
+use upload::{
+    Content,
+    Limits,
+    UploadLimits,
+    Uploads,
+};
+use usage::Usage;
+use voice::VoiceConfig;
+
+mod access;
+mod adapters;
+mod admin;
+mod aggregate;
+mod alerts;
+mod announcements;
+mod api_keys;
+mod bans;
+mod bot_http;
+mod broadcast;
+mod callbacks;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod chart;
+mod chats;
+mod client_ip;
+#[cfg(feature = "coap")]
+mod coap;
+mod compliance;
+mod compress;
+mod config;
+mod coordination;
+mod cors;
+mod defunct;
+mod diff;
+mod discovery;
+mod errors;
+mod escalation;
+mod failover;
+mod filter;
+mod format;
+mod geoip;
+mod heartbeat;
+mod hostname;
+mod internal;
+mod jwt;
+mod lines;
+mod locale;
+mod logs;
+mod maintenance;
+mod mentions;
+mod messages;
+mod metrics;
+mod mirror;
+mod photo;
+mod pipe;
+mod proxy_protocol;
+mod qr;
+mod quotas;
+mod recipient_groups;
+#[cfg(feature = "redis")]
+mod redis_bridge;
+mod reminders;
+mod remote_config;
+#[cfg(feature = "scripting")]
+mod script;
+mod send_options;
+mod server;
+mod service;
+mod setup;
+mod severity;
+#[cfg(feature = "smtp")]
+mod smtp;
+mod sniff;
+mod socks;
+mod spool;
+mod state;
+mod stream;
+mod submit;
+mod subscriptions;
+mod summary;
+mod threshold;
+mod transform;
+mod updates;
+mod upload;
+mod usage;
+mod version;
+mod voice;
+mod websocket;
 
 const TELEGRAM_API_BASE_URL: &str = "https://api.telegram.org";
 const TELEGRAM_SEND_MESSAGE_METHOD: &str = "sendMessage";
 const TELEGRAM_SEND_DOCUMENT_METHOD: &str = "sendDocument";
+const TELEGRAM_SEND_PHOTO_METHOD: &str = "sendPhoto";
+const TELEGRAM_SEND_VOICE_METHOD: &str = "sendVoice";
+const TELEGRAM_SET_MESSAGE_REACTION_METHOD: &str = "setMessageReaction";
+const TELEGRAM_PIN_CHAT_MESSAGE_METHOD: &str = "pinChatMessage";
+const TELEGRAM_EDIT_MESSAGE_TEXT_METHOD: &str = "editMessageText";
+const TELEGRAM_UNPIN_CHAT_MESSAGE_METHOD: &str = "unpinChatMessage";
+const TELEGRAM_DELETE_MESSAGE_METHOD: &str = "deleteMessage";
 const TELEGRAM_MARKDOWN_V2_PARSE_MODE: &str = "MarkdownV2";
+/// Parts of descriptions Telegram rejects requests with if their markup or parse mode is wrong,
+/// e.g. "can't parse entities", "can't find end of the entity" or "unsupported parse_mode"
+const TELEGRAM_PARSE_ERRORS: [&str; 3] = ["can't parse", "entity", "parse_mode"];
+/// Extra attempts for Bot API requests that failed transiently
+const DELIVERY_RETRIES: usize = 2;
+const DELIVERY_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Requests Telegram asks to wait longer for aren't retried, the caller is waiting meanwhile
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Largest body of a request, multipart ones have their own limits too
+const MAX_BODY_SIZE: usize = 50 * 1000 * 1000;
+/// Longest text sent with `GET`, URLs end up in logs of every proxy on the way
+const MAX_GET_MESSAGE_SIZE: usize = 1024;
+
+/// Shutdown waits this long at most for its notice to be delivered
+const SHUTDOWN_NOTICE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default format of request logs with country of the client appended
+const LOG_FORMAT_WITH_COUNTRY: &str =
+    r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T country=%{country}xi"#;
 
 type Topics = HashMap<String, Topic>;
+/// First segments of routes of microphone itself, which are matched before
+/// `/{topic_name}/{sender}`
+const RESERVED_TOPIC_NAMES: [&str; 8] = [
+    "admin",
+    "broadcast",
+    "gitea",
+    "gitlab",
+    "grafana",
+    "sentry",
+    "status",
+    "telegram",
+];
+
+/// Topics that can be replaced at runtime, readers take a snapshot with `current`
+struct LiveTopics {
+    topics: RwLock<Arc<Topics>>,
+}
+
+impl LiveTopics {
+    fn new(topics: Topics) -> Self {
+        Self {
+            topics: RwLock::new(Arc::new(topics)),
+        }
+    }
+
+    fn current(&self) -> Arc<Topics> {
+        self.topics.read().expect("Topics lock is poisoned").clone()
+    }
+
+    fn replace(&self, topics: Topics) {
+        *self.topics.write().expect("Topics lock is poisoned") = Arc::new(topics);
+    }
+}
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct Config {
-    port:   u16,
-    secret: String,
-    topics: Topics,
+    /// Checked and migrated by `config::load` before the rest is deserialized
+    #[serde(rename = "version", default)]
+    _version:             i64,
+    server:               ServerConfig,
+    telegram:             TelegramConfig,
+    topics:               Topics,
+    /// Recipients shared by topics, referenced with `recipient_groups` of a topic
+    #[serde(default)]
+    recipient_groups:     BTreeMap<String, Vec<String>>,
+    /// Display names shown in message headers instead of senders
+    #[serde(default)]
+    senders:              BTreeMap<String, DisplayName>,
+    #[cfg(feature = "redis")]
+    redis:                Option<redis_bridge::RedisConfig>,
+    coordination:         Option<coordination::CoordinationConfig>,
+    /// Directory files are delivered from, for jobs that can't make HTTP requests
+    spool:                Option<spool::SpoolConfig>,
+    /// Listener of `secret|topic|sender|message` lines, for devices that can't speak HTTP
+    lines:                Option<lines::LinesConfig>,
+    /// CoAP endpoint for devices, `POST coap://host/{topic}/{sender}`
+    #[cfg(feature = "coap")]
+    coap:                 Option<coap::CoapConfig>,
+    /// Enables admin API for requests with `Authorization: Bearer <admin_token>` header
+    admin_token:          Option<String>,
+    /// Tokens of admin API with roles, for teams sharing the instance
+    #[serde(default)]
+    admin_tokens:         BTreeMap<String, admin::AdminToken>,
+    /// Automatic banning of addresses that keep getting rejected
+    ban:                  Option<bans::BanConfig>,
+    /// MaxMind country database used by `allow_countries` of topics
+    geoip_database:       Option<PathBuf>,
+    /// How often config given by URL is fetched again to pick up changed topics
+    #[serde(default = "default_config_poll_interval", with = "humantime_serde")]
+    config_poll_interval: Duration,
+    /// In staging every delivery goes to `sandbox_chat` instead of its recipients
+    #[serde(default)]
+    environment:          Environment,
+    sandbox_chat:         Option<String>,
+    /// Mail server of topics that fall back to email
+    #[cfg(feature = "smtp")]
+    smtp:                 Option<smtp::SmtpConfig>,
+    /// Failures injected into Bot API requests
+    #[cfg(feature = "chaos")]
+    chaos:                Option<chaos::ChaosConfig>,
+    /// Proxy Telegram is reached through, unless the bot has its own
+    #[serde(default, deserialize_with = "bot_http::deserialize_proxy")]
+    proxy:                Option<reqwest::Url>,
+    /// Topic that gets alerts about microphone itself, like rejected config changes
+    internal_topic:       Option<String>,
+    /// Startup and graceful shutdown are announced to `internal_topic`
+    #[serde(default)]
+    announce_restarts:    bool,
+    /// User-Agent and headers of requests microphone makes
+    #[serde(default)]
+    outbound:             bot_http::Outbound,
+    /// Copies of accepted requests for archival
+    mirror:               Option<mirror::MirrorConfig>,
+    /// Counts of alert thresholds are kept here across restarts
+    state_file:           Option<PathBuf>,
+    /// Who pressed Ack of which alert and how long it took is appended here
+    ack_log:              Option<PathBuf>,
+    /// Hash-chained audit log of every message sent
+    compliance:           Option<compliance::ComplianceConfig>,
+    /// Identity provider whose tokens authenticate callers of topics and admin API
+    jwt:                  Option<jwt::JwtConfig>,
+    /// Keys of producers managed with admin API
+    api_keys:             Option<api_keys::ApiKeysConfig>,
+    /// Messages posted to topics on schedule
+    #[serde(default)]
+    announcements:        announcements::Announcements,
+    /// How recipients written as `@username` or aliases are resolved to chat ids
+    #[serde(default)]
+    chats:                chats::ChatsConfig,
+    /// Where messages posted to `/{topic}/{sender}/remind` wait to be sent
+    reminders:            Option<reminders::RemindersConfig>,
+    /// Topics whose recipients get messages posted to `/broadcast/{sender}`
+    broadcast:            Option<broadcast::BroadcastConfig>,
+}
+
+fn default_config_poll_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+impl Config {
+    /// Chat deliveries are redirected to, if any
+    fn sandbox_chat(&self) -> Result<Option<String>, String> {
+        match (self.environment, &self.sandbox_chat) {
+            (Environment::Production, _) => Ok(None),
+            (Environment::Staging, Some(sandbox_chat)) => Ok(Some(sandbox_chat.clone())),
+            (Environment::Staging, None) =>
+                Err("environment = \"staging\" requires sandbox_chat".to_owned()),
+        }
+    }
+}
+
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Environment {
+    #[default]
+    Production,
+    Staging,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServerConfig {
+    port:                u16,
+    /// Proxies allowed to tell client address with `Forwarded`, `X-Forwarded-For`
+    /// or `X-Real-IP` headers
+    #[serde(default)]
+    trusted_proxies:     Vec<IpNet>,
+    /// Connections have to start with PROXY protocol header, the address it reports is
+    /// treated as the client address
+    #[serde(default)]
+    proxy_protocol:      bool,
+    /// Posted files over this many bytes are written to temporary files instead of memory
+    #[serde(default = "default_upload_memory_limit")]
+    upload_memory_limit: usize,
+    /// Directory of these temporary files, system temporary directory by default
+    upload_dir:          Option<PathBuf>,
+    #[serde(default)]
+    upload_limits:       UploadLimits,
+    #[serde(default)]
+    connections:         server::Connections,
+}
+
+fn default_upload_memory_limit() -> usize {
+    1024 * 1024
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TelegramConfig {
+    /// Bot token
+    secret:  String,
+    /// How button presses reach the bot
+    #[serde(default)]
+    updates: updates::UpdatesConfig,
+    /// Connections to Bot API
+    #[serde(default)]
+    http:    bot_http::HttpConfig,
 }
 
 #[derive(Debug)]
 #[derive(Deserialize)]
 #[derive(Clone)]
+#[serde(deny_unknown_fields)]
 struct Topic {
-    recipients: Vec<String>,
-    allow_list: Vec<IpNet>,
+    recipients:             Vec<String>,
+    /// Names of groups from `[recipient_groups]` whose members get messages of the topic too
+    #[serde(default)]
+    recipient_groups:       Vec<String>,
+    /// Chats can add themselves to recipients with `/subscribe <topic>` sent to the bot
+    #[serde(default)]
+    subscriptions:          subscriptions::SubscriptionPolicy,
+    #[serde(default)]
+    allow_list:             Vec<IpNet>,
+    /// Hostname patterns like `*.ci.internal` matched against forward-confirmed PTR records
+    #[serde(default)]
+    allow_hosts:            Vec<String>,
+    /// ISO country codes that allowed clients have to come from, requires `geoip_database`
+    #[serde(default)]
+    allow_countries:        Vec<String>,
+    /// Groups of callers with JWT of `[jwt]` that can post from any address
+    #[serde(default)]
+    allow_groups:           Vec<String>,
+    /// Expected value of `X-Gitlab-Token` header for `/gitlab/{topic}` endpoint
+    gitlab_token:           Option<String>,
+    /// Secret used by Gitea to sign payloads for `/gitea/{topic}` endpoint
+    gitea_secret:           Option<String>,
+    /// Client secret of Sentry integration signing payloads for `/sentry/{topic}` endpoint
+    sentry_secret:          Option<String>,
+    /// Alerts for the same Sentry issue within this window are collapsed into one
+    #[serde(default, with = "humantime_serde")]
+    sentry_collapse_window: Option<Duration>,
+    /// Alerts are delivered only once they fire this often
+    alert_threshold:        Option<threshold::Threshold>,
+    /// Repeats of a text message within this window after it was delivered are counted in a
+    /// follow-up instead of being sent
+    #[serde(default, with = "humantime_serde")]
+    aggregate_window:       Option<Duration>,
+    /// Messages accepted for the topic can be followed at `GET /{topic}/stream`
+    #[serde(default)]
+    stream:                 bool,
+    /// Number of messages, their severities and top senders are posted periodically
+    summary:                Option<summary::SummaryConfig>,
+    /// Senders of the topic that sent a heartbeat are reported if the next one is late
+    #[serde(default, with = "humantime_serde")]
+    expect_heartbeat_every: Option<Duration>,
+    /// Periods during which messages are held or dropped
+    #[serde(default)]
+    maintenance:            Vec<MaintenanceWindow>,
+    #[serde(default)]
+    maintenance_mode:       MaintenanceMode,
+    /// Messages over these limits are rejected with 429 until the end of the UTC day
+    max_messages_per_day:   Option<u64>,
+    max_bytes_per_day:      Option<u64>,
+    /// Reply 202 with message id right away and deliver in background, its state is polled with
+    /// `GET /status/{message_id}`
+    #[serde(default)]
+    accept_async:           bool,
+    /// Stages posted messages go through before they are sent
+    #[serde(default)]
+    format:                 Pipeline,
+    /// Links in messages aren't unfurled, `X-Disable-Link-Preview` header overrides it
+    #[serde(default)]
+    disable_link_preview:   bool,
+    /// Messages of the topic can't be forwarded or saved by recipients
+    #[serde(default)]
+    protect_content:        bool,
+    /// Messages with `X-Severity: critical` are pinned, and unpinned once acknowledged
+    #[serde(default)]
+    pin_critical:           bool,
+    /// Text files up to this many bytes are shown in the message as code block
+    inline_files_up_to:     Option<usize>,
+    /// Text files over this many bytes are sent gzipped, with `.gz` suffix
+    gzip_text_over:         Option<usize>,
+    /// Several files posted at once are sent as one zip archive
+    #[serde(default)]
+    bundle:                 bool,
+    /// Images with longer side over this many pixels are sent as documents instead of photos
+    max_photo_dimension:    Option<u32>,
+    /// What happens to the message of an alert fired with `X-Alert-Id` once it's resolved
+    #[serde(default)]
+    resolve_mode:           ResolveMode,
+    /// Effects and reactions of posted messages by `X-Severity`
+    #[serde(default)]
+    styles:                 HashMap<Severity, Style>,
+    /// People mentioned at the end of text messages, by severity and sender
+    #[serde(default)]
+    mentions:               Vec<mentions::MentionRule>,
+    /// Critical messages get Ack button and are escalated if nobody presses it in time
+    escalation:             Option<EscalationConfig>,
+    /// Text messages are followed by voice notes reading them out
+    voice:                  Option<VoiceConfig>,
+    /// Language of texts microphone adds, like the `From:` header and Ack button
+    #[serde(default)]
+    locale:                 Locale,
+    /// Backends text messages are delivered by, later ones only if earlier ones failed
+    #[serde(rename = "try", default = "failover::default_backends")]
+    backends:               Vec<failover::Backend>,
+    /// Addresses that get messages falling back to smtp
+    #[cfg(feature = "smtp")]
+    #[serde(default)]
+    email_recipients:       Vec<String>,
+    /// Bounds of multipart requests instead of ones of the server
+    #[serde(default)]
+    upload_limits:          UploadLimits,
+    /// Rhai script that can rewrite text messages, change their recipients or drop them
+    #[cfg(feature = "scripting")]
+    script:                 Option<script::Script>,
+    /// Program or service text messages are passed through after the script
+    transform:              Option<transform::TransformConfig>,
+    /// Rules dropping, silencing or rerouting text messages before anything else
+    #[serde(default)]
+    filters:                Vec<filter::Filter>,
+    /// `GET /{topic}/{sender}?m=text` sends the text too, for clients that can't POST
+    #[serde(default)]
+    allow_get:              bool,
+    /// Origins of browser pages that can post to the topic with `fetch`, `*` allows any
+    #[serde(default)]
+    cors_origins:           Vec<String>,
+    /// Secret of the `GET /{topic}/submit?token=` page, which posts with it in
+    /// `X-Submit-Token` from any address
+    submit_token:           Option<String>,
 }
 
 impl Topic {
     pub fn is_allowed(&self, address: IpAddr) -> bool {
         self.allow_list.iter().any(|allow| allow.contains(&address))
     }
+
+    pub fn allows_groups(&self, groups: &[String]) -> bool {
+        groups.iter().any(|group| self.allow_groups.contains(group))
+    }
 }
 
 struct TgClient {
-    http_client:      reqwest::Client,
+    http_client:      bot_http::BotHttp,
+    connections:      bot_http::ConnectionTracker,
     base_request_url: String,
+    metrics:          Arc<Metrics>,
+    chats:            chats::Chats,
+    recipient_groups: RecipientGroups,
+    /// Chats that subscribed themselves with `/subscribe`
+    subscribers:      subscriptions::Subscribers,
+    /// Recipients whose chats the bot was kicked from
+    defunct:          defunct::DefunctRecipients,
+    locales:          Locales,
+    /// Chat that gets every delivery instead of its recipient in staging environment
+    sandbox_chat:     Option<String>,
+    senders:          BTreeMap<String, DisplayName>,
+    /// Fallbacks of topics that try other backends after Telegram
+    backends:         failover::Registry,
+    alerts:           InternalAlerts,
+    /// Every text and caption is appended to it before being sent, in compliance mode
+    audit_log:        Option<Arc<compliance::AuditLog>>,
+    /// Full-size images of photos downscaled for their topic, sent on demand
+    originals:        photo::Originals,
+    #[cfg(feature = "chaos")]
+    chaos:            Option<chaos::ChaosConfig>,
 }
 
 impl TgClient {
-    pub fn new(secret: String) -> Self {
-        let http_client = ClientBuilder::new()
-            .timeout(std::time::Duration::from_secs(10))
-            .user_agent("reqwest")
-            .build()
-            .expect("Failed to build http client");
-
+    pub fn new(secret: String, http: &bot_http::HttpConfig, metrics: Arc<Metrics>) -> Self {
         let base_request_url = format!("{}/bot{}", TELEGRAM_API_BASE_URL, secret);
 
         Self {
-            http_client,
+            http_client: bot_http::BotHttp::new(http, TELEGRAM_API_BASE_URL),
+            connections: bot_http::ConnectionTracker::default(),
             base_request_url,
+            metrics,
+            chats: chats::Chats::default(),
+            recipient_groups: RecipientGroups::default(),
+            subscribers: subscriptions::Subscribers::default(),
+            defunct: defunct::DefunctRecipients::default(),
+            locales: Locales::default(),
+            sandbox_chat: None,
+            senders: BTreeMap::new(),
+            backends: failover::Registry::default(),
+            alerts: InternalAlerts::default(),
+            audit_log: None,
+            originals: photo::Originals::default(),
+            #[cfg(feature = "chaos")]
+            chaos: None,
         }
     }
 
-    async fn send_message(
-        &self,
-        recipient: &str,
-        topic: &str,
-        sender: &str,
-        text: &str,
-    ) -> Result<reqwest::Response, reqwest::Error> {
-        self.http_client
-            .post(&format!(
-                "{}/{}",
-                self.base_request_url, TELEGRAM_SEND_MESSAGE_METHOD
-            ))
-            .json(&SendMessagePayload::new(
-                recipient,
-                &format!(
-                    "From: *{}@{}*\n\n{}",
-                    *TgMarkdownString::new(sender),
-                    topic,
-                    text
-                ),
-            ))
-            .send()
-            .await
+    #[cfg(feature = "chaos")]
+    pub fn with_chaos(mut self, chaos: Option<chaos::ChaosConfig>) -> Self {
+        self.chaos = chaos;
+        self
     }
 
-    async fn send_message_to_all(
-        &self,
-        recipients: &[String],
-        topic: &str,
-        sender: &str,
-        text: &str,
-    ) -> Vec<Result<reqwest::Response, reqwest::Error>> {
-        futures::future::join_all(
-            recipients
-                .iter()
-                .map(|recipient| self.send_message(recipient, topic, sender, text))
-                .collect::<Vec<_>>(),
-        )
-        .await
+    pub fn with_sandbox_chat(mut self, sandbox_chat: Option<String>) -> Self {
+        self.sandbox_chat = sandbox_chat;
+        self
     }
 
-    async fn send_document(
-        &self,
-        recipient: &str,
-        topic: &str,
-        sender: &str,
-        message: &str,
-        filename: &str,
-        file_content: &[u8],
-    ) -> Result<reqwest::Response, reqwest::Error> {
-        let caption = format!(
-            "From: *{}@{}*\n\n{}",
-            *TgMarkdownString::new(sender),
-            topic,
-            message
-        );
+    pub fn with_senders(mut self, senders: BTreeMap<String, DisplayName>) -> Self {
+        self.senders = senders;
+        self
+    }
 
-        let form = Form::new()
-            .text("chat_id", recipient.to_owned())
-            .text("caption", caption)
-            .text("parse_mode", TELEGRAM_MARKDOWN_V2_PARSE_MODE)
-            .part(
-                "document",
-                Part::bytes(file_content.to_owned()).file_name(filename.to_owned()),
-            );
+    pub fn with_chats(mut self, chats: chats::Chats) -> Self {
+        self.chats = chats;
+        self
+    }
 
-        self.http_client
-            .post(&format!(
-                "{}/{}",
-                self.base_request_url, TELEGRAM_SEND_DOCUMENT_METHOD
-            ))
-            .multipart(form)
-            .send()
-            .await
+    pub fn with_recipient_groups(mut self, groups: BTreeMap<String, Vec<String>>) -> Self {
+        self.recipient_groups = RecipientGroups::new(groups);
+        self
     }
 
-    async fn send_document_to_all(
-        &self,
-        recipients: &[String],
-        topic: &str,
-        sender: &str,
-        message: &str,
-        filename: &str,
-        file_content: &[u8],
-    ) -> Vec<Result<reqwest::Response, reqwest::Error>> {
-        futures::future::join_all(
-            recipients
-                .iter()
-                .map(|recipient| {
-                    self.send_document(recipient, topic, sender, message, filename, file_content)
-                })
-                .collect::<Vec<_>>(),
-        )
-        .await
+    pub fn with_backends(mut self, backends: failover::Registry) -> Self {
+        self.backends = backends;
+        self
     }
-}
 
-#[derive(Serialize)]
-struct TgMarkdownString(String);
+    pub fn with_internal_alerts(mut self, alerts: InternalAlerts) -> Self {
+        self.http_client = self.http_client.with_alerts(alerts.clone());
+        self.alerts = alerts;
+        self
+    }
 
-impl std::ops::Deref for TgMarkdownString {
-    type Target = String;
+    pub fn with_audit_log(mut self, audit_log: Option<Arc<compliance::AuditLog>>) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    pub fn with_locales(mut self, topics: &Topics) -> Self {
+        self.locales = Locales::of_topics(topics);
+        self
     }
-}
 
-impl TgMarkdownString {
-    pub fn new(s: &str) -> Self {
-        let need_to_escape = [
-            '_', '*', '[', ']', '(', ')', '~', '`', '>', '#', '+', '-', '=', '|', '{', '}', '.',
-            '!',
-        ];
-        let mut escaped_string = String::new();
+    /// Language of texts added to messages of the topic
+    pub fn locale(&self, topic: &str) -> Locale {
+        self.locales.of(topic)
+    }
 
-        for ch in s.chars() {
-            if need_to_escape.contains(&ch) {
-                escaped_string.push('\\');
+    /// Recipients of the topic and its groups, and chats that subscribed to it if it's open.
+    /// Defunct recipients are left out
+    pub fn recipients_of(&self, topic: &str, topic_info: &Topic) -> Vec<String> {
+        let mut recipients = self.recipient_groups.recipients_of(topic_info);
+        if topic_info.subscriptions == subscriptions::SubscriptionPolicy::Open {
+            for subscriber in self.subscribers.of(topic) {
+                if !recipients.contains(&subscriber) {
+                    recipients.push(subscriber);
+                }
             }
-            escaped_string.push(ch);
         }
+        recipients.retain(|recipient| !self.defunct.contains(recipient));
 
-        Self(escaped_string)
+        recipients
     }
-}
 
-#[derive(Serialize)]
-struct SendMessagePayload<'a> {
-    chat_id:    &'a str,
-    parse_mode: &'static str,
-    text:       String,
-}
+    /// Marks the recipient defunct if Telegram rejected the delivery as the bot can't write to its
+    /// chat, the internal topic is told once. Staging deliveries all go to the sandbox chat
+    async fn noticed(
+        &self,
+        recipient: &str,
+        chat_id: &str,
+        response: reqwest::Response,
+    ) -> reqwest::Response {
+        if self.sandbox_chat.is_some()
+            || !matches!(
+                response.status(),
+                StatusCode::BAD_REQUEST | StatusCode::FORBIDDEN
+            )
+        {
+            return response;
+        }
 
-impl<'a> SendMessagePayload<'a> {
-    pub fn new(chat_id: &'a str, text: &str) -> Self {
-        Self {
-            chat_id,
-            text: text.to_owned(),
-            parse_mode: TELEGRAM_MARKDOWN_V2_PARSE_MODE,
+        let (response, body) = buffered(response).await;
+        if let Some(reason) = defunct::reason(&body) {
+            if self.defunct.mark(recipient, chat_id, &reason) {
+                log::warn!(
+                    "Recipient {} is defunct, not delivering to it anymore: {}",
+                    recipient,
+                    reason
+                );
+                self.alerts.raise(Incident::RecipientDefunct {
+                    recipient: recipient.to_owned(),
+                    reason,
+                });
+            }
         }
-    }
-}
 
-#[actix_web::main]
-async fn main() -> Result<(), std::io::Error> {
-    let config_path = std::env::args()
-        .nth(1)
-        .expect("Provide config file path as the first argument to the program");
+        response
+    }
 
-    let config: Config =
-        toml::from_str(&std::fs::read_to_string(config_path).expect("Failed to read config file"))
-            .expect("Failed to parse config file");
+    fn context<'a>(&'a self, topic: &'a str, sender: &'a str) -> format::Context<'a> {
+        format::Context {
+            topic,
+            sender,
+            sender_name: self.senders.get(sender),
+            locale: self.locale(topic),
+        }
+    }
 
-    let topics_data = web::Data::new(Arc::new(config.topics.clone()));
+    fn render(
+        &self,
+        pipeline: &Pipeline,
+        topic: &str,
+        sender: &str,
+        text: &str,
+        options: &SendOptions,
+    ) -> Vec<String> {
+        let context = self.context(topic, sender);
+        let mut chunks = match options.code_language {
+            Some(language) => pipeline.render_code(&context, text, language),
+            None => pipeline.render(&context, text),
+        };
+        if let (Some(mentions), Some(last)) = (options.mentions, chunks.last_mut()) {
+            last.push_str("\n\n");
+            last.push_str(mentions);
+        }
 
-    let tg_data = web::Data::new(Arc::new(TgClient::new(config.secret)));
+        chunks
+    }
 
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    /// Chat the delivery to recipient actually goes to
+    async fn chat_id(&self, recipient: &str) -> String {
+        match &self.sandbox_chat {
+            Some(sandbox_chat) => sandbox_chat.clone(),
+            None => self.chats.resolve(self, recipient).await,
+        }
+    }
 
-    const MAIN_RESOURCE_PATH: &str = "/{topic_name}/{sender}";
+    /// In staging the text also tells who the message was meant for
+    fn sandboxed(&self, recipient: &str, topic: &str, text: &str) -> String {
+        match &self.sandbox_chat {
+            Some(_) => {
+                let locale = self.locale(topic);
+                format!(
+                    "🧪 *{}*, {}\n{}",
+                    locale.text(Phrase::Staging),
+                    locale.fill(
+                        Phrase::MeantFor,
+                        &[TgMarkdownString::code(recipient).as_str()]
+                    ),
+                    text
+                )
+            }
+            None => text.to_owned(),
+        }
+    }
 
-    HttpServer::new(move || {
-        App::new()
-            .wrap(Logger::default())
-            .app_data(topics_data.clone())
-            .app_data(tg_data.clone())
-            .app_data(PayloadConfig::new(50 * 1000 * 1000))
-            .service(
-                web::resource(MAIN_RESOURCE_PATH)
-                    .guard(guard::fn_guard(|ctx| {
-                        ctx.header::<header::ContentType>()
-                            .map(|val| val.0.to_string().contains("multipart/form-data"))
-                            .unwrap_or(false)
-                    }))
-                    .route(web::post().to(post_message_with_document)),
-            )
-            .service(
-                web::resource(MAIN_RESOURCE_PATH)
-                    .route(web::post().to(post_message)),
-            )
-    })
-    .workers(1)
-    .bind(("0.0.0.0", config.port))?
-    .run()
-    .await
-}
+    /// Every Bot API request goes through here
+    /// Makes the request, and again while it fails transiently. Each attempt gets the request
+    /// built anew, so files of multipart ones are read from the beginning
+    async fn send<F>(&self, request: F) -> Result<reqwest::Response, reqwest::Error>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let mut retries = 0;
+        loop {
+            let (result, delay) = retry_delay(self.send_once(request()).await).await;
+            match delay {
+                Some(delay) if retries < DELIVERY_RETRIES => {
+                    retries += 1;
+                    let _ = RETRIES.try_with(|count| count.set(count.get() + 1));
+                    sleep(delay).await;
+                }
+                _ => return result,
+            }
+        }
+    }
 
-#[derive(Deserialize)]
-struct PostPathData {
-    topic_name: String,
-    sender:     String,
-}
+    async fn send_once(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        #[cfg(feature = "chaos")]
+        if let Some(fault) = self.chaos.as_ref().and_then(chaos::ChaosConfig::fault) {
+            return fault.inject(request).await;
+        }
 
-fn extract_client_address(connection_info: ConnectionInfo) -> Result<IpAddr, HttpResponse> {
-    let client_address =
-        if let Some(ip_address_string) = connection_info.realip_remote_addr() {
-            match ip_address_string.parse() {
-                Ok(ip_address) => ip_address,
-                Err(_) =>
-                    return Err(HttpResponse::InternalServerError()
-                        .body("Cannot parse ip address from string")),
-            }
-        } else {
-            return Err(HttpResponse::InternalServerError()
-                .body("Cannot get ip address string from request"));
-        };
+        let response = self.http_client.send(request).await?;
+        if self.connections.is_new(&response) {
+            self.metrics
+                .increment("microphone_telegram_connections_total", &[]);
+        }
 
-    Ok(client_address)
-}
+        Ok(response)
+    }
 
-async fn post_message(
-    connection_info: ConnectionInfo,
-    topics: web::Data<Arc<Topics>>,
-    tg_client: web::Data<Arc<TgClient>>,
-    post_query: web::Path<PostPathData>,
-    message: String,
-) -> impl Responder {
-    let client_address: IpAddr =
-        if let Some(ip_address_string) = connection_info.realip_remote_addr() {
-            match ip_address_string.parse() {
-                Ok(ip_address) => ip_address,
-                Err(_) =>
-                    return HttpResponse::InternalServerError()
-                        .body("Cannot parse ip address from string"),
-            }
-        } else {
-            return HttpResponse::InternalServerError()
-                .body("Cannot get ip address string from request");
+    /// Whether the request carrying the text can be made, in compliance mode only once it's in
+    /// the audit log
+    fn audited(
+        &self,
+        topic: &str,
+        chat_id: &str,
+        method: &str,
+        text: &str,
+        attachment_bytes: Option<usize>,
+    ) -> bool {
+        let audit_log = match &self.audit_log {
+            Some(audit_log) => audit_log,
+            None => return true,
         };
 
-    match topics.get(&post_query.topic_name) {
-        Some(topic_info) if topic_info.is_allowed(client_address) => {
-            let responses = tg_client
-                .send_message_to_all(
-                    &topic_info.recipients,
-                    &post_query.topic_name,
-                    &post_query.sender,
-                    &message,
-                )
-                .await;
-
-            if responses.iter().all(|res| {
-                res.as_ref()
-                    .map_or_else(|_| false, |resp| resp.status() == StatusCode::OK)
-            }) {
-                HttpResponse::NoContent().finish()
-            } else {
-                HttpResponse::InternalServerError().body("bAdBaDnOtGoOd")
+        match audit_log.record(topic, chat_id, method, text, attachment_bytes) {
+            Ok(()) => true,
+            Err(err) => {
+                log::error!(
+                    "Not sending message of \"{}\", failed to append it to audit log: {}",
+                    topic,
+                    err
+                );
+                false
             }
         }
-        _ => HttpResponse::NotFound().body("No such topic"),
     }
-}
 
-async fn post_message_with_document(
-    connection_info: ConnectionInfo,
-    topics: web::Data<Arc<Topics>>,
-    tg_client: web::Data<Arc<TgClient>>,
-    path_data: web::Path<PostPathData>,
-    mut multipart: actix_multipart::Multipart,
-) -> impl Responder {
-    let client_address = match extract_client_address(connection_info) {
-        Ok(client_address) => client_address,
-        Err(err_response) => return err_response,
-    };
-
-    let mut message: Option<String> = None;
-    let mut file_content = Vec::new();
-    let mut filename = String::new();
+    /// Sends text message the way it's audited
+    async fn send_text(
+        &self,
+        topic: &str,
+        payload: &SendMessagePayload<'_>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        if !self.audited(
+            topic,
+            payload.chat_id,
+            TELEGRAM_SEND_MESSAGE_METHOD,
+            &payload.text,
+            None,
+        ) {
+            return Ok(compliance::unaudited());
+        }
 
-    while let Some(item) = multipart.next().await {
-        let mut field = match item {
-            Ok(field) => field,
-            Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
-        };
+        self.send(|| self.message_request(topic, payload)).await
+    }
 
-        match field.name() {
-            "message" => {
-                let mut message_bytes_buffer: Vec<u8> = Vec::new();
-                while let Some(chunk) = field.next().await {
-                    let chunk_bytes = match chunk {
-                        Ok(chunk_bytes) => chunk_bytes,
-                        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
-                    };
+    fn count_egress(&self, topic: &str, bytes: usize) {
+        self.metrics.add(
+            "microphone_egress_bytes_total",
+            &[("topic", topic)],
+            bytes as u64,
+        );
+    }
 
-                    message_bytes_buffer.extend(chunk_bytes);
-                }
+    /// Sends rendered chunks one by one, effect, reaction and pin go to the first one, reply markup
+    /// to the last one. Stops at the first chunk Telegram doesn't accept. Response of delivered
+    /// message carries `SentMessage` of the first chunk
+    async fn send_message(
+        &self,
+        recipient: &str,
+        topic: &str,
+        chunks: &[String],
+        options: SendOptions<'_>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let chat_id = self.chat_id(recipient).await;
+        let mut first_message = None;
+        let mut last_response = None;
+        let mut message_ids = Vec::with_capacity(chunks.len());
 
-                message = match String::from_utf8(message_bytes_buffer) {
-                    Ok(message) => Some(message),
-                    Err(_) => return HttpResponse::BadRequest().body("Message is not valid UTF-8"),
+        for (index, chunk) in chunks.iter().enumerate() {
+            let text = self.sandboxed(recipient, topic, chunk);
+            let mut payload = SendMessagePayload::new(&chat_id, &text);
+            payload.link_preview_options = LinkPreviewOptions::of(&options);
+            payload.protect_content = options.protect_content;
+            payload.disable_notification = options.silent;
+            if index == 0 {
+                payload.reply_parameters = options.reply_to.map(ReplyParameters::to);
+                if is_private_chat(&chat_id) {
+                    payload.message_effect_id = options.effect;
                 }
             }
-            "file" => {
-                filename = match field.content_disposition().get_filename() {
-                    Some(filename) => filename.to_owned(),
-                    None => return HttpResponse::BadRequest().body("Multipart filename missing"),
-                };
-                while let Some(chunk) = field.next().await {
-                    match chunk {
-                        Ok(chunk_bytes) => file_content.extend(chunk_bytes),
-                        Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+            if index + 1 == chunks.len() {
+                payload.reply_markup = options.reply_markup;
+            }
+
+            let mut response = self.send_text(topic, &payload).await?;
+            if response.status() == StatusCode::BAD_REQUEST {
+                let (rejected, description) = parse_error(response).await;
+                response = match description {
+                    Some(description) => {
+                        payload.text = self.downgraded(topic, &payload.text, &description);
+                        payload.parse_mode = None;
+                        self.send_text(topic, &payload).await?
                     }
-                }
+                    None => rejected,
+                };
             }
-            field_name =>
-                return HttpResponse::BadRequest()
-                    .body(format!("Unexpected mutlipart field \"{}\"", field_name)),
-        };
-    }
+            if response.status() != StatusCode::OK {
+                return Ok(self.noticed(recipient, &chat_id, response).await);
+            }
+            let (response, message_id) = read_message_id(response).await;
+            message_ids.extend(message_id);
+            if index == 0 {
+                first_message = message_id.map(|message_id| SentMessage {
+                    recipient: recipient.to_owned(),
+                    chat_id: chat_id.clone(),
+                    message_id,
+                    text,
+                });
+            }
+            last_response = Some(response);
+        }
 
-    let message = message.unwrap_or_default();
+        let mut response = last_response.expect("Rendered message has at least one chunk");
+        response.extensions_mut().insert(TelegramMessages {
+            chat_id,
+            message_ids,
+        });
+        if let Some(sent) = first_message {
+            if let Some(reaction) = options.reaction {
+                self.react(&sent, reaction).await;
+            }
+            if options.pin {
+                self.pin(&sent).await;
+            }
+            response.extensions_mut().insert(sent);
+        }
 
-    if file_content.is_empty() {
-        return HttpResponse::BadRequest().body("Multipart no file provided");
+        Ok(response)
     }
 
-    match topics.get(&path_data.topic_name) {
-        Some(topic_info) if topic_info.is_allowed(client_address) => {
-            let responses = tg_client
+    fn message_request(
+        &self,
+        topic: &str,
+        payload: &SendMessagePayload<'_>,
+    ) -> reqwest::RequestBuilder {
+        let body = serde_json::to_vec(payload).expect("Failed to serialize message");
+        self.count_egress(topic, body.len());
+
+        self.http_client
+            .post(format!(
+                "{}/{}",
+                self.base_request_url, TELEGRAM_SEND_MESSAGE_METHOD
+            ))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+    }
+
+    /// Plain text of markup Telegram couldn't parse, with a footer saying formatting was dropped
+    fn downgraded(&self, topic: &str, markdown: &str, description: &str) -> String {
+        self.metrics
+            .increment("microphone_parse_errors_total", &[("topic", topic)]);
+        log::warn!(
+            "Telegram couldn't parse message of \"{}\", sending it as plain text: {}",
+            topic,
+            description
+        );
+        log::debug!(
+            "Unparsable message of \"{}\": {}",
+            topic,
+            format::masked(markdown)
+        );
+
+        format!(
+            "{}\n\n{}",
+            format::plain_text(markdown),
+            self.locale(topic).text(Phrase::FormattingDropped)
+        )
+    }
+
+    /// Sends multipart request with caption, once more with plain caption if Telegram couldn't
+    /// parse it. The form is made with or without parse mode
+    async fn send_captioned<F>(
+        &self,
+        topic: &str,
+        chat_id: &str,
+        method: &str,
+        caption: String,
+        attachment_len: usize,
+        form: F,
+    ) -> Result<reqwest::Response, reqwest::Error>
+    where
+        F: Fn(String, bool) -> Form,
+    {
+        let form = &form;
+        let send = |caption: String, markdown: bool| async move {
+            if !self.audited(topic, chat_id, method, &caption, Some(attachment_len)) {
+                return Ok(compliance::unaudited());
+            }
+            self.count_egress(topic, caption.len() + attachment_len);
+            let url = format!("{}/{}", self.base_request_url, method);
+
+            self.send(|| {
+                self.http_client
+                    .post(url.clone())
+                    .multipart(form(caption.clone(), markdown))
+            })
+            .await
+        };
+
+        let response = send(caption.clone(), true).await?;
+        if response.status() != StatusCode::BAD_REQUEST {
+            return Ok(response);
+        }
+        match parse_error(response).await {
+            (_, Some(description)) => {
+                let caption = self.downgraded(topic, &caption, &description);
+                send(caption, false).await
+            }
+            (response, None) => Ok(response),
+        }
+    }
+
+    async fn react(&self, sent: &SentMessage, emoji: &str) {
+        self.call_quietly(
+            TELEGRAM_SET_MESSAGE_REACTION_METHOD,
+            &serde_json::json!({
+                "chat_id": sent.chat_id,
+                "message_id": sent.message_id,
+                "reaction": [{ "type": "emoji", "emoji": emoji }],
+            }),
+        )
+        .await;
+    }
+
+    /// Bot has to be allowed to pin messages in groups and channels
+    async fn pin(&self, sent: &SentMessage) {
+        self.call_quietly(
+            TELEGRAM_PIN_CHAT_MESSAGE_METHOD,
+            &serde_json::json!({
+                "chat_id": sent.chat_id,
+                "message_id": sent.message_id,
+                "disable_notification": true,
+            }),
+        )
+        .await;
+    }
+
+    async fn unpin(&self, sent: &SentMessage) {
+        self.call_quietly(
+            TELEGRAM_UNPIN_CHAT_MESSAGE_METHOD,
+            &serde_json::json!({
+                "chat_id": sent.chat_id,
+                "message_id": sent.message_id,
+            }),
+        )
+        .await;
+    }
+
+    async fn edit_text(
+        &self,
+        topic: &str,
+        sent: &SentMessage,
+        text: String,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        if !self.audited(
+            topic,
+            &sent.chat_id,
+            TELEGRAM_EDIT_MESSAGE_TEXT_METHOD,
+            &text,
+            None,
+        ) {
+            return Ok(compliance::unaudited());
+        }
+
+        self.call_method(
+            TELEGRAM_EDIT_MESSAGE_TEXT_METHOD,
+            &serde_json::json!({
+                "chat_id": sent.chat_id,
+                "message_id": sent.message_id,
+                "text": text,
+                "parse_mode": TELEGRAM_MARKDOWN_V2_PARSE_MODE,
+            }),
+        )
+        .await
+    }
+
+    /// For calls about delivered messages, failing them doesn't fail the delivery
+    async fn call_quietly(&self, method: &str, payload: &serde_json::Value) {
+        match self.call_method(method, payload).await {
+            Ok(response) if response.status() == StatusCode::OK => (),
+            Ok(response) => log::warn!(
+                "Telegram responded with {} to {}",
+                response.status(),
+                method
+            ),
+            Err(err) => log::warn!("Failed to call {}: {}", method, err.without_url()),
+        }
+    }
+
+    /// Calls arbitrary Bot API method with JSON payload
+    async fn call_method<T: Serialize>(
+        &self,
+        method: &str,
+        payload: &T,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let url = format!("{}/{}", self.base_request_url, method);
+
+        self.send(|| self.http_client.post(url.clone()).json(payload))
+            .await
+    }
+
+    /// Posted messages, rendered by the pipeline of their topic
+    async fn send_formatted_to_all(
+        &self,
+        recipients: &[String],
+        pipeline: &Pipeline,
+        topic: &str,
+        sender: &str,
+        text: &str,
+        options: SendOptions<'_>,
+    ) -> Vec<Delivery> {
+        let chunks = self.render(pipeline, topic, sender, text, &options);
+
+        deliver_to_all(recipients, |recipient| {
+            self.send_message(recipient, topic, &chunks, options)
+        })
+        .await
+    }
+
+    /// Messages composed by microphone itself, which only get the header
+    async fn send_message_with_markup_to_all(
+        &self,
+        recipients: &[String],
+        topic: &str,
+        sender: &str,
+        text: &str,
+        reply_markup: &serde_json::Value,
+    ) -> Vec<Delivery> {
+        self.send_formatted_to_all(
+            recipients,
+            &Pipeline::default(),
+            topic,
+            sender,
+            text,
+            SendOptions {
+                reply_markup: Some(reply_markup),
+                ..SendOptions::default()
+            },
+        )
+        .await
+    }
+
+    async fn send_message_to_all(
+        &self,
+        recipients: &[String],
+        topic: &str,
+        sender: &str,
+        text: &str,
+    ) -> Vec<Delivery> {
+        self.send_formatted_to_all(
+            recipients,
+            &Pipeline::default(),
+            topic,
+            sender,
+            text,
+            SendOptions::default(),
+        )
+        .await
+    }
+
+    async fn send_document(
+        &self,
+        recipient: &str,
+        topic: &str,
+        caption: &str,
+        filename: &str,
+        file_content: &Content,
+        options: SendOptions<'_>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let caption = self.sandboxed(recipient, topic, caption);
+        let chat_id = self.chat_id(recipient).await;
+        let form = |caption, markdown| {
+            let form = captioned_form(&chat_id, caption, markdown)
+                .part("document", file_content.part(filename));
+            with_options(form, options)
+        };
+
+        let response = self
+            .send_captioned(
+                topic,
+                &chat_id,
+                TELEGRAM_SEND_DOCUMENT_METHOD,
+                caption,
+                file_content.len(),
+                form,
+            )
+            .await?;
+        let response = self.noticed(recipient, &chat_id, response).await;
+
+        Ok(with_telegram_messages(response, chat_id).await)
+    }
+
+    /// Text file is shown as code block after the rendered message instead of as attachment.
+    /// The block goes to a message of its own if the last chunk has no room for it, and the file
+    /// is attached after all if the escaped block doesn't fit in any message
+    async fn send_inline_file_to_all(
+        &self,
+        recipients: &[String],
+        pipeline: &Pipeline,
+        topic: &str,
+        sender: &str,
+        message: &str,
+        filename: &str,
+        content: &str,
+        options: SendOptions<'_>,
+    ) -> Vec<Delivery> {
+        let block = format!(
+            "{}\n{}",
+            *TgMarkdownString::code(filename),
+            *TgMarkdownString::pre(
+                content,
+                options
+                    .code_language
+                    .or_else(|| format::language_of(filename))
+            )
+        );
+        let block_length = block.chars().count();
+        if block_length > TELEGRAM_MESSAGE_LIMIT {
+            let files = [(
+                filename.to_owned(),
+                Content::Memory(content.as_bytes().to_vec()),
+            )];
+            return self
                 .send_document_to_all(
-                    &topic_info.recipients,
-                    &path_data.topic_name,
-                    &path_data.sender,
+                    recipients, pipeline, topic, sender, message, &files, options,
+                )
+                .await;
+        }
+
+        let mut chunks = pipeline.render(&self.context(topic, sender), message);
+        let last = chunks
+            .last_mut()
+            .expect("Rendered message has at least one chunk");
+        let separator = match last.as_str() {
+            "" => "",
+            last if last.ends_with('\n') => "",
+            _ => "\n\n",
+        };
+        if last.chars().count() + separator.len() + block_length <= TELEGRAM_MESSAGE_LIMIT {
+            last.push_str(separator);
+            last.push_str(&block);
+        } else {
+            chunks.push(block);
+        }
+
+        deliver_to_all(recipients, |recipient| {
+            self.send_message(recipient, topic, &chunks, options)
+        })
+        .await
+    }
+
+    /// Captions aren't split, chunks of the rendered message are joined back. Of several files
+    /// only the first one gets the caption
+    async fn send_document_to_all(
+        &self,
+        recipients: &[String],
+        pipeline: &Pipeline,
+        topic: &str,
+        sender: &str,
+        message: &str,
+        files: &[Attachment],
+        options: SendOptions<'_>,
+    ) -> Vec<Delivery> {
+        let caption = &pipeline
+            .render(&self.context(topic, sender), message)
+            .concat();
+
+        deliver_to_all(recipients, |recipient| async move {
+            let mut last_response = None;
+            let mut message_ids = Vec::with_capacity(files.len());
+            for (index, (filename, content)) in files.iter().enumerate() {
+                let caption = if index == 0 { caption.as_str() } else { "" };
+                let response = self
+                    .send_document(recipient, topic, caption, filename, content, options)
+                    .await?;
+                if response.status() != StatusCode::OK {
+                    return Ok(response);
+                }
+                if let Some(sent) = response.extensions().get::<TelegramMessages>() {
+                    message_ids.extend(&sent.message_ids);
+                }
+                last_response = Some(response);
+            }
+
+            let mut response = last_response.expect("Documents are sent with at least one file");
+            if let Some(sent) = response.extensions_mut().get_mut::<TelegramMessages>() {
+                sent.message_ids = message_ids;
+            }
+
+            Ok(response)
+        })
+        .await
+    }
+
+    async fn send_photo(
+        &self,
+        recipient: &str,
+        topic: &str,
+        caption: &str,
+        photo: &[u8],
+        options: SendOptions<'_>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let caption = self.sandboxed(recipient, topic, caption);
+        let chat_id = self.chat_id(recipient).await;
+        let form = |caption, markdown| {
+            let form = captioned_form(&chat_id, caption, markdown)
+                .part("photo", Part::bytes(photo.to_owned()).file_name("photo"));
+            let form = match options.reply_markup {
+                Some(reply_markup) => form.text("reply_markup", reply_markup.to_string()),
+                None => form,
+            };
+            with_options(form, options)
+        };
+
+        let response = self
+            .send_captioned(
+                topic,
+                &chat_id,
+                TELEGRAM_SEND_PHOTO_METHOD,
+                caption,
+                photo.len(),
+                form,
+            )
+            .await?;
+        let response = self.noticed(recipient, &chat_id, response).await;
+
+        Ok(with_telegram_messages(response, chat_id).await)
+    }
+
+    async fn send_voice(
+        &self,
+        recipient: &str,
+        topic: &str,
+        voice: &[u8],
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let caption = self.sandboxed(recipient, topic, "");
+        let chat_id = self.chat_id(recipient).await;
+        let form = |caption, markdown| {
+            captioned_form(&chat_id, caption, markdown)
+                .part("voice", Part::bytes(voice.to_owned()).file_name("voice"))
+        };
+
+        let response = self
+            .send_captioned(
+                topic,
+                &chat_id,
+                TELEGRAM_SEND_VOICE_METHOD,
+                caption,
+                voice.len(),
+                form,
+            )
+            .await?;
+
+        Ok(self.noticed(recipient, &chat_id, response).await)
+    }
+
+    async fn send_photo_to_all(
+        &self,
+        recipients: &[String],
+        topic: &str,
+        sender: &str,
+        caption: &str,
+        photo: &[u8],
+        options: SendOptions<'_>,
+    ) -> Vec<Delivery> {
+        let original = photo;
+        let max_dimension = options.max_photo_dimension;
+        let downscaled = if photo::fits(photo, max_dimension) {
+            None
+        } else {
+            let image = photo.to_vec();
+            Some(
+                web::block(move || photo::downscaled(&image, max_dimension))
+                    .await
+                    .unwrap_or_else(|err| Err(err.to_string())),
+            )
+        };
+        let photo = match downscaled {
+            None => photo,
+            Some(Ok(ref downscaled)) => downscaled.as_slice(),
+            Some(Err(err)) => {
+                log::info!("Photo of {} is sent as document, {}", topic, err);
+                let files = [(
+                    photo::filename(photo).to_owned(),
+                    Content::Memory(photo.to_vec()),
+                )];
+                return self
+                    .send_document_to_all(
+                        recipients,
+                        &Pipeline::default(),
+                        topic,
+                        sender,
+                        caption,
+                        &files,
+                        options,
+                    )
+                    .await;
+            }
+        };
+        let caption = Pipeline::default()
+            .render(&self.context(topic, sender), caption)
+            .concat();
+        // Photos downscaled only to fit limits of Telegram keep no original
+        let original_markup = (downscaled.is_some() && max_dimension.is_some()).then(|| {
+            let key = self.originals.keep(topic, original);
+            photo::original_markup(&key, self.locale(topic))
+        });
+        let options = SendOptions {
+            reply_markup: original_markup.as_ref().or(options.reply_markup),
+            ..options
+        };
+
+        deliver_to_all(recipients, |recipient| {
+            self.send_photo(recipient, topic, &caption, photo, options)
+        })
+        .await
+    }
+
+    /// Original of a downscaled photo, sent as document to the chat that asked for it
+    async fn send_original(
+        &self,
+        chat_id: i64,
+        original: &photo::Original,
+        options: SendOptions<'_>,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let content = Content::Memory(original.image.clone());
+
+        self.send_document(
+            &chat_id.to_string(),
+            &original.topic,
+            "",
+            photo::filename(&original.image),
+            &content,
+            options,
+        )
+        .await
+    }
+}
+
+/// Adds fields of options multipart requests support
+fn captioned_form(chat_id: &str, caption: String, markdown: bool) -> Form {
+    let form = Form::new()
+        .text("chat_id", chat_id.to_owned())
+        .text("caption", caption);
+    if markdown {
+        form.text("parse_mode", TELEGRAM_MARKDOWN_V2_PARSE_MODE)
+    } else {
+        form
+    }
+}
+
+fn with_options(form: Form, options: SendOptions) -> Form {
+    let form = if options.protect_content {
+        form.text("protect_content", "true")
+    } else {
+        form
+    };
+    if options.silent {
+        form.text("disable_notification", "true")
+    } else {
+        form
+    }
+}
+
+/// Message Telegram accepted, later requests about it refer to it by these
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+struct SentMessage {
+    recipient:  String,
+    chat_id:    String,
+    message_id: i64,
+    /// Text as it was sent, to edit the message later
+    text:       String,
+}
+
+/// Every Telegram message a delivery became, several for chunked texts and bundles of files. Kept
+/// under the id of the posted message, so it can be deleted from all chats at once
+#[derive(Clone)]
+#[derive(Serialize)]
+struct TelegramMessages {
+    chat_id:     String,
+    message_ids: Vec<i64>,
+}
+
+/// Chats with users have positive ids, groups and channels negative ones or usernames
+fn is_private_chat(chat_id: &str) -> bool {
+    chat_id.parse::<i64>().is_ok_and(|id| id > 0)
+}
+
+/// Reads the body, the response is rebuilt around it so it can be passed on
+async fn buffered(response: reqwest::Response) -> (reqwest::Response, web::Bytes) {
+    let status = response.status();
+    let headers = response.headers().clone();
+    // Message is delivered even if the body can't be read
+    let body = response.bytes().await.unwrap_or_default();
+
+    let mut rebuilt = http::Response::new(body.clone());
+    *rebuilt.status_mut() = status;
+    *rebuilt.headers_mut() = headers;
+
+    (rebuilt.into(), body)
+}
+
+/// Description of the response if Telegram rejected the request for its markup
+async fn parse_error(response: reqwest::Response) -> (reqwest::Response, Option<String>) {
+    let (response, body) = buffered(response).await;
+    let description = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|body| body["description"].as_str().map(str::to_owned))
+        .filter(|description| {
+            let description = description.to_lowercase();
+            TELEGRAM_PARSE_ERRORS
+                .iter()
+                .any(|error| description.contains(error))
+        });
+
+    (response, description)
+}
+
+async fn read_message_id(response: reqwest::Response) -> (reqwest::Response, Option<i64>) {
+    let (response, body) = buffered(response).await;
+    let message_id = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|body| body["result"]["message_id"].as_i64());
+
+    (response, message_id)
+}
+
+/// Remembers id of the message Telegram accepted in the response
+async fn with_telegram_messages(response: reqwest::Response, chat_id: String) -> reqwest::Response {
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let (mut response, message_id) = read_message_id(response).await;
+    response.extensions_mut().insert(TelegramMessages {
+        chat_id,
+        message_ids: message_id.into_iter().collect(),
+    });
+
+    response
+}
+
+/// Outcome of sending to one recipient
+struct Delivery {
+    recipient:       String,
+    result:          Result<reqwest::Response, reqwest::Error>,
+    attempts:        usize,
+    last_attempt_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Delivery {
+    fn is_delivered(&self) -> bool {
+        matches!(&self.result, Ok(resp) if resp.status() == StatusCode::OK)
+    }
+
+    /// Delivered text message, unknown for files
+    fn sent_message(&self) -> Option<&SentMessage> {
+        self.result
+            .as_ref()
+            .ok()
+            .and_then(|resp| resp.extensions().get::<SentMessage>())
+    }
+
+    fn telegram_messages(&self) -> Option<&TelegramMessages> {
+        self.result
+            .as_ref()
+            .ok()
+            .and_then(|resp| resp.extensions().get::<TelegramMessages>())
+    }
+
+    /// Rate limiting and server errors, timed out requests are not as Telegram might have
+    /// delivered the message anyway
+    fn is_transient_failure(&self) -> bool {
+        match &self.result {
+            Ok(resp) =>
+                resp.status() == StatusCode::TOO_MANY_REQUESTS || resp.status().is_server_error(),
+            Err(err) => !err.is_timeout(),
+        }
+    }
+
+    fn failure(&self) -> Option<String> {
+        match &self.result {
+            Ok(resp) if resp.status() == StatusCode::OK => None,
+            Ok(resp) => Some(format!("Telegram responded with {}", resp.status())),
+            Err(err) => Some(err.to_string()),
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Bot API requests retried while delivering to one recipient
+    static RETRIES: Cell<usize>;
+}
+
+/// Delay before the request is made again if it failed transiently. Telegram tells how long
+/// rate limited requests have to wait, the response is read for it
+async fn retry_delay(
+    result: Result<reqwest::Response, reqwest::Error>,
+) -> (Result<reqwest::Response, reqwest::Error>, Option<Duration>) {
+    match result {
+        Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+            let (response, body) = buffered(response).await;
+            let delay = match serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|body| body["parameters"]["retry_after"].as_u64())
+            {
+                Some(seconds) =>
+                    Some(Duration::from_secs(seconds)).filter(|delay| *delay <= MAX_RETRY_AFTER),
+                None => Some(DELIVERY_RETRY_DELAY),
+            };
+            (Ok(response), delay)
+        }
+        Ok(response) if response.status().is_server_error() =>
+            (Ok(response), Some(DELIVERY_RETRY_DELAY)),
+        Err(err) if !err.is_timeout() => (Err(err), Some(DELIVERY_RETRY_DELAY)),
+        result => (result, None),
+    }
+}
+
+/// Sends to every recipient at once. Requests that fail transiently are retried by themselves,
+/// so chunks and files delivered before them aren't sent twice
+async fn deliver_to_all<'a, F, Fut>(recipients: &'a [String], send: F) -> Vec<Delivery>
+where
+    F: Fn(&'a str) -> Fut,
+    Fut: Future<Output = Result<reqwest::Response, reqwest::Error>>,
+{
+    futures::future::join_all(recipients.iter().map(|recipient| {
+        RETRIES.scope(Cell::new(0), async {
+            // URLs of Bot API requests carry the token, errors end up in responses
+            let result = send(recipient).await.map_err(reqwest::Error::without_url);
+
+            Delivery {
+                recipient: recipient.clone(),
+                result,
+                attempts: 1 + RETRIES.with(Cell::get),
+                last_attempt_at: chrono::Utc::now(),
+            }
+        })
+    }))
+    .await
+}
+
+#[derive(Serialize)]
+struct TgMarkdownString(String);
+
+impl std::ops::Deref for TgMarkdownString {
+    type Target = String;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl TgMarkdownString {
+    pub fn new(s: &str) -> Self {
+        let need_to_escape = [
+            '_', '*', '[', ']', '(', ')', '~', '`', '>', '#', '+', '-', '=', '|', '{', '}', '.',
+            '!', '\\',
+        ];
+        let mut escaped_string = String::new();
+
+        for ch in s.chars() {
+            if need_to_escape.contains(&ch) {
+                escaped_string.push('\\');
+            }
+            escaped_string.push(ch);
+        }
+
+        Self(escaped_string)
+    }
+
+    /// Inline link, inside of the url part only `)` and `\` have to be escaped
+    pub fn link(text: &str, url: &str) -> Self {
+        let escaped_url = url.replace('\\', "\\\\").replace(')', "\\)");
+
+        Self(format!("[{}]({})", *Self::new(text), escaped_url))
+    }
+
+    /// Inside of inline code and code blocks only `` ` `` and `\` have to be escaped
+    pub fn escape_code(s: &str) -> String {
+        s.replace('\\', "\\\\").replace('`', "\\`")
+    }
+
+    pub fn code(s: &str) -> Self {
+        Self(format!("`{}`", Self::escape_code(s)))
+    }
+
+    /// Code block, language enables syntax highlighting
+    pub fn pre(s: &str, language: Option<&str>) -> Self {
+        Self::fenced(&Self::escape_code(s), language)
+    }
+
+    /// Code block of already escaped code, trailing line breaks would show as empty lines
+    pub fn fenced(escaped_code: &str, language: Option<&str>) -> Self {
+        Self(format!(
+            "```{}\n{}\n```",
+            language.unwrap_or_default(),
+            escaped_code.trim_end_matches('\n')
+        ))
+    }
+}
+
+#[derive(Serialize)]
+struct SendMessagePayload<'a> {
+    chat_id:              &'a str,
+    /// Messages Telegram couldn't parse are sent again without it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode:           Option<&'static str>,
+    text:                 String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup:         Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link_preview_options: Option<LinkPreviewOptions>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    protect_content:      bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    disable_notification: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_effect_id:    Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_parameters:     Option<ReplyParameters>,
+}
+
+#[derive(Serialize)]
+struct ReplyParameters {
+    message_id:                  i64,
+    /// Message is still sent if the one it replies to was deleted
+    allow_sending_without_reply: bool,
+}
+
+impl ReplyParameters {
+    fn to(message_id: i64) -> Self {
+        Self {
+            message_id,
+            allow_sending_without_reply: true,
+        }
+    }
+}
+
+impl<'a> SendMessagePayload<'a> {
+    pub fn new(chat_id: &'a str, text: &str) -> Self {
+        Self {
+            chat_id,
+            text: text.to_owned(),
+            parse_mode: Some(TELEGRAM_MARKDOWN_V2_PARSE_MODE),
+            reply_markup: None,
+            link_preview_options: None,
+            protect_content: false,
+            disable_notification: false,
+            message_effect_id: None,
+            reply_parameters: None,
+        }
+    }
+}
+
+/// Settings of topics that can't be checked by deserialization alone
+fn check_topics(
+    topics: &Topics,
+    recipient_groups: &BTreeMap<String, Vec<String>>,
+    geoip: &GeoIp,
+    has_jwt: bool,
+    backends: &failover::Registry,
+    internal_topic: Option<&str>,
+) -> Result<(), String> {
+    if let Some(internal_topic) = internal_topic {
+        if !topics.contains_key(internal_topic) {
+            return Err(format!(
+                "internal_topic \"{}\" is not a topic",
+                internal_topic
+            ));
+        }
+    }
+
+    for (topic_name, topic_info) in topics {
+        if RESERVED_TOPIC_NAMES.contains(&topic_name.as_str()) {
+            return Err(format!(
+                "Topic \"{}\" can't be used, its name is taken by routes of microphone",
+                topic_name
+            ));
+        }
+
+        if let Some(group) = topic_info
+            .recipient_groups
+            .iter()
+            .find(|group| !recipient_groups.contains_key(*group))
+        {
+            return Err(format!(
+                "Topic \"{}\" refers to unknown recipient group \"{}\"",
+                topic_name, group
+            ));
+        }
+
+        if !topic_info.allow_countries.is_empty() && !geoip.is_enabled() {
+            return Err(format!(
+                "Topic \"{}\" has allow_countries, but geoip_database is not configured",
+                topic_name
+            ));
+        }
+        if !topic_info.allow_groups.is_empty() && !has_jwt {
+            return Err(format!(
+                "Topic \"{}\" has allow_groups, but [jwt] is not configured",
+                topic_name
+            ));
+        }
+
+        if let Some(transform) = &topic_info.transform {
+            transform
+                .check()
+                .map_err(|err| format!("Topic \"{}\": {}", topic_name, err))?;
+        }
+
+        filter::check(topic_name, &topic_info.filters, topics)?;
+        failover::check(topic_name, topic_info, backends)?;
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), std::io::Error> {
+    logs::init();
+
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    // The service control manager starts microphone with `service <config>` arguments
+    #[cfg(windows)]
+    if args.first().is_some_and(|arg| arg == "service") {
+        return service::run_as_service(args.split_off(1), serve);
+    }
+    let _daemon = match service::Daemon::start(&mut args) {
+        Ok(daemon) => daemon,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    serve(args)
+}
+
+fn serve(args: Vec<String>) -> Result<(), std::io::Error> {
+    actix_web::rt::System::new().block_on(run(args))
+}
+
+async fn run(args: Vec<String>) -> Result<(), std::io::Error> {
+    let mut args = args.into_iter();
+    let first_argument = args
+        .next()
+        .expect("Provide config file path as the first argument to the program");
+
+    match first_argument.as_str() {
+        "pipe" => return pipe::run(args.collect()).await,
+        "chats" => return chats::run(args.collect()).await,
+        "init" => return setup::run_init(args.collect()),
+        "topic" => return setup::run_topic(args.collect()),
+        _ => (),
+    }
+
+    let config: Config = match config::load_source(&first_argument).await {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let sandbox_chat = match config.sandbox_chat() {
+        Ok(sandbox_chat) => sandbox_chat,
+        Err(err) => panic!("{}", err),
+    };
+    if let Some(sandbox_chat) = &sandbox_chat {
+        log::warn!(
+            "Staging environment, every delivery goes to {}",
+            sandbox_chat
+        );
+    }
+
+    let geoip = Arc::new(match &config.geoip_database {
+        Some(database) => GeoIp::open(database),
+        None => GeoIp::default(),
+    });
+    let geoip_data = web::Data::new(geoip.clone());
+    let backends = failover::Registry::default();
+    #[cfg(feature = "smtp")]
+    let backends = backends.with_mailer(config.smtp.map(Mailer::new));
+    if let Err(err) = check_topics(
+        &config.topics,
+        &config.recipient_groups,
+        &geoip,
+        config.jwt.is_some(),
+        &backends,
+        config.internal_topic.as_deref(),
+    ) {
+        panic!("{}", err);
+    }
+    if let Err(err) = announcements::check(&config.announcements, &config.topics) {
+        panic!("{}", err);
+    }
+    if let Some(Err(err)) = config
+        .broadcast
+        .as_ref()
+        .map(|broadcast| broadcast.check(&config.topics))
+    {
+        panic!("{}", err);
+    }
+    if config.announce_restarts && config.internal_topic.is_none() {
+        panic!("announce_restarts requires internal_topic");
+    }
+
+    let topics = Arc::new(LiveTopics::new(config.topics));
+    let topics_data = web::Data::new(topics.clone());
+
+    let metrics = Arc::new(Metrics::default());
+    let metrics_data = web::Data::new(metrics.clone());
+
+    let (alerts, incidents) = InternalAlerts::new(config.internal_topic);
+
+    let jwt = config
+        .jwt
+        .map(|jwt_config| jwt::Jwt::spawn(jwt_config, &config.outbound));
+    let jwt_data = web::Data::new(jwt.clone());
+    let api_keys_data = web::Data::new(config.api_keys.map(|api_keys_config| {
+        Arc::new(api_keys::ApiKeys::load(api_keys_config).expect("Failed to load API keys"))
+    }));
+
+    let audit_log = config.compliance.map(|compliance_config| {
+        Arc::new(compliance::AuditLog::open(compliance_config).expect("Failed to open audit log"))
+    });
+    let audit_log_data = web::Data::new(audit_log.clone());
+
+    let tg_client = TgClient::new(
+        config.telegram.secret,
+        &config
+            .telegram
+            .http
+            .or_proxy(config.proxy)
+            .with_outbound(config.outbound.clone()),
+        metrics.clone(),
+    )
+    .with_chats(chats::Chats::load(config.chats).expect("Failed to load chat ids"))
+    .with_recipient_groups(config.recipient_groups)
+    .with_locales(&topics.current())
+    .with_senders(config.senders)
+    .with_sandbox_chat(sandbox_chat)
+    .with_backends(backends)
+    .with_internal_alerts(alerts)
+    .with_audit_log(audit_log);
+    #[cfg(feature = "chaos")]
+    let tg_client = {
+        if config.chaos.is_some() {
+            log::warn!("Chaos mode, failures are injected into Bot API requests");
+        }
+        tg_client.with_chaos(config.chaos)
+    };
+    let tg_client = Arc::new(tg_client);
+    let tg_data = web::Data::new(tg_client.clone());
+    let callback_client_data = web::Data::new(callbacks::CallbackClient::new(&config.outbound));
+    let transform_client_data = web::Data::new(transform::TransformClient::new(&config.outbound));
+    let grafana_client_data = web::Data::new(adapters::grafana::ImageClient::new(&config.outbound));
+    let mirror = config.mirror.map(|mirror_config| {
+        Arc::new(
+            Mirror::spawn(mirror_config, &config.outbound, metrics.clone())
+                .expect("Failed to set up request mirror"),
+        )
+    });
+    internal::spawn_sender(incidents, topics.clone(), tg_client.clone());
+    if config.announce_restarts {
+        tg_client.alerts.raise(Incident::Started {
+            topics: topics.current().len(),
+        });
+    }
+
+    let coordinator = match config.coordination {
+        Some(coordination_config) => Coordinator::spawn(coordination_config),
+        None => Coordinator::standalone(),
+    };
+    let coordinator_data = web::Data::new(coordinator.clone());
+    chats::spawn_refresher(tg_client.clone(), topics.clone());
+    announcements::spawn(
+        config.announcements,
+        topics.clone(),
+        tg_client.clone(),
+        coordinator.clone(),
+    );
+
+    let maintenance = Arc::new(Maintenance::default());
+    let maintenance_data = web::Data::new(maintenance.clone());
+    maintenance::spawn_summary_sender(maintenance.clone(), topics.clone(), tg_client.clone());
+
+    let heartbeats = Arc::new(heartbeat::Heartbeats::default());
+    let heartbeats_data = web::Data::new(heartbeats.clone());
+    heartbeat::spawn_checker(
+        heartbeats,
+        topics.clone(),
+        tg_client.clone(),
+        coordinator.clone(),
+        maintenance.clone(),
+    );
+
+    let escalations = Escalations::default();
+    let escalations = Arc::new(match &config.ack_log {
+        Some(ack_log) => escalations
+            .with_ack_log(ack_log)
+            .expect("Failed to open ack log"),
+        None => escalations,
+    });
+    let escalations_data = web::Data::new(escalations.clone());
+    let alerts_data = web::Data::new(Arc::new(Alerts::default()));
+    escalation::spawn_checker(escalations.clone(), topics.clone(), tg_client.clone());
+    let aggregates = Arc::new(Aggregates::default());
+    let aggregates_data = web::Data::new(aggregates.clone());
+    let thresholds_data = web::Data::new(Arc::new(
+        Thresholds::load(config.state_file.clone()).expect("Failed to load state file"),
+    ));
+    let usage_data = web::Data::new(Arc::new(Usage::default()));
+    let reminders = config.reminders.map(|reminders_config| {
+        Arc::new(reminders::Reminders::load(reminders_config).expect("Failed to load reminders"))
+    });
+    if let Some(reminders) = &reminders {
+        reminders::spawn_sender(reminders.clone(), topics.clone(), tg_client.clone());
+    }
+    let reminders_data = web::Data::new(reminders);
+    let broadcast_data = web::Data::new(config.broadcast);
+    aggregate::spawn_flusher(aggregates, tg_client.clone());
+    let summaries = Arc::new(Summaries::default());
+    let summaries_data = web::Data::new(summaries.clone());
+    let streams = Arc::new(Streams::default());
+    let streams_data = web::Data::new(streams.clone());
+    summary::spawn_sender(summaries, topics.clone(), tg_client.clone());
+
+    if config::is_remote(&first_argument) {
+        remote_config::spawn_poller(
+            first_argument,
+            config.config_poll_interval,
+            topics.clone(),
+            tg_client.clone(),
+            geoip.clone(),
+            jwt.is_some(),
+        );
+    }
+
+    if updates::poller_runs(&topics.current(), &config.telegram.updates) {
+        updates::spawn_poller(
+            tg_client.clone(),
+            escalations,
+            topics.clone(),
+            coordinator.clone(),
+        );
+    }
+    let updates_data = web::Data::new(config.telegram.updates);
+
+    let quotas = Arc::new(Quotas::default());
+    let quotas_data = web::Data::new(quotas.clone());
+
+    let messages_data = web::Data::new(Arc::new(Messages::default()));
+
+    let uploads_data = web::Data::new(Arc::new(Uploads::new(
+        config.server.upload_memory_limit,
+        config.server.upload_dir,
+        config.server.upload_limits,
+    )));
+
+    let hostnames = Arc::new(Hostnames::default());
+    let hostnames_data = web::Data::new(hostnames.clone());
+
+    let bans = Arc::new(Bans::new(config.ban, metrics.clone()));
+    let bans_data = web::Data::new(bans.clone());
+
+    let client_ip_data = web::Data::new(client_ip::ClientIpConfig {
+        trusted_proxies: config.server.trusted_proxies,
+    });
+
+    let connections = config.server.connections;
+    let header_limits = connections.clone();
+
+    let admin_data = web::Data::new(admin::AdminConfig {
+        token:  config.admin_token,
+        tokens: config.admin_tokens,
+        jwt:    jwt.clone(),
+    });
+
+    let recent_sentry_issues = web::Data::new(Arc::new(adapters::sentry::RecentIssues::default()));
+
+    if let Some(spool_config) = config.spool {
+        spool::spawn(spool_config, topics.clone(), tg_client.clone());
+    }
+
+    let device_pipeline = Arc::new(lines::Pipeline {
+        topics: topics.clone(),
+        tg_client: tg_client.clone(),
+        maintenance,
+        quotas,
+        streams,
+        metrics,
+        hostnames,
+        geoip: geoip.clone(),
+    });
+    if let Some(lines_config) = config.lines {
+        lines::spawn(lines_config, device_pipeline.clone());
+    }
+    #[cfg(feature = "coap")]
+    if let Some(coap_config) = config.coap {
+        coap::spawn(coap_config, device_pipeline.clone());
+    }
+
+    #[cfg(feature = "redis")]
+    if let Some(redis_config) = config.redis {
+        redis_bridge::spawn(redis_config, topics.clone(), tg_client.clone());
+    }
+
+    const MAIN_RESOURCE_PATH: &str = "/{topic_name}/{sender}";
+
+    let app = move || {
+        let logger = if geoip.is_enabled() {
+            let geoip = geoip.clone();
+
+            Logger::new(LOG_FORMAT_WITH_COUNTRY).custom_request_replace("country", move |request| {
+                ClientIp::of_service_request(request)
+                    .ok()
+                    .and_then(|ClientIp(client_address)| geoip.country(client_address))
+                    .unwrap_or_else(|| "-".to_owned())
+            })
+        } else {
+            Logger::default()
+        };
+
+        let bans = bans.clone();
+        let header_limits = header_limits.clone();
+        let mirror = mirror.clone();
+
+        App::new()
+            .wrap_fn(|request, service| -> LocalBoxFuture<'static, _> {
+                let origin = match cors::allowed_origin(&request) {
+                    Some(origin) => origin,
+                    None => return Box::pin(service.call(request)),
+                };
+                if cors::is_preflight(&request) {
+                    return Box::pin(ready(Ok(cors::preflight(request, origin))));
+                }
+
+                let response = service.call(request);
+                Box::pin(async move {
+                    let mut response = response.await?;
+                    cors::allow(response.headers_mut(), origin);
+                    Ok(response)
+                })
+            })
+            .wrap_fn(move |request, service| -> LocalBoxFuture<'static, _> {
+                let mirror = match &mirror {
+                    Some(mirror) => mirror.clone(),
+                    None => return Box::pin(service.call(request)),
+                };
+                let (request, tap) = mirror.tap(request);
+                let response = service.call(request);
+                Box::pin(async move {
+                    let response = response.await?;
+                    if let Some(tap) = tap {
+                        mirror.record(tap, &response);
+                    }
+
+                    Ok(response)
+                })
+            })
+            .wrap_fn(move |request, service| -> LocalBoxFuture<'static, _> {
+                let request_id = errors::request_id(&request);
+                let client_address = ClientIp::of_service_request(&request)
+                    .ok()
+                    .map(|ClientIp(client_address)| client_address);
+
+                // Operators can't lock themselves out of managing bans
+                if client_address.is_some_and(|client_address| bans.is_banned(client_address))
+                    && !admin::Operator::is_present(&request)
+                {
+                    let response = request.error_response(ApiError::new(
+                        ErrorCode::AddressBanned,
+                        "Address is banned",
+                    ));
+                    return Box::pin(ready(Ok(errors::envelope(response, &request_id))));
+                }
+                if let Err(err) = header_limits.check_headers(&request) {
+                    let response = request.error_response(err);
+                    return Box::pin(ready(Ok(errors::envelope(response, &request_id))));
+                }
+
+                let bans = bans.clone();
+                let response = service.call(request);
+                Box::pin(async move {
+                    let response = response.await?;
+                    if let Some(client_address) = client_address {
+                        bans.observe(client_address, response.status());
+                    }
+
+                    Ok(errors::envelope(
+                        response.map_into_boxed_body(),
+                        &request_id,
+                    ))
+                })
+            })
+            .wrap(logger)
+            .app_data(topics_data.clone())
+            .app_data(callback_client_data.clone())
+            .app_data(transform_client_data.clone())
+            .app_data(grafana_client_data.clone())
+            .app_data(tg_data.clone())
+            .app_data(coordinator_data.clone())
+            .app_data(recent_sentry_issues.clone())
+            .app_data(heartbeats_data.clone())
+            .app_data(maintenance_data.clone())
+            .app_data(admin_data.clone())
+            .app_data(client_ip_data.clone())
+            .app_data(hostnames_data.clone())
+            .app_data(quotas_data.clone())
+            .app_data(messages_data.clone())
+            .app_data(uploads_data.clone())
+            .app_data(geoip_data.clone())
+            .app_data(metrics_data.clone())
+            .app_data(bans_data.clone())
+            .app_data(escalations_data.clone())
+            .app_data(aggregates_data.clone())
+            .app_data(summaries_data.clone())
+            .app_data(streams_data.clone())
+            .app_data(thresholds_data.clone())
+            .app_data(usage_data.clone())
+            .app_data(alerts_data.clone())
+            .app_data(updates_data.clone())
+            .app_data(audit_log_data.clone())
+            .app_data(jwt_data.clone())
+            .app_data(api_keys_data.clone())
+            .app_data(reminders_data.clone())
+            .app_data(broadcast_data.clone())
+            .app_data(PayloadConfig::new(MAX_BODY_SIZE))
+            .service(web::resource("/metrics").route(web::get().to(metrics::get_metrics)))
+            .service(web::resource("/version").route(web::get().to(version::get_version)))
+            .service(web::resource("/topics").route(web::get().to(discovery::list_topics)))
+            .service(
+                web::scope("/admin")
+                    .route("/chats", web::get().to(chats::list_chats))
+                    .route("/defunct", web::get().to(defunct::list_defunct))
+                    .route("/defunct/{recipient}", web::delete().to(defunct::restore))
+                    .route(
+                        "/recipient_groups",
+                        web::get().to(recipient_groups::list_groups),
+                    )
+                    .route(
+                        "/recipient_groups/{name}",
+                        web::put().to(recipient_groups::put_group),
+                    )
+                    .route(
+                        "/recipient_groups/{name}/members/{recipient}",
+                        web::put().to(recipient_groups::add_member),
+                    )
+                    .route(
+                        "/recipient_groups/{name}/members/{recipient}",
+                        web::delete().to(recipient_groups::remove_member),
+                    )
+                    .route("/messages", web::get().to(messages::list_messages))
+                    .route(
+                        "/messages/{message_id}",
+                        web::get().to(messages::get_message),
+                    )
+                    .route(
+                        "/messages/{message_id}",
+                        web::delete().to(messages::delete_message),
+                    )
+                    .route("/bans", web::get().to(bans::list_bans))
+                    .route("/bans", web::delete().to(bans::delete_bans))
+                    .route("/bans/{address}", web::delete().to(bans::delete_ban))
+                    .route("/logs/stream", web::get().to(logs::stream_logs))
+                    .route("/state", web::get().to(state::export_state))
+                    .route("/state", web::put().to(state::import_state))
+                    .route("/compliance/verify", web::get().to(compliance::verify_log))
+                    .route("/api_keys", web::get().to(api_keys::list_keys))
+                    .route("/api_keys", web::post().to(api_keys::create_key))
+                    .route("/api_keys/{id}", web::delete().to(api_keys::revoke_key))
+                    .route("/usage", web::get().to(usage::get_usage))
+                    .route(
+                        "/alerts/unacked",
+                        web::get().to(escalation::list_unacknowledged),
+                    )
+                    .route("/maintenance", web::get().to(maintenance::list_windows))
+                    .route(
+                        "/maintenance/{topic_name}",
+                        web::post().to(maintenance::create_window),
+                    )
+                    .route(
+                        "/maintenance/{topic_name}/{id}",
+                        web::delete().to(maintenance::delete_window),
+                    ),
+            )
+            .service(
+                web::resource("/status/{message_id}").route(web::get().to(messages::get_status)),
+            )
+            .service(web::resource("/telegram/updates").route(web::post().to(updates::post_update)))
+            .service(
+                web::resource("/broadcast/{sender}")
+                    .route(web::post().to(broadcast::post_broadcast)),
+            )
+            .service(
+                web::resource("/gitlab/{topic_name}")
+                    .route(web::post().to(adapters::gitlab::handle)),
+            )
+            .service(
+                web::resource("/grafana/{topic_name}")
+                    .route(web::post().to(adapters::grafana::handle)),
+            )
+            .service(
+                web::resource("/sentry/{topic_name}")
+                    .route(web::post().to(adapters::sentry::handle)),
+            )
+            .service(
+                web::resource("/gitea/{topic_name}").route(web::post().to(adapters::gitea::handle)),
+            )
+            .service(
+                web::resource(["/ws", "/ws/{topic_name}/{sender}"])
+                    .guard(guard::Get())
+                    .route(web::get().to(websocket::connect)),
+            )
+            // Guarded, so messages of a sender named "stream" are still posted
+            .service(
+                web::resource("/{topic_name}/stream")
+                    .guard(guard::Get())
+                    .route(web::get().to(stream::subscribe)),
+            )
+            .service(
+                web::resource("/{topic_name}/reminders")
+                    .guard(guard::Get())
+                    .route(web::get().to(reminders::list_reminders)),
+            )
+            .service(
+                web::resource("/{topic_name}/reminders/{id}")
+                    .guard(guard::Delete())
+                    .route(web::delete().to(reminders::cancel_reminder)),
+            )
+            .service(
+                web::resource("/{topic_name}/{sender}/remind")
+                    .route(web::post().to(reminders::create_reminder)),
+            )
+            .service(
+                web::resource("/{topic_name}/submit")
+                    .guard(guard::Get())
+                    .route(web::get().to(submit::get_submit_form)),
+            )
+            .service(
+                web::resource("/{topic_name}/{sender}/chart")
+                    .route(web::post().to(chart::post_chart)),
+            )
+            .service(
+                web::resource("/{topic_name}/{sender}/qr").route(web::post().to(qr::post_qr_code)),
+            )
+            .service(
+                web::resource("/{topic_name}/{sender}/heartbeat")
+                    .route(web::post().to(heartbeat::post_heartbeat)),
+            )
+            .service(
+                web::resource(MAIN_RESOURCE_PATH)
+                    .guard(guard::fn_guard(|ctx| {
+                        ctx.header::<header::ContentType>()
+                            .map(|val| val.0.to_string().contains("multipart/form-data"))
+                            .unwrap_or(false)
+                    }))
+                    .route(web::post().to(post_message_with_document)),
+            )
+            .service(
+                web::resource(MAIN_RESOURCE_PATH)
+                    .guard(guard::Post())
+                    .guard(guard::fn_guard(|ctx| {
+                        ctx.header::<header::ContentType>()
+                            .map(|val| {
+                                val.0
+                                    .to_string()
+                                    .contains("application/x-www-form-urlencoded")
+                            })
+                            .unwrap_or(false)
+                    }))
+                    .route(web::post().to(post_form_message)),
+            )
+            .service(
+                web::resource(MAIN_RESOURCE_PATH)
+                    .guard(guard::Post())
+                    .guard(guard::fn_guard(|ctx| {
+                        !sniff::is_declared(
+                            ctx.head()
+                                .headers()
+                                .get(header::CONTENT_TYPE)
+                                .and_then(|value| value.to_str().ok()),
+                        )
+                    }))
+                    .route(web::post().to(sniff::post_sniffed_message)),
+            )
+            .service(
+                web::resource(MAIN_RESOURCE_PATH)
+                    .route(web::post().to(post_message))
+                    .route(web::get().to(get_message)),
+            )
+            .default_service(web::to(|| async {
+                HttpResponse::from(ApiError::new(ErrorCode::NotFound, "No such route"))
+            }))
+    };
+
+    let server = server::serve(
+        app,
+        ("0.0.0.0", config.server.port),
+        1,
+        config.server.proxy_protocol,
+        &connections,
+    )?;
+    service::started(&server)?;
+    let result = server.await;
+
+    if config.announce_restarts {
+        let notice = internal::deliver(&Incident::Stopping, &topics, &tg_client);
+        if timeout(SHUTDOWN_NOTICE_TIMEOUT, notice).await.is_err() {
+            log::warn!("Shutdown notice wasn't delivered in time");
+        }
+    }
+
+    result
+}
+
+#[derive(Deserialize)]
+struct PostPathData {
+    topic_name: String,
+    sender:     String,
+}
+
+#[derive(Serialize)]
+struct FailedDelivery<'a> {
+    recipient: &'a str,
+    error:     String,
+}
+
+/// Partial success is reported with 207 and per recipient detail, so callers don't resend
+/// the message to recipients that already got it
+fn delivery_response(deliveries: &[Delivery]) -> HttpResponse {
+    let failed = deliveries
+        .iter()
+        .filter_map(|delivery| {
+            delivery.failure().map(|error| FailedDelivery {
+                recipient: &delivery.recipient,
+                error,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if failed.is_empty() {
+        HttpResponse::NoContent().finish()
+    } else if failed.len() == deliveries.len() {
+        HttpResponse::from(
+            ApiError::new(
+                ErrorCode::DeliveryFailed,
+                "Message was not delivered to anyone",
+            )
+            .with_details(serde_json::json!({ "failed": failed })),
+        )
+    } else {
+        let delivered = deliveries
+            .iter()
+            .filter(|delivery| delivery.is_delivered())
+            .map(|delivery| delivery.recipient.as_str())
+            .collect::<Vec<_>>();
+
+        HttpResponse::MultiStatus().json(serde_json::json!({
+            "delivered": delivered,
+            "failed": failed,
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+struct FormMessage {
+    message: Option<String>,
+}
+
+/// Form posted by devices that can't send anything else, its `message` field is the text. Bodies
+/// without the field are text themselves, that's what `curl --data` sends
+async fn post_form_message(
+    topic: AllowedTopic,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    metrics: web::Data<Arc<Metrics>>,
+    escalations: web::Data<Arc<Escalations>>,
+    alerts: web::Data<Arc<Alerts>>,
+    messages: web::Data<Arc<Messages>>,
+    headers: (Severity, AlertUpdate, Callback, RequestOptions),
+    post_query: web::Path<PostPathData>,
+    request: HttpRequest,
+    body: String,
+) -> impl Responder {
+    let message = match serde_urlencoded::from_str::<FormMessage>(&body) {
+        Ok(FormMessage {
+            message: Some(message),
+        }) => message,
+        _ => body,
+    };
+
+    post_message(
+        topic,
+        tg_client,
+        maintenance,
+        quotas,
+        metrics,
+        escalations,
+        alerts,
+        messages,
+        headers,
+        post_query,
+        request,
+        message,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct GetMessageQuery {
+    m: String,
+}
+
+/// Text in query of `GET`, for topics with `allow_get`
+async fn get_message(
+    topic: AllowedTopic,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    metrics: web::Data<Arc<Metrics>>,
+    escalations: web::Data<Arc<Escalations>>,
+    alerts: web::Data<Arc<Alerts>>,
+    messages: web::Data<Arc<Messages>>,
+    headers: (Severity, AlertUpdate, Callback, RequestOptions),
+    post_query: web::Path<PostPathData>,
+    request: HttpRequest,
+    query: web::Query<GetMessageQuery>,
+) -> HttpResponse {
+    if !topic.info.allow_get {
+        return HttpResponse::from(ApiError::new(
+            ErrorCode::MethodNotAllowed,
+            "Messages are posted with POST",
+        ));
+    }
+    let message = query.into_inner().m;
+    if message.is_empty() || message.len() > MAX_GET_MESSAGE_SIZE {
+        return HttpResponse::from(ApiError::new(
+            ErrorCode::InvalidMessage,
+            format!(
+                "Message sent with GET has to be 1 to {} bytes",
+                MAX_GET_MESSAGE_SIZE
+            ),
+        ));
+    }
+
+    post_message(
+        topic,
+        tg_client,
+        maintenance,
+        quotas,
+        metrics,
+        escalations,
+        alerts,
+        messages,
+        headers,
+        post_query,
+        request,
+        message,
+    )
+    .await
+}
+
+async fn post_message(
+    topic: AllowedTopic,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    metrics: web::Data<Arc<Metrics>>,
+    escalations: web::Data<Arc<Escalations>>,
+    alerts: web::Data<Arc<Alerts>>,
+    messages: web::Data<Arc<Messages>>,
+    // Actix handlers take at most 12 extractors, headers are grouped
+    (severity, alert, callback, request_options): (Severity, AlertUpdate, Callback, RequestOptions),
+    post_query: web::Path<PostPathData>,
+    request: HttpRequest,
+    message: String,
+) -> HttpResponse {
+    metrics.count_ingress(&topic.name, message.len(), 0);
+    let meter = request
+        .app_data::<web::Data<Arc<Usage>>>()
+        .expect("Usage is in app data")
+        .meter(&topic, message.len());
+    if let Some(summary) = &topic.info.summary {
+        request
+            .app_data::<web::Data<Arc<Summaries>>>()
+            .expect("Summaries are in app data")
+            .record(&topic.name, summary, &post_query.sender, severity);
+    }
+
+    let filtered = filter::apply(
+        &topic.info.filters,
+        &post_query.sender,
+        severity,
+        message,
+        |action| {
+            metrics.increment(
+                "microphone_filtered_total",
+                &[("topic", &topic.name), ("action", action)],
+            )
+        },
+    );
+    let filter::Filtered {
+        message,
+        silent,
+        reroute,
+    } = match filtered {
+        Some(filtered) => filtered,
+        None => return HttpResponse::Accepted().body("Dropped by filter of the topic"),
+    };
+    let topic = match reroute {
+        Some(target) => rerouted(topic, target, &request),
+        None => topic,
+    };
+    let topic_info = &topic.info;
+
+    let (message, script_recipients) =
+        match scripted(&topic, &post_query.sender, message, &request, &metrics) {
+            Some(scripted) => scripted,
+            None => return HttpResponse::Accepted().body("Dropped by script of the topic"),
+        };
+    let message = match &topic_info.transform {
+        Some(transform) => {
+            let client = request
+                .app_data::<web::Data<transform::TransformClient>>()
+                .expect("Transform client is in app data");
+            match transform
+                .apply(client, &metrics, &topic.name, &post_query.sender, message)
+                .await
+            {
+                Ok(message) => message,
+                Err(err) => return HttpResponse::from(err),
+            }
+        }
+        None => message,
+    };
+
+    if maintenance.intercept(&topic.name, topic_info, &post_query.sender, &message) {
+        return HttpResponse::Accepted().body("Topic is under maintenance");
+    }
+
+    if let Some(threshold) = &topic_info.alert_threshold {
+        let thresholds = request
+            .app_data::<web::Data<Arc<Thresholds>>>()
+            .expect("Thresholds are in app data");
+        if let Some(id) = alert.firing() {
+            if !thresholds.reached(&topic.name, id, threshold) {
+                return HttpResponse::Accepted().body("Alert is below threshold of the topic");
+            }
+        }
+        // Resolves of blips nobody was told about aren't sent either
+        if let Some(id) = alert.resolving() {
+            if thresholds.forget(&topic.name, id) && !alerts.is_firing(&topic.name, id) {
+                return HttpResponse::Accepted().body("Alert never reached threshold of the topic");
+            }
+        }
+    }
+
+    let aggregates = request
+        .app_data::<web::Data<Arc<Aggregates>>>()
+        .expect("Aggregates are in app data")
+        .get_ref()
+        .clone();
+    let is_aggregated = topic_info
+        .aggregate_window
+        .is_some_and(|window| aggregates.repeat(&topic.name, &post_query.sender, &message, window));
+    if is_aggregated {
+        return HttpResponse::Accepted().body("Counted as repeat of a recent message");
+    }
+
+    if let Some(response) =
+        quotas::enforce(&quotas, &tg_client, &topic.name, topic_info, message.len()).await
+    {
+        // The message wasn't delivered, so its next repeat shouldn't be counted
+        aggregates.attach(&topic.name, &post_query.sender, &message, &[]);
+        return response;
+    }
+
+    request
+        .app_data::<web::Data<Arc<Streams>>>()
+        .expect("Streams are in app data")
+        .publish(
+            &topic.name,
+            topic_info,
+            &post_query.sender,
+            severity,
+            &message,
+            &[],
+        );
+
+    let is_critical = severity == Severity::Critical;
+    let ack_id = (is_critical && topic_info.escalation.is_some())
+        .then(|| escalations.register(&topic.name, &post_query.sender, &message));
+    let locale = tg_client.locale(&topic.name);
+    let reply_markup = ack_id.map(|id| escalation::ack_markup(id, locale));
+    let pin = is_critical && topic_info.pin_critical;
+
+    let tg_client = tg_client.get_ref().clone();
+    let recipients =
+        script_recipients.unwrap_or_else(|| tg_client.recipients_of(&topic.name, topic_info));
+    let pipeline = topic_info.format.clone();
+    let options = request_options.apply(topic_info);
+    let code_language = request_options.code_language;
+    let style = topic_info
+        .styles
+        .get(&severity)
+        .cloned()
+        .unwrap_or_default();
+    let mentions = mentions::render(&topic_info.mentions, &post_query.sender, severity);
+    let topic_name = topic.name.clone();
+    let sender = post_query.sender.clone();
+    let escalations = escalations.get_ref().clone();
+    let alerts = alerts.get_ref().clone();
+    let resolve_mode = topic_info.resolve_mode;
+    let voice = topic_info.voice.clone();
+    let fallbacks = failover::Fallbacks::of_topic(topic_info);
+    let aggregates = topic_info.aggregate_window.map(|_| aggregates);
+    messages::dispatch(
+        &messages,
+        &topic.name,
+        &post_query.sender,
+        topic_info.accept_async,
+        callback,
+        meter,
+        async move {
+            let options = SendOptions {
+                reply_markup: reply_markup.as_ref(),
+                pin,
+                code_language: code_language.as_deref(),
+                silent,
+                mentions: mentions.as_deref(),
+                ..options.with_style(&style)
+            };
+
+            if let Some(id) = alert.resolving() {
+                let resolve = alerts::Resolve {
+                    topic: &topic_name,
+                    sender: &sender,
+                    id,
+                    text: &message,
+                    mode: resolve_mode,
+                    pipeline: &pipeline,
+                    options,
+                };
+                if let Some(deliveries) =
+                    alerts::resolve(&alerts, &escalations, &tg_client, resolve).await
+                {
+                    return deliveries;
+                }
+            }
+
+            let deliveries = tg_client
+                .send_formatted_to_all(
+                    &recipients,
+                    &pipeline,
+                    &topic_name,
+                    &sender,
                     &message,
-                    &filename,
-                    &file_content,
+                    options,
                 )
                 .await;
+            let deliveries = fallbacks
+                .deliver(&tg_client, &topic_name, &sender, &message, deliveries)
+                .await;
+            if let Some(voice) = voice {
+                voice::speak(
+                    voice,
+                    tg_client.clone(),
+                    recipients.clone(),
+                    topic_name.clone(),
+                    message.clone(),
+                );
+            }
+            if let Some(id) = alert.firing() {
+                alerts.fire(&topic_name, id, &deliveries, ack_id, pin);
+            }
+            if let Some(aggregates) = aggregates {
+                aggregates.attach(&topic_name, &sender, &message, &deliveries);
+            }
+
+            // Pinned messages are unpinned once the alert is acknowledged
+            if let (Some(ack_id), true) = (ack_id, pin) {
+                escalations.attach_pinned(
+                    ack_id,
+                    deliveries
+                        .iter()
+                        .filter_map(Delivery::sent_message)
+                        .cloned()
+                        .collect(),
+                );
+            }
+
+            deliveries
+        },
+    )
+    .await
+}
+
+/// Topic the filter rerouted the message to, the original one if it's gone since config was checked
+fn rerouted(topic: AllowedTopic, target: String, request: &HttpRequest) -> AllowedTopic {
+    let info = request
+        .app_data::<web::Data<Arc<LiveTopics>>>()
+        .and_then(|topics| topics.current().get(&target).cloned());
+    match info {
+        Some(info) => AllowedTopic {
+            name: target,
+            info,
+            api_key: topic.api_key,
+        },
+        None => {
+            log::warn!(
+                "Filter of \"{}\" reroutes to unknown topic \"{}\"",
+                topic.name,
+                target
+            );
+            topic
+        }
+    }
+}
+
+/// Message and recipients overriding ones of the topic left by its script, `None` if it dropped
+/// the message. Messages the script failed on are sent as they are
+#[cfg(feature = "scripting")]
+fn scripted(
+    topic: &AllowedTopic,
+    sender: &str,
+    message: String,
+    request: &HttpRequest,
+    metrics: &Metrics,
+) -> Option<(String, Option<Vec<String>>)> {
+    let script = match &topic.info.script {
+        Some(script) => script,
+        None => return Some((message, None)),
+    };
+
+    match script.run(sender, message.clone(), request.headers()) {
+        Ok(script::Outcome::Send {
+            message,
+            recipients,
+        }) => Some((message, recipients)),
+        Ok(script::Outcome::Drop) => {
+            log::info!("Script of \"{}\" dropped message of {}", topic.name, sender);
+            None
+        }
+        Err(err) => {
+            log::warn!("Script of \"{}\" failed: {}", topic.name, err);
+            metrics.increment("microphone_script_errors_total", &[("topic", &topic.name)]);
+            Some((message, None))
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+fn scripted(
+    _: &AllowedTopic,
+    _: &str,
+    message: String,
+    _: &HttpRequest,
+    _: &Metrics,
+) -> Option<(String, Option<Vec<String>>)> {
+    Some((message, None))
+}
+
+/// Diffs shorter than this are shown in the message, longer ones are attached
+const MAX_INLINE_DIFF_CHARS: usize = 3500;
+
+/// File sent as document, its name and content
+type Attachment = (String, Content);
+
+async fn read_file_field(
+    field: &mut actix_multipart::Field,
+    uploads: &Uploads,
+    limits: Limits,
+) -> Result<Attachment, ApiError> {
+    let filename = match field.content_disposition().get_filename() {
+        Some(filename) => filename.to_owned(),
+        None =>
+            return Err(ApiError::new(
+                ErrorCode::InvalidMultipart,
+                "Multipart filename missing",
+            )),
+    };
+
+    Ok((filename, uploads.read(field, limits.max_field_size).await?))
+}
+
+struct RenderedDiff {
+    filename: String,
+    content:  String,
+}
+
+/// Unified diff of two text files, identical files get a note instead
+fn render_diff(
+    before: Attachment,
+    after: Attachment,
+    locale: Locale,
+) -> Result<RenderedDiff, ApiError> {
+    let (before_name, before) = before;
+    let (after_name, after) = after;
+    let (before, after) = (before.load()?.into_owned(), after.load()?.into_owned());
+    let (before, after) = match (String::from_utf8(before), String::from_utf8(after)) {
+        (Ok(before), Ok(after)) => (before, after),
+        _ =>
+            return Err(ApiError::new(
+                ErrorCode::InvalidMultipart,
+                "Files to diff have to be valid UTF-8",
+            )),
+    };
+
+    let diff = diff::unified(&before_name, &before, &after_name, &after);
+    if diff.is_empty() {
+        return Ok(RenderedDiff {
+            filename: locale.fill(Phrase::Identical, &[&before_name, &after_name]),
+            content:  locale.text(Phrase::NoDifferences).to_owned(),
+        });
+    }
+
+    Ok(RenderedDiff {
+        filename: format!("{}.diff", after_name),
+        content:  diff,
+    })
+}
+
+/// Fields of multipart request with files
+struct MultipartFields {
+    message: Option<String>,
+    files:   Vec<Attachment>,
+    before:  Option<Attachment>,
+    after:   Option<Attachment>,
+}
+
+async fn read_message_field(
+    field: &mut actix_multipart::Field,
+    limits: Limits,
+) -> Result<String, ApiError> {
+    let mut message_bytes_buffer: Vec<u8> = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let chunk_bytes =
+            chunk.map_err(|err| ApiError::new(ErrorCode::InvalidMultipart, err.to_string()))?;
+        if message_bytes_buffer.len() + chunk_bytes.len() > limits.max_field_size {
+            return Err(upload::field_too_large("message", limits.max_field_size));
+        }
+
+        message_bytes_buffer.extend(chunk_bytes);
+    }
+
+    String::from_utf8(message_bytes_buffer)
+        .map_err(|_| ApiError::new(ErrorCode::InvalidMessage, "Message is not valid UTF-8"))
+}
+
+/// Reads every field before rejecting the request, so all problems with it are reported together
+async fn read_multipart(
+    multipart: &mut actix_multipart::Multipart,
+    uploads: &Uploads,
+    limits: Limits,
+) -> Result<MultipartFields, ApiError> {
+    let mut fields = MultipartFields {
+        message: None,
+        files:   Vec::new(),
+        before:  None,
+        after:   None,
+    };
+    let mut problems = Problems::default();
+    let mut count = 0;
+
+    while let Some(item) = multipart.next().await {
+        // Nothing after a broken boundary can be read
+        let mut field = match item {
+            Ok(field) => field,
+            Err(err) => {
+                problems.push(
+                    "multipart",
+                    ApiError::new(ErrorCode::InvalidMultipart, err.to_string()),
+                );
+                break;
+            }
+        };
+        count += 1;
+        if count > limits.max_fields {
+            problems.push(
+                "multipart",
+                ApiError::new(
+                    ErrorCode::MultipartTooLarge,
+                    format!("Multipart has more than {} fields", limits.max_fields),
+                ),
+            );
+            break;
+        }
+
+        let field_name = field.name().to_owned();
+        let read = match field_name.as_str() {
+            "message" => read_message_field(&mut field, limits)
+                .await
+                .map(|message| fields.message = Some(message)),
+            "file" => read_file_field(&mut field, uploads, limits)
+                .await
+                .map(|file| fields.files.push(file)),
+            "before" => read_file_field(&mut field, uploads, limits)
+                .await
+                .map(|file| fields.before = Some(file)),
+            "after" => read_file_field(&mut field, uploads, limits)
+                .await
+                .map(|file| fields.after = Some(file)),
+            _ => Err(ApiError::new(
+                ErrorCode::InvalidMultipart,
+                format!("Unexpected mutlipart field \"{}\"", field_name),
+            )),
+        };
+        match read {
+            Ok(()) => {}
+            Err(err) if err.code() == ErrorCode::InternalError => return Err(err),
+            Err(err) => problems.push(field_name, err),
+        }
+    }
+
+    // Files that failed to be read aren't missing
+    if !["file", "before", "after"]
+        .iter()
+        .any(|field| problems.concern(field))
+    {
+        match (&fields.before, &fields.after) {
+            (None, None) if fields.files.is_empty() => problems.push(
+                "file",
+                ApiError::new(ErrorCode::InvalidMultipart, "Multipart no file provided"),
+            ),
+            (None, None) => {}
+            (Some(_), Some(_)) if fields.files.is_empty() => {}
+            _ => problems.push(
+                "file",
+                ApiError::new(
+                    ErrorCode::InvalidMultipart,
+                    "Multipart needs either file or both before and after",
+                ),
+            ),
+        }
+    }
+    for (filename, content) in &fields.files {
+        if content.is_empty() {
+            problems.push(
+                "file",
+                ApiError::new(
+                    ErrorCode::InvalidMultipart,
+                    format!("File \"{}\" is empty", filename),
+                ),
+            );
+        }
+    }
+
+    problems.check(ErrorCode::InvalidMultipart)?;
+    Ok(fields)
+}
+
+async fn post_message_with_document(
+    topic: AllowedTopic,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    metrics: web::Data<Arc<Metrics>>,
+    messages: web::Data<Arc<Messages>>,
+    uploads: web::Data<Arc<Uploads>>,
+    callback: Callback,
+    request_options: RequestOptions,
+    path_data: web::Path<PostPathData>,
+    request: HttpRequest,
+    mut multipart: actix_multipart::Multipart,
+) -> HttpResponse {
+    let topic_info = &topic.info;
+    let limits = uploads.limits_of(&topic_info.upload_limits);
+    let fields = match timeout(
+        limits.timeout,
+        read_multipart(&mut multipart, &uploads, limits),
+    )
+    .await
+    {
+        Ok(Ok(fields)) => fields,
+        Ok(Err(err)) => return HttpResponse::from(err),
+        Err(_) =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::UploadTimedOut,
+                format!(
+                    "Request wasn't read within {}",
+                    format_duration(limits.timeout)
+                ),
+            )),
+    };
+    let MultipartFields {
+        message,
+        mut files,
+        before,
+        after,
+    } = fields;
+    let message = message.unwrap_or_default();
+
+    // Diff of `before` and `after` is sent in place of a file, `read_multipart` checked they
+    // come together
+    let mut is_inline_diff = false;
+    if let (Some(before), Some(after)) = (before, after) {
+        let diff = match render_diff(before, after, tg_client.locale(&topic.name)) {
+            Ok(diff) => diff,
+            Err(err) => return HttpResponse::from(err),
+        };
+        is_inline_diff = diff.content.chars().count() <= MAX_INLINE_DIFF_CHARS;
+        files.push((diff.filename, Content::Memory(diff.content.into_bytes())));
+    }
+
+    let files_size = files
+        .iter()
+        .map(|(_, content)| content.len())
+        .sum::<usize>();
+    metrics.count_ingress(&topic.name, message.len() + files_size, 1);
+    let meter = request
+        .app_data::<web::Data<Arc<Usage>>>()
+        .expect("Usage is in app data")
+        .meter(&topic, message.len() + files_size);
+    if let Some(summary) = &topic_info.summary {
+        request
+            .app_data::<web::Data<Arc<Summaries>>>()
+            .expect("Summaries are in app data")
+            .record(&topic.name, summary, &path_data.sender, Severity::default());
+    }
+
+    let filenames = files
+        .iter()
+        .map(|(filename, _)| filename.as_str())
+        .collect::<Vec<_>>();
+    if maintenance.intercept(
+        &path_data.topic_name,
+        topic_info,
+        &path_data.sender,
+        &format!("{} [{}]", message, filenames.join(", ")),
+    ) {
+        return HttpResponse::Accepted().body("Topic is under maintenance");
+    }
 
-            if responses.iter().all(|res| {
-                res.as_ref()
-                    .map_or_else(|_| false, |resp| resp.status() == StatusCode::OK)
-            }) {
-                HttpResponse::NoContent().finish()
-            } else {
-                HttpResponse::InternalServerError().body("bAdBaDnOtGoOd")
+    if let Some(response) = quotas::enforce(
+        &quotas,
+        &tg_client,
+        &topic.name,
+        topic_info,
+        message.len() + files_size,
+    )
+    .await
+    {
+        return response;
+    }
+    request
+        .app_data::<web::Data<Arc<Streams>>>()
+        .expect("Streams are in app data")
+        .publish(
+            &topic.name,
+            topic_info,
+            &path_data.sender,
+            Severity::default(),
+            &message,
+            &filenames,
+        );
+
+    let tg_client = tg_client.get_ref().clone();
+    let recipients = tg_client.recipients_of(&topic.name, topic_info);
+    let pipeline = topic_info.format.clone();
+    let options = request_options.apply(topic_info);
+    let code_language = request_options.code_language;
+    let inline_content = match files.as_slice() {
+        [(_, content)]
+            if is_inline_diff
+                || topic_info
+                    .inline_files_up_to
+                    .is_some_and(|limit| content.len() <= limit) =>
+            match content.load() {
+                Ok(content) => String::from_utf8(content.into_owned()).ok(),
+                Err(err) => return HttpResponse::from(err),
+            },
+        _ => None,
+    };
+    if files.len() > 1 && topic_info.bundle {
+        let archive_name = format!(
+            "{}-{}.zip",
+            topic.name,
+            chrono::Utc::now().format("%Y%m%dT%H%M%S")
+        );
+        let mut loaded = Vec::with_capacity(files.len());
+        for (filename, content) in &files {
+            match content.load() {
+                Ok(content) => loaded.push((filename.clone(), content.into_owned())),
+                Err(err) => return HttpResponse::from(err),
+            }
+        }
+        let archive = match web::block(move || compress::zip(&loaded)).await {
+            Ok(archive) => archive,
+            Err(err) => {
+                log::error!("Failed to bundle files: {}", err);
+                return HttpResponse::from(ApiError::new(
+                    ErrorCode::InternalError,
+                    "Failed to bundle files",
+                ));
+            }
+        };
+        files = vec![(archive_name, Content::Memory(archive))];
+    }
+    if let Some(over) = topic_info
+        .gzip_text_over
+        .filter(|_| inline_content.is_none())
+    {
+        for (filename, content) in &mut files {
+            if content.len() <= over {
+                continue;
+            }
+            let text = match content.load() {
+                Ok(text) if std::str::from_utf8(&text).is_ok() => text.into_owned(),
+                Ok(_) => continue,
+                Err(err) => return HttpResponse::from(err),
+            };
+            let gzipped = match web::block(move || compress::gzip(&text)).await {
+                Ok(gzipped) => gzipped,
+                Err(err) => {
+                    log::error!("Failed to gzip {}: {}", filename, err);
+                    return HttpResponse::from(ApiError::new(
+                        ErrorCode::InternalError,
+                        "Failed to gzip files",
+                    ));
+                }
+            };
+            *content = Content::Memory(gzipped);
+            filename.push_str(".gz");
+        }
+    }
+    let topic_name = path_data.topic_name.clone();
+    let sender = path_data.sender.clone();
+    messages::dispatch(
+        &messages,
+        &path_data.topic_name,
+        &path_data.sender,
+        topic_info.accept_async,
+        callback,
+        meter,
+        async move {
+            let options = SendOptions {
+                code_language: code_language.as_deref(),
+                ..options
+            };
+
+            if let Some(content) = inline_content {
+                let (filename, _) = &files[0];
+                return tg_client
+                    .send_inline_file_to_all(
+                        &recipients,
+                        &pipeline,
+                        &topic_name,
+                        &sender,
+                        &message,
+                        filename,
+                        &content,
+                        options,
+                    )
+                    .await;
             }
+
+            tg_client
+                .send_document_to_all(
+                    &recipients,
+                    &pipeline,
+                    &topic_name,
+                    &sender,
+                    &message,
+                    &files,
+                    options,
+                )
+                .await
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topics(names: &[&str]) -> Topics {
+        names
+            .iter()
+            .map(|name| {
+                let topic = toml::from_str::<Topic>("recipients = [\"111\"]").unwrap();
+                (name.to_string(), topic)
+            })
+            .collect()
+    }
+
+    fn checked(topics: &Topics) -> Result<(), String> {
+        check_topics(
+            topics,
+            &BTreeMap::new(),
+            &GeoIp::default(),
+            false,
+            &failover::Registry::default(),
+            None,
+        )
+    }
+
+    #[test]
+    fn topics_with_other_names_are_accepted() {
+        assert_eq!(checked(&topics(&["ops", "admins"])), Ok(()));
+    }
+
+    #[test]
+    fn topics_named_like_routes_are_rejected() {
+        for name in RESERVED_TOPIC_NAMES {
+            assert_eq!(
+                checked(&topics(&["ops", name])),
+                Err(format!(
+                    "Topic \"{}\" can't be used, its name is taken by routes of microphone",
+                    name
+                ))
+            );
         }
-        _ => HttpResponse::NotFound().body("No such topic"),
     }
 }
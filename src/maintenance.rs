@@ -0,0 +1,436 @@
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+        VecDeque,
+    },
+    str::FromStr,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    rt::time::interval,
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use cron::Schedule;
+use serde::{
+    Deserialize,
+    Deserializer,
+    Serialize,
+};
+
+use crate::{
+    admin::{
+        Operator,
+        Viewer,
+    },
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    locale::{
+        Locale,
+        Phrase,
+    },
+    LiveTopics,
+    TgClient,
+    TgMarkdownString,
+    Topic,
+    Topics,
+};
+
+const SENDER: &str = "maintenance";
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+/// Summary quotes only the latest held messages, each shortened to keep it readable
+const MAX_QUOTED_MESSAGES: usize = 5;
+const MAX_QUOTED_LENGTH: usize = 200;
+
+#[derive(Debug)]
+#[derive(Deserialize)]
+#[derive(Clone)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum MaintenanceWindow {
+    Interval {
+        start: DateTime<Utc>,
+        end:   DateTime<Utc>,
+    },
+    Recurring {
+        /// Cron expression with seconds field, e.g. "0 0 3 * * Sun"
+        #[serde(deserialize_with = "deserialize_schedule")]
+        cron:     Box<Schedule>,
+        #[serde(with = "humantime_serde")]
+        duration: Duration,
+    },
+}
+
+pub fn deserialize_schedule<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<Schedule>, D::Error> {
+    let expression = String::deserialize(deserializer)?;
+
+    Schedule::from_str(&expression)
+        .map(Box::new)
+        .map_err(serde::de::Error::custom)
+}
+
+impl MaintenanceWindow {
+    /// End of the window if it is in effect at the given moment
+    fn active_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Interval { start, end } => (*start <= now && now < *end).then_some(*end),
+            Self::Recurring { cron, duration } => {
+                let duration = chrono::Duration::from_std(*duration).ok()?;
+                let occurrence = cron.after(&(now - duration)).next()?;
+
+                (occurrence <= now).then_some(occurrence + duration)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Deserialize)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MaintenanceMode {
+    /// Messages are dropped
+    Suppress,
+    /// Messages are held and summarized once maintenance is over
+    #[default]
+    Summary,
+}
+
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[derive(Clone)]
+struct AdHocWindow {
+    id:     u64,
+    topic:  String,
+    start:  DateTime<Utc>,
+    end:    DateTime<Utc>,
+    reason: Option<String>,
+}
+
+#[derive(Default)]
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+struct HeldMessages {
+    senders: BTreeMap<String, usize>,
+    latest:  VecDeque<(String, String)>,
+}
+
+/// Windows created with admin API and messages held so far, as they're exported
+#[derive(Default)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Snapshot {
+    #[serde(default)]
+    windows: Vec<AdHocWindow>,
+    #[serde(default)]
+    held:    BTreeMap<String, HeldMessages>,
+}
+
+#[derive(Default)]
+pub struct Maintenance {
+    next_id: AtomicU64,
+    ad_hoc:  Mutex<Vec<AdHocWindow>>,
+    held:    Mutex<HashMap<String, HeldMessages>>,
+}
+
+impl Maintenance {
+    fn active_until(
+        &self,
+        topic_name: &str,
+        topic_info: &Topic,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let configured = topic_info
+            .maintenance
+            .iter()
+            .filter_map(|window| window.active_until(now));
+
+        let ad_hoc = self
+            .ad_hoc
+            .lock()
+            .expect("Maintenance lock is poisoned")
+            .iter()
+            .filter(|window| window.topic == topic_name && window.start <= now && now < window.end)
+            .map(|window| window.end)
+            .collect::<Vec<_>>();
+
+        configured.chain(ad_hoc).max()
+    }
+
+    pub fn is_active(&self, topic_name: &str, topic_info: &Topic) -> bool {
+        self.active_until(topic_name, topic_info, Utc::now())
+            .is_some()
+    }
+
+    /// Holds or drops the message if the topic is under maintenance, returns whether it did
+    pub fn intercept(
+        &self,
+        topic_name: &str,
+        topic_info: &Topic,
+        sender: &str,
+        text: &str,
+    ) -> bool {
+        if !self.is_active(topic_name, topic_info) {
+            return false;
+        }
+
+        if topic_info.maintenance_mode == MaintenanceMode::Summary {
+            let mut held = self.held.lock().expect("Maintenance lock is poisoned");
+            let held_messages = held.entry(topic_name.to_owned()).or_default();
+
+            *held_messages.senders.entry(sender.to_owned()).or_default() += 1;
+            held_messages.latest.push_back((
+                sender.to_owned(),
+                text.chars().take(MAX_QUOTED_LENGTH).collect(),
+            ));
+            if held_messages.latest.len() > MAX_QUOTED_MESSAGES {
+                held_messages.latest.pop_front();
+            }
+        }
+
+        true
+    }
+
+    fn take_finished(&self, topics: &Topics) -> Vec<(String, HeldMessages)> {
+        let held_topics = self
+            .held
+            .lock()
+            .expect("Maintenance lock is poisoned")
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let finished = held_topics
+            .into_iter()
+            .filter(|topic_name| match topics.get(topic_name) {
+                Some(topic_info) => !self.is_active(topic_name, topic_info),
+                None => true,
+            })
+            .collect::<Vec<_>>();
+
+        let mut held = self.held.lock().expect("Maintenance lock is poisoned");
+        finished
+            .into_iter()
+            .filter_map(|topic_name| held.remove_entry(&topic_name))
+            .collect()
+    }
+
+    pub fn export(&self) -> Snapshot {
+        Snapshot {
+            windows: self
+                .ad_hoc
+                .lock()
+                .expect("Maintenance lock is poisoned")
+                .clone(),
+            held:    self
+                .held
+                .lock()
+                .expect("Maintenance lock is poisoned")
+                .iter()
+                .map(|(topic_name, held_messages)| (topic_name.clone(), held_messages.clone()))
+                .collect(),
+        }
+    }
+
+    /// Windows keep their ids, so they can be deleted by the ids the other instance gave them
+    pub fn import(&self, snapshot: Snapshot) {
+        let mut ad_hoc = self.ad_hoc.lock().expect("Maintenance lock is poisoned");
+        for window in snapshot.windows {
+            self.next_id.fetch_max(window.id + 1, Ordering::SeqCst);
+            ad_hoc.retain(|existing| existing.id != window.id);
+            ad_hoc.push(window);
+        }
+
+        self.held
+            .lock()
+            .expect("Maintenance lock is poisoned")
+            .extend(snapshot.held);
+    }
+
+    fn forget_expired(&self) {
+        let now = Utc::now();
+        self.ad_hoc
+            .lock()
+            .expect("Maintenance lock is poisoned")
+            .retain(|window| window.end > now);
+    }
+}
+
+fn render_summary(held_messages: &HeldMessages, locale: Locale) -> String {
+    let total: usize = held_messages.senders.values().sum();
+    let held = if total == 1 {
+        Phrase::HeldMessage
+    } else {
+        Phrase::HeldMessages
+    };
+    let mut text = format!(
+        "🛠 *{}*\n{}\n",
+        locale.text(Phrase::MaintenanceOver),
+        *TgMarkdownString::new(&locale.fill(held, &[&total.to_string()]))
+    );
+
+    for (sender, count) in &held_messages.senders {
+        text.push_str(&format!("\n{}: {}", *TgMarkdownString::new(sender), count));
+    }
+
+    text.push_str(&format!(
+        "\n\n*{}*",
+        *TgMarkdownString::new(locale.text(Phrase::LatestMessages))
+    ));
+    for (sender, message) in &held_messages.latest {
+        text.push_str(&format!(
+            "\n_{}_: {}",
+            *TgMarkdownString::new(sender),
+            *TgMarkdownString::new(message)
+        ));
+    }
+
+    text
+}
+
+/// Held messages stay in memory of the instance that received them, so every instance
+/// sends its own summary
+pub fn spawn_summary_sender(
+    maintenance: Arc<Maintenance>,
+    topics: Arc<LiveTopics>,
+    tg_client: Arc<TgClient>,
+) {
+    actix_web::rt::spawn(async move {
+        let mut ticks = interval(FLUSH_INTERVAL);
+
+        loop {
+            ticks.tick().await;
+            maintenance.forget_expired();
+
+            let topics = topics.current();
+            for (topic_name, held_messages) in maintenance.take_finished(&topics) {
+                let topic_info = match topics.get(&topic_name) {
+                    Some(topic_info) => topic_info,
+                    None => continue,
+                };
+
+                let responses = tg_client
+                    .send_message_to_all(
+                        &tg_client.recipients_of(&topic_name, topic_info),
+                        &topic_name,
+                        SENDER,
+                        &render_summary(&held_messages, tg_client.locale(&topic_name)),
+                    )
+                    .await;
+
+                if responses.iter().any(|delivery| !delivery.is_delivered()) {
+                    log::warn!("Failed to send maintenance summary for \"{}\"", topic_name);
+                }
+            }
+        }
+    });
+}
+
+#[derive(Deserialize)]
+pub struct NewWindow {
+    start:    Option<DateTime<Utc>>,
+    #[serde(with = "humantime_serde")]
+    duration: Duration,
+    reason:   Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WindowPath {
+    topic_name: String,
+    id:         Option<u64>,
+}
+
+pub async fn create_window(
+    _: Operator,
+    topics: web::Data<Arc<LiveTopics>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    path_data: web::Path<WindowPath>,
+    new_window: web::Json<NewWindow>,
+) -> impl Responder {
+    if !topics.current().contains_key(&path_data.topic_name) {
+        return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic"));
+    }
+
+    let start = new_window.start.unwrap_or_else(Utc::now);
+    let duration = match chrono::Duration::from_std(new_window.duration) {
+        Ok(duration) => duration,
+        Err(_) =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::InvalidDuration,
+                "Duration is too long",
+            )),
+    };
+
+    let window = AdHocWindow {
+        id: maintenance.next_id.fetch_add(1, Ordering::SeqCst),
+        topic: path_data.topic_name.clone(),
+        start,
+        end: start + duration,
+        reason: new_window.reason.clone(),
+    };
+
+    maintenance
+        .ad_hoc
+        .lock()
+        .expect("Maintenance lock is poisoned")
+        .push(window.clone());
+
+    HttpResponse::Created().json(window)
+}
+
+pub async fn list_windows(_: Viewer, maintenance: web::Data<Arc<Maintenance>>) -> impl Responder {
+    maintenance.forget_expired();
+
+    let windows = maintenance
+        .ad_hoc
+        .lock()
+        .expect("Maintenance lock is poisoned")
+        .clone();
+
+    HttpResponse::Ok().json(windows)
+}
+
+pub async fn delete_window(
+    _: Operator,
+    maintenance: web::Data<Arc<Maintenance>>,
+    path_data: web::Path<WindowPath>,
+) -> impl Responder {
+    let mut ad_hoc = maintenance
+        .ad_hoc
+        .lock()
+        .expect("Maintenance lock is poisoned");
+
+    let windows_before = ad_hoc.len();
+    ad_hoc.retain(|window| window.topic != path_data.topic_name || Some(window.id) != path_data.id);
+
+    if ad_hoc.len() == windows_before {
+        HttpResponse::from(ApiError::new(
+            ErrorCode::MaintenanceWindowNotFound,
+            "No such maintenance window",
+        ))
+    } else {
+        HttpResponse::NoContent().finish()
+    }
+}
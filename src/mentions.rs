@@ -0,0 +1,62 @@
+use serde::Deserialize;
+
+use crate::{
+    severity::Severity,
+    TgMarkdownString,
+};
+
+/// People mentioned at the end of matching messages, so they get notified even in muted
+/// group chats
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MentionRule {
+    /// Messages of this severity or higher, any by default
+    #[serde(default)]
+    severity: Severity,
+    /// Messages of this sender only
+    sender:   Option<String>,
+    users:    Vec<Mention>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Mention {
+    /// `@username`
+    Username(String),
+    /// User without a username, mentioned by id under the name
+    User { id: i64, name: String },
+}
+
+impl Mention {
+    fn render(&self) -> String {
+        match self {
+            Self::Username(username) =>
+                TgMarkdownString::new(&format!("@{}", username.trim_start_matches('@'))).to_string(),
+            Self::User { id, name } =>
+                format!("[{}](tg://user?id={})", *TgMarkdownString::new(name), id),
+        }
+    }
+}
+
+/// Mentions of every rule the message matches in MarkdownV2, each user once
+pub fn render(rules: &[MentionRule], sender: &str, severity: Severity) -> Option<String> {
+    let mut mentions = Vec::new();
+    let users = rules
+        .iter()
+        .filter(|rule| {
+            severity >= rule.severity && rule.sender.as_deref().is_none_or(|only| only == sender)
+        })
+        .flat_map(|rule| &rule.users);
+    for user in users {
+        let mention = user.render();
+        if !mentions.contains(&mention) {
+            mentions.push(mention);
+        }
+    }
+
+    (!mentions.is_empty()).then(|| mentions.join(" "))
+}
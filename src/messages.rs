@@ -0,0 +1,334 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use actix_web::{
+    http::header::HeaderName,
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    admin::Operator,
+    callbacks::Callback,
+    delivery_response,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    usage::Meter,
+    Delivery,
+    TelegramMessages,
+    TgClient,
+    TELEGRAM_DELETE_MESSAGE_METHOD,
+};
+
+const MESSAGE_ID_HEADER: HeaderName = HeaderName::from_static("x-message-id");
+/// Older messages are forgotten
+const HISTORY_SIZE: usize = 10_000;
+const DEFAULT_LIST_LIMIT: usize = 100;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Queued,
+    Delivered,
+    PartiallyDelivered,
+    Failed,
+}
+
+#[derive(Clone)]
+#[derive(Serialize)]
+pub struct RecipientOutcome {
+    recipient:       String,
+    delivered:       bool,
+    attempts:        usize,
+    last_attempt_at: DateTime<Utc>,
+    /// Error of the last attempt
+    error:           Option<String>,
+    /// Ids of the messages in the chat of the recipient
+    #[serde(skip_serializing_if = "Option::is_none")]
+    telegram:        Option<TelegramMessages>,
+}
+
+#[derive(Clone)]
+#[derive(Serialize)]
+pub struct MessageRecord {
+    id:           String,
+    topic:        String,
+    sender:       String,
+    state:        State,
+    received_at:  DateTime<Utc>,
+    completed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at:   Option<DateTime<Utc>>,
+    recipients:   Vec<RecipientOutcome>,
+}
+
+/// Recent messages and how their delivery went
+#[derive(Default)]
+pub struct Messages {
+    records: Mutex<VecDeque<MessageRecord>>,
+}
+
+impl Messages {
+    fn track(&self, topic: &str, sender: &str) -> MessageRecord {
+        let record = MessageRecord {
+            id:           Uuid::new_v4().to_string(),
+            topic:        topic.to_owned(),
+            sender:       sender.to_owned(),
+            state:        State::Queued,
+            received_at:  Utc::now(),
+            completed_at: None,
+            deleted_at:   None,
+            recipients:   Vec::new(),
+        };
+
+        let mut records = self.records.lock().expect("Messages lock is poisoned");
+        if records.len() == HISTORY_SIZE {
+            records.pop_front();
+        }
+        records.push_back(record.clone());
+
+        record
+    }
+
+    fn complete(&self, mut record: MessageRecord, deliveries: &[Delivery]) -> MessageRecord {
+        record.recipients = deliveries
+            .iter()
+            .map(|delivery| RecipientOutcome {
+                recipient:       delivery.recipient.clone(),
+                delivered:       delivery.is_delivered(),
+                attempts:        delivery.attempts,
+                last_attempt_at: delivery.last_attempt_at,
+                error:           delivery.failure(),
+                telegram:        delivery.telegram_messages().cloned(),
+            })
+            .collect();
+        let delivered = record
+            .recipients
+            .iter()
+            .filter(|outcome| outcome.delivered)
+            .count();
+        record.state = match delivered {
+            _ if delivered == record.recipients.len() => State::Delivered,
+            0 => State::Failed,
+            _ => State::PartiallyDelivered,
+        };
+        record.completed_at = Some(Utc::now());
+
+        self.store(&record);
+
+        record
+    }
+
+    fn store(&self, record: &MessageRecord) {
+        let mut records = self.records.lock().expect("Messages lock is poisoned");
+        if let Some(stored) = records
+            .iter_mut()
+            .rev()
+            .find(|stored| stored.id == record.id)
+        {
+            *stored = record.clone();
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<MessageRecord> {
+        self.records
+            .lock()
+            .expect("Messages lock is poisoned")
+            .iter()
+            .rev()
+            .find(|record| record.id == id)
+            .cloned()
+    }
+}
+
+/// Delivers the message and records the outcome. Replies with the outcome, unless the topic
+/// accepts asynchronously or the request has callback URL, then replies 202 with message id
+/// right away. Either way the id is returned in `X-Message-Id` header
+pub async fn dispatch<F>(
+    messages: &Arc<Messages>,
+    topic: &str,
+    sender: &str,
+    accept_async: bool,
+    callback: Callback,
+    meter: Meter,
+    delivery: F,
+) -> HttpResponse
+where
+    F: Future<Output = Vec<Delivery>> + 'static,
+{
+    let record = messages.track(topic, sender);
+    let id = record.id.clone();
+    let callback = callback.into_target();
+
+    let mut response = if accept_async || callback.is_some() {
+        let messages = messages.clone();
+        actix_web::rt::spawn(async move {
+            let deliveries = delivery.await;
+            meter.record(&deliveries);
+            let record = messages.complete(record, &deliveries);
+            if let Some(callback) = callback {
+                callback.report(&record).await;
+            }
+        });
+
+        HttpResponse::Accepted().json(json!({ "message_id": id }))
+    } else {
+        let deliveries = delivery.await;
+        meter.record(&deliveries);
+        messages.complete(record, &deliveries);
+        delivery_response(&deliveries)
+    };
+
+    if let Ok(value) = id.parse() {
+        response.headers_mut().insert(MESSAGE_ID_HEADER, value);
+    }
+
+    response
+}
+
+pub async fn get_status(
+    messages: web::Data<Arc<Messages>>,
+    message_id: web::Path<String>,
+) -> impl Responder {
+    match messages.get(&message_id) {
+        Some(record) => HttpResponse::Ok().json(record),
+        None => HttpResponse::from(ApiError::new(
+            ErrorCode::MessageNotFound,
+            "No such message, it might have been forgotten",
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    topic: Option<String>,
+    state: Option<State>,
+    #[serde(default = "default_list_limit")]
+    limit: usize,
+}
+
+fn default_list_limit() -> usize {
+    DEFAULT_LIST_LIMIT
+}
+
+/// Recent messages, newest first
+pub async fn list_messages(
+    _: Operator,
+    messages: web::Data<Arc<Messages>>,
+    query: web::Query<ListQuery>,
+) -> impl Responder {
+    let records = messages
+        .records
+        .lock()
+        .expect("Messages lock is poisoned")
+        .iter()
+        .rev()
+        .filter(|record| {
+            query
+                .topic
+                .as_ref()
+                .is_none_or(|topic| record.topic == *topic)
+        })
+        .filter(|record| query.state.is_none_or(|state| record.state == state))
+        .take(query.limit)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    HttpResponse::Ok().json(records)
+}
+
+/// Deletes every Telegram message the posted message became, in chats of all recipients.
+/// Telegram allows deleting messages of the bot for 48 hours after they were sent
+pub async fn delete_message(
+    _: Operator,
+    messages: web::Data<Arc<Messages>>,
+    tg_client: web::Data<Arc<TgClient>>,
+    message_id: web::Path<String>,
+) -> impl Responder {
+    let mut record = match messages.get(&message_id) {
+        Some(record) => record,
+        None =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::MessageNotFound,
+                "No such message, it might have been forgotten",
+            )),
+    };
+    if record.completed_at.is_none() {
+        return HttpResponse::from(ApiError::new(
+            ErrorCode::MessageQueued,
+            "Message is still being delivered",
+        ));
+    }
+
+    let (mut deleted, mut failed) = (0, 0);
+    for sent in record
+        .recipients
+        .iter()
+        .filter_map(|outcome| outcome.telegram.as_ref())
+    {
+        for id in &sent.message_ids {
+            let response = tg_client
+                .call_method(
+                    TELEGRAM_DELETE_MESSAGE_METHOD,
+                    &json!({ "chat_id": sent.chat_id, "message_id": id }),
+                )
+                .await;
+            match response {
+                Ok(response) if response.status().is_success() => deleted += 1,
+                Ok(response) => {
+                    log::warn!(
+                        "Telegram responded with {} to deleting message {} in {}",
+                        response.status(),
+                        id,
+                        sent.chat_id
+                    );
+                    failed += 1;
+                }
+                Err(err) => {
+                    log::warn!("Failed to delete message: {}", err.without_url());
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if failed == 0 {
+        record.deleted_at = Some(Utc::now());
+        messages.store(&record);
+    }
+
+    HttpResponse::Ok().json(json!({ "deleted": deleted, "failed": failed }))
+}
+
+/// Same as `/status/{message_id}` for operators browsing the history
+pub async fn get_message(
+    _: Operator,
+    messages: web::Data<Arc<Messages>>,
+    message_id: web::Path<String>,
+) -> impl Responder {
+    get_status(messages, message_id).await
+}
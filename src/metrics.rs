@@ -0,0 +1,94 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+
+use crate::admin::Viewer;
+
+type Labels = Vec<(&'static str, String)>;
+
+/// Counters exposed in Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<(&'static str, Labels), u64>>,
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    pub fn increment(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        self.add(name, labels, 1);
+    }
+
+    pub fn add(&self, name: &'static str, labels: &[(&'static str, &str)], value: u64) {
+        let labels = labels
+            .iter()
+            .map(|(label, value)| (*label, value.to_string()))
+            .collect();
+
+        *self
+            .counters
+            .lock()
+            .expect("Metrics lock is poisoned")
+            .entry((name, labels))
+            .or_default() += value;
+    }
+
+    /// Counts body of accepted request, along with the number of attached files
+    pub fn count_ingress(&self, topic: &str, bytes: usize, attachments: u64) {
+        self.add(
+            "microphone_ingress_bytes_total",
+            &[("topic", topic)],
+            bytes as u64,
+        );
+        if attachments > 0 {
+            self.add(
+                "microphone_attachments_total",
+                &[("topic", topic)],
+                attachments,
+            );
+        }
+    }
+
+    fn render(&self) -> String {
+        let counters = self.counters.lock().expect("Metrics lock is poisoned");
+        let mut text = String::new();
+        let mut previous_name = "";
+
+        for ((name, labels), value) in counters.iter() {
+            if *name != previous_name {
+                text.push_str(&format!("# TYPE {} counter\n", name));
+                previous_name = name;
+            }
+
+            let labels = labels
+                .iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape_label_value(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            text.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+        }
+
+        text
+    }
+}
+
+pub async fn get_metrics(_: Viewer, metrics: web::Data<Arc<Metrics>>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
@@ -0,0 +1,276 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io,
+    path::PathBuf,
+    rc::Rc,
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_http::BoxedPayloadStream;
+use actix_web::{
+    dev::{
+        Payload,
+        ServiceRequest,
+        ServiceResponse,
+    },
+    http::Method,
+    web::Bytes,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use futures::StreamExt;
+use reqwest::Url;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::mpsc,
+};
+
+use crate::{
+    bot_http::{
+        self,
+        Outbound,
+    },
+    client_ip::ClientIp,
+    metrics::Metrics,
+};
+
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Credentials aren't mirrored
+const REDACTED_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-gitlab-token",
+];
+
+/// Copies of accepted requests to topics, in `[mirror]`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorConfig {
+    /// Every record is posted as JSON here
+    #[serde(default, deserialize_with = "bot_http::deserialize_url")]
+    url:        Option<Url>,
+    /// Records are appended here as JSON lines
+    file:       Option<PathBuf>,
+    /// Longer bodies are cut, `truncated` is set then
+    #[serde(default = "default_max_body")]
+    max_body:   usize,
+    /// Records waiting to be written, further ones are dropped while it's full
+    #[serde(default = "default_queue_size")]
+    queue_size: usize,
+}
+
+fn default_max_body() -> usize {
+    1024 * 1024
+}
+
+fn default_queue_size() -> usize {
+    1000
+}
+
+#[derive(Serialize)]
+struct Record {
+    received_at: DateTime<Utc>,
+    client:      Option<String>,
+    method:      String,
+    path:        String,
+    query:       String,
+    /// Values of repeated headers are joined with commas
+    headers:     BTreeMap<String, String>,
+    status:      u16,
+    /// Bodies that aren't UTF-8 are in `body_base64` instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body:        Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body_base64: Option<String>,
+    truncated:   bool,
+}
+
+#[derive(Default)]
+struct Captured {
+    body:      Vec<u8>,
+    truncated: bool,
+}
+
+/// Request being handled, with its body copied as the handler reads it
+pub struct Tap {
+    record:   Record,
+    captured: Rc<RefCell<Captured>>,
+}
+
+/// Queue of records written to the mirror in background, so the request doesn't wait for it
+pub struct Mirror {
+    records:  mpsc::Sender<Record>,
+    max_body: usize,
+    metrics:  Arc<Metrics>,
+}
+
+impl Mirror {
+    /// Starts writing records, opening the file right away so a wrong path fails at startup
+    pub fn spawn(
+        config: MirrorConfig,
+        outbound: &Outbound,
+        metrics: Arc<Metrics>,
+    ) -> io::Result<Self> {
+        if config.url.is_none() && config.file.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "[mirror] needs url, file or both",
+            ));
+        }
+
+        let mut file = match &config.file {
+            Some(path) => Some(tokio::fs::File::from_std(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            )),
+            None => None,
+        };
+        let client = outbound
+            .client_builder()
+            .timeout(POST_TIMEOUT)
+            .build()
+            .expect("Failed to build mirror client");
+        let (records, mut queued) = mpsc::channel::<Record>(config.queue_size.max(1));
+
+        let writer_metrics = metrics.clone();
+        actix_web::rt::spawn(async move {
+            while let Some(record) = queued.recv().await {
+                let failed = |destination: &str, err: String| {
+                    log::warn!("Failed to mirror request to {}: {}", destination, err);
+                    writer_metrics.increment(
+                        "microphone_mirror_failures_total",
+                        &[("destination", destination)],
+                    );
+                };
+
+                if let Some(file) = &mut file {
+                    let mut line = serde_json::to_vec(&record).expect("Failed to serialize record");
+                    line.push(b'\n');
+                    if let Err(err) = file.write_all(&line).await {
+                        failed("file", err.to_string());
+                    }
+                }
+                if let Some(url) = &config.url {
+                    let result = client
+                        .post(url.clone())
+                        .json(&record)
+                        .send()
+                        .await
+                        .and_then(|response| response.error_for_status());
+                    if let Err(err) = result {
+                        failed("url", err.to_string());
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            records,
+            max_body: config.max_body,
+            metrics,
+        })
+    }
+
+    /// Starts copying body of posted requests, admin ones are never mirrored
+    pub fn tap(&self, request: ServiceRequest) -> (ServiceRequest, Option<Tap>) {
+        if request.method() != Method::POST || request.path().starts_with("/admin/") {
+            return (request, None);
+        }
+
+        let record = Record {
+            received_at: Utc::now(),
+            client:      ClientIp::of_service_request(&request)
+                .ok()
+                .map(|ClientIp(client_address)| client_address.to_string()),
+            method:      request.method().to_string(),
+            path:        request.path().to_owned(),
+            query:       request.query_string().to_owned(),
+            headers:     headers_of(&request),
+            status:      0,
+            body:        None,
+            body_base64: None,
+            truncated:   false,
+        };
+
+        let captured = Rc::new(RefCell::new(Captured::default()));
+        let (http_request, payload) = request.into_parts();
+        let max_body = self.max_body;
+        let copied = captured.clone();
+        let payload = payload.inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                copy(&mut copied.borrow_mut(), chunk, max_body);
+            }
+        });
+        let request = ServiceRequest::from_parts(
+            http_request,
+            Payload::from(Box::pin(payload) as BoxedPayloadStream),
+        );
+
+        (request, Some(Tap { record, captured }))
+    }
+
+    /// Queues the request if it was accepted for a topic
+    pub fn record<B>(&self, tap: Tap, response: &ServiceResponse<B>) {
+        let is_topic = response.request().match_info().get("topic_name").is_some();
+        if !response.status().is_success() || !is_topic {
+            return;
+        }
+
+        let Tap {
+            mut record,
+            captured,
+        } = tap;
+        let captured = std::mem::take(&mut *captured.borrow_mut());
+        record.status = response.status().as_u16();
+        record.truncated = captured.truncated;
+        match String::from_utf8(captured.body) {
+            Ok(body) => record.body = Some(body),
+            Err(err) => record.body_base64 = Some(base64::encode(err.into_bytes())),
+        }
+
+        if self.records.try_send(record).is_err() {
+            self.metrics
+                .increment("microphone_mirror_dropped_total", &[]);
+        }
+    }
+}
+
+fn copy(captured: &mut Captured, chunk: &Bytes, max_body: usize) {
+    let room = max_body.saturating_sub(captured.body.len());
+    if chunk.len() > room {
+        captured.truncated = true;
+    }
+    captured
+        .body
+        .extend_from_slice(&chunk[..chunk.len().min(room)]);
+}
+
+fn headers_of(request: &ServiceRequest) -> BTreeMap<String, String> {
+    let mut headers = BTreeMap::<String, String>::new();
+    for (name, value) in request.headers() {
+        if REDACTED_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        let value = String::from_utf8_lossy(value.as_bytes());
+        headers
+            .entry(name.to_string())
+            .and_modify(|values| {
+                values.push_str(", ");
+                values.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+
+    headers
+}
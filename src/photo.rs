@@ -0,0 +1,521 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    compress,
+    locale::{
+        Locale,
+        Phrase,
+    },
+};
+
+/// Button of a downscaled photo sends its original, the key follows
+pub const ORIGINAL_CALLBACK_PREFIX: &str = "original:";
+
+/// Telegram rejects photos larger than this, documents can be much larger
+const MAX_PHOTO_SIZE: usize = 10 * 1024 * 1024;
+/// Telegram limit on width and height of a photo together
+const MAX_PHOTO_DIMENSIONS: u32 = 10_000;
+/// Telegram limit on how many times one side of a photo can be longer than the other
+const MAX_ASPECT_RATIO: u32 = 20;
+/// Larger images aren't decoded to be downscaled, 3 bytes of each pixel are kept in memory
+const MAX_DECODED_PIXELS: u64 = 50_000_000;
+/// Originals are dropped after this long, the oldest ones earlier if they take more memory
+const ORIGINAL_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_ORIGINALS_SIZE: usize = 256 * 1024 * 1024;
+
+/// Full-size image of a photo that was downscaled
+#[derive(Clone)]
+pub struct Original {
+    pub topic: String,
+    pub image: Vec<u8>,
+}
+
+/// Originals of photos downscaled for `max_photo_dimension`, kept in memory of the instance that
+/// sent them. Keys are random, as anyone in a chat with the bot could send any callback data
+#[derive(Default)]
+pub struct Originals {
+    kept: Mutex<VecDeque<(Instant, String, Original)>>,
+}
+
+impl Originals {
+    /// Keeps the image and returns its key
+    pub fn keep(&self, topic: &str, image: &[u8]) -> String {
+        let key = Uuid::new_v4().simple().to_string();
+        let mut kept = self.kept.lock().expect("Originals lock is poisoned");
+        kept.retain(|(kept_at, _, _)| kept_at.elapsed() < ORIGINAL_TTL);
+        let mut size = kept
+            .iter()
+            .map(|(_, _, original)| original.image.len())
+            .sum::<usize>();
+        while size + image.len() > MAX_ORIGINALS_SIZE {
+            match kept.pop_front() {
+                Some((_, _, original)) => size -= original.image.len(),
+                None => break,
+            }
+        }
+        kept.push_back((
+            Instant::now(),
+            key.clone(),
+            Original {
+                topic: topic.to_owned(),
+                image: image.to_vec(),
+            },
+        ));
+
+        key
+    }
+
+    pub fn get(&self, key: &str) -> Option<Original> {
+        self.kept
+            .lock()
+            .expect("Originals lock is poisoned")
+            .iter()
+            .find(|(kept_at, kept_key, _)| kept_key == key && kept_at.elapsed() < ORIGINAL_TTL)
+            .map(|(_, _, original)| original.clone())
+    }
+}
+
+pub fn original_markup(key: &str, locale: Locale) -> serde_json::Value {
+    json!({
+        "inline_keyboard": [[{
+            "text": format!("🖼 {}", locale.text(Phrase::Original)),
+            "callback_data": format!("{}{}", ORIGINAL_CALLBACK_PREFIX, key),
+        }]]
+    })
+}
+
+/// Image that doesn't [fit](fits) downscaled to the limits of Telegram and `max_dimension` of
+/// the topic. Images that can't be downscaled are better sent as documents, which keeps the
+/// original, the error tells why. Only PNG is decoded, JPEG images are rejected
+pub fn downscaled(image: &[u8], max_dimension: Option<u32>) -> Result<Vec<u8>, String> {
+    if image.starts_with(&[0xFF, 0xD8]) {
+        return Err("JPEG images aren't downscaled".to_owned());
+    }
+    let (width, height) = dimensions(image).ok_or("image format is unknown")?;
+    let (longer, shorter) = (width.max(height), width.min(height).max(1));
+    if longer / shorter > MAX_ASPECT_RATIO {
+        return Err(format!(
+            "image is more than {} times longer than wide",
+            MAX_ASPECT_RATIO
+        ));
+    }
+
+    let pixels = decode_png(image).ok_or("image can't be decoded")?;
+    let scale = (max_dimension.unwrap_or(u32::MAX).min(longer) as f64 / longer as f64)
+        .min(MAX_PHOTO_DIMENSIONS as f64 / (width as f64 + height as f64))
+        .min(1.0);
+    let scaled_width = ((width as f64 * scale) as u32).max(1);
+    let scaled_height = ((height as f64 * scale) as u32).max(1);
+    let scaled = png(
+        scaled_width,
+        scaled_height,
+        &downscale(width, height, &pixels, scaled_width, scaled_height),
+    );
+
+    if scaled.len() > MAX_PHOTO_SIZE {
+        return Err(format!(
+            "downscaled image is larger than {} bytes",
+            MAX_PHOTO_SIZE
+        ));
+    }
+
+    Ok(scaled)
+}
+
+/// Whether the image can go as photo as it is. Images of unknown format are left to Telegram to
+/// judge
+pub fn fits(image: &[u8], max_dimension: Option<u32>) -> bool {
+    if image.len() > MAX_PHOTO_SIZE {
+        return false;
+    }
+
+    let (width, height) = match dimensions(image) {
+        Some(dimensions) => dimensions,
+        None => return true,
+    };
+    let (longer, shorter) = (width.max(height), width.min(height).max(1));
+
+    // Dimensions from headers can be anything
+    u64::from(width) + u64::from(height) <= u64::from(MAX_PHOTO_DIMENSIONS)
+        && longer / shorter <= MAX_ASPECT_RATIO
+        && max_dimension.is_none_or(|max_dimension| longer <= max_dimension)
+}
+
+/// Name for the image sent as document
+pub fn filename(image: &[u8]) -> &'static str {
+    if image.starts_with(b"\x89PNG") {
+        "image.png"
+    } else if image.starts_with(&[0xFF, 0xD8]) {
+        "image.jpg"
+    } else {
+        "image"
+    }
+}
+
+/// Width and height of PNG and JPEG images, read from their headers
+fn dimensions(image: &[u8]) -> Option<(u32, u32)> {
+    let be_u16 = |at: usize| Some(u16::from_be_bytes(image.get(at..at + 2)?.try_into().ok()?));
+    let be_u32 = |at: usize| Some(u32::from_be_bytes(image.get(at..at + 4)?.try_into().ok()?));
+
+    if image.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((be_u32(16)?, be_u32(20)?));
+    }
+    if !image.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    // Segments of JPEG are walked until the frame header
+    let mut at = 2;
+    loop {
+        if *image.get(at)? != 0xFF {
+            return None;
+        }
+        let marker = *image.get(at + 1)?;
+        match marker {
+            // Padding
+            0xFF => at += 1,
+            // Markers without segment
+            0x01 | 0xD0..=0xD7 => at += 2,
+            // Start of frame, except huffman and arithmetic coding tables
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let height = be_u16(at + 5)?;
+                let width = be_u16(at + 7)?;
+                return Some((width as u32, height as u32));
+            }
+            _ => at += 2 + be_u16(at + 2)? as usize,
+        }
+    }
+}
+
+/// PNG image of RGB pixels, row by row
+pub fn png(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    let row_length = width as usize * 3;
+    let mut scanlines = Vec::with_capacity((row_length + 1) * height as usize);
+    for row in pixels.chunks(row_length) {
+        // No filter
+        scanlines.push(0);
+        scanlines.extend(row);
+    }
+
+    let mut header = Vec::new();
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // 8 bits per channel, RGB, deflate, no filters, no interlace
+    header.extend([8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (kind, data) in [
+        (b"IHDR", header),
+        (b"IDAT", compress::zlib(&scanlines)),
+        (b"IEND", Vec::new()),
+    ] {
+        png.extend((data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend(kind);
+        png.extend(data);
+        let crc = compress::crc32(&png[start..]);
+        png.extend(crc.to_be_bytes());
+    }
+
+    png
+}
+
+/// RGB pixels of non-interlaced PNG image, row by row. Transparent pixels are put over white as
+/// Telegram shows photos without transparency
+fn decode_png(image: &[u8]) -> Option<Vec<u8>> {
+    let (width, height) = dimensions(image)?;
+    if width == 0 || height == 0 || u64::from(width) * u64::from(height) > MAX_DECODED_PIXELS {
+        return None;
+    }
+    let (width, height) = (width as usize, height as usize);
+
+    let mut at = 8;
+    let (mut header, mut palette, mut data) = (None, &[][..], Vec::new());
+    while let Some(length) = image.get(at..at + 4) {
+        let length = u32::from_be_bytes(length.try_into().ok()?) as usize;
+        let kind = image.get(at + 4..at + 8)?;
+        let chunk = image.get(at + 8..(at + 8).checked_add(length)?)?;
+        match kind {
+            b"IHDR" => header = Some(chunk.get(8..13)?),
+            b"PLTE" => palette = chunk,
+            b"IDAT" => data.extend(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        at += 12 + length;
+    }
+
+    // Bit depth, color type, compression, filter method and interlace
+    let &[depth, color, 0, 0, 0] = header? else {
+        return None;
+    };
+    let channels = match (color, depth) {
+        (0, 1 | 2 | 4 | 8 | 16) | (3, 1 | 2 | 4 | 8) => 1,
+        (4, 8 | 16) => 2,
+        (2, 8 | 16) => 3,
+        (6, 8 | 16) => 4,
+        _ => return None,
+    };
+    let bits = channels * depth as usize;
+    let row_length = (width * bits).div_ceil(8);
+    let scanlines = compress::unzlib(&data, (row_length + 1) * height)?;
+    if scanlines.len() != (row_length + 1) * height {
+        return None;
+    }
+
+    // Filters refer to the same byte of the pixel to the left, or of the byte if pixels are smaller
+    let step = bits.div_ceil(8);
+    let mut rows = vec![0; row_length * height];
+    for (y, scanline) in scanlines.chunks(row_length + 1).enumerate() {
+        let (filter, line) = (scanline[0], &scanline[1..]);
+        let (done, rest) = rows.split_at_mut(y * row_length);
+        let previous = done
+            .get(done.len().saturating_sub(row_length)..)
+            .filter(|_| y > 0);
+        let row = &mut rest[..row_length];
+        for x in 0..row_length {
+            let left = if x >= step { row[x - step] } else { 0 };
+            let up = previous.map_or(0, |previous| previous[x]);
+            let up_left = match previous {
+                Some(previous) if x >= step => previous[x - step],
+                _ => 0,
+            };
+            row[x] = line[x].wrapping_add(match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return None,
+            });
+        }
+    }
+
+    // Samples scaled to 8 bits
+    let sample = |row: &[u8], index: usize| -> u8 {
+        match depth {
+            16 => row[index * 2],
+            8 => row[index],
+            _ => {
+                let bit = index * depth as usize;
+                let value = (row[bit / 8] >> (8 - depth as usize - bit % 8)) & ((1 << depth) - 1);
+                if color == 3 {
+                    value
+                } else {
+                    (value as u16 * 255 / ((1 << depth) - 1)) as u8
+                }
+            }
+        }
+    };
+    let over_white = |value: u8, alpha: u8| {
+        ((value as u16 * alpha as u16 + 255 * (255 - alpha as u16)) / 255) as u8
+    };
+
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for row in rows.chunks(row_length) {
+        for x in 0..width {
+            let first = x * channels;
+            match color {
+                0 => pixels.extend([sample(row, first); 3]),
+                3 => pixels.extend(palette.get(sample(row, first) as usize * 3..)?.get(..3)?),
+                4 => {
+                    let (value, alpha) = (sample(row, first), sample(row, first + 1));
+                    pixels.extend([over_white(value, alpha); 3]);
+                }
+                2 => pixels.extend((0..3).map(|channel| sample(row, first + channel))),
+                _ => {
+                    let alpha = sample(row, first + 3);
+                    pixels.extend(
+                        (0..3).map(|channel| over_white(sample(row, first + channel), alpha)),
+                    );
+                }
+            }
+        }
+    }
+
+    Some(pixels)
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let (to_left, to_up, to_up_left) = (
+        (estimate - left as i16).abs(),
+        (estimate - up as i16).abs(),
+        (estimate - up_left as i16).abs(),
+    );
+
+    if to_left <= to_up && to_left <= to_up_left {
+        left
+    } else if to_up <= to_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+/// RGB pixels averaged over areas each scaled pixel covers
+fn downscale(
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+    scaled_width: u32,
+    scaled_height: u32,
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let (scaled_width, scaled_height) = (scaled_width as usize, scaled_height as usize);
+    // Scaled pixels cover at least one pixel
+    let span = |scaled: usize, of: usize, length: usize| {
+        let start = scaled * length / of;
+        start..((scaled + 1) * length / of).max(start + 1)
+    };
+
+    let mut scaled = Vec::with_capacity(scaled_width * scaled_height * 3);
+    for y in 0..scaled_height {
+        let rows = span(y, scaled_height, height);
+        for x in 0..scaled_width {
+            let columns = span(x, scaled_width, width);
+            let mut sums = [0u64; 3];
+            for row in rows.clone() {
+                for column in columns.clone() {
+                    let pixel = &pixels[(row * width + column) * 3..][..3];
+                    for (sum, value) in sums.iter_mut().zip(pixel) {
+                        *sum += *value as u64;
+                    }
+                }
+            }
+            let count = (rows.len() * columns.len()) as u64;
+            scaled.extend(sums.map(|sum| (sum / count) as u8));
+        }
+    }
+
+    scaled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RGBA image of 3 by 5 pixels, rows with each filter in turn, made by another encoder
+    const FILTERED_RGBA: &str = "89504e470d0a1a0a0000000d4948445200000003000000050806000000807156a2000000404944415478da636038c1f05fe3040343009066e45a05e430083602713d13d7230606ae478240acc4c02ca2c1d020f951e9a0e4476d0716b00c0350864189010061da104612ab6cf90000000049454e44ae426082";
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&hex[at..at + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn header_only(width: u32, height: u32) -> Vec<u8> {
+        let mut image = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        image.extend(width.to_be_bytes());
+        image.extend(height.to_be_bytes());
+        image
+    }
+
+    #[test]
+    fn crafted_dimensions_dont_overflow() {
+        assert!(!fits(&header_only(u32::MAX, u32::MAX), None));
+        assert!(downscaled(&header_only(u32::MAX, u32::MAX), None).is_err());
+    }
+
+    #[test]
+    fn images_within_limits_are_sent_as_they_are() {
+        let image = png(40, 30, &[7; 40 * 30 * 3]);
+
+        assert!(fits(&image, Some(40)));
+        assert!(!fits(&image, Some(39)));
+    }
+
+    #[test]
+    fn decodes_own_images() {
+        let pixels = (0..6 * 4 * 3).map(|value| value as u8).collect::<Vec<_>>();
+
+        assert_eq!(decode_png(&png(6, 4, &pixels)), Some(pixels));
+    }
+
+    #[test]
+    fn decodes_filtered_images_with_transparency_over_white() {
+        let rows: [[[u8; 3]; 3]; 5] = [
+            [[0, 200, 0], [255, 255, 255], [80, 200, 0]],
+            [[10, 170, 0], [152, 212, 135], [90, 170, 34]],
+            [[20, 140, 0], [157, 197, 144], [100, 140, 68]],
+            [[30, 110, 0], [162, 182, 152], [110, 110, 102]],
+            [[40, 80, 0], [167, 167, 161], [120, 80, 136]],
+        ];
+
+        assert_eq!(
+            decode_png(&unhex(FILTERED_RGBA)),
+            Some(rows.concat().concat())
+        );
+    }
+
+    #[test]
+    fn downscales_to_max_dimension_averaging_pixels() {
+        // Columns alternate black and white
+        let pixels = (0..400 * 100)
+            .flat_map(|pixel| [if pixel % 2 == 0 { 0 } else { 254 }; 3])
+            .collect::<Vec<_>>();
+
+        let image = png(400, 100, &pixels);
+        let scaled = downscaled(&image, Some(200)).unwrap();
+
+        assert_eq!(dimensions(&scaled), Some((200, 50)));
+        assert_eq!(decode_png(&scaled), Some(vec![127; 200 * 50 * 3]));
+    }
+
+    #[test]
+    fn downscales_to_limit_of_width_and_height_together() {
+        let image = png(9600, 480, &vec![0; 9600 * 480 * 3]);
+        let scaled = downscaled(&image, None).unwrap();
+
+        assert_eq!(dimensions(&scaled), Some((9523, 476)));
+    }
+
+    #[test]
+    fn too_narrow_and_broken_images_go_as_documents() {
+        assert!(downscaled(&png(2100, 100, &vec![0; 2100 * 100 * 3]), None).is_err());
+
+        let mut broken = png(400, 100, &vec![0; 400 * 100 * 3]);
+        broken.truncate(60);
+        assert!(downscaled(&broken, Some(200)).is_err());
+    }
+
+    #[test]
+    fn originals_are_found_by_key_until_memory_limit_drops_the_oldest() {
+        let originals = Originals::default();
+        let first = originals.keep("ops", &vec![1; MAX_ORIGINALS_SIZE / 2]);
+        let second = originals.keep("ci", &vec![2; MAX_ORIGINALS_SIZE / 2]);
+
+        assert_ne!(first, second);
+        assert_eq!(originals.get(&first).unwrap().topic, "ops");
+        assert!(originals.get("unknown").is_none());
+
+        originals.keep("ops", &[3]);
+
+        assert!(originals.get(&first).is_none());
+        assert_eq!(originals.get(&second).unwrap().image[0], 2);
+    }
+
+    #[test]
+    fn jpeg_images_are_rejected() {
+        // Start of image and frame header of 4000 by 3000 pixels
+        let jpeg = unhex("ffd8ffc00011080bb80fa003012200021101031101");
+
+        assert_eq!(dimensions(&jpeg), Some((4000, 3000)));
+        assert!(!fits(&jpeg, Some(2560)));
+        assert_eq!(
+            downscaled(&jpeg, Some(2560)),
+            Err("JPEG images aren't downscaled".to_owned())
+        );
+    }
+}
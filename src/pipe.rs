@@ -0,0 +1,382 @@
+use std::{
+    io::BufRead,
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::rt::time::{
+    sleep,
+    timeout,
+    Instant,
+};
+use futures::{
+    channel::mpsc,
+    StreamExt,
+};
+use reqwest::{
+    ClientBuilder,
+    StatusCode,
+};
+
+use crate::{
+    config,
+    format::Pipeline,
+    send_options::SendOptions,
+    Config,
+    Delivery,
+    TgClient,
+};
+
+const USAGE: &str = "\
+Usage: microphone pipe --topic <topic> --sender <sender> (--url <url> | --config <path>)
+                       [--batch-lines <n>] [--batch-wait <seconds>] [--min-interval <seconds>]
+                       [--max-retries <n>]
+
+Reads lines from stdin and posts them in batches to the given topic.
+
+    --url           Base url of a running microphone instance, e.g. http://microphone
+    --config        Config file to send messages directly through Telegram bot instead
+    --batch-lines   Maximum number of lines in a single message (default: 50)
+    --batch-wait    Seconds to wait for more lines before sending a batch (default: 2)
+    --min-interval  Minimum number of seconds between two messages (default: 1)
+    --max-retries   Number of retries for a batch that failed transiently before it is
+                    dropped, rejected batches are dropped right away (default: 5)";
+
+/// Telegram refuses messages longer than 4096 characters, leave some room for the header
+const MAX_BATCH_CHARS: usize = 3500;
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Batch that wasn't sent, rejected ones would be rejected again
+enum Failure {
+    /// Connection failed, rate limited or server error
+    Transient(String),
+    Rejected(String),
+}
+
+enum Destination {
+    Instance {
+        http_client: reqwest::Client,
+        url:         String,
+    },
+    Direct {
+        tg_client:  Box<TgClient>,
+        recipients: Vec<String>,
+        pipeline:   Pipeline,
+        options:    SendOptions<'static>,
+    },
+}
+
+struct PipeOptions {
+    topic:        String,
+    sender:       String,
+    url:          Option<String>,
+    config_path:  Option<String>,
+    batch_lines:  usize,
+    batch_wait:   Duration,
+    min_interval: Duration,
+    max_retries:  u32,
+}
+
+impl PipeOptions {
+    fn parse(args: Vec<String>) -> Result<Self, String> {
+        let mut topic = None;
+        let mut sender = None;
+        let mut url = None;
+        let mut config_path = None;
+        let mut batch_lines = 50;
+        let mut batch_wait = Duration::from_secs(2);
+        let mut min_interval = Duration::from_secs(1);
+        let mut max_retries = 5;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for \"{}\"", arg))
+            };
+
+            match arg.as_str() {
+                "--topic" => topic = Some(value()?),
+                "--sender" => sender = Some(value()?),
+                "--url" => url = Some(value()?),
+                "--config" => config_path = Some(value()?),
+                "--batch-lines" => batch_lines = parse_number(&arg, &value()?)?,
+                "--batch-wait" => batch_wait = Duration::from_secs(parse_number(&arg, &value()?)?),
+                "--min-interval" =>
+                    min_interval = Duration::from_secs(parse_number(&arg, &value()?)?),
+                "--max-retries" => max_retries = parse_number(&arg, &value()?)?,
+                unknown => return Err(format!("Unexpected argument \"{}\"", unknown)),
+            }
+        }
+
+        if url.is_some() == config_path.is_some() {
+            return Err("Exactly one of \"--url\" or \"--config\" must be provided".to_owned());
+        }
+
+        if batch_lines == 0 {
+            return Err("\"--batch-lines\" must be greater than zero".to_owned());
+        }
+
+        Ok(Self {
+            topic: topic.ok_or("Missing \"--topic\"")?,
+            sender: sender.ok_or("Missing \"--sender\"")?,
+            url,
+            config_path,
+            batch_lines,
+            batch_wait,
+            min_interval,
+            max_retries,
+        })
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Value \"{}\" for \"{}\" is not a valid number", value, arg))
+}
+
+impl Destination {
+    async fn new(options: &PipeOptions) -> Result<Self, String> {
+        if let Some(url) = &options.url {
+            let http_client = ClientBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .map_err(|err| err.to_string())?;
+
+            return Ok(Self::Instance {
+                http_client,
+                url: format!(
+                    "{}/{}/{}",
+                    url.trim_end_matches('/'),
+                    options.topic,
+                    options.sender
+                ),
+            });
+        }
+
+        let config: Config =
+            config::load_source(options.config_path.as_deref().unwrap_or_default())
+                .await
+                .map_err(|err| err.to_string())?;
+        let sandbox_chat = config.sandbox_chat()?;
+        let tg_client = TgClient::new(
+            config.telegram.secret,
+            &config
+                .telegram
+                .http
+                .or_proxy(config.proxy)
+                .with_outbound(config.outbound.clone()),
+            Arc::default(),
+        )
+        .with_recipient_groups(config.recipient_groups)
+        .with_locales(&config.topics)
+        .with_senders(config.senders)
+        .with_sandbox_chat(sandbox_chat);
+        let topic_info = match config.topics.get(&options.topic) {
+            Some(topic_info) => topic_info,
+            None => return Err(format!("No such topic \"{}\" in config", options.topic)),
+        };
+
+        Ok(Self::Direct {
+            recipients: tg_client.recipients_of(&options.topic, topic_info),
+            pipeline:   topic_info.format.clone(),
+            options:    SendOptions::of_topic(topic_info),
+            tg_client:  Box::new(tg_client),
+        })
+    }
+
+    async fn send(&self, topic: &str, sender: &str, text: &str) -> Result<(), Failure> {
+        match self {
+            Self::Instance { http_client, url } => {
+                let response = http_client
+                    .post(url)
+                    .body(text.to_owned())
+                    .send()
+                    .await
+                    .map_err(|err| Failure::Transient(err.to_string()))?;
+
+                let status = response.status();
+                if status.is_success() {
+                    return Ok(());
+                }
+                let error = match response.json::<serde_json::Value>().await {
+                    Ok(body) => match body["message"].as_str() {
+                        Some(message) => format!("Instance responded with {}: {}", status, message),
+                        None => format!("Instance responded with {}", status),
+                    },
+                    Err(_) => format!("Instance responded with {}", status),
+                };
+
+                if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+                    Err(Failure::Transient(error))
+                } else {
+                    Err(Failure::Rejected(error))
+                }
+            }
+            Self::Direct {
+                tg_client,
+                recipients,
+                pipeline,
+                options,
+            } => {
+                let responses = tg_client
+                    .send_formatted_to_all(recipients, pipeline, topic, sender, text, *options)
+                    .await;
+
+                let undelivered = responses
+                    .iter()
+                    .filter(|delivery| !delivery.is_delivered())
+                    .map(|delivery| delivery.recipient.as_str())
+                    .collect::<Vec<_>>();
+
+                // Retrying the batch would notify recipients that already got it once more
+                if undelivered.is_empty() {
+                    Ok(())
+                } else if undelivered.len() < responses.len() {
+                    eprintln!("Batch was not delivered to {}", undelivered.join(", "));
+                    Ok(())
+                } else if responses.iter().any(Delivery::is_transient_failure) {
+                    Err(Failure::Transient(
+                        "Telegram did not accept message for any of the recipients".to_owned(),
+                    ))
+                } else {
+                    Err(Failure::Rejected(
+                        "Telegram rejected message for every recipient".to_owned(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+pub async fn run(args: Vec<String>) -> Result<(), std::io::Error> {
+    let options = match PipeOptions::parse(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let destination = match Destination::new(&options).await {
+        Ok(destination) => destination,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let (lines_sender, mut lines) = mpsc::unbounded();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            match line {
+                Ok(line) =>
+                    if lines_sender.unbounded_send(line).is_err() {
+                        break;
+                    },
+                Err(err) => {
+                    eprintln!("Failed to read stdin: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut batch: Vec<String> = Vec::new();
+    let mut batch_chars = 0;
+    let mut last_sent: Option<Instant> = None;
+    let mut stdin_closed = false;
+
+    while !stdin_closed || !batch.is_empty() {
+        let next_line = if batch.is_empty() {
+            lines.next().await
+        } else {
+            match timeout(options.batch_wait, lines.next()).await {
+                Ok(line) => line,
+                // Nothing new arrived for a while, send what we have
+                Err(_) => {
+                    send_batch(&destination, &options, &mut batch, &mut last_sent).await;
+                    batch_chars = 0;
+                    continue;
+                }
+            }
+        };
+
+        match next_line {
+            Some(line) => {
+                if batch_chars + line.len() > MAX_BATCH_CHARS && !batch.is_empty() {
+                    send_batch(&destination, &options, &mut batch, &mut last_sent).await;
+                    batch_chars = 0;
+                }
+
+                batch_chars += line.len() + 1;
+                batch.push(line);
+
+                if batch.len() >= options.batch_lines {
+                    send_batch(&destination, &options, &mut batch, &mut last_sent).await;
+                    batch_chars = 0;
+                }
+            }
+            None => {
+                stdin_closed = true;
+                if !batch.is_empty() {
+                    send_batch(&destination, &options, &mut batch, &mut last_sent).await;
+                    batch_chars = 0;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_batch(
+    destination: &Destination,
+    options: &PipeOptions,
+    batch: &mut Vec<String>,
+    last_sent: &mut Option<Instant>,
+) {
+    let text = batch.join("\n");
+    batch.clear();
+
+    let mut backoff = options.min_interval.max(Duration::from_secs(1));
+    let mut attempt = 0;
+
+    loop {
+        if let Some(last_sent) = last_sent {
+            let elapsed = last_sent.elapsed();
+            if elapsed < options.min_interval {
+                sleep(options.min_interval - elapsed).await;
+            }
+        }
+        *last_sent = Some(Instant::now());
+
+        match destination
+            .send(&options.topic, &options.sender, &text)
+            .await
+        {
+            Ok(()) => return,
+            Err(Failure::Transient(err)) if attempt < options.max_retries => {
+                attempt += 1;
+                eprintln!(
+                    "Failed to send batch: {}. Retrying in {}s ({}/{})",
+                    err,
+                    backoff.as_secs(),
+                    attempt,
+                    options.max_retries
+                );
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(Failure::Transient(err) | Failure::Rejected(err)) => {
+                eprintln!(
+                    "Failed to send batch: {}. Dropping {} bytes",
+                    err,
+                    text.len()
+                );
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    io,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        Ipv6Addr,
+        SocketAddr,
+    },
+    time::Duration,
+};
+
+use actix_web::rt::{
+    net::TcpStream,
+    time::timeout,
+};
+use tokio::io::AsyncReadExt;
+
+/// Signature opening binary (v2) header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest possible text (v1) header including CRLF
+const V1_MAX_LENGTH: usize = 107;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parses the rest of text header, e.g. "PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n"
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let parts = line
+        .strip_suffix("\r\n")
+        .ok_or_else(|| invalid("PROXY header is not terminated"))?
+        .split(' ')
+        .collect::<Vec<_>>();
+
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let address: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("Malformed PROXY source address"))?;
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| invalid("Malformed PROXY source port"))?;
+
+            Ok(Some(SocketAddr::new(address, port)))
+        }
+        _ => Err(invalid("Malformed PROXY header")),
+    }
+}
+
+/// Parses address block of binary header, `LOCAL` command and unsupported families yield `None`
+fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+    if version_command & 0x0F == 0 {
+        return Ok(None);
+    }
+
+    match family >> 4 {
+        1 if addresses.len() >= 12 => {
+            let address = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+
+            Ok(Some(SocketAddr::new(address.into(), port)))
+        }
+        2 if addresses.len() >= 36 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        1 | 2 => Err(invalid("PROXY address block is too short")),
+        _ => Ok(None),
+    }
+}
+
+/// Consumes PROXY header from the stream and returns source address it reports
+async fn read_header(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    // Shortest v1 header "PROXY UNKNOWN\r\n" is longer than v2 signature
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).await?;
+
+        let mut addresses = vec![0; u16::from_be_bytes([header[2], header[3]]) as usize];
+        stream.read_exact(&mut addresses).await?;
+
+        return parse_v2(header[0], header[1], &addresses);
+    }
+
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("Connection doesn't start with PROXY header"));
+    }
+
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(invalid("PROXY header is too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    parse_v1(std::str::from_utf8(&line).map_err(|_| invalid("PROXY header is not ASCII"))?)
+}
+
+/// Consumes PROXY header from the stream and returns source address it reports, giving up
+/// on clients that don't send it in time
+pub async fn read_source(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    timeout(HEADER_TIMEOUT, read_header(stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "PROXY header timed out"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::AsyncWriteExt,
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Stream of the connection that sent the bytes
+    async fn sent(bytes: &[u8]) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.write_all(bytes).await.unwrap();
+        drop(client);
+
+        listener.accept().await.unwrap().0
+    }
+
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20 | command, family]);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+
+        header
+    }
+
+    #[test]
+    fn parses_text_headers() {
+        assert_eq!(
+            parse_v1("PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n").unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        assert_eq!(
+            parse_v1("PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+        assert_eq!(parse_v1("PROXY UNKNOWN\r\n").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_unterminated_text_headers() {
+        assert!(parse_v1("PROXY TCP4 192.0.2.1 192.0.2.2 56324 443").is_err());
+        assert!(parse_v1("PROXY TCP4 192.0.2.1 192.0.2.2 56324\r\n").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_text_headers() {
+        for header in [
+            "PROXY TCP4 192.0.2.300 192.0.2.2 56324 443\r\n",
+            "PROXY TCP4 192.0.2.1 192.0.2.2 65536 443\r\n",
+            "PROXY UDP4 192.0.2.1 192.0.2.2 56324 443\r\n",
+            "PROXY  TCP4 192.0.2.1 192.0.2.2 56324 443\r\n",
+        ] {
+            assert!(parse_v1(header).is_err(), "{}", header);
+        }
+    }
+
+    #[test]
+    fn parses_binary_address_blocks() {
+        let ipv4 = [192, 0, 2, 1, 192, 0, 2, 2, 0xDC, 0x04, 0x01, 0xBB];
+        assert_eq!(
+            parse_v2(0x21, 0x11, &ipv4).unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+
+        let mut ipv6 = Ipv6Addr::from([0x2001, 0xDB8, 0, 0, 0, 0, 0, 1])
+            .octets()
+            .to_vec();
+        ipv6.extend([0; 16]);
+        ipv6.extend([0xDC, 0x04, 0x01, 0xBB]);
+        assert_eq!(
+            parse_v2(0x21, 0x21, &ipv6).unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap())
+        );
+
+        // Health checks of the proxy itself, and unix sockets
+        assert_eq!(parse_v2(0x20, 0x00, &[]).unwrap(), None);
+        assert_eq!(parse_v2(0x21, 0x31, &[0; 216]).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_truncated_binary_address_blocks() {
+        assert!(parse_v2(0x21, 0x11, &[192, 0, 2, 1, 192, 0, 2, 2, 0xDC, 0x04]).is_err());
+        assert!(parse_v2(0x21, 0x21, &[0; 35]).is_err());
+    }
+
+    #[test]
+    fn rejects_other_binary_versions() {
+        assert!(parse_v2(0x11, 0x11, &[0; 12]).is_err());
+    }
+
+    #[actix_web::test]
+    async fn leaves_the_request_after_header() {
+        let mut header = v2(
+            1,
+            0x11,
+            &[192, 0, 2, 1, 192, 0, 2, 2, 0xDC, 0x04, 0x01, 0xBB],
+        );
+        header.extend(b"GET / HTTP/1.1\r\n");
+        let mut stream = sent(&header).await;
+
+        assert_eq!(
+            read_source(&mut stream).await.unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        let mut request = String::new();
+        stream.read_to_string(&mut request).await.unwrap();
+        assert_eq!(request, "GET / HTTP/1.1\r\n");
+
+        let mut stream = sent(b"PROXY UNKNOWN\r\nGET").await;
+        assert_eq!(read_source(&mut stream).await.unwrap(), None);
+        assert_eq!(stream.read_u8().await.unwrap(), b'G');
+    }
+
+    #[actix_web::test]
+    async fn rejects_truncated_and_missing_headers() {
+        let header = v2(
+            1,
+            0x11,
+            &[192, 0, 2, 1, 192, 0, 2, 2, 0xDC, 0x04, 0x01, 0xBB],
+        );
+        let mut stream = sent(&header[..20]).await;
+        assert_eq!(
+            read_source(&mut stream).await.unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        let mut stream = sent(b"PROXY TCP4 192.0.2.1").await;
+        assert_eq!(
+            read_source(&mut stream).await.unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        let mut stream = sent(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert_eq!(
+            read_source(&mut stream).await.unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut stream = sent(format!("PROXY {}\r\n", "A".repeat(200)).as_bytes()).await;
+        assert_eq!(
+            read_source(&mut stream).await.unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,767 @@
+use std::sync::Arc;
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+
+use crate::{
+    access::AllowedTopic,
+    callbacks::Callback,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    maintenance::Maintenance,
+    messages::{
+        self,
+        Messages,
+    },
+    metrics::Metrics,
+    photo,
+    quotas::{
+        self,
+        Quotas,
+    },
+    send_options::SendOptions,
+    usage::Usage,
+    PostPathData,
+    TgClient,
+    TgMarkdownString,
+};
+
+/// Light modules around the code, scanners need them to find it
+const QUIET_ZONE: usize = 4;
+/// Images are about this wide, whatever the version
+const IMAGE_SIZE: usize = 512;
+
+/// Error correction codewords per block and number of blocks by version, for medium level
+/// which recovers 15% of the code
+const ECC_CODEWORDS_PER_BLOCK: [usize; 41] = [
+    0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+const ERROR_CORRECTION_BLOCKS: [usize; 41] = [
+    0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23,
+    25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+/// Format information bits of medium error correction level
+const MEDIUM_FORMAT_BITS: u32 = 0;
+/// Telegram rejects captions over 1024 characters, counted in UTF-16 units. The rest is left
+/// for the header
+const MAX_CAPTION_LENGTH: usize = 900;
+
+/// QR code in byte mode with medium error correction
+pub struct QrCode {
+    size:        usize,
+    modules:     Vec<bool>,
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Smallest code that holds the data, `None` if it doesn't fit even the largest one
+    pub fn encode(data: &[u8]) -> Option<Self> {
+        let version = (1..=40).find(|version| {
+            let count_bits = if *version <= 9 { 8 } else { 16 };
+            4 + count_bits + data.len() * 8 <= data_codewords(*version) * 8
+        })?;
+
+        let mut code = Self {
+            size:        version * 4 + 17,
+            modules:     vec![false; (version * 4 + 17).pow(2)],
+            is_function: vec![false; (version * 4 + 17).pow(2)],
+        };
+        code.draw_function_patterns(version);
+        code.draw_codewords(&codewords(version, data));
+
+        // Mask that leaves the fewest patterns confusing scanners
+        let mask = (0..8)
+            .min_by_key(|mask| {
+                code.apply_mask(*mask);
+                code.draw_format_bits(*mask);
+                let penalty = code.penalty();
+                code.apply_mask(*mask);
+                penalty
+            })
+            .expect("There are 8 masks");
+        code.apply_mask(mask);
+        code.draw_format_bits(mask);
+
+        Some(code)
+    }
+
+    /// PNG with black modules on white, quiet zone included
+    pub fn to_png(&self) -> Vec<u8> {
+        let modules = self.size + 2 * QUIET_ZONE;
+        let scale = (IMAGE_SIZE / modules).max(1);
+        let width = modules * scale;
+
+        let mut pixels = Vec::with_capacity(width * width * 3);
+        for y in 0..width {
+            for x in 0..width {
+                let (x, y) = (x / scale, y / scale);
+                let is_dark = (QUIET_ZONE..QUIET_ZONE + self.size).contains(&x)
+                    && (QUIET_ZONE..QUIET_ZONE + self.size).contains(&y)
+                    && self.get(x - QUIET_ZONE, y - QUIET_ZONE);
+                let value = if is_dark { 0 } else { 0xFF };
+                pixels.extend([value; 3]);
+            }
+        }
+
+        photo::png(width as u32, width as u32, &pixels)
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, is_dark: bool) {
+        self.modules[y * self.size + x] = is_dark;
+        self.is_function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        let size = self.size;
+
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            self.draw_finder_pattern(x, y);
+        }
+
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, x) in positions.iter().enumerate() {
+            for (j, y) in positions.iter().enumerate() {
+                // Corners with finder patterns
+                if (i, j) == (0, 0) || (i, j) == (0, last) || (i, j) == (last, 0) {
+                    continue;
+                }
+                for dy in 0..5usize {
+                    for dx in 0..5usize {
+                        let distance = dx.abs_diff(2).max(dy.abs_diff(2));
+                        self.set_function(x - 2 + dx, y - 2 + dy, distance != 1);
+                    }
+                }
+            }
+        }
+
+        // Reserved until the mask is chosen
+        self.draw_format_bits(0);
+
+        if version >= 7 {
+            let mut remainder = version as u32;
+            for _ in 0..12 {
+                remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+            }
+            let bits = (version as u32) << 12 | remainder;
+            for i in 0..18 {
+                let is_dark = bits >> i & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                self.set_function(a, b, is_dark);
+                self.set_function(b, a, is_dark);
+            }
+        }
+    }
+
+    /// Finder pattern centered at the position, with separator around it
+    fn draw_finder_pattern(&mut self, x: usize, y: usize) {
+        for dy in 0..9 {
+            for dx in 0..9 {
+                let (Some(xx), Some(yy)) = ((x + dx).checked_sub(4), (y + dy).checked_sub(4))
+                else {
+                    continue;
+                };
+                if xx >= self.size || yy >= self.size {
+                    continue;
+                }
+                let distance = dx.abs_diff(4).max(dy.abs_diff(4));
+                self.set_function(xx, yy, distance != 2 && distance != 4);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let data = MEDIUM_FORMAT_BITS << 3 | mask;
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| bits >> i & 1 == 1;
+        let size = self.size;
+
+        // Around the top left finder pattern
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        // Copy next to the other two
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Codewords go in two module wide columns zigzagging from the bottom right corner
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut bit = 0;
+        let mut right = size as isize - 1;
+        while right >= 1 {
+            // Vertical timing pattern takes a whole column
+            if right == 6 {
+                right = 5;
+            }
+            let is_upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                for j in 0..2 {
+                    let x = right as usize - j;
+                    let y = if is_upward {
+                        size - 1 - vertical
+                    } else {
+                        vertical
+                    };
+                    if !self.is_function[y * size + x] && bit < codewords.len() * 8 {
+                        self.modules[y * size + x] = codewords[bit / 8] >> (7 - bit % 8) & 1 == 1;
+                        bit += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    /// Flips data modules by the mask pattern, applying it twice undoes it
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if flip && !self.is_function[y * self.size + x] {
+                    self.modules[y * self.size + x] ^= true;
+                }
+            }
+        }
+    }
+
+    /// Penalty of the specification for runs, blocks, finder-like patterns and imbalance
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+
+        for horizontal in [true, false] {
+            let line = |i: usize, j: usize| {
+                if horizontal {
+                    self.get(j, i)
+                } else {
+                    self.get(i, j)
+                }
+            };
+            for i in 0..size {
+                let mut run = 1;
+                for j in 1..size {
+                    if line(i, j) == line(i, j - 1) {
+                        run += 1;
+                        if run == 5 {
+                            penalty += 3;
+                        } else if run > 5 {
+                            penalty += 1;
+                        }
+                    } else {
+                        run = 1;
+                    }
+                }
+
+                for j in 0..size.saturating_sub(10) {
+                    let window = (j..j + 11).map(|j| line(i, j)).collect::<Vec<_>>();
+                    let finder = [true, false, true, true, true, false, true];
+                    if window[..7] == finder && window[7..].iter().all(|dark| !dark)
+                        || window[4..] == finder && window[..4].iter().all(|dark| !dark)
+                    {
+                        penalty += 40;
+                    }
+                }
+            }
+        }
+
+        for y in 1..size {
+            for x in 1..size {
+                let color = self.get(x, y);
+                if color == self.get(x - 1, y)
+                    && color == self.get(x, y - 1)
+                    && color == self.get(x - 1, y - 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        let dark = self.modules.iter().filter(|dark| **dark).count();
+        let total = size * size;
+        penalty += (dark * 20).abs_diff(total * 10) / total * 10;
+
+        penalty
+    }
+}
+
+/// Centers of alignment patterns on both axes
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+
+    let count = version / 7 + 2;
+    let step = (version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let size = version * 4 + 17;
+    let mut positions = (0..count - 1)
+        .map(|i| size - 7 - i * step)
+        .collect::<Vec<_>>();
+    positions.push(6);
+    positions.reverse();
+
+    positions
+}
+
+/// Modules left for data and error correction once function patterns are drawn
+fn raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+
+    modules
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[version] * ERROR_CORRECTION_BLOCKS[version]
+}
+
+/// Data in byte mode padded to capacity, split into blocks with error correction and
+/// interleaved
+fn codewords(version: usize, data: &[u8]) -> Vec<u8> {
+    let capacity = data_codewords(version);
+    let mut bits = Vec::new();
+    let mut push = |value: usize, length: usize| {
+        bits.extend((0..length).rev().map(|i| value >> i & 1 == 1));
+    };
+    push(0b0100, 4);
+    push(data.len(), if version <= 9 { 8 } else { 16 });
+    for byte in data {
+        push(*byte as usize, 8);
+    }
+    let terminator = (capacity * 8 - bits.len()).min(4);
+    bits.resize(bits.len() + terminator, false);
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+
+    let mut padded = bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |value, bit| value << 1 | *bit as u8))
+        .collect::<Vec<_>>();
+    for pad in [0xEC, 0x11].into_iter().cycle() {
+        if padded.len() >= capacity {
+            break;
+        }
+        padded.push(pad);
+    }
+
+    let blocks_count = ERROR_CORRECTION_BLOCKS[version];
+    let ecc_length = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = raw_data_modules(version) / 8;
+    let short_blocks = blocks_count - raw_codewords % blocks_count;
+    let short_block_length = raw_codewords / blocks_count;
+    let divisor = reed_solomon_divisor(ecc_length);
+
+    // Short blocks get a placeholder so all are of the same length, it's skipped when
+    // interleaving
+    let mut blocks = Vec::with_capacity(blocks_count);
+    let mut rest = padded.as_slice();
+    for i in 0..blocks_count {
+        let length = short_block_length - ecc_length + usize::from(i >= short_blocks);
+        let (data, remaining) = rest.split_at(length);
+        rest = remaining;
+
+        let mut block = data.to_vec();
+        let ecc = reed_solomon_remainder(data, &divisor);
+        if i < short_blocks {
+            block.push(0);
+        }
+        block.extend(ecc);
+        blocks.push(block);
+    }
+
+    let mut interleaved = Vec::with_capacity(raw_codewords);
+    for i in 0..=short_block_length {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_length - ecc_length || j >= short_blocks {
+                interleaved.push(block[i]);
+            }
+        }
+    }
+
+    interleaved
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0; degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_multiply(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_multiply(root, 2);
+    }
+
+    divisor
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut remainder = vec![0; divisor.len()];
+    for byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (value, coefficient) in remainder.iter_mut().zip(divisor) {
+            *value ^= gf_multiply(*coefficient, factor);
+        }
+    }
+
+    remainder
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut product: u16 = 0;
+    for i in (0..8).rev() {
+        product = (product << 1) ^ ((product >> 7) * 0x11D);
+        product ^= ((y >> i) & 1) as u16 * x as u16;
+    }
+
+    product as u8
+}
+
+/// Text as code block, texts QR codes hold can be too long for a caption and are cut
+fn caption(text: &str) -> String {
+    let mut length = 0;
+    let end = text.char_indices().find_map(|(index, ch)| {
+        length += ch.len_utf16();
+        (length > MAX_CAPTION_LENGTH).then_some(index)
+    });
+
+    match end {
+        Some(end) => format!("{}…", *TgMarkdownString::code(&text[..end])),
+        None => TgMarkdownString::code(text).0,
+    }
+}
+
+/// Sends a QR code of the text as photo, with the text, cut if it's long, as caption
+pub async fn post_qr_code(
+    topic: AllowedTopic,
+    tg_client: web::Data<Arc<TgClient>>,
+    maintenance: web::Data<Arc<Maintenance>>,
+    quotas: web::Data<Arc<Quotas>>,
+    metrics: web::Data<Arc<Metrics>>,
+    messages: web::Data<Arc<Messages>>,
+    usage: web::Data<Arc<Usage>>,
+    callback: Callback,
+    path_data: web::Path<PostPathData>,
+    text: String,
+) -> impl Responder {
+    let topic_info = &topic.info;
+    metrics.count_ingress(&topic.name, text.len(), 0);
+    let meter = usage.meter(&topic, text.len());
+
+    if text.is_empty() {
+        return HttpResponse::from(ApiError::new(
+            ErrorCode::InvalidMessage,
+            "Text of QR code is empty",
+        ));
+    }
+    let image = match QrCode::encode(text.as_bytes()) {
+        Some(code) => code.to_png(),
+        None =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::InvalidMessage,
+                "Text is too long for QR code",
+            )),
+    };
+
+    if maintenance.intercept(&path_data.topic_name, topic_info, &path_data.sender, &text) {
+        return HttpResponse::Accepted().body("Topic is under maintenance");
+    }
+
+    if let Some(response) =
+        quotas::enforce(&quotas, &tg_client, &topic.name, topic_info, text.len()).await
+    {
+        return response;
+    }
+
+    let tg_client = tg_client.get_ref().clone();
+    let recipients = tg_client.recipients_of(&topic.name, topic_info);
+    let options = SendOptions::of_topic(topic_info);
+    let topic_name = path_data.topic_name.clone();
+    let sender = path_data.sender.clone();
+    messages::dispatch(
+        &messages,
+        &path_data.topic_name,
+        &path_data.sender,
+        topic_info.accept_async,
+        callback,
+        meter,
+        async move {
+            tg_client
+                .send_photo_to_all(
+                    &recipients,
+                    &topic_name,
+                    &sender,
+                    &caption(&text),
+                    &image,
+                    options,
+                )
+                .await
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Format information of medium level by mask, from the table of the specification
+    const MEDIUM_FORMATS: [u32; 8] = [
+        0b101010000010010,
+        0b101000100100101,
+        0b101111001111100,
+        0b101101101001011,
+        0b100010111111001,
+        0b100000011001110,
+        0b100111110010111,
+        0b100101010100000,
+    ];
+
+    fn version_of(code: &QrCode) -> usize {
+        (code.size - 17) / 4
+    }
+
+    /// Format information next to the top left finder pattern and its copy
+    fn format_bits(code: &QrCode) -> (u32, u32) {
+        let size = code.size;
+        let mut positions = (0..6).map(|i| (8, i)).collect::<Vec<_>>();
+        positions.extend([(8, 7), (8, 8), (7, 8)]);
+        positions.extend((9..15).map(|i| (14 - i, 8)));
+        let mut copy = (0..8).map(|i| (size - 1 - i, 8)).collect::<Vec<_>>();
+        copy.extend((8..15).map(|i| (8, size - 15 + i)));
+
+        let read = |positions: &[(usize, usize)]| {
+            positions.iter().enumerate().fold(0, |bits, (i, (x, y))| {
+                bits | u32::from(code.get(*x, *y)) << i
+            })
+        };
+
+        (read(&positions), read(&copy))
+    }
+
+    /// Data modules read in the order they are placed, two columns upwards then downwards
+    /// from the bottom right corner, skipping the vertical timing pattern
+    fn read_codewords(code: &QrCode) -> Vec<u8> {
+        let size = code.size;
+        let mut bits = Vec::new();
+        let mut columns = (1..size).rev().step_by(2).collect::<Vec<_>>();
+        for right in columns.iter_mut().filter(|right| **right <= 6) {
+            *right -= 1;
+        }
+        for (pair, right) in columns.into_iter().enumerate() {
+            let rows = (0..size).collect::<Vec<_>>();
+            let rows = if pair % 2 == 0 {
+                rows.into_iter().rev().collect()
+            } else {
+                rows
+            };
+            for y in rows {
+                for x in [right, right - 1] {
+                    if !code.is_function[y * size + x] {
+                        bits.push(code.get(x, y));
+                    }
+                }
+            }
+        }
+
+        bits.chunks_exact(8)
+            .map(|byte| {
+                byte.iter()
+                    .fold(0, |value, bit| value << 1 | u8::from(*bit))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reed_solomon_matches_specification_example() {
+        // "HELLO WORLD" of version 1-M in alphanumeric mode
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+
+        assert_eq!(
+            reed_solomon_remainder(&data, &reed_solomon_divisor(10)),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+    }
+
+    #[test]
+    fn smallest_version_holds_the_data() {
+        // Byte mode capacities of medium level from the specification
+        for (version, capacity) in [(1, 14), (2, 26), (3, 42), (4, 62), (7, 122), (10, 213)] {
+            let fitting = QrCode::encode(&vec![b'a'; capacity]).unwrap();
+            let overflowing = QrCode::encode(&vec![b'a'; capacity + 1]).unwrap();
+
+            assert_eq!(version_of(&fitting), version);
+            assert_eq!(version_of(&overflowing), version + 1);
+        }
+    }
+
+    #[test]
+    fn too_long_data_doesnt_fit() {
+        assert_eq!(
+            QrCode::encode(&[b'a'; 2331]).map(|code| version_of(&code)),
+            Some(40)
+        );
+        assert!(QrCode::encode(&[b'a'; 2332]).is_none());
+    }
+
+    #[test]
+    fn alignment_positions_match_specification() {
+        assert_eq!(alignment_positions(1), Vec::<usize>::new());
+        assert_eq!(alignment_positions(2), [6, 18]);
+        assert_eq!(alignment_positions(7), [6, 22, 38]);
+        assert_eq!(alignment_positions(14), [6, 26, 46, 66]);
+        assert_eq!(alignment_positions(32), [6, 34, 60, 86, 112, 138]);
+        assert_eq!(alignment_positions(36), [6, 24, 50, 76, 102, 128, 154]);
+        assert_eq!(alignment_positions(40), [6, 30, 58, 86, 114, 142, 170]);
+    }
+
+    #[test]
+    fn format_bits_match_specification() {
+        for data in [
+            "a",
+            "WIFI:S:lab;T:WPA;P:secret;;",
+            "https://example.com/some/path",
+        ] {
+            let code = QrCode::encode(data.as_bytes()).unwrap();
+            let (bits, copy) = format_bits(&code);
+
+            assert_eq!(bits, copy);
+            assert!(MEDIUM_FORMATS.contains(&bits), "{:015b}", bits);
+        }
+    }
+
+    #[test]
+    fn version_bits_match_specification() {
+        let code = QrCode::encode(&[b'a'; 110]).unwrap();
+        assert_eq!(version_of(&code), 7);
+
+        let size = code.size;
+        let (mut bottom_left, mut top_right) = (0, 0);
+        for i in 0..18 {
+            let (a, b) = (size - 11 + i % 3, i / 3);
+            top_right |= u32::from(code.get(a, b)) << i;
+            bottom_left |= u32::from(code.get(b, a)) << i;
+        }
+
+        assert_eq!(top_right, 0x07C94);
+        assert_eq!(bottom_left, 0x07C94);
+    }
+
+    #[test]
+    fn short_blocks_come_first_and_are_interleaved() {
+        // Version 8-M has two blocks of 38 data codewords and two of 39, 22 ECC codewords each
+        let data = (0..150).map(|i| i as u8).collect::<Vec<_>>();
+        let interleaved = codewords(8, &data);
+        assert_eq!(interleaved.len(), 242);
+
+        let lengths = [38, 38, 39, 39];
+        let mut blocks = vec![Vec::new(); 4];
+        let mut position = 0;
+        for i in 0..39 {
+            for (block, length) in blocks.iter_mut().zip(lengths) {
+                if i < length {
+                    block.push(interleaved[position]);
+                    position += 1;
+                }
+            }
+        }
+        let divisor = reed_solomon_divisor(22);
+        for _ in 0..22 {
+            for block in &mut blocks {
+                block.push(interleaved[position]);
+                position += 1;
+            }
+        }
+
+        let mut padded = Vec::new();
+        for (block, length) in blocks.iter().zip(lengths) {
+            let (data, ecc) = block.split_at(length);
+            assert_eq!(reed_solomon_remainder(data, &divisor), ecc);
+            padded.extend_from_slice(data);
+        }
+        // Byte mode indicator and 8 bit count, then the data shifted by the 4 bit indicator
+        assert_eq!(&padded[..3], [0x49, 0x60, 0x00]);
+        assert_eq!(&padded[152..], [0xEC, 0x11]);
+    }
+
+    #[test]
+    fn unmasked_modules_read_back_as_codewords() {
+        let data = b"WIFI:S:lab;T:WPA;P:secret;;";
+        let mut code = QrCode::encode(data).unwrap();
+        let (bits, _) = format_bits(&code);
+        let mask = MEDIUM_FORMATS
+            .iter()
+            .position(|format| *format == bits)
+            .unwrap();
+
+        code.apply_mask(mask as u32);
+
+        assert_eq!(read_codewords(&code), codewords(version_of(&code), data));
+    }
+
+    #[test]
+    fn long_captions_are_cut() {
+        assert_eq!(caption("a_b"), "`a_b`");
+
+        let text = "a".repeat(1000);
+        assert_eq!(caption(&text), format!("`{}`…", "a".repeat(900)));
+
+        // Characters outside of the basic plane are two UTF-16 units
+        let text = "🙂".repeat(500);
+        assert_eq!(caption(&text), format!("`{}`…", "🙂".repeat(450)));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+};
+
+use actix_web::{
+    http::header::{
+        self,
+        HeaderValue,
+    },
+    HttpResponse,
+};
+use chrono::{
+    NaiveDate,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    internal::Incident,
+    locale::Phrase,
+    TgClient,
+    TgMarkdownString,
+    Topic,
+};
+
+const SENDER: &str = "quota";
+
+/// Usage of a topic during one UTC day
+struct Usage {
+    day:      NaiveDate,
+    messages: u64,
+    bytes:    u64,
+}
+
+/// Usage of a topic as it's exported
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Snapshot {
+    topic:    String,
+    day:      NaiveDate,
+    messages: u64,
+    bytes:    u64,
+}
+
+enum Admission {
+    Admitted,
+    /// First message over the quota, recipients are told about it
+    Tripped,
+    Exceeded,
+}
+
+/// Daily message and byte counters of topics that have quotas
+#[derive(Default)]
+pub struct Quotas {
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl Quotas {
+    fn admit(&self, topic_name: &str, topic_info: &Topic, bytes: usize) -> Admission {
+        if topic_info.max_messages_per_day.is_none() && topic_info.max_bytes_per_day.is_none() {
+            return Admission::Admitted;
+        }
+
+        let today = Utc::now().date_naive();
+        let mut usage = self.usage.lock().expect("Quotas lock is poisoned");
+        let usage = usage.entry(topic_name.to_owned()).or_insert(Usage {
+            day:      today,
+            messages: 0,
+            bytes:    0,
+        });
+
+        if usage.day != today {
+            *usage = Usage {
+                day:      today,
+                messages: 0,
+                bytes:    0,
+            };
+        }
+
+        let bytes = bytes as u64;
+        let is_within = |messages: u64, bytes: u64| {
+            topic_info
+                .max_messages_per_day
+                .is_none_or(|max_messages| messages <= max_messages)
+                && topic_info
+                    .max_bytes_per_day
+                    .is_none_or(|max_bytes| bytes <= max_bytes)
+        };
+
+        if is_within(usage.messages + 1, usage.bytes + bytes) {
+            usage.messages += 1;
+            usage.bytes += bytes;
+            Admission::Admitted
+        } else if is_within(usage.messages, usage.bytes) {
+            // Counting the rejected message marks the quota as tripped for the rest of the day
+            usage.messages += 1;
+            usage.bytes += bytes;
+            Admission::Tripped
+        } else {
+            Admission::Exceeded
+        }
+    }
+
+    pub fn export(&self) -> Vec<Snapshot> {
+        self.usage
+            .lock()
+            .expect("Quotas lock is poisoned")
+            .iter()
+            .map(|(topic, usage)| Snapshot {
+                topic:    topic.clone(),
+                day:      usage.day,
+                messages: usage.messages,
+                bytes:    usage.bytes,
+            })
+            .collect()
+    }
+
+    pub fn import(&self, snapshots: Vec<Snapshot>) {
+        let mut usage = self.usage.lock().expect("Quotas lock is poisoned");
+        for snapshot in snapshots {
+            usage.insert(
+                snapshot.topic,
+                Usage {
+                    day:      snapshot.day,
+                    messages: snapshot.messages,
+                    bytes:    snapshot.bytes,
+                },
+            );
+        }
+    }
+}
+
+fn seconds_until_tomorrow() -> i64 {
+    let now = Utc::now();
+    let tomorrow = (now.date_naive() + chrono::Duration::days(1))
+        .and_hms_opt(0, 0, 0)
+        .expect("Midnight is a valid time")
+        .and_utc();
+
+    (tomorrow - now).num_seconds().max(1)
+}
+
+/// Returns response to reply with instead of delivering the message if the topic is over
+/// its daily quota
+pub async fn enforce(
+    quotas: &Quotas,
+    tg_client: &TgClient,
+    topic_name: &str,
+    topic_info: &Topic,
+    bytes: usize,
+) -> Option<HttpResponse> {
+    match quotas.admit(topic_name, topic_info, bytes) {
+        Admission::Admitted => return None,
+        Admission::Tripped => {
+            log::warn!("Topic \"{}\" exceeded its daily quota", topic_name);
+
+            let locale = tg_client.locale(topic_name);
+            let text = format!(
+                "⛔ *{}*\n{}",
+                locale.text(Phrase::QuotaExceeded),
+                *TgMarkdownString::new(locale.text(Phrase::QuotaRejects))
+            );
+            let responses = tg_client
+                .send_message_to_all(
+                    &tg_client.recipients_of(topic_name, topic_info),
+                    topic_name,
+                    SENDER,
+                    &text,
+                )
+                .await;
+            if responses.iter().any(|delivery| !delivery.is_delivered()) {
+                log::warn!("Failed to notify \"{}\" about exceeded quota", topic_name);
+            }
+            // Recipients of the internal topic were just told if it's the one over its quota
+            if tg_client.alerts.topic() != Some(topic_name) {
+                tg_client.alerts.raise(Incident::QuotaTripped {
+                    topic: topic_name.to_owned(),
+                });
+            }
+        }
+        Admission::Exceeded => (),
+    }
+
+    let mut response = HttpResponse::from(ApiError::new(
+        ErrorCode::QuotaExceeded,
+        "Daily quota of the topic is exceeded",
+    ));
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(seconds_until_tomorrow()),
+    );
+
+    Some(response)
+}
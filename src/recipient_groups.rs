@@ -0,0 +1,157 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+use serde::Deserialize;
+
+use crate::{
+    admin::{
+        Admin,
+        Viewer,
+    },
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    TgClient,
+    Topic,
+};
+
+/// Named sets of recipients shared by topics, membership can be changed at runtime
+#[derive(Default)]
+pub struct RecipientGroups {
+    groups: Mutex<BTreeMap<String, Vec<String>>>,
+}
+
+impl RecipientGroups {
+    pub fn new(groups: BTreeMap<String, Vec<String>>) -> Self {
+        Self {
+            groups: Mutex::new(groups),
+        }
+    }
+
+    pub fn replace(&self, groups: BTreeMap<String, Vec<String>>) {
+        *self
+            .groups
+            .lock()
+            .expect("Recipient groups lock is poisoned") = groups;
+    }
+
+    /// Recipients of the topic followed by members of its groups, without duplicates
+    pub fn recipients_of(&self, topic_info: &Topic) -> Vec<String> {
+        let groups = self
+            .groups
+            .lock()
+            .expect("Recipient groups lock is poisoned");
+        let mut recipients = topic_info.recipients.clone();
+
+        for member in topic_info
+            .recipient_groups
+            .iter()
+            .filter_map(|name| groups.get(name))
+            .flatten()
+        {
+            if !recipients.contains(member) {
+                recipients.push(member.clone());
+            }
+        }
+
+        recipients
+    }
+
+    fn update<T>(&self, name: &str, change: impl FnOnce(&mut Vec<String>) -> T) -> Option<T> {
+        self.groups
+            .lock()
+            .expect("Recipient groups lock is poisoned")
+            .get_mut(name)
+            .map(change)
+    }
+}
+
+fn no_such_group() -> HttpResponse {
+    HttpResponse::from(ApiError::new(
+        ErrorCode::RecipientGroupNotFound,
+        "No such recipient group",
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct GroupPath {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct MemberPath {
+    name:      String,
+    recipient: String,
+}
+
+pub async fn list_groups(_: Viewer, tg_client: web::Data<Arc<TgClient>>) -> impl Responder {
+    let groups = tg_client
+        .recipient_groups
+        .groups
+        .lock()
+        .expect("Recipient groups lock is poisoned")
+        .clone();
+
+    HttpResponse::Ok().json(groups)
+}
+
+/// Replaces members of the group
+pub async fn put_group(
+    _: Admin,
+    tg_client: web::Data<Arc<TgClient>>,
+    path_data: web::Path<GroupPath>,
+    members: web::Json<Vec<String>>,
+) -> impl Responder {
+    match tg_client
+        .recipient_groups
+        .update(&path_data.name, |group| *group = members.into_inner())
+    {
+        Some(()) => HttpResponse::NoContent().finish(),
+        None => no_such_group(),
+    }
+}
+
+pub async fn add_member(
+    _: Admin,
+    tg_client: web::Data<Arc<TgClient>>,
+    path_data: web::Path<MemberPath>,
+) -> impl Responder {
+    match tg_client.recipient_groups.update(&path_data.name, |group| {
+        if !group.contains(&path_data.recipient) {
+            group.push(path_data.recipient.clone());
+        }
+    }) {
+        Some(()) => HttpResponse::NoContent().finish(),
+        None => no_such_group(),
+    }
+}
+
+pub async fn remove_member(
+    _: Admin,
+    tg_client: web::Data<Arc<TgClient>>,
+    path_data: web::Path<MemberPath>,
+) -> impl Responder {
+    match tg_client.recipient_groups.update(&path_data.name, |group| {
+        let members_before = group.len();
+        group.retain(|member| *member != path_data.recipient);
+        group.len() != members_before
+    }) {
+        Some(true) => HttpResponse::NoContent().finish(),
+        Some(false) => HttpResponse::from(ApiError::new(
+            ErrorCode::RecipientNotFound,
+            "Recipient is not a member of the group",
+        )),
+        None => no_such_group(),
+    }
+}
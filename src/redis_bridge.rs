@@ -0,0 +1,245 @@
+use std::{
+    collections::{
+        hash_map::DefaultHasher,
+        HashMap,
+    },
+    hash::{
+        Hash,
+        Hasher,
+    },
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::rt::time::sleep;
+use futures::StreamExt;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    send_options::SendOptions,
+    LiveTopics,
+    TgClient,
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisConfig {
+    url:          String,
+    /// Channel name to topic name mapping
+    #[serde(default)]
+    channels:     HashMap<String, String>,
+    /// Redis list shared by replicas, messages are delivered by whichever replica pops them
+    queue:        Option<String>,
+    /// How long a replica holds the claim for a received message before it could be handled again
+    #[serde(default = "default_claim_ttl_ms")]
+    claim_ttl_ms: u64,
+}
+
+fn default_claim_ttl_ms() -> u64 {
+    10_000
+}
+
+#[derive(Serialize, Deserialize)]
+struct QueuedMessage {
+    topic:  String,
+    sender: String,
+    text:   String,
+}
+
+/// Channel messages can carry sender explicitly, otherwise the channel name is used as a sender
+#[derive(Deserialize)]
+struct ChannelPayload {
+    sender: String,
+    text:   String,
+}
+
+pub fn spawn(config: RedisConfig, topics: Arc<LiveTopics>, tg_client: Arc<TgClient>) {
+    let config = Arc::new(config);
+
+    if let Some(queue) = config.queue.clone() {
+        let config = config.clone();
+        let topics = topics.clone();
+        let tg_client = tg_client.clone();
+
+        actix_web::rt::spawn(async move {
+            loop {
+                if let Err(err) = run_queue_worker(&config, &queue, &topics, &tg_client).await {
+                    log::error!("Redis queue worker failed: {}", err);
+                }
+                sleep(RECONNECT_DELAY).await;
+            }
+        });
+    }
+
+    if config.channels.is_empty() {
+        return;
+    }
+
+    actix_web::rt::spawn(async move {
+        loop {
+            if let Err(err) = run_subscriber(&config, &topics, &tg_client).await {
+                log::error!("Redis subscriber failed: {}", err);
+            }
+            sleep(RECONNECT_DELAY).await;
+        }
+    });
+}
+
+async fn run_subscriber(
+    config: &RedisConfig,
+    topics: &Arc<LiveTopics>,
+    tg_client: &Arc<TgClient>,
+) -> redis::RedisResult<()> {
+    let client = redis::Client::open(config.url.as_str())?;
+    let mut connection = client.get_multiplexed_tokio_connection().await?;
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+
+    for channel in config.channels.keys() {
+        pubsub.subscribe(channel).await?;
+    }
+    log::info!("Subscribed to {} redis channels", config.channels.len());
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let channel = message.get_channel_name();
+        let payload: String = match message.get_payload() {
+            Ok(payload) => payload,
+            Err(err) => {
+                log::warn!(
+                    "Dropping message from redis channel \"{}\": {}",
+                    channel,
+                    err
+                );
+                continue;
+            }
+        };
+
+        let topic = match config.channels.get(channel) {
+            Some(topic) => topic.clone(),
+            None => continue,
+        };
+
+        // Every replica receives every published message, only the one that claims it goes on
+        if !claim(&mut connection, config, channel, &payload).await? {
+            continue;
+        }
+
+        let queued_message = match serde_json::from_str::<ChannelPayload>(&payload) {
+            Ok(ChannelPayload { sender, text }) => QueuedMessage {
+                topic,
+                sender,
+                text,
+            },
+            Err(_) => QueuedMessage {
+                topic,
+                sender: channel.to_owned(),
+                text: payload,
+            },
+        };
+
+        match &config.queue {
+            Some(queue) => {
+                let serialized =
+                    serde_json::to_string(&queued_message).expect("Failed to serialize message");
+                redis::cmd("LPUSH")
+                    .arg(queue)
+                    .arg(serialized)
+                    .query_async::<_, ()>(&mut connection)
+                    .await?
+            }
+            None => {
+                let topics = topics.clone();
+                let tg_client = tg_client.clone();
+                actix_web::rt::spawn(async move {
+                    deliver(&topics, &tg_client, queued_message).await;
+                });
+            }
+        }
+    }
+
+    Err((redis::ErrorKind::IoError, "Subscription connection closed").into())
+}
+
+/// Pub/sub messages carry no ids so the claim is keyed by content, identical messages published
+/// within a claim ttl will be delivered once
+async fn claim(
+    connection: &mut redis::aio::MultiplexedConnection,
+    config: &RedisConfig,
+    channel: &str,
+    payload: &str,
+) -> redis::RedisResult<bool> {
+    let mut hasher = DefaultHasher::new();
+    channel.hash(&mut hasher);
+    payload.hash(&mut hasher);
+
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(format!("microphone:claim:{:x}", hasher.finish()))
+        .arg(1)
+        .arg("NX")
+        .arg("PX")
+        .arg(config.claim_ttl_ms)
+        .query_async(connection)
+        .await?;
+
+    Ok(claimed.is_some())
+}
+
+async fn run_queue_worker(
+    config: &RedisConfig,
+    queue: &str,
+    topics: &LiveTopics,
+    tg_client: &TgClient,
+) -> redis::RedisResult<()> {
+    let client = redis::Client::open(config.url.as_str())?;
+    let mut connection = client.get_async_connection().await?;
+
+    loop {
+        let (_, serialized): (String, String) = redis::cmd("BRPOP")
+            .arg(queue)
+            .arg(0)
+            .query_async(&mut connection)
+            .await?;
+
+        match serde_json::from_str(&serialized) {
+            Ok(queued_message) => deliver(topics, tg_client, queued_message).await,
+            Err(err) => log::warn!("Dropping malformed message from redis queue: {}", err),
+        }
+    }
+}
+
+async fn deliver(topics: &LiveTopics, tg_client: &TgClient, message: QueuedMessage) {
+    let topics = topics.current();
+    let topic_info = match topics.get(&message.topic) {
+        Some(topic_info) => topic_info,
+        None => {
+            log::warn!(
+                "Dropping message from redis for unknown topic \"{}\"",
+                message.topic
+            );
+            return;
+        }
+    };
+
+    let responses = tg_client
+        .send_formatted_to_all(
+            &tg_client.recipients_of(&message.topic, topic_info),
+            &topic_info.format,
+            &message.topic,
+            &message.sender,
+            &message.text,
+            SendOptions::of_topic(topic_info),
+        )
+        .await;
+
+    if !responses.iter().all(|delivery| delivery.is_delivered()) {
+        log::warn!(
+            "Failed to deliver message from redis to some recipients of \"{}\"",
+            message.topic
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    io,
+    path::PathBuf,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    rt::time::timeout,
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{
+    access::AllowedTopic,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    send_options::SendOptions,
+    threshold::write_atomically,
+    LiveTopics,
+    PostPathData,
+    TgClient,
+};
+
+/// Reminders are checked at least this often, in case the clock jumped
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemindersConfig {
+    /// Reminders that are yet to be sent are kept here, a missing file is no reminders
+    file: PathBuf,
+}
+
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Reminder {
+    id:         String,
+    topic:      String,
+    sender:     String,
+    text:       String,
+    created_at: DateTime<Utc>,
+    due_at:     DateTime<Utc>,
+}
+
+#[derive(Default)]
+#[derive(Deserialize)]
+struct Stored {
+    #[serde(default)]
+    reminders: Vec<Reminder>,
+}
+
+/// Messages posted to be sent later, kept in a file so they survive restarts. Every instance
+/// sends reminders that were posted to it
+pub struct Reminders {
+    file:      PathBuf,
+    reminders: Mutex<Vec<Reminder>>,
+    added:     Notify,
+}
+
+impl Reminders {
+    pub fn load(config: RemindersConfig) -> io::Result<Self> {
+        let stored = match std::fs::read(&config.file) {
+            Ok(contents) => serde_json::from_slice::<Stored>(&contents)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Stored::default(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            file:      config.file,
+            reminders: Mutex::new(stored.reminders),
+            added:     Notify::new(),
+        })
+    }
+
+    fn save(&self, reminders: &[Reminder]) -> io::Result<()> {
+        write_atomically(
+            &self.file,
+            &serde_json::json!({
+                "reminders": reminders,
+            }),
+        )
+    }
+
+    /// Removes reminders that are due, or time until the next one if none is
+    fn take_due(&self) -> Result<Vec<Reminder>, Duration> {
+        let mut reminders = self.reminders.lock().expect("Reminders lock is poisoned");
+        let now = Utc::now();
+
+        let (due, pending) = reminders
+            .drain(..)
+            .partition::<Vec<_>, _>(|reminder| reminder.due_at <= now);
+        *reminders = pending;
+        if due.is_empty() {
+            return Err(reminders
+                .iter()
+                .map(|reminder| (reminder.due_at - now).to_std().unwrap_or_default())
+                .min()
+                .unwrap_or(CHECK_INTERVAL)
+                .min(CHECK_INTERVAL));
+        }
+
+        // Sent at most once, a reminder that fails to be delivered isn't retried after restart
+        if let Err(err) = self.save(&reminders) {
+            log::error!("Failed to save reminders: {}", err);
+        }
+        Ok(due)
+    }
+}
+
+pub fn spawn_sender(reminders: Arc<Reminders>, topics: Arc<LiveTopics>, tg_client: Arc<TgClient>) {
+    actix_web::rt::spawn(async move {
+        loop {
+            let due = match reminders.take_due() {
+                Ok(due) => due,
+                Err(until_next) => {
+                    let _ = timeout(until_next, reminders.added.notified()).await;
+                    continue;
+                }
+            };
+
+            let topics = topics.current();
+            for reminder in due {
+                // Topics can be gone since config was reloaded
+                let topic_info = match topics.get(&reminder.topic) {
+                    Some(topic_info) => topic_info,
+                    None => {
+                        log::warn!(
+                            "Dropped reminder {}, topic \"{}\" is gone",
+                            reminder.id,
+                            reminder.topic
+                        );
+                        continue;
+                    }
+                };
+
+                let responses = tg_client
+                    .send_formatted_to_all(
+                        &tg_client.recipients_of(&reminder.topic, topic_info),
+                        &topic_info.format,
+                        &reminder.topic,
+                        &reminder.sender,
+                        &reminder.text,
+                        SendOptions::of_topic(topic_info),
+                    )
+                    .await;
+                if responses.iter().any(|delivery| !delivery.is_delivered()) {
+                    log::warn!("Failed to send reminder {} to some recipients", reminder.id);
+                }
+            }
+        }
+    });
+}
+
+fn disabled() -> HttpResponse {
+    HttpResponse::from(ApiError::new(
+        ErrorCode::RemindersDisabled,
+        "Reminders are not configured",
+    ))
+}
+
+fn not_saved(err: io::Error) -> HttpResponse {
+    log::error!("Failed to save reminders: {}", err);
+    HttpResponse::from(ApiError::new(
+        ErrorCode::InternalError,
+        "Failed to save reminders",
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct RemindQuery {
+    /// How long from now the message is sent
+    #[serde(rename = "in", with = "humantime_serde")]
+    delay: Duration,
+}
+
+/// Responds with the reminder, its id cancels it
+pub async fn create_reminder(
+    topic: AllowedTopic,
+    reminders: web::Data<Option<Arc<Reminders>>>,
+    path_data: web::Path<PostPathData>,
+    query: web::Query<RemindQuery>,
+    text: String,
+) -> impl Responder {
+    let reminders = match reminders.as_ref() {
+        Some(reminders) => reminders,
+        None => return disabled(),
+    };
+
+    if text.is_empty() {
+        return HttpResponse::from(ApiError::new(
+            ErrorCode::InvalidMessage,
+            "Text of reminder is empty",
+        ));
+    }
+    let now = Utc::now();
+    let due_at = match chrono::Duration::from_std(query.delay)
+        .ok()
+        .and_then(|delay| now.checked_add_signed(delay))
+    {
+        Some(due_at) => due_at,
+        None =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::InvalidDuration,
+                "Reminder is too far away",
+            )),
+    };
+
+    let reminder = Reminder {
+        id: Uuid::new_v4().simple().to_string()[..12].to_owned(),
+        topic: topic.name,
+        sender: path_data.into_inner().sender,
+        text,
+        created_at: now,
+        due_at,
+    };
+
+    let mut pending = reminders
+        .reminders
+        .lock()
+        .expect("Reminders lock is poisoned");
+    pending.push(reminder.clone());
+    if let Err(err) = reminders.save(&pending) {
+        pending.pop();
+        return not_saved(err);
+    }
+    reminders.added.notify_one();
+
+    HttpResponse::Created().json(reminder)
+}
+
+/// Reminders of the topic that are yet to be sent, the soonest first
+pub async fn list_reminders(
+    topic: AllowedTopic,
+    reminders: web::Data<Option<Arc<Reminders>>>,
+) -> impl Responder {
+    let reminders = match reminders.as_ref() {
+        Some(reminders) => reminders,
+        None => return disabled(),
+    };
+
+    let mut of_topic = reminders
+        .reminders
+        .lock()
+        .expect("Reminders lock is poisoned")
+        .iter()
+        .filter(|reminder| reminder.topic == topic.name)
+        .cloned()
+        .collect::<Vec<_>>();
+    of_topic.sort_by_key(|reminder| reminder.due_at);
+
+    HttpResponse::Ok().json(of_topic)
+}
+
+#[derive(Deserialize)]
+pub struct ReminderPath {
+    id: String,
+}
+
+pub async fn cancel_reminder(
+    topic: AllowedTopic,
+    reminders: web::Data<Option<Arc<Reminders>>>,
+    path_data: web::Path<ReminderPath>,
+) -> impl Responder {
+    let reminders = match reminders.as_ref() {
+        Some(reminders) => reminders,
+        None => return disabled(),
+    };
+
+    let mut pending = reminders
+        .reminders
+        .lock()
+        .expect("Reminders lock is poisoned");
+    let position = match pending
+        .iter()
+        .position(|reminder| reminder.topic == topic.name && reminder.id == path_data.id)
+    {
+        Some(position) => position,
+        None =>
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::ReminderNotFound,
+                "No such reminder",
+            )),
+    };
+
+    let cancelled = pending.remove(position);
+    if let Err(err) = reminders.save(&pending) {
+        pending.insert(position, cancelled);
+        return not_saved(err);
+    }
+
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,87 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::rt::time::interval;
+
+use crate::{
+    check_topics,
+    config,
+    geoip::GeoIp,
+    internal::Incident,
+    Config,
+    LiveTopics,
+    TgClient,
+};
+
+fn reject_config(tg_client: &TgClient, error: String) {
+    log::error!(
+        "Changed config is invalid, keeping the running one: {}",
+        error
+    );
+    tg_client.alerts.raise(Incident::ConfigRejected { error });
+}
+
+/// Fetches config given by URL every `poll_interval` and swaps topics and recipient groups when
+/// it changes. Invalid config is logged and the running one is kept
+pub fn spawn_poller(
+    url: String,
+    poll_interval: Duration,
+    topics: Arc<LiveTopics>,
+    tg_client: Arc<TgClient>,
+    geoip: Arc<GeoIp>,
+    has_jwt: bool,
+) {
+    actix_web::rt::spawn(async move {
+        let mut ticks = interval(poll_interval);
+        // The first tick completes immediately, serves as the baseline of what's running
+        ticks.tick().await;
+        let mut applied = config::fetch(&url).await.ok();
+
+        loop {
+            ticks.tick().await;
+
+            let text = match config::fetch(&url).await {
+                Ok(text) => text,
+                Err(err) => {
+                    log::warn!("{}", err);
+                    continue;
+                }
+            };
+            if applied.as_ref() == Some(&text) {
+                continue;
+            }
+
+            let config: Config = match config::from_text(config::remote_path(&url), &text) {
+                Ok(config) => config,
+                Err(err) => {
+                    reject_config(&tg_client, err.to_string());
+                    applied = Some(text);
+                    continue;
+                }
+            };
+            if let Err(err) = check_topics(
+                &config.topics,
+                &config.recipient_groups,
+                &geoip,
+                has_jwt,
+                &tg_client.backends,
+                tg_client.alerts.topic(),
+            ) {
+                reject_config(&tg_client, err);
+                applied = Some(text);
+                continue;
+            }
+
+            tg_client.locales.replace(&config.topics);
+            topics.replace(config.topics);
+            tg_client.recipient_groups.replace(config.recipient_groups);
+            applied = Some(text);
+            log::info!(
+                "Applied changed topics and recipient groups from {}, other settings require restart",
+                url
+            );
+        }
+    });
+}
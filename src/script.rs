@@ -0,0 +1,161 @@
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        OnceLock,
+    },
+};
+
+use actix_web::http::header::HeaderMap;
+use rhai::{
+    Dynamic,
+    Engine,
+    Map,
+    Scope,
+    AST,
+};
+use serde::{
+    Deserialize,
+    Deserializer,
+};
+
+/// Scripts that run this many operations are stopped, so a loop can't hang the topic
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_STRING_SIZE: usize = 1024 * 1024;
+const MAX_COLLECTION_SIZE: usize = 10_000;
+
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_COLLECTION_SIZE)
+            .set_max_map_size(MAX_COLLECTION_SIZE)
+            .on_print(|text| log::info!("Script printed: {}", text))
+            .on_debug(|text, _, position| log::debug!("Script at {}: {}", position, text));
+
+        engine
+    })
+}
+
+/// Rhai script of a topic, compiled when config is loaded so syntax errors reject it
+#[derive(Clone)]
+pub struct Script(Arc<AST>);
+
+impl fmt::Debug for Script {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("Script")
+    }
+}
+
+impl<'de> Deserialize<'de> for Script {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        let ast = engine()
+            .compile(&source)
+            .map_err(|err| serde::de::Error::custom(format!("Invalid script: {}", err)))?;
+
+        Ok(Self(Arc::new(ast)))
+    }
+}
+
+/// What the script decided to do with the message
+pub enum Outcome {
+    Send {
+        message:    String,
+        /// Replace recipients of the topic, groups included
+        recipients: Option<Vec<String>>,
+    },
+    Drop,
+}
+
+impl Script {
+    /// Runs the script with `sender`, `message` and `headers` in scope. It returns nothing to send
+    /// `message`, changed in place or not, a string to send instead, or a map with `message`,
+    /// `recipients` and `drop`
+    pub fn run(
+        &self,
+        sender: &str,
+        message: String,
+        headers: &HeaderMap,
+    ) -> Result<Outcome, String> {
+        let mut scope = Scope::new();
+        scope.push_constant("sender", sender.to_owned());
+        scope.push("message", message);
+        scope.push_constant("headers", headers_map(headers));
+
+        let result = engine()
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.0)
+            .map_err(|err| err.to_string())?;
+        let message = scope
+            .get_value::<String>("message")
+            .ok_or_else(|| "`message` has to stay string".to_owned())?;
+
+        if result.is_unit() {
+            return Ok(Outcome::Send {
+                message,
+                recipients: None,
+            });
+        }
+        if result.is_string() {
+            return Ok(Outcome::Send {
+                message:    result.into_string().expect("Checked to be string"),
+                recipients: None,
+            });
+        }
+        let mut map = match result.try_cast::<Map>() {
+            Some(map) => map,
+            None => return Err("Script has to return nothing, a string or a map".to_owned()),
+        };
+
+        if let Some(drop) = map.remove("drop") {
+            match drop.as_bool() {
+                Ok(true) => return Ok(Outcome::Drop),
+                Ok(false) => (),
+                Err(_) => return Err("`drop` has to be bool".to_owned()),
+            }
+        }
+        let message = match map.remove("message") {
+            Some(text) => text
+                .into_string()
+                .map_err(|_| "`message` has to be string".to_owned())?,
+            None => message,
+        };
+        let recipients = match map.remove("recipients") {
+            Some(recipients) => Some(
+                recipients
+                    .into_typed_array::<String>()
+                    .map_err(|_| "`recipients` has to be array of strings".to_owned())?,
+            ),
+            None => None,
+        };
+        if let Some(key) = map.keys().next() {
+            return Err(format!("Script returned unknown key `{}`", key));
+        }
+
+        Ok(Outcome::Send {
+            message,
+            recipients,
+        })
+    }
+}
+
+/// Header names are lowercase, values of repeated headers are joined with commas
+fn headers_map(headers: &HeaderMap) -> Map {
+    let mut map = Map::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        match map.get_mut(name.as_str()) {
+            Some(values) => *values = format!("{}, {}", values, value).into(),
+            None => {
+                map.insert(name.as_str().into(), value.into());
+            }
+        }
+    }
+
+    map
+}
@@ -0,0 +1,153 @@
+use std::future::{
+    ready,
+    Ready,
+};
+
+use actix_web::{
+    dev::Payload,
+    FromRequest,
+    HttpRequest,
+};
+use serde::Serialize;
+
+use crate::{
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    severity::Style,
+    Topic,
+};
+
+/// How a message is sent, on top of its text
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+pub struct SendOptions<'a> {
+    pub reply_markup:         Option<&'a serde_json::Value>,
+    pub disable_link_preview: bool,
+    /// Recipients can't forward or save the message
+    pub protect_content:      bool,
+    pub effect:               Option<&'a str>,
+    /// Emoji the bot reacts with once the message is sent
+    pub reaction:             Option<&'a str>,
+    pub pin:                  bool,
+    /// Id of the message in recipient chat this one replies to
+    pub reply_to:             Option<i64>,
+    /// Message is sent as code block highlighted as this language
+    pub code_language:        Option<&'a str>,
+    /// Recipients get the message without notification sound
+    pub silent:               bool,
+    /// MarkdownV2 mentions appended to the last part of the text
+    pub mentions:             Option<&'a str>,
+    /// Photos are downscaled so their longer side is this long at most
+    pub max_photo_dimension:  Option<u32>,
+}
+
+impl<'a> SendOptions<'a> {
+    /// Options topic settings call for
+    pub fn of_topic(topic_info: &Topic) -> Self {
+        Self {
+            disable_link_preview: topic_info.disable_link_preview,
+            protect_content: topic_info.protect_content,
+            max_photo_dimension: topic_info.max_photo_dimension,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_style(self, style: &'a Style) -> Self {
+        Self {
+            effect: style.effect.as_deref(),
+            reaction: style.reaction.as_deref(),
+            ..self
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct LinkPreviewOptions {
+    is_disabled: bool,
+}
+
+impl LinkPreviewOptions {
+    pub fn of(options: &SendOptions) -> Option<Self> {
+        options
+            .disable_link_preview
+            .then_some(Self { is_disabled: true })
+    }
+}
+
+/// Settings of the topic overridden for one request with headers
+pub struct RequestOptions {
+    /// `X-Disable-Link-Preview: true` or `false`
+    disable_link_preview: Option<bool>,
+    /// `X-Code-Language`, e.g. `rust` or `diff`
+    pub code_language:    Option<String>,
+}
+
+impl RequestOptions {
+    pub fn apply<'a>(&self, topic_info: &Topic) -> SendOptions<'a> {
+        let mut options = SendOptions::of_topic(topic_info);
+        if let Some(disable_link_preview) = self.disable_link_preview {
+            options.disable_link_preview = disable_link_preview;
+        }
+
+        options
+    }
+}
+
+fn code_language_header(request: &HttpRequest) -> Result<Option<String>, ApiError> {
+    let value = match request.headers().get("X-Code-Language") {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    // Anything else could end the code block
+    match value.to_str().map(str::trim) {
+        Ok(language)
+            if !language.is_empty()
+                && language
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || "+#-_.".contains(ch)) =>
+            Ok(Some(language.to_owned())),
+        _ => Err(ApiError::new(
+            ErrorCode::InvalidHeader,
+            "X-Code-Language has to consist of letters, digits and +#-_.",
+        )),
+    }
+}
+
+fn bool_header(request: &HttpRequest, name: &str) -> Result<Option<bool>, ApiError> {
+    let value = match request.headers().get(name) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    match value
+        .to_str()
+        .map(|value| value.trim().to_ascii_lowercase())
+    {
+        Ok(value) if value == "true" => Ok(Some(true)),
+        Ok(value) if value == "false" => Ok(Some(false)),
+        _ => Err(ApiError::new(
+            ErrorCode::InvalidHeader,
+            format!("{} has to be true or false", name),
+        )),
+    }
+}
+
+impl FromRequest for RequestOptions {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let parse = || {
+            Ok::<_, ApiError>(Self {
+                disable_link_preview: bool_header(request, "X-Disable-Link-Preview")?,
+                code_language:        code_language_header(request)?,
+            })
+        };
+
+        ready(parse().map_err(Into::into))
+    }
+}
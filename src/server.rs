@@ -0,0 +1,234 @@
+use std::{
+    fmt,
+    io,
+    time::Duration,
+};
+
+use actix_http::{
+    body::MessageBody,
+    HttpService,
+    KeepAlive,
+    Protocol,
+    Request,
+    Response,
+};
+use actix_service::{
+    fn_service,
+    map_config,
+    IntoServiceFactory,
+    Service,
+    ServiceFactory,
+    ServiceFactoryExt,
+};
+use actix_web::{
+    dev::{
+        AppConfig,
+        Server,
+        ServiceRequest,
+    },
+    rt::{
+        net::TcpStream,
+        time::{
+            sleep,
+            timeout,
+        },
+    },
+    Error,
+};
+use serde::Deserialize;
+
+use crate::{
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    proxy_protocol,
+};
+
+/// Clients speaking HTTP/2 without TLS (h2c with prior knowledge) open with it
+const HTTP2_PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// Pause before looking at the start of a connection again when only part of it arrived
+const PREFACE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How inbound connections are handled, in `[server.connections]`
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Connections {
+    /// Connections opening with HTTP/2 preface are served as h2c, others as HTTP/1
+    #[serde(default = "default_http2")]
+    http2:                  bool,
+    /// Idle connections are closed after this, "0s" closes them after every response
+    #[serde(default = "default_keep_alive", with = "humantime_serde")]
+    keep_alive:             Duration,
+    /// Headers of the first request have to arrive within this after connecting
+    #[serde(default = "default_client_request_timeout", with = "humantime_serde")]
+    client_request_timeout: Duration,
+    /// HTTP/1 requests over 96 headers or 128 KiB of them are rejected regardless
+    max_headers:            Option<usize>,
+    /// Total length of names and values of headers
+    max_header_bytes:       Option<usize>,
+}
+
+fn default_http2() -> bool {
+    true
+}
+
+fn default_keep_alive() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_client_request_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl Default for Connections {
+    fn default() -> Self {
+        Self {
+            http2:                  default_http2(),
+            keep_alive:             default_keep_alive(),
+            client_request_timeout: default_client_request_timeout(),
+            max_headers:            None,
+            max_header_bytes:       None,
+        }
+    }
+}
+
+impl Connections {
+    fn keep_alive(&self) -> KeepAlive {
+        if self.keep_alive.is_zero() {
+            KeepAlive::Disabled
+        } else {
+            KeepAlive::Timeout(self.keep_alive)
+        }
+    }
+
+    /// Checks headers of the request against the limits
+    pub fn check_headers(&self, request: &ServiceRequest) -> Result<(), ApiError> {
+        let headers = request.headers();
+        if let Some(max_headers) = self.max_headers {
+            if headers.len() > max_headers {
+                return Err(ApiError::new(
+                    ErrorCode::HeadersTooLarge,
+                    format!("Request has over {} headers", max_headers),
+                ));
+            }
+        }
+        if let Some(max_header_bytes) = self.max_header_bytes {
+            let bytes = headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len())
+                .sum::<usize>();
+            if bytes > max_header_bytes {
+                return Err(ApiError::new(
+                    ErrorCode::HeadersTooLarge,
+                    format!("Headers of the request are over {} bytes", max_header_bytes),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Tells HTTP/2 connections from HTTP/1 ones by their first bytes without consuming them
+async fn starts_with_preface(stream: &TcpStream) -> bool {
+    let mut start = [0; HTTP2_PREFACE.len()];
+    loop {
+        let peeked = match stream.peek(&mut start).await {
+            Ok(peeked) => peeked,
+            Err(_) => return false,
+        };
+        if peeked == 0 || !HTTP2_PREFACE.starts_with(&start[..peeked]) {
+            return false;
+        }
+        if peeked == start.len() {
+            return true;
+        }
+
+        sleep(PREFACE_POLL_INTERVAL).await;
+    }
+}
+
+/// Serves the app on HTTP/1 and, if enabled, h2c connections. With PROXY protocol
+/// connections have to start with its header, the address it reports becomes peer address
+/// of the requests
+pub fn serve<F, I, S, B>(
+    app_factory: F,
+    address: (&str, u16),
+    workers: usize,
+    proxy_protocol: bool,
+    connections: &Connections,
+) -> io::Result<Server>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service<Request>>::Future: 'static,
+    S::Service: 'static,
+    B: MessageBody + 'static,
+{
+    let connections = connections.clone();
+    let keep_alive = connections.keep_alive();
+    let client_request_timeout = connections.client_request_timeout;
+
+    Ok(Server::build()
+        .workers(workers)
+        .bind("microphone", address, move || {
+            let app_factory = app_factory.clone();
+            let connections = connections.clone();
+
+            fn_service(move |mut stream: TcpStream| {
+                let connections = connections.clone();
+
+                async move {
+                    let _ = stream.set_nodelay(true);
+                    let peer_address = if proxy_protocol {
+                        match proxy_protocol::read_source(&mut stream).await {
+                            Ok(proxied_address) =>
+                                proxied_address.or_else(|| stream.peer_addr().ok()),
+                            Err(err) => {
+                                log::warn!("Rejected connection: {}", err);
+                                return Err(());
+                            }
+                        }
+                    } else {
+                        stream.peer_addr().ok()
+                    };
+
+                    // Whatever doesn't open with the preface in time is left to HTTP/1 to reject
+                    let http2 = connections.http2
+                        && timeout(
+                            connections.client_request_timeout,
+                            starts_with_preface(&stream),
+                        )
+                        .await
+                        .unwrap_or(false);
+                    let protocol = if http2 {
+                        Protocol::Http2
+                    } else {
+                        Protocol::Http1
+                    };
+
+                    Ok((stream, protocol, peer_address))
+                }
+            })
+            .and_then(
+                HttpService::build()
+                    .keep_alive(keep_alive)
+                    .client_request_timeout(client_request_timeout)
+                    .finish(map_config(
+                        app_factory()
+                            .into_factory()
+                            .map_err(|err| err.into().error_response()),
+                        |_| AppConfig::default(),
+                    ))
+                    .map_err(|_| ()),
+            )
+        })?
+        .run())
+}
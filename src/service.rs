@@ -0,0 +1,425 @@
+use std::{
+    io,
+    path::PathBuf,
+};
+
+use actix_web::dev::Server;
+
+#[cfg(unix)]
+use self::unix::{
+    detach,
+    write_pid_file,
+};
+#[cfg(windows)]
+pub use self::windows::{
+    report,
+    run_as_service,
+};
+
+/// How the server was asked to run, by `--daemon`, `--pid-file` and `--log-file` arguments.
+/// The pid file is removed when it's dropped
+pub struct Daemon {
+    pid_file: Option<PathBuf>,
+}
+
+impl Daemon {
+    /// Takes the arguments out and detaches from the terminal if `--daemon` is among them.
+    /// Has to be called before any thread is started
+    pub fn start(args: &mut Vec<String>) -> Result<Self, String> {
+        let mut daemonize = false;
+        let mut pid_file = None;
+        let mut log_file = None;
+
+        let mut rest = Vec::with_capacity(args.len());
+        let mut taken = std::mem::take(args).into_iter();
+        while let Some(arg) = taken.next() {
+            let mut value = || {
+                taken
+                    .next()
+                    .map(PathBuf::from)
+                    .ok_or_else(|| format!("Missing value for \"{}\"", arg))
+            };
+
+            match arg.as_str() {
+                "--daemon" => daemonize = true,
+                "--pid-file" => pid_file = Some(value()?),
+                "--log-file" => log_file = Some(value()?),
+                _ => rest.push(arg),
+            }
+        }
+        *args = rest;
+
+        if log_file.is_some() && !daemonize {
+            return Err("\"--log-file\" requires \"--daemon\"".to_owned());
+        }
+        if cfg!(not(unix)) && (daemonize || pid_file.is_some()) {
+            return Err(
+                "\"--daemon\" and \"--pid-file\" are supported on Unix only, install microphone \
+                 as a service instead"
+                    .to_owned(),
+            );
+        }
+
+        #[cfg(unix)]
+        {
+            if daemonize {
+                detach(log_file.as_deref())
+                    .map_err(|err| format!("Failed to start daemon: {}", err))?;
+            }
+            if let Some(path) = &pid_file {
+                write_pid_file(path).map_err(|err| {
+                    format!("Failed to write pid file {}: {}", path.display(), err)
+                })?;
+            }
+        }
+
+        Ok(Self { pid_file })
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        if let Some(path) = &self.pid_file {
+            if let Err(err) = std::fs::remove_file(path) {
+                log::warn!("Failed to remove pid file {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+/// Tells whoever started microphone that it's listening: the process that waits for the daemon
+/// to detach, or the service control manager
+#[cfg(unix)]
+pub fn started(_: &Server) -> io::Result<()> {
+    unix::notify_started()
+}
+
+#[cfg(windows)]
+pub fn started(server: &Server) -> io::Result<()> {
+    windows::notify_started(server.handle())
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::{
+            File,
+            OpenOptions,
+        },
+        io::{
+            self,
+            Read,
+            Write,
+        },
+        os::unix::io::{
+            AsRawFd,
+            FromRawFd,
+        },
+        path::Path,
+        sync::Mutex,
+    };
+
+    /// Write end of the pipe the starting process waits on until the daemon is listening
+    static STARTED: Mutex<Option<File>> = Mutex::new(None);
+
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        if result == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// True in the parent
+    fn fork() -> io::Result<bool> {
+        Ok(check(unsafe { libc::fork() })? != 0)
+    }
+
+    /// Forks twice, so the daemon is neither a session leader nor a child of the shell. The
+    /// starting process exits once the daemon is listening, with 1 if it exits before that
+    pub fn detach(log_file: Option<&Path>) -> io::Result<()> {
+        // Opened before forking, so a wrong path is told to whoever starts microphone
+        let output = match log_file {
+            Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+            None => OpenOptions::new().write(true).open("/dev/null")?,
+        };
+        let input = File::open("/dev/null")?;
+
+        let mut fds = [0; 2];
+        check(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let (mut waiting, started) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        // Processes the daemon spawns mustn't keep the starting process waiting
+        check(unsafe { libc::fcntl(started.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) })?;
+
+        if fork()? {
+            drop(started);
+            let mut byte = [0];
+            let code = match waiting.read(&mut byte) {
+                Ok(1) => 0,
+                _ => {
+                    eprintln!("microphone exited before it started listening, see its log");
+                    1
+                }
+            };
+            std::process::exit(code);
+        }
+        drop(waiting);
+
+        check(unsafe { libc::setsid() })?;
+        if fork()? {
+            std::process::exit(0);
+        }
+
+        for (file, target) in [(&input, 0), (&output, 1), (&output, 2)] {
+            check(unsafe { libc::dup2(file.as_raw_fd(), target) })?;
+        }
+        *STARTED.lock().expect("Daemon lock is poisoned") = Some(started);
+
+        Ok(())
+    }
+
+    /// Refuses to overwrite pid file of a process that's still running
+    pub fn write_pid_file(path: &Path) -> io::Result<()> {
+        let running = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| contents.trim().parse::<libc::pid_t>().ok())
+            .filter(|pid| *pid as u32 != std::process::id())
+            .filter(|pid| {
+                let signalled = unsafe { libc::kill(*pid, 0) };
+                signalled == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+            });
+        if let Some(pid) = running {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("microphone is already running with pid {}", pid),
+            ));
+        }
+
+        std::fs::write(path, format!("{}\n", std::process::id()))
+    }
+
+    pub fn notify_started() -> io::Result<()> {
+        match STARTED.lock().expect("Daemon lock is poisoned").take() {
+            Some(mut started) => started.write_all(&[1]),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{
+        ffi::c_void,
+        io,
+        panic::{
+            self,
+            AssertUnwindSafe,
+        },
+        ptr,
+        sync::{
+            atomic::{
+                AtomicIsize,
+                Ordering,
+            },
+            OnceLock,
+        },
+    };
+
+    use actix_web::dev::ServerHandle;
+    use log::Level;
+    use windows_sys::{
+        core::PWSTR,
+        Win32::{
+            Foundation::{
+                ERROR_CALL_NOT_IMPLEMENTED,
+                NO_ERROR,
+            },
+            System::{
+                EventLog::{
+                    RegisterEventSourceW,
+                    ReportEventW,
+                    EVENTLOG_ERROR_TYPE,
+                    EVENTLOG_INFORMATION_TYPE,
+                    EVENTLOG_WARNING_TYPE,
+                },
+                Services::{
+                    RegisterServiceCtrlHandlerExW,
+                    SetServiceStatus,
+                    StartServiceCtrlDispatcherW,
+                    SERVICE_ACCEPT_SHUTDOWN,
+                    SERVICE_ACCEPT_STOP,
+                    SERVICE_CONTROL_INTERROGATE,
+                    SERVICE_CONTROL_SHUTDOWN,
+                    SERVICE_CONTROL_STOP,
+                    SERVICE_RUNNING,
+                    SERVICE_START_PENDING,
+                    SERVICE_STATUS,
+                    SERVICE_STOPPED,
+                    SERVICE_STOP_PENDING,
+                    SERVICE_TABLE_ENTRYW,
+                    SERVICE_WIN32_OWN_PROCESS,
+                },
+            },
+        },
+    };
+
+    /// Service and event source name
+    const SERVICE_NAME: &str = "microphone";
+    /// How long the service control manager is told to wait for starting or stopping
+    const PENDING_WAIT_HINT_MS: u32 = 30_000;
+
+    struct Service {
+        args:  Vec<String>,
+        serve: fn(Vec<String>) -> io::Result<()>,
+    }
+
+    static SERVICE: OnceLock<Service> = OnceLock::new();
+    static SERVER: OnceLock<ServerHandle> = OnceLock::new();
+    static STATUS_HANDLE: AtomicIsize = AtomicIsize::new(0);
+    static EVENT_SOURCE: AtomicIsize = AtomicIsize::new(0);
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    /// Runs the server as a service started by the service control manager, returns once the
+    /// service is stopped. Logs go to the Application event log
+    pub fn run_as_service(
+        args: Vec<String>,
+        serve: fn(Vec<String>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let _ = SERVICE.set(Service { args, serve });
+        let source = unsafe { RegisterEventSourceW(ptr::null(), wide(SERVICE_NAME).as_ptr()) };
+        EVENT_SOURCE.store(source, Ordering::SeqCst);
+
+        let mut name = wide(SERVICE_NAME);
+        let table = [
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: name.as_mut_ptr(),
+                lpServiceProc: Some(service_main),
+            },
+            SERVICE_TABLE_ENTRYW {
+                lpServiceName: ptr::null_mut(),
+                lpServiceProc: None,
+            },
+        ];
+        if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn set_status(state: u32, exit_code: u32) {
+        let is_pending = state == SERVICE_START_PENDING || state == SERVICE_STOP_PENDING;
+        let status = SERVICE_STATUS {
+            dwServiceType:             SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState:            state,
+            dwControlsAccepted:        if state == SERVICE_RUNNING {
+                SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+            } else {
+                0
+            },
+            dwWin32ExitCode:           exit_code,
+            dwServiceSpecificExitCode: 0,
+            dwCheckPoint:              0,
+            dwWaitHint:                if is_pending { PENDING_WAIT_HINT_MS } else { 0 },
+        };
+
+        unsafe { SetServiceStatus(STATUS_HANDLE.load(Ordering::SeqCst), &status) };
+    }
+
+    unsafe extern "system" fn service_main(_: u32, _: *mut PWSTR) {
+        let name = wide(SERVICE_NAME);
+        let status_handle =
+            RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(handle_control), ptr::null());
+        if status_handle == 0 {
+            log::error!(
+                "Failed to register service control handler: {}",
+                io::Error::last_os_error()
+            );
+            return;
+        }
+        STATUS_HANDLE.store(status_handle, Ordering::SeqCst);
+        set_status(SERVICE_START_PENDING, 0);
+
+        let service = SERVICE
+            .get()
+            .expect("Service is set before it's dispatched");
+        // Panics mustn't unwind into the service control manager
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| (service.serve)(service.args.clone())));
+        let exit_code = match result {
+            Ok(Ok(())) => 0,
+            Ok(Err(err)) => {
+                log::error!("Service failed: {}", err);
+                1
+            }
+            Err(_) => {
+                log::error!("Service panicked");
+                1
+            }
+        };
+        set_status(SERVICE_STOPPED, exit_code);
+    }
+
+    unsafe extern "system" fn handle_control(
+        control: u32,
+        _: u32,
+        _: *mut c_void,
+        _: *mut c_void,
+    ) -> u32 {
+        match control {
+            SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+                set_status(SERVICE_STOP_PENDING, 0);
+                // Stop command is sent right away, the server stops gracefully on its own
+                if let Some(server) = SERVER.get() {
+                    drop(server.stop(true));
+                }
+                NO_ERROR
+            }
+            SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+            _ => ERROR_CALL_NOT_IMPLEMENTED,
+        }
+    }
+
+    pub fn notify_started(server: ServerHandle) -> io::Result<()> {
+        let _ = SERVER.set(server);
+        if STATUS_HANDLE.load(Ordering::SeqCst) != 0 {
+            set_status(SERVICE_RUNNING, 0);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the record to the event log, when running as a service
+    pub fn report(record: &log::Record) {
+        let source = EVENT_SOURCE.load(Ordering::SeqCst);
+        if source == 0 {
+            return;
+        }
+        let event_type = match record.level() {
+            Level::Error => EVENTLOG_ERROR_TYPE,
+            Level::Warn => EVENTLOG_WARNING_TYPE,
+            Level::Info => EVENTLOG_INFORMATION_TYPE,
+            Level::Debug | Level::Trace => return,
+        };
+
+        let mut message = wide(&format!("{}: {}", record.target(), record.args()));
+        let strings = [message.as_mut_ptr()];
+        unsafe {
+            ReportEventW(
+                source,
+                event_type,
+                0,
+                0,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                ptr::null(),
+            )
+        };
+    }
+}
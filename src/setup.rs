@@ -0,0 +1,230 @@
+use std::path::Path;
+
+use ipnet::IpNet;
+use toml_edit::{
+    value,
+    Array,
+    DocumentMut,
+    Item,
+    Table,
+};
+
+use crate::{
+    config,
+    Config,
+};
+
+const INIT_USAGE: &str = "\
+Usage: microphone init [--output <path>]
+
+Prints commented example config, or writes it to the given file if it doesn't exist yet.";
+
+const TOPIC_USAGE: &str = "\
+Usage: microphone topic add --config <path> --name <name> --recipient <chat id>...
+                            [--allow <cidr>...]
+
+Adds a topic to the config file, keeping its comments and formatting.
+
+    --config     Config file to edit
+    --name       Name of the topic, it's the first segment of the path messages are posted to
+    --recipient  Chat id messages are forwarded to, can be repeated
+    --allow      Network allowed to post to the topic, can be repeated";
+
+const EXAMPLE_CONFIG: &str = r#"# Refer to https://toml.io if you're not familiar with TOML format
+# and to README for the rest of the settings
+
+# Layout version of the file
+version = {version}
+
+# Enables admin API for requests with `Authorization: Bearer <admin_token>` header
+# admin_token = "change me"
+
+[server]
+# Port that the service will listen to
+port = 80
+# Proxies allowed to tell client address with forwarding headers
+# trusted_proxies = ["127.0.0.1/32"]
+
+[telegram]
+# Telegram bot token that you'll get after bot creation with @BotFather
+secret = "123456789:replace-with-bot-token"
+
+# Messages posted to /myLab/{sender} are forwarded to recipients of the topic
+[topics.myLab]
+# Chat ids, see https://core.telegram.org/bots/api#sendmessage and @myidbot
+recipients = ["11111111"]
+# Networks allowed to post to the topic in CIDR notation
+allow_list = ["192.168.69.0/24"]
+# Hostname patterns matched against forward-confirmed PTR records of clients
+# allow_hosts = ["*.ci.internal"]
+# Senders are reported if their heartbeat is late
+# expect_heartbeat_every = "5m"
+# Messages over these limits are rejected until the end of the UTC day
+# max_messages_per_day = 500
+# max_bytes_per_day = 10485760
+
+# Addresses that keep getting rejected are banned for a while
+# [ban]
+# max_rejections = 10
+# within = "10m"
+# duration = "1h"
+"#;
+
+fn example_config() -> String {
+    EXAMPLE_CONFIG.replacen("{version}", &config::CURRENT_VERSION.to_string(), 1)
+}
+
+pub fn run_init(args: Vec<String>) -> Result<(), std::io::Error> {
+    let output = match args.as_slice() {
+        [] => None,
+        [flag, output] if flag == "--output" => Some(output),
+        _ => {
+            eprintln!("{}", INIT_USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    match output {
+        None => print!("{}", example_config()),
+        Some(output) => {
+            if Path::new(output).exists() {
+                eprintln!("{} already exists", output);
+                std::process::exit(1);
+            }
+            std::fs::write(output, example_config())?;
+        }
+    }
+
+    Ok(())
+}
+
+struct TopicOptions {
+    config_path: String,
+    name:        String,
+    recipients:  Vec<String>,
+    allow_list:  Vec<IpNet>,
+}
+
+impl TopicOptions {
+    fn parse(args: Vec<String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        match args.next().as_deref() {
+            Some("add") => (),
+            Some(unknown) => return Err(format!("Unexpected subcommand \"{}\"", unknown)),
+            None => return Err("Missing subcommand".to_owned()),
+        }
+
+        let mut config_path = None;
+        let mut name = None;
+        let mut recipients = Vec::new();
+        let mut allow_list = Vec::new();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for \"{}\"", arg))
+            };
+
+            match arg.as_str() {
+                "--config" => config_path = Some(value()?),
+                "--name" => name = Some(value()?),
+                "--recipient" => recipients.push(value()?),
+                "--allow" => {
+                    let network = value()?;
+                    allow_list.push(network.parse().map_err(|_| {
+                        format!(
+                            "Value \"{}\" for \"{}\" is not a valid network",
+                            network, arg
+                        )
+                    })?);
+                }
+                unknown => return Err(format!("Unexpected argument \"{}\"", unknown)),
+            }
+        }
+
+        if recipients.is_empty() {
+            return Err("At least one \"--recipient\" must be provided".to_owned());
+        }
+
+        Ok(Self {
+            config_path: config_path.ok_or("Missing \"--config\"")?,
+            name: name.ok_or("Missing \"--name\"")?,
+            recipients,
+            allow_list,
+        })
+    }
+}
+
+fn add_topic(text: &str, options: &TopicOptions) -> Result<String, String> {
+    let mut document = text.parse::<DocumentMut>().map_err(|err| err.to_string())?;
+
+    // Comments at the end of the file would otherwise end up below the new topic
+    let trailing = document.trailing().as_str().unwrap_or_default().to_owned();
+    document.set_trailing("");
+
+    let topics = document
+        .entry("topics")
+        .or_insert_with(|| {
+            let mut topics = Table::new();
+            topics.set_implicit(true);
+            Item::Table(topics)
+        })
+        .as_table_mut()
+        .ok_or("`topics` is not a table")?;
+
+    if topics.contains_key(&options.name) {
+        return Err(format!("Topic \"{}\" already exists", options.name));
+    }
+
+    let mut topic = Table::new();
+    topic["recipients"] = value(options.recipients.iter().collect::<Array>());
+    if !options.allow_list.is_empty() {
+        topic["allow_list"] = value(
+            options
+                .allow_list
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Array>(),
+        );
+    }
+    topic.decor_mut().set_prefix(match trailing.trim_end() {
+        "" => "\n".to_owned(),
+        trailing => format!("{}\n\n", trailing),
+    });
+    topics.insert(&options.name, Item::Table(topic));
+
+    Ok(document.to_string())
+}
+
+pub fn run_topic(args: Vec<String>) -> Result<(), std::io::Error> {
+    let options = match TopicOptions::parse(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, TOPIC_USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let path = Path::new(&options.config_path);
+    if config::Format::of(path) != config::Format::Toml {
+        eprintln!("Only TOML configs can be edited");
+        std::process::exit(1);
+    }
+
+    let text = std::fs::read_to_string(path)?;
+    let edited = match add_topic(&text, &options) {
+        Ok(edited) => edited,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Refuse to leave the file in a state microphone won't start with
+    if let Err(err) = config::from_text::<Config>(path, &edited) {
+        eprintln!("Edited config is invalid, file is left untouched: {}", err);
+        std::process::exit(1);
+    }
+
+    std::fs::write(path, edited)
+}
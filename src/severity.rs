@@ -0,0 +1,84 @@
+use std::future::{
+    ready,
+    Ready,
+};
+
+use actix_web::{
+    dev::Payload,
+    FromRequest,
+    HttpRequest,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::errors::{
+    ApiError,
+    ErrorCode,
+};
+
+/// Severity of a message, taken from `X-Severity` header
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Eq)]
+#[derive(PartialOrd)]
+#[derive(Ord)]
+#[derive(Hash)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "info" => Some(Self::Info),
+            "warning" => Some(Self::Warning),
+            "critical" => Some(Self::Critical),
+            _ => None,
+        }
+    }
+}
+
+/// How messages of a severity stand out
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Style {
+    /// Id of message effect, Telegram shows effects in private chats only
+    pub effect:   Option<String>,
+    /// Emoji the bot reacts with to its own message
+    pub reaction: Option<String>,
+}
+
+impl FromRequest for Severity {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let value = match request.headers().get("X-Severity") {
+            Some(value) => value,
+            None => return ready(Ok(Self::default())),
+        };
+
+        match value.to_str().ok().and_then(Self::parse) {
+            Some(severity) => ready(Ok(severity)),
+            None => ready(Err(ApiError::new(
+                ErrorCode::InvalidSeverity,
+                "X-Severity has to be one of: info, warning, critical",
+            )
+            .into())),
+        }
+    }
+}
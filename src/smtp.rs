@@ -0,0 +1,253 @@
+use std::time::Duration;
+
+use actix_web::rt::time::timeout;
+use chrono::Utc;
+use serde::Deserialize;
+use tokio::{
+    io::{
+        AsyncBufReadExt,
+        AsyncRead,
+        AsyncWrite,
+        AsyncWriteExt,
+        BufReader,
+    },
+    net::TcpStream,
+};
+use tokio_native_tls::{
+    native_tls,
+    TlsConnector,
+};
+use uuid::Uuid;
+
+/// Length of base64 lines in message bodies, as recommended for MIME
+const BASE64_LINE_LENGTH: usize = 76;
+
+/// How the connection to the mail server is protected
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tls {
+    /// TLS from the start, usually port 465
+    #[default]
+    Implicit,
+    /// Plain connection upgraded with STARTTLS, usually port 587
+    Starttls,
+    /// Only for relays on the same host or network
+    None,
+}
+
+/// Mail server messages are sent through when they fall back from Telegram
+#[derive(Debug)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    host:     String,
+    /// 465 for implicit TLS, 587 for STARTTLS and 25 without TLS by default
+    port:     Option<u16>,
+    #[serde(default)]
+    tls:      Tls,
+    /// Login of `AUTH PLAIN`, no authentication without it
+    username: Option<String>,
+    password: Option<String>,
+    from:     String,
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    timeout:  Duration,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Plain text email
+pub struct Email<'a> {
+    pub to:      &'a [String],
+    pub subject: &'a str,
+    pub body:    &'a str,
+}
+
+/// Sends emails, one connection per email as they are rare
+pub struct Mailer {
+    config: SmtpConfig,
+}
+
+impl Mailer {
+    pub fn new(config: SmtpConfig) -> Self {
+        Self { config }
+    }
+
+    pub async fn send(&self, email: &Email<'_>) -> Result<(), String> {
+        timeout(self.config.timeout, self.deliver(email))
+            .await
+            .map_err(|_| format!("{} timed out", self.config.host))?
+    }
+
+    async fn deliver(&self, email: &Email<'_>) -> Result<(), String> {
+        let config = &self.config;
+        let port = config.port.unwrap_or(match config.tls {
+            Tls::Implicit => 465,
+            Tls::Starttls => 587,
+            Tls::None => 25,
+        });
+        let stream = TcpStream::connect((config.host.as_str(), port))
+            .await
+            .map_err(|err| format!("Failed to connect to {}: {}", config.host, err))?;
+
+        match config.tls {
+            Tls::None => {
+                let mut session = Session::start(stream).await?;
+                session.send(config, email).await
+            }
+            Tls::Implicit => {
+                let stream = self.tls_connector()?.connect(&config.host, stream).await;
+                let stream = stream.map_err(|err| format!("TLS handshake failed: {}", err))?;
+                let mut session = Session::start(stream).await?;
+                session.send(config, email).await
+            }
+            Tls::Starttls => {
+                let mut session = Session::start(stream).await?;
+                session.command("STARTTLS", 220).await?;
+                let stream = self
+                    .tls_connector()?
+                    .connect(&config.host, session.stream.into_inner())
+                    .await;
+                let stream = stream.map_err(|err| format!("TLS handshake failed: {}", err))?;
+                // Capabilities learned before TLS are forgotten
+                let mut session = Session::greeted(stream).await?;
+                session.send(config, email).await
+            }
+        }
+    }
+
+    fn tls_connector(&self) -> Result<TlsConnector, String> {
+        native_tls::TlsConnector::new()
+            .map(TlsConnector::from)
+            .map_err(|err| format!("Failed to set up TLS: {}", err))
+    }
+}
+
+/// SMTP conversation with the server, usable before and after TLS is set up
+struct Session<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Session<S> {
+    /// Waits for greeting of the server and introduces itself
+    async fn start(stream: S) -> Result<Self, String> {
+        let mut session = Self {
+            stream: BufReader::new(stream),
+        };
+        session.expect(220).await?;
+        session.command("EHLO microphone", 250).await?;
+
+        Ok(session)
+    }
+
+    /// Introduces itself again on a connection the server greeted already
+    async fn greeted(stream: S) -> Result<Self, String> {
+        let mut session = Self {
+            stream: BufReader::new(stream),
+        };
+        session.command("EHLO microphone", 250).await?;
+
+        Ok(session)
+    }
+
+    async fn send(&mut self, config: &SmtpConfig, email: &Email<'_>) -> Result<(), String> {
+        if let Some(username) = &config.username {
+            let password = config.password.as_deref().unwrap_or_default();
+            let credentials = base64::encode(format!("\0{}\0{}", username, password));
+            self.command(&format!("AUTH PLAIN {}", credentials), 235)
+                .await?;
+        }
+
+        self.command(&format!("MAIL FROM:<{}>", config.from), 250)
+            .await?;
+        for recipient in email.to {
+            self.command(&format!("RCPT TO:<{}>", recipient), 250)
+                .await?;
+        }
+        self.command("DATA", 354).await?;
+        self.write(&message(&config.from, email)).await?;
+        self.expect(250).await?;
+        // The email is accepted, failing to say goodbye doesn't matter
+        let _ = self.write("QUIT\r\n").await;
+
+        Ok(())
+    }
+
+    async fn command(&mut self, command: &str, expected: u16) -> Result<(), String> {
+        self.write(&format!("{}\r\n", command)).await?;
+        self.expect(expected).await
+    }
+
+    async fn write(&mut self, text: &str) -> Result<(), String> {
+        self.stream
+            .write_all(text.as_bytes())
+            .await
+            .map_err(|err| format!("Failed to write to mail server: {}", err))?;
+        self.stream
+            .flush()
+            .await
+            .map_err(|err| format!("Failed to write to mail server: {}", err))
+    }
+
+    /// Reads reply, which can span several `250-...` lines, and checks its code
+    async fn expect(&mut self, expected: u16) -> Result<(), String> {
+        loop {
+            let mut line = String::new();
+            let read = self
+                .stream
+                .read_line(&mut line)
+                .await
+                .map_err(|err| format!("Failed to read from mail server: {}", err))?;
+            if read == 0 {
+                return Err("Mail server closed connection".to_owned());
+            }
+
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            if code != Some(expected) {
+                return Err(format!("Mail server replied {}", line.trim_end()));
+            }
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Headers and base64 body, ending with the line that finishes DATA
+fn message(from: &str, email: &Email) -> String {
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@microphone>\r\n\
+         MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\
+         Content-Transfer-Encoding: base64\r\n\r\n",
+        from,
+        email.to.join(", "),
+        encoded_word(email.subject),
+        Utc::now().to_rfc2822(),
+        Uuid::new_v4(),
+    );
+
+    // Base64 never starts a line with dot, so the body needs no dot stuffing
+    let body = base64::encode(email.body.lines().collect::<Vec<_>>().join("\r\n"));
+    for line in body.as_bytes().chunks(BASE64_LINE_LENGTH) {
+        message.push_str(std::str::from_utf8(line).expect("Base64 is ASCII"));
+        message.push_str("\r\n");
+    }
+    message.push_str(".\r\n");
+
+    message
+}
+
+/// Headers are ASCII, anything else is encoded as RFC 2047 word
+fn encoded_word(text: &str) -> String {
+    if text.is_ascii() {
+        text.to_owned()
+    } else {
+        format!("=?UTF-8?B?{}?=", base64::encode(text))
+    }
+}
@@ -0,0 +1,228 @@
+use std::sync::Arc;
+
+use actix_web::{
+    http::header::{
+        self,
+        HeaderMap,
+        HeaderValue,
+    },
+    web,
+    HttpRequest,
+    HttpResponse,
+};
+use serde::de::IgnoredAny;
+
+use crate::{
+    access::{
+        AllowedTopic,
+        Caller,
+    },
+    alerts::{
+        AlertUpdate,
+        Alerts,
+    },
+    callbacks::Callback,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    escalation::Escalations,
+    maintenance::Maintenance,
+    messages::Messages,
+    metrics::Metrics,
+    quotas::Quotas,
+    send_options::RequestOptions,
+    severity::Severity,
+    upload::Uploads,
+    PostPathData,
+    TgClient,
+};
+
+/// Types that get a route of their own, bodies declared as anything else are sniffed
+const DECLARED_TYPES: [&str; 4] = [
+    "text/",
+    "application/json",
+    "application/x-www-form-urlencoded",
+    "multipart/form-data",
+];
+const SUPPORTED_TYPES: [&str; 4] = [
+    "text/plain",
+    "application/json",
+    "application/x-www-form-urlencoded",
+    "multipart/form-data",
+];
+/// RFC 2046 limit, longer first lines aren't boundaries
+const MAX_BOUNDARY_LEN: usize = 70;
+
+enum Sniffed {
+    Multipart(String),
+    Json,
+    Text,
+}
+
+/// Request has Content-Type of a route, otherwise its body is sniffed
+pub fn is_declared(content_type: Option<&str>) -> bool {
+    content_type.is_some_and(|content_type| {
+        let content_type = content_type.trim_start().to_ascii_lowercase();
+        DECLARED_TYPES
+            .iter()
+            .any(|declared| content_type.starts_with(declared))
+    })
+}
+
+/// Boundary of the first line, if the next ones are headers of a form field
+fn multipart_boundary(body: &[u8]) -> Option<String> {
+    let line_end = body
+        .windows(2)
+        .take(MAX_BOUNDARY_LEN + 3)
+        .position(|pair| pair == b"\r\n")?;
+    let boundary = std::str::from_utf8(body[..line_end].strip_prefix(b"--")?).ok()?;
+    if boundary.is_empty() || boundary.ends_with(' ') || !boundary.is_ascii() {
+        return None;
+    }
+
+    let headers = String::from_utf8_lossy(&body[line_end + 2..]);
+    let headers = headers.split("\r\n\r\n").next().unwrap_or_default();
+    headers
+        .lines()
+        .any(|line| {
+            line.to_ascii_lowercase()
+                .starts_with("content-disposition: form-data")
+        })
+        .then(|| boundary.to_owned())
+}
+
+fn sniff(body: &[u8]) -> Option<Sniffed> {
+    if let Some(boundary) = multipart_boundary(body) {
+        return Some(Sniffed::Multipart(boundary));
+    }
+
+    let text = std::str::from_utf8(body).ok()?;
+    if text.contains('\0') {
+        return None;
+    }
+    let trimmed = text.trim_start();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<IgnoredAny>(text).is_ok()
+    {
+        return Some(Sniffed::Json);
+    }
+
+    Some(Sniffed::Text)
+}
+
+fn unsupported() -> HttpResponse {
+    HttpResponse::from(
+        ApiError::new(
+            ErrorCode::UnsupportedMediaType,
+            format!(
+                "Body of unknown type is neither text, JSON nor multipart, send it as one of {}",
+                SUPPORTED_TYPES.join(", ")
+            ),
+        )
+        .with_details(serde_json::json!({ "supported": SUPPORTED_TYPES })),
+    )
+}
+
+/// What handlers of text and files need together, actix handlers take at most 12 extractors
+type AppData = (
+    web::Data<Arc<TgClient>>,
+    web::Data<Arc<Maintenance>>,
+    web::Data<Arc<Quotas>>,
+    web::Data<Arc<Metrics>>,
+    web::Data<Arc<Escalations>>,
+    web::Data<Arc<Alerts>>,
+    web::Data<Arc<Messages>>,
+    web::Data<Arc<Uploads>>,
+);
+
+/// Posts with Content-Type missing or of no route, multipart bodies are sent as files, JSON as
+/// code block and other text as it is
+pub async fn post_sniffed_message(
+    topic: AllowedTopic,
+    (tg_client, maintenance, quotas, metrics, escalations, alerts, messages, uploads): AppData,
+    (severity, alert, callback, mut request_options): (
+        Severity,
+        AlertUpdate,
+        Callback,
+        RequestOptions,
+    ),
+    path_data: web::Path<PostPathData>,
+    request: HttpRequest,
+    body: web::Bytes,
+) -> HttpResponse {
+    let sniffed = match sniff(&body) {
+        Some(sniffed) => sniffed,
+        None => return unsupported(),
+    };
+
+    match sniffed {
+        Sniffed::Multipart(boundary) => {
+            // Scope of API key was checked against the declared type, which wasn't multipart
+            let attachments = match Caller::of(&request) {
+                Ok(caller) => caller
+                    .api_key
+                    .is_none_or(|api_key| api_key.scope.attachments),
+                Err(err) => return HttpResponse::from(err),
+            };
+            if !attachments {
+                return HttpResponse::from(ApiError::new(
+                    ErrorCode::OutOfScope,
+                    "API key can send text only",
+                ));
+            }
+
+            let content_type = format!("multipart/form-data; boundary={}", boundary);
+            let content_type = match HeaderValue::from_str(&content_type) {
+                Ok(content_type) => content_type,
+                Err(_) => return unsupported(),
+            };
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, content_type);
+            let multipart = actix_multipart::Multipart::new(
+                &headers,
+                futures::stream::once(async move { Ok(body) }),
+            );
+
+            crate::post_message_with_document(
+                topic,
+                tg_client,
+                maintenance,
+                quotas,
+                metrics,
+                messages,
+                uploads,
+                callback,
+                request_options,
+                path_data,
+                request,
+                multipart,
+            )
+            .await
+        }
+        Sniffed::Json | Sniffed::Text => {
+            if let Sniffed::Json = sniffed {
+                request_options
+                    .code_language
+                    .get_or_insert_with(|| "json".to_owned());
+            }
+            let message = String::from_utf8(body.to_vec()).expect("Sniffed text is valid UTF-8");
+
+            crate::post_message(
+                topic,
+                tg_client,
+                maintenance,
+                quotas,
+                metrics,
+                escalations,
+                alerts,
+                messages,
+                (severity, alert, callback, request_options),
+                path_data,
+                request,
+                message,
+            )
+            .await
+        }
+    }
+}
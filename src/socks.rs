@@ -0,0 +1,259 @@
+use std::{
+    io,
+    net::{
+        IpAddr,
+        Ipv4Addr,
+        SocketAddr,
+    },
+    sync::Arc,
+};
+
+use actix_web::rt::net::{
+    TcpListener,
+    TcpStream,
+};
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use tokio::{
+    io::{
+        copy_bidirectional,
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::lookup_host,
+};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const CONNECT: u8 = 1;
+const SUCCEEDED: u8 = 0;
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+/// Longest request head the bridge reads before giving up on it
+const MAX_HEAD_LENGTH: usize = 8 * 1024;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// SOCKS5 proxy given as `socks5://` URL, `socks5h://` ones resolve hosts themselves
+struct Proxy {
+    address:         (String, u16),
+    credentials:     Option<(String, String)>,
+    resolves_itself: bool,
+}
+
+/// Credentials in URL are percent-encoded
+fn decode(text: &str) -> String {
+    percent_decode_str(text).decode_utf8_lossy().into_owned()
+}
+
+/// Starts local HTTP proxy passing connections on through the SOCKS5 one, as Bot API client
+/// only speaks HTTP proxies. Returns URL of the local proxy
+pub fn bridge(url: &Url) -> io::Result<Url> {
+    let proxy = Proxy {
+        address:         (
+            url.host_str()
+                .ok_or_else(|| invalid("SOCKS proxy has no host"))?
+                .to_owned(),
+            url.port().unwrap_or(1080),
+        ),
+        credentials:     match url.username() {
+            "" => None,
+            username => Some((decode(username), decode(url.password().unwrap_or_default()))),
+        },
+        resolves_itself: url.scheme() == "socks5h",
+    };
+
+    let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    listener.set_nonblocking(true)?;
+    let local_address = listener.local_addr()?;
+    let proxy = Arc::new(proxy);
+    actix_web::rt::spawn(async move {
+        let listener = match TcpListener::from_std(listener) {
+            Ok(listener) => listener,
+            Err(err) => {
+                log::error!("SOCKS bridge failed to listen: {}", err);
+                return;
+            }
+        };
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    log::warn!("SOCKS bridge failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+            let proxy = proxy.clone();
+            actix_web::rt::spawn(async move {
+                if let Err(err) = proxy.pass(stream).await {
+                    log::warn!("SOCKS proxy connection failed: {}", err);
+                }
+            });
+        }
+    });
+
+    Ok(Url::parse(&format!("http://{}", local_address)).expect("Local address is a valid URL"))
+}
+
+/// Reads request head, `CONNECT host:port` for HTTPS or request with absolute URL for HTTP
+async fn read_head(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD_LENGTH {
+            return Err(invalid("Request head is too long"));
+        }
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+
+    Ok(head)
+}
+
+/// Host and port the request is for, and whether it asks for a tunnel
+fn target(head: &[u8]) -> io::Result<(String, u16, bool)> {
+    let line = head
+        .split(|byte| *byte == b'\r')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .ok_or_else(|| invalid("Malformed request line"))?;
+    let mut parts = line.split(' ');
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Err(invalid("Malformed request line")),
+    };
+
+    if method == "CONNECT" {
+        let (host, port) = target
+            .rsplit_once(':')
+            .ok_or_else(|| invalid("CONNECT target has no port"))?;
+        let port = port
+            .parse()
+            .map_err(|_| invalid("Malformed CONNECT port"))?;
+
+        return Ok((host.trim_matches(['[', ']']).to_owned(), port, true));
+    }
+
+    let url = Url::parse(target).map_err(|_| invalid("Request target is not absolute URL"))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| invalid("Request target has no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| invalid("Request target has no port"))?;
+
+    Ok((host.trim_matches(['[', ']']).to_owned(), port, false))
+}
+
+impl Proxy {
+    async fn pass(&self, mut client: TcpStream) -> io::Result<()> {
+        let head = read_head(&mut client).await?;
+        let (host, port, tunnel) = target(&head)?;
+
+        let mut upstream = match self.connect(&host, port).await {
+            Ok(upstream) => upstream,
+            Err(err) => {
+                let _ = client.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").await;
+                return Err(err);
+            }
+        };
+        if tunnel {
+            client
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await?;
+        } else {
+            // Servers accept absolute URLs in request line, so the request goes on as is
+            upstream.write_all(&head).await?;
+        }
+
+        copy_bidirectional(&mut client, &mut upstream).await?;
+
+        Ok(())
+    }
+
+    /// Connection to the host through the proxy
+    async fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect((self.address.0.as_str(), self.address.1)).await?;
+
+        let method = match self.credentials {
+            Some(_) => USERNAME_PASSWORD,
+            None => NO_AUTHENTICATION,
+        };
+        stream.write_all(&[VERSION, 1, method]).await?;
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice).await?;
+        if choice != [VERSION, method] {
+            return Err(invalid("SOCKS proxy refused authentication method"));
+        }
+
+        if let Some((username, password)) = &self.credentials {
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+
+            let mut status = [0; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != SUCCEEDED {
+                return Err(invalid("SOCKS proxy rejected credentials"));
+            }
+        }
+
+        let mut request = vec![VERSION, CONNECT, 0];
+        let address = match host.parse::<IpAddr>() {
+            Ok(address) => Some(address),
+            Err(_) if self.resolves_itself => None,
+            Err(_) => Some(
+                lookup_host((host, port))
+                    .await?
+                    .next()
+                    .map(|address: SocketAddr| address.ip())
+                    .ok_or_else(|| invalid(format!("{} resolved to no addresses", host)))?,
+            ),
+        };
+        match address {
+            Some(IpAddr::V4(address)) => {
+                request.push(IPV4);
+                request.extend_from_slice(&address.octets());
+            }
+            Some(IpAddr::V6(address)) => {
+                request.push(IPV6);
+                request.extend_from_slice(&address.octets());
+            }
+            None => {
+                request.extend_from_slice(&[DOMAIN_NAME, host.len() as u8]);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != SUCCEEDED {
+            return Err(invalid(format!(
+                "SOCKS proxy failed to connect to {}, reply {}",
+                host, reply[1]
+            )));
+        }
+        // Address the proxy connected from isn't needed
+        let bound_length = match reply[3] {
+            IPV4 => 4,
+            IPV6 => 16,
+            DOMAIN_NAME => stream.read_u8().await? as usize,
+            _ => return Err(invalid("SOCKS proxy replied with unknown address type")),
+        };
+        let mut bound = vec![0; bound_length + 2];
+        stream.read_exact(&mut bound).await?;
+
+        Ok(stream)
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Arc,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+use actix_web::rt::time::interval;
+use serde::Deserialize;
+
+use crate::{
+    internal::Incident,
+    send_options::SendOptions,
+    upload::Content,
+    LiveTopics,
+    TgClient,
+};
+
+/// Files modified more recently than this might still be being written
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpoolConfig {
+    /// Files dropped in `{directory}/{topic}/{sender}/` are delivered to the topic
+    directory:  PathBuf,
+    #[serde(default = "default_scan_every", with = "humantime_serde")]
+    scan_every: Duration,
+    /// Delivered files are moved here keeping the `{topic}/{sender}/` layout, deleted otherwise
+    archive:    Option<PathBuf>,
+}
+
+fn default_scan_every() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// File waiting in the spool
+struct Spooled {
+    path:   PathBuf,
+    topic:  String,
+    sender: String,
+}
+
+impl Spooled {
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+pub fn spawn(config: SpoolConfig, topics: Arc<LiveTopics>, tg_client: Arc<TgClient>) {
+    actix_web::rt::spawn(async move {
+        let mut ticks = interval(config.scan_every);
+        // Undelivered files last reported, reported again once there are twice as many
+        let mut reported = 0;
+        let mut previously_undelivered = 0;
+
+        loop {
+            ticks.tick().await;
+
+            let mut undelivered = 0;
+            for spooled in scan(&config.directory) {
+                if deliver(&topics, &tg_client, &spooled).await {
+                    dispose(&config, &spooled);
+                } else {
+                    undelivered += 1;
+                }
+            }
+
+            // Files failing once are just retried, the spool is stuck if they keep failing
+            let is_stuck = undelivered > 0 && previously_undelivered > 0;
+            previously_undelivered = undelivered;
+            if undelivered == 0 {
+                reported = 0;
+            } else if is_stuck && undelivered >= reported * 2 {
+                tg_client
+                    .alerts
+                    .raise(Incident::SpoolStuck { files: undelivered });
+                reported = undelivered;
+            }
+        }
+    });
+}
+
+fn subdirectories(directory: &Path) -> Vec<(String, PathBuf)> {
+    match std::fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+            .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry.path())))
+            .collect(),
+        Err(err) => {
+            log::warn!(
+                "Failed to read spool directory {}: {}",
+                directory.display(),
+                err
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Files that are done being written. Names starting with a dot are skipped, so writers can
+/// create `.name` and rename it once complete
+fn scan(directory: &Path) -> Vec<Spooled> {
+    let settled_before = SystemTime::now() - SETTLE_TIME;
+    let mut spooled = Vec::new();
+
+    for (topic, topic_directory) in subdirectories(directory) {
+        for (sender, sender_directory) in subdirectories(&topic_directory) {
+            let entries = match std::fs::read_dir(&sender_directory) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for entry in entries.filter_map(Result::ok) {
+                let is_settled_file = entry.metadata().is_ok_and(|metadata| {
+                    metadata.is_file()
+                        && metadata
+                            .modified()
+                            .is_ok_and(|modified| modified < settled_before)
+                });
+                if !is_settled_file || entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+
+                spooled.push(Spooled {
+                    path:   entry.path(),
+                    topic:  topic.clone(),
+                    sender: sender.clone(),
+                });
+            }
+        }
+    }
+
+    spooled
+}
+
+/// Text files are sent as messages, anything else as document. Returns whether the file is
+/// done with, it stays in the spool to be retried if nobody got it
+async fn deliver(topics: &LiveTopics, tg_client: &TgClient, spooled: &Spooled) -> bool {
+    let topics = topics.current();
+    let topic_info = match topics.get(&spooled.topic) {
+        Some(topic_info) => topic_info,
+        None => {
+            log::debug!(
+                "Skipping spooled file for unknown topic \"{}\"",
+                spooled.topic
+            );
+            return false;
+        }
+    };
+
+    let content = match std::fs::read(&spooled.path) {
+        Ok(content) => content,
+        Err(err) => {
+            log::warn!("Failed to read {}: {}", spooled.path.display(), err);
+            return false;
+        }
+    };
+
+    let is_text = matches!(
+        spooled
+            .path
+            .extension()
+            .and_then(|extension| extension.to_str()),
+        None | Some("txt")
+    );
+    let recipients = tg_client.recipients_of(&spooled.topic, topic_info);
+    let responses = match String::from_utf8(content) {
+        Ok(text) if is_text =>
+            tg_client
+                .send_formatted_to_all(
+                    &recipients,
+                    &topic_info.format,
+                    &spooled.topic,
+                    &spooled.sender,
+                    &text,
+                    SendOptions::of_topic(topic_info),
+                )
+                .await,
+        content => {
+            let content = content.map_or_else(|err| err.into_bytes(), String::into_bytes);
+            tg_client
+                .send_document_to_all(
+                    &recipients,
+                    &topic_info.format,
+                    &spooled.topic,
+                    &spooled.sender,
+                    "",
+                    &[(spooled.file_name(), Content::Memory(content))],
+                    SendOptions::of_topic(topic_info),
+                )
+                .await
+        }
+    };
+
+    if responses.iter().all(|delivery| !delivery.is_delivered()) {
+        log::warn!(
+            "Failed to deliver spooled {}, will retry",
+            spooled.path.display()
+        );
+        return false;
+    }
+    if !responses.iter().all(|delivery| delivery.is_delivered()) {
+        log::warn!(
+            "Failed to deliver spooled {} to some recipients of \"{}\"",
+            spooled.path.display(),
+            spooled.topic
+        );
+    }
+
+    true
+}
+
+/// Archived files get a timestamp prefix so files of the same name don't replace each other
+fn dispose(config: &SpoolConfig, spooled: &Spooled) {
+    let result = match &config.archive {
+        Some(archive) => {
+            let directory = archive.join(&spooled.topic).join(&spooled.sender);
+            std::fs::create_dir_all(&directory).and_then(|_| {
+                std::fs::rename(
+                    &spooled.path,
+                    directory.join(format!(
+                        "{}-{}",
+                        chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+                        spooled.file_name()
+                    )),
+                )
+            })
+        }
+        None => std::fs::remove_file(&spooled.path),
+    };
+
+    if let Err(err) = result {
+        log::error!(
+            "Failed to remove delivered {} from spool: {}",
+            spooled.path.display(),
+            err
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::Instant,
+};
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    adapters::sentry,
+    admin::Admin,
+    aggregate,
+    alerts,
+    bans,
+    escalation,
+    maintenance,
+    quotas,
+    threshold,
+    usage,
+    TgClient,
+};
+
+/// Runtime state of an instance, exported by `GET /admin/state` and imported by
+/// `PUT /admin/state` on another one. Sections missing from the import are left as they are
+#[derive(Default)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeState {
+    #[serde(default)]
+    bans:          Vec<bans::Ban>,
+    #[serde(default)]
+    quotas:        Vec<quotas::Snapshot>,
+    #[serde(default)]
+    maintenance:   maintenance::Snapshot,
+    #[serde(default)]
+    thresholds:    Vec<threshold::Pending>,
+    #[serde(default)]
+    sentry_issues: Vec<sentry::Snapshot>,
+    #[serde(default)]
+    aggregates:    Vec<aggregate::Snapshot>,
+    #[serde(default)]
+    escalations:   Vec<escalation::Snapshot>,
+    #[serde(default)]
+    alerts:        Vec<alerts::Snapshot>,
+    #[serde(default)]
+    usage:         Vec<usage::Snapshot>,
+    /// Chats that subscribed themselves, by topic
+    #[serde(default)]
+    subscribers:   BTreeMap<String, Vec<String>>,
+}
+
+/// Wall clock time of a monotonic instant, instants mean nothing on another host
+pub fn wall_time(instant: Instant) -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::from_std(instant.elapsed()).unwrap_or(chrono::Duration::zero())
+}
+
+/// Instant of a wall clock time, times in the future or before boot are now and boot
+pub fn instant_at(time: DateTime<Utc>) -> Instant {
+    let ago = (Utc::now() - time).to_std().unwrap_or_default();
+    let now = Instant::now();
+
+    now.checked_sub(ago).unwrap_or(now)
+}
+
+pub async fn export_state(
+    _: Admin,
+    bans: web::Data<Arc<bans::Bans>>,
+    quotas: web::Data<Arc<quotas::Quotas>>,
+    maintenance: web::Data<Arc<maintenance::Maintenance>>,
+    thresholds: web::Data<Arc<threshold::Thresholds>>,
+    sentry_issues: web::Data<Arc<sentry::RecentIssues>>,
+    aggregates: web::Data<Arc<aggregate::Aggregates>>,
+    escalations: web::Data<Arc<escalation::Escalations>>,
+    alerts: web::Data<Arc<alerts::Alerts>>,
+    usage: web::Data<Arc<usage::Usage>>,
+    tg_client: web::Data<Arc<TgClient>>,
+) -> impl Responder {
+    HttpResponse::Ok().json(RuntimeState {
+        bans:          bans.export(),
+        quotas:        quotas.export(),
+        maintenance:   maintenance.export(),
+        thresholds:    thresholds.export(),
+        sentry_issues: sentry_issues.export(),
+        aggregates:    aggregates.export(),
+        escalations:   escalations.export(),
+        alerts:        alerts.export(),
+        usage:         usage.export(),
+        subscribers:   tg_client.subscribers.export(),
+    })
+}
+
+/// Entries replace ones of the same ban, topic, window or alert, others are kept
+pub async fn import_state(
+    _: Admin,
+    bans: web::Data<Arc<bans::Bans>>,
+    quotas: web::Data<Arc<quotas::Quotas>>,
+    maintenance: web::Data<Arc<maintenance::Maintenance>>,
+    thresholds: web::Data<Arc<threshold::Thresholds>>,
+    sentry_issues: web::Data<Arc<sentry::RecentIssues>>,
+    aggregates: web::Data<Arc<aggregate::Aggregates>>,
+    escalations: web::Data<Arc<escalation::Escalations>>,
+    alerts: web::Data<Arc<alerts::Alerts>>,
+    usage: web::Data<Arc<usage::Usage>>,
+    tg_client: web::Data<Arc<TgClient>>,
+    state: web::Json<RuntimeState>,
+) -> impl Responder {
+    let state = state.into_inner();
+
+    bans.import(state.bans);
+    quotas.import(state.quotas);
+    maintenance.import(state.maintenance);
+    thresholds.import(state.thresholds);
+    sentry_issues.import(state.sentry_issues);
+    aggregates.import(state.aggregates);
+    escalations.import(state.escalations);
+    alerts.import(state.alerts);
+    usage.import(state.usage);
+    tg_client.subscribers.import(state.subscribers);
+
+    HttpResponse::NoContent().finish()
+}
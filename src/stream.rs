@@ -0,0 +1,154 @@
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::{
+    http::header::{
+        CacheControl,
+        CacheDirective,
+    },
+    rt::time::interval,
+    web::{
+        self,
+        Bytes,
+    },
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use futures::{
+    stream,
+    Stream,
+    StreamExt,
+};
+use serde::Serialize;
+use tokio::sync::broadcast::{
+    self,
+    error::RecvError,
+};
+
+use crate::{
+    access::AllowedTopic,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    severity::Severity,
+    Topic,
+};
+
+/// Messages a slow subscriber falls behind by before it's told some were skipped
+const CAPACITY: usize = 1024;
+/// Comment sent while nothing happens, so proxies don't close the idle stream
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Server-sent events response with the events and keepalive comments in between
+pub fn sse(events: impl Stream<Item = String> + 'static) -> HttpResponse {
+    let keepalives = stream::unfold(interval(KEEPALIVE_INTERVAL), |mut ticks| async move {
+        ticks.tick().await;
+        Some((": keepalive\n\n".to_owned(), ticks))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(
+            stream::select(events.boxed_local(), keepalives)
+                .map(|event| Ok::<_, Infallible>(Bytes::from(event))),
+        )
+}
+
+/// Events of a broadcast channel, a subscriber that fell behind gets a comment with the number
+/// of skipped ones
+pub fn events<T: Clone + 'static>(
+    receiver: broadcast::Receiver<T>,
+    render: impl Fn(T) -> Option<String> + 'static,
+) -> impl Stream<Item = String> {
+    let render = Arc::new(render);
+    stream::unfold(receiver, move |mut receiver| {
+        let render = render.clone();
+        async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(item) => match render(item) {
+                        Some(data) => format!("data: {}\n\n", data),
+                        None => continue,
+                    },
+                    Err(RecvError::Lagged(skipped)) => format!(": skipped {}\n\n", skipped),
+                    Err(RecvError::Closed) => return None,
+                };
+
+                return Some((event, receiver));
+            }
+        }
+    })
+}
+
+#[derive(Serialize)]
+struct Published {
+    topic:    String,
+    sender:   String,
+    severity: Severity,
+    message:  String,
+    /// Names of files posted with the message
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files:    Vec<String>,
+    time:     DateTime<Utc>,
+}
+
+/// Messages accepted for topics with `stream`, passed to `GET /{topic}/stream` subscribers
+pub struct Streams {
+    sender: broadcast::Sender<Arc<Published>>,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl Streams {
+    pub fn publish(
+        &self,
+        topic: &str,
+        topic_info: &Topic,
+        sender: &str,
+        severity: Severity,
+        message: &str,
+        files: &[&str],
+    ) {
+        if !topic_info.stream || self.sender.receiver_count() == 0 {
+            return;
+        }
+
+        let _ = self.sender.send(Arc::new(Published {
+            topic: topic.to_owned(),
+            sender: sender.to_owned(),
+            severity,
+            message: message.to_owned(),
+            files: files.iter().map(|file| (*file).to_owned()).collect(),
+            time: Utc::now(),
+        }));
+    }
+}
+
+/// Server-sent events with JSON of every message accepted for the topic from now on
+pub async fn subscribe(topic: AllowedTopic, streams: web::Data<Arc<Streams>>) -> impl Responder {
+    if !topic.info.stream {
+        return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic"));
+    }
+
+    let topic_name = topic.name;
+    sse(events(streams.sender.subscribe(), move |published| {
+        (published.topic == topic_name).then(|| {
+            serde_json::to_string(&*published).expect("Failed to serialize published message")
+        })
+    }))
+}
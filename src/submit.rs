@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use actix_web::{
+    http::header,
+    web,
+    HttpResponse,
+    Responder,
+};
+use serde::Deserialize;
+
+use crate::{
+    adapters::secrets_match,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    LiveTopics,
+};
+
+/// Header the page posts with `submit_token` of the topic in
+pub const SUBMIT_TOKEN_HEADER: &str = "X-Submit-Token";
+
+/// Posts text, or files with it, to `./{sender}` next to itself with token of its own link
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="referrer" content="no-referrer">
+<title>microphone</title>
+<style>
+body { font-family: sans-serif; max-width: 40em; margin: 2em auto; padding: 0 1em; }
+label { display: block; margin-bottom: 1em; }
+input, textarea { display: block; width: 100%; box-sizing: border-box; margin-top: .3em; }
+textarea { height: 12em; }
+</style>
+</head>
+<body>
+<h1 id="topic"></h1>
+<form id="form">
+<label>Your name <input name="sender" required pattern="[^/]+"></label>
+<label>Message <textarea name="message"></textarea></label>
+<label>Files <input name="file" type="file" multiple></label>
+<button>Send</button>
+</form>
+<p id="result" role="status"></p>
+<script>
+const path = location.pathname.split("/");
+const token = new URLSearchParams(location.search).get("token");
+const form = document.getElementById("form");
+const result = document.getElementById("result");
+document.getElementById("topic").textContent = decodeURIComponent(path[path.length - 2]);
+
+form.addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const data = new FormData(form);
+  const message = data.get("message");
+  const files = data.getAll("file").filter((file) => file.size > 0);
+  if (!message && files.length === 0) {
+    result.textContent = "Nothing to send";
+    return;
+  }
+
+  let body = message;
+  if (files.length > 0) {
+    body = new FormData();
+    if (message) {
+      body.append("message", message);
+    }
+    files.forEach((file) => body.append("file", file));
+  }
+
+  result.textContent = "Sending...";
+  try {
+    const response = await fetch("./" + encodeURIComponent(data.get("sender")), {
+      method: "POST",
+      headers: { "X-Submit-Token": token },
+      body,
+    });
+    if (response.ok) {
+      result.textContent = "Sent";
+      form.reset();
+    } else {
+      const error = await response.json().catch(() => ({}));
+      result.textContent = "Not sent: " + (error.message || response.statusText);
+    }
+  } catch (err) {
+    result.textContent = "Not sent: " + err;
+  }
+});
+</script>
+</body>
+</html>
+"#;
+
+#[derive(Deserialize)]
+pub struct SubmitPath {
+    topic_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct SubmitQuery {
+    #[serde(default)]
+    token: String,
+}
+
+/// Page people without curl post to the topic from, for topics with `submit_token`
+pub async fn get_submit_form(
+    topics: web::Data<Arc<LiveTopics>>,
+    path_data: web::Path<SubmitPath>,
+    query: web::Query<SubmitQuery>,
+) -> impl Responder {
+    let topics = topics.current();
+    let expected_token = match topics
+        .get(&path_data.topic_name)
+        .and_then(|topic_info| topic_info.submit_token.as_ref())
+    {
+        Some(expected_token) => expected_token,
+        None =>
+            return HttpResponse::from(ApiError::new(ErrorCode::TopicNotFound, "No such topic")),
+    };
+
+    if !secrets_match(expected_token, &query.token) {
+        return HttpResponse::from(ApiError::new(
+            ErrorCode::InvalidToken,
+            "Invalid token of submission form",
+        ));
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            "default-src 'none'; script-src 'unsafe-inline'; style-src 'unsafe-inline'; \
+             connect-src 'self'; frame-ancestors 'none'",
+        ))
+        .body(PAGE)
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    locale::Phrase,
+    TgClient,
+    Topics,
+};
+
+const TELEGRAM_SEND_MESSAGE_METHOD: &str = "sendMessage";
+
+/// Whether chats can add themselves to recipients of the topic with `/subscribe`
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionPolicy {
+    #[default]
+    Closed,
+    Open,
+}
+
+/// Chats that subscribed to topics themselves, by topic. They are recipients only while the
+/// topic is open for subscriptions
+#[derive(Default)]
+pub struct Subscribers {
+    chats: Mutex<BTreeMap<String, Vec<String>>>,
+}
+
+enum Command<'a> {
+    Subscribe(&'a str),
+    Unsubscribe(&'a str),
+}
+
+/// `/subscribe logs`, with `@bot_name` after the command in groups
+fn parse(text: &str) -> Option<Command<'_>> {
+    let mut words = text.split_whitespace();
+    let command = words.next()?;
+    let command = command
+        .split_once('@')
+        .map_or(command, |(command, _)| command);
+    let topic = words.next().unwrap_or_default();
+
+    match command {
+        "/subscribe" => Some(Command::Subscribe(topic)),
+        "/unsubscribe" => Some(Command::Unsubscribe(topic)),
+        _ => None,
+    }
+}
+
+impl Subscribers {
+    pub fn of(&self, topic: &str) -> Vec<String> {
+        self.chats
+            .lock()
+            .expect("Subscribers lock is poisoned")
+            .get(topic)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// False if the chat is subscribed already
+    fn subscribe(&self, topic: &str, chat: &str) -> bool {
+        let mut chats = self.chats.lock().expect("Subscribers lock is poisoned");
+        let subscribers = chats.entry(topic.to_owned()).or_default();
+        if subscribers.iter().any(|subscriber| subscriber == chat) {
+            return false;
+        }
+
+        subscribers.push(chat.to_owned());
+        true
+    }
+
+    /// False if the chat isn't subscribed
+    fn unsubscribe(&self, topic: &str, chat: &str) -> bool {
+        let mut chats = self.chats.lock().expect("Subscribers lock is poisoned");
+        let subscribers = match chats.get_mut(topic) {
+            Some(subscribers) => subscribers,
+            None => return false,
+        };
+
+        let subscribers_before = subscribers.len();
+        subscribers.retain(|subscriber| subscriber != chat);
+        let unsubscribed = subscribers.len() != subscribers_before;
+        if subscribers.is_empty() {
+            chats.remove(topic);
+        }
+        unsubscribed
+    }
+
+    pub fn export(&self) -> BTreeMap<String, Vec<String>> {
+        self.chats
+            .lock()
+            .expect("Subscribers lock is poisoned")
+            .clone()
+    }
+
+    pub fn import(&self, subscribers: BTreeMap<String, Vec<String>>) {
+        self.chats
+            .lock()
+            .expect("Subscribers lock is poisoned")
+            .extend(subscribers);
+    }
+}
+
+/// Some topic is open for subscriptions, so commands have to be received
+pub fn any_open(topics: &Topics) -> bool {
+    topics
+        .values()
+        .any(|topic_info| topic_info.subscriptions == SubscriptionPolicy::Open)
+}
+
+/// Answers `/subscribe` and `/unsubscribe` sent to the bot, other messages are ignored.
+/// Closed and unknown topics get the same answer, so topic names can't be guessed
+pub async fn handle_command(tg_client: &TgClient, topics: &Topics, chat_id: i64, text: &str) {
+    let command = match parse(text) {
+        Some(command) => command,
+        None => return,
+    };
+    let topic = match command {
+        Command::Subscribe(topic) | Command::Unsubscribe(topic) => topic,
+    };
+    let locale = tg_client.locale(topic);
+    let chat = chat_id.to_string();
+
+    let is_open = topics
+        .get(topic)
+        .is_some_and(|topic_info| topic_info.subscriptions == SubscriptionPolicy::Open);
+    let reply = match command {
+        _ if topic.is_empty() => locale.text(Phrase::SubscribeUsage).to_owned(),
+        _ if !is_open => locale.fill(Phrase::SubscriptionClosed, &[topic]),
+        Command::Subscribe(_) if tg_client.subscribers.subscribe(topic, &chat) => {
+            log::info!("Chat {} subscribed to \"{}\"", chat, topic);
+            locale.fill(Phrase::Subscribed, &[topic])
+        }
+        Command::Subscribe(_) => locale.fill(Phrase::AlreadySubscribed, &[topic]),
+        Command::Unsubscribe(_) if tg_client.subscribers.unsubscribe(topic, &chat) => {
+            log::info!("Chat {} unsubscribed from \"{}\"", chat, topic);
+            locale.fill(Phrase::Unsubscribed, &[topic])
+        }
+        Command::Unsubscribe(_) => locale.fill(Phrase::NotSubscribed, &[topic]),
+    };
+
+    let answer = tg_client
+        .call_method(
+            TELEGRAM_SEND_MESSAGE_METHOD,
+            &json!({
+                "chat_id": chat_id,
+                "text": reply,
+            }),
+        )
+        .await;
+    if let Err(err) = answer {
+        log::warn!("Failed to answer {}: {}", text, err);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use actix_web::rt::time::interval;
+use humantime_serde::re::humantime::format_duration;
+use serde::Deserialize;
+
+use crate::{
+    locale::{
+        Locale,
+        Phrase,
+    },
+    severity::Severity,
+    LiveTopics,
+    TgClient,
+    TgMarkdownString,
+};
+
+const SENDER: &str = "summary";
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const TOP_SENDERS: usize = 3;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SummaryConfig {
+    /// Period the summary covers and how often it's posted
+    #[serde(with = "humantime_serde")]
+    pub every:      Duration,
+    /// Chats that get the summary instead of topic recipients, like a separate stats chat
+    #[serde(default)]
+    pub recipients: Vec<String>,
+}
+
+struct Record {
+    posted_at: Instant,
+    sender:    String,
+    severity:  Severity,
+}
+
+/// Kept a bit longer than two periods, summaries are sent up to `CHECK_INTERVAL` late
+fn retention(every: Duration) -> Duration {
+    every * 2 + CHECK_INTERVAL * 2
+}
+
+/// Messages posted to topics with `summary`, kept so the summary can compare the last period
+/// with the one before
+#[derive(Default)]
+pub struct Summaries {
+    records: Mutex<HashMap<String, VecDeque<Record>>>,
+}
+
+struct Stats {
+    total:    usize,
+    critical: usize,
+    warnings: usize,
+    previous: usize,
+    /// Most active senders of the period, with their number of messages
+    senders:  Vec<(String, usize)>,
+}
+
+impl Summaries {
+    pub fn record(&self, topic: &str, config: &SummaryConfig, sender: &str, severity: Severity) {
+        let mut records = self.records.lock().expect("Summaries lock is poisoned");
+        let now = Instant::now();
+
+        let topic_records = records.entry(topic.to_owned()).or_default();
+        while topic_records
+            .front()
+            .is_some_and(|record| now.duration_since(record.posted_at) > retention(config.every))
+        {
+            topic_records.pop_front();
+        }
+        topic_records.push_back(Record {
+            posted_at: now,
+            sender: sender.to_owned(),
+            severity,
+        });
+    }
+
+    /// Counts of messages posted within `period`, and of ones posted within the same period
+    /// before
+    fn stats(&self, topic: &str, period: Duration) -> Stats {
+        let records = self.records.lock().expect("Summaries lock is poisoned");
+        let now = Instant::now();
+        let mut stats = Stats {
+            total:    0,
+            critical: 0,
+            warnings: 0,
+            previous: 0,
+            senders:  Vec::new(),
+        };
+
+        let topic_records = match records.get(topic) {
+            Some(topic_records) => topic_records,
+            None => return stats,
+        };
+        let mut senders = HashMap::<&str, usize>::new();
+        for record in topic_records.iter() {
+            let age = now.duration_since(record.posted_at);
+            if age > period * 2 {
+                continue;
+            }
+            if age > period {
+                stats.previous += 1;
+                continue;
+            }
+
+            stats.total += 1;
+            match record.severity {
+                Severity::Critical => stats.critical += 1,
+                Severity::Warning => stats.warnings += 1,
+                Severity::Info => (),
+            }
+            *senders.entry(&record.sender).or_default() += 1;
+        }
+
+        let mut senders = senders
+            .into_iter()
+            .map(|(sender, count)| (sender.to_owned(), count))
+            .collect::<Vec<_>>();
+        senders.sort_by(|(a_sender, a_count), (b_sender, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_sender.cmp(b_sender))
+        });
+        senders.truncate(TOP_SENDERS);
+        stats.senders = senders;
+
+        stats
+    }
+}
+
+fn render(stats: &Stats, every: Duration, locale: Locale) -> String {
+    let period = format_duration(every).to_string();
+    let mut text = format!(
+        "📊 *{}*\n{}",
+        *TgMarkdownString::new(&locale.fill(Phrase::SummaryOf, &[&period])),
+        *TgMarkdownString::new(&locale.fill(
+            Phrase::SummaryCounts,
+            &[
+                &stats.total.to_string(),
+                &stats.critical.to_string(),
+                &stats.warnings.to_string()
+            ]
+        ))
+    );
+    text.push_str(&format!(
+        "\n{}",
+        *TgMarkdownString::new(&locale.fill(
+            Phrase::SummaryPrevious,
+            &[&period, &stats.previous.to_string()]
+        ))
+    ));
+
+    if !stats.senders.is_empty() {
+        let senders = stats
+            .senders
+            .iter()
+            .map(|(sender, count)| format!("{} {}", sender, count))
+            .collect::<Vec<_>>()
+            .join(", ");
+        text.push_str(&format!(
+            "\n{}",
+            *TgMarkdownString::new(&locale.fill(Phrase::TopSenders, &[&senders]))
+        ));
+    }
+
+    text
+}
+
+/// Messages are counted in memory of the instance that received them, so every instance sends
+/// its own summary. Periods without messages after one without messages aren't reported
+pub fn spawn_sender(summaries: Arc<Summaries>, topics: Arc<LiveTopics>, tg_client: Arc<TgClient>) {
+    actix_web::rt::spawn(async move {
+        let mut ticks = interval(CHECK_INTERVAL);
+        let mut last_sent = HashMap::<String, Instant>::new();
+
+        loop {
+            ticks.tick().await;
+
+            let topics = topics.current();
+            last_sent.retain(|topic, _| {
+                topics
+                    .get(topic)
+                    .is_some_and(|topic_info| topic_info.summary.is_some())
+            });
+
+            for (topic_name, topic_info) in topics.iter() {
+                let config = match &topic_info.summary {
+                    Some(config) => config,
+                    None => continue,
+                };
+                let sent_at = last_sent
+                    .entry(topic_name.clone())
+                    .or_insert_with(Instant::now);
+                let period = sent_at.elapsed();
+                if period < config.every {
+                    continue;
+                }
+                *sent_at = Instant::now();
+
+                let stats = summaries.stats(topic_name, period);
+                if stats.total == 0 && stats.previous == 0 {
+                    continue;
+                }
+
+                let recipients = if config.recipients.is_empty() {
+                    tg_client.recipients_of(topic_name, topic_info)
+                } else {
+                    config.recipients.clone()
+                };
+                let responses = tg_client
+                    .send_message_to_all(
+                        &recipients,
+                        topic_name,
+                        SENDER,
+                        &render(&stats, config.every, tg_client.locale(topic_name)),
+                    )
+                    .await;
+
+                if responses.iter().any(|delivery| !delivery.is_delivered()) {
+                    log::warn!("Failed to send summary for \"{}\"", topic_name);
+                }
+            }
+        }
+    });
+}
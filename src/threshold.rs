@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    io::{
+        self,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tempfile::NamedTempFile;
+
+/// Alerts that stopped firing below their threshold are forgotten eventually
+const RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Alert is delivered once it fires this many times within the window
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Threshold {
+    count:  usize,
+    #[serde(with = "humantime_serde")]
+    window: Duration,
+}
+
+/// Times alerts fired, by topic and alert id
+type Occurrences = HashMap<(String, String), Vec<DateTime<Utc>>>;
+
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Pending {
+    topic:       String,
+    alert_id:    String,
+    occurrences: Vec<DateTime<Utc>>,
+}
+
+/// Contents of `state_file`
+#[derive(Default)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+struct State {
+    #[serde(default)]
+    thresholds: Vec<Pending>,
+}
+
+/// Times alerts of topics with `alert_threshold` fired without reaching it, by topic and alert
+/// id. Kept in `state_file` if it's set, so a restart doesn't start the counts over
+pub struct Thresholds {
+    pending:    Mutex<Occurrences>,
+    state_file: Option<PathBuf>,
+}
+
+impl Thresholds {
+    /// Loads counts left by the previous run, a missing file is a fresh start
+    pub fn load(state_file: Option<PathBuf>) -> io::Result<Self> {
+        let state = match &state_file {
+            Some(path) => match std::fs::read(path) {
+                Ok(contents) => serde_json::from_slice::<State>(&contents)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => State::default(),
+                Err(err) => return Err(err),
+            },
+            None => State::default(),
+        };
+
+        Ok(Self {
+            pending: Mutex::new(
+                state
+                    .thresholds
+                    .into_iter()
+                    .map(|pending| ((pending.topic, pending.alert_id), pending.occurrences))
+                    .collect(),
+            ),
+            state_file,
+        })
+    }
+
+    /// Counts the alert firing, true once it reaches the threshold. The count starts over then
+    pub fn reached(&self, topic: &str, id: &str, threshold: &Threshold) -> bool {
+        let mut pending = self.pending.lock().expect("Thresholds lock is poisoned");
+        let now = Utc::now();
+        let window = chrono::Duration::from_std(threshold.window).unwrap_or(chrono::Duration::MAX);
+
+        let key = (topic.to_owned(), id.to_owned());
+        let occurrences = pending.entry(key.clone()).or_default();
+        occurrences.retain(|occurred_at| now - *occurred_at < window);
+        occurrences.push(now);
+        let reached = occurrences.len() >= threshold.count;
+        if reached {
+            pending.remove(&key);
+        }
+        let retention = chrono::Duration::from_std(RETENTION).expect("Retention is in range");
+        pending.retain(|_, occurrences| {
+            occurrences
+                .last()
+                .is_some_and(|occurred_at| now - *occurred_at < retention)
+        });
+
+        self.save(&pending);
+        reached
+    }
+
+    /// Forgets the alert, true if it fired without reaching the threshold
+    pub fn forget(&self, topic: &str, id: &str) -> bool {
+        let mut pending = self.pending.lock().expect("Thresholds lock is poisoned");
+        let forgotten = pending.remove(&(topic.to_owned(), id.to_owned())).is_some();
+        if forgotten {
+            self.save(&pending);
+        }
+
+        forgotten
+    }
+
+    pub fn export(&self) -> Vec<Pending> {
+        to_pending(&self.pending.lock().expect("Thresholds lock is poisoned"))
+    }
+
+    pub fn import(&self, imported: Vec<Pending>) {
+        let mut pending = self.pending.lock().expect("Thresholds lock is poisoned");
+        for imported in imported {
+            pending.insert((imported.topic, imported.alert_id), imported.occurrences);
+        }
+
+        self.save(&pending);
+    }
+
+    fn save(&self, pending: &Occurrences) {
+        let path = match &self.state_file {
+            Some(path) => path,
+            None => return,
+        };
+        let state = State {
+            thresholds: to_pending(pending),
+        };
+
+        if let Err(err) = write_atomically(path, &state) {
+            log::warn!("Failed to save state to {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn to_pending(pending: &Occurrences) -> Vec<Pending> {
+    pending
+        .iter()
+        .map(|((topic, alert_id), occurrences)| Pending {
+            topic:       topic.clone(),
+            alert_id:    alert_id.clone(),
+            occurrences: occurrences.clone(),
+        })
+        .collect()
+}
+
+/// Written next to the file and renamed over it, so a crash doesn't leave it half written
+pub fn write_atomically(path: &Path, state: &impl Serialize) -> io::Result<()> {
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut file = NamedTempFile::new_in(directory)?;
+    file.write_all(&serde_json::to_vec(state).expect("Failed to serialize state"))?;
+    file.persist(path).map_err(|err| err.error)?;
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::{
+    process::Stdio,
+    time::Duration,
+};
+
+use actix_web::rt::time::timeout;
+use reqwest::Url;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    process::Command,
+};
+
+use crate::{
+    bot_http::{
+        self,
+        Outbound,
+    },
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    metrics::Metrics,
+};
+
+/// Client transformers are posted to, in app data
+pub struct TransformClient(reqwest::Client);
+
+impl TransformClient {
+    pub fn new(outbound: &Outbound) -> Self {
+        Self(
+            outbound
+                .client_builder()
+                .build()
+                .expect("Failed to build transform client"),
+        )
+    }
+}
+
+/// Program or service text messages of the topic are passed through, its output is sent instead
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformConfig {
+    /// Program and its arguments, it gets the message on stdin and writes the new one to stdout
+    command:    Option<Vec<String>>,
+    /// Gets `{topic, sender, message}` JSON posted and responds with the new message as text
+    #[serde(default, deserialize_with = "bot_http::deserialize_url")]
+    url:        Option<Url>,
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    timeout:    Duration,
+    #[serde(default)]
+    on_failure: OnFailure,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// What happens to the message if the transformer fails, times out or outputs nothing
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    /// Sent as it was posted
+    #[default]
+    PassThrough,
+    /// Rejected with 502, so the client can retry
+    Reject,
+}
+
+#[derive(Serialize)]
+struct Input<'a> {
+    topic:   &'a str,
+    sender:  &'a str,
+    message: &'a str,
+}
+
+impl TransformConfig {
+    pub fn check(&self) -> Result<(), String> {
+        match (&self.command, &self.url) {
+            (Some(command), None) if command.is_empty() => Err("transform command is empty".into()),
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err("transform needs either command or url".into()),
+        }
+    }
+
+    /// Message to send instead of the posted one, or error response if it's rejected
+    pub async fn apply(
+        &self,
+        client: &TransformClient,
+        metrics: &Metrics,
+        topic: &str,
+        sender: &str,
+        message: String,
+    ) -> Result<String, ApiError> {
+        let input = Input {
+            topic,
+            sender,
+            message: &message,
+        };
+        let output = match (&self.command, &self.url) {
+            (Some(command), _) => timeout(self.timeout, self.run(command, &input)).await,
+            (_, Some(url)) => timeout(self.timeout, self.post(client, url, &input)).await,
+            (None, None) => unreachable!("Transforms are checked to have command or url"),
+        };
+        let error = match output {
+            Ok(Ok(output)) if !output.is_empty() => return Ok(output),
+            Ok(Ok(_)) => "output is empty".to_owned(),
+            Ok(Err(err)) => err,
+            Err(_) => format!("timed out after {:?}", self.timeout),
+        };
+
+        log::warn!("Transform of \"{}\" failed: {}", topic, error);
+        metrics.increment("microphone_transform_failures_total", &[("topic", topic)]);
+        match self.on_failure {
+            OnFailure::PassThrough => Ok(message),
+            OnFailure::Reject => Err(ApiError::new(
+                ErrorCode::TransformFailed,
+                "Transform of the message failed",
+            )),
+        }
+    }
+
+    /// Topic and sender are in `MICROPHONE_TOPIC` and `MICROPHONE_SENDER`, trailing newline of
+    /// the output is dropped
+    async fn run(&self, command: &[String], input: &Input<'_>) -> Result<String, String> {
+        let (program, arguments) = command.split_first().expect("Command is checked");
+
+        let mut child = Command::new(program)
+            .args(arguments)
+            .env("MICROPHONE_TOPIC", input.topic)
+            .env("MICROPHONE_SENDER", input.sender)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| format!("failed to run {}: {}", program, err))?;
+
+        let mut stdin = child.stdin.take().expect("Stdin is piped");
+        let message = input.message.to_owned();
+        // Written while output is read, so neither side waits on a full pipe
+        let write = async move {
+            let _ = stdin.write_all(message.as_bytes()).await;
+        };
+        let output = futures::join!(write, child.wait_with_output())
+            .1
+            .map_err(|err| format!("failed to run {}: {}", program, err))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(match stderr.trim() {
+                "" => format!("{} exited with {}", program, output.status),
+                stderr => format!("{} exited with {}: {}", program, output.status, stderr),
+            });
+        }
+
+        let mut output = String::from_utf8(output.stdout)
+            .map_err(|_| format!("{} output is not valid UTF-8", program))?;
+        if output.ends_with('\n') {
+            output.pop();
+        }
+
+        Ok(output)
+    }
+
+    async fn post(
+        &self,
+        client: &TransformClient,
+        url: &Url,
+        input: &Input<'_>,
+    ) -> Result<String, String> {
+        client
+            .0
+            .post(url.clone())
+            .json(input)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| err.to_string())?
+            .text()
+            .await
+            .map_err(|err| err.to_string())
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::{
+    rt::time::sleep,
+    web,
+    HttpRequest,
+    HttpResponse,
+    Responder,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    adapters::secrets_match,
+    coordination::Coordinator,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    escalation::{
+        acknowledged_markup,
+        Escalations,
+        ACK_CALLBACK_PREFIX,
+    },
+    locale::{
+        Locale,
+        Phrase,
+    },
+    photo::ORIGINAL_CALLBACK_PREFIX,
+    send_options::SendOptions,
+    subscriptions,
+    LiveTopics,
+    TgClient,
+    Topics,
+};
+
+const TELEGRAM_GET_UPDATES_METHOD: &str = "getUpdates";
+const TELEGRAM_ANSWER_CALLBACK_QUERY_METHOD: &str = "answerCallbackQuery";
+const TELEGRAM_EDIT_MESSAGE_REPLY_MARKUP_METHOD: &str = "editMessageReplyMarkup";
+/// How long Telegram holds a getUpdates request open waiting for updates
+const LONG_POLLING_TIMEOUT_SECONDS: u64 = 30;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+#[derive(Deserialize)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdatesMode {
+    /// Leader instance asks Telegram for updates with getUpdates
+    #[default]
+    Polling,
+    /// Telegram posts updates to `/telegram/updates`
+    Webhook,
+}
+
+#[derive(Debug)]
+#[derive(Deserialize)]
+#[derive(Clone)]
+#[derive(Default)]
+#[serde(deny_unknown_fields)]
+pub struct UpdatesConfig {
+    #[serde(default)]
+    pub mode:         UpdatesMode,
+    /// Expected value of `X-Telegram-Bot-Api-Secret-Token` header in webhook mode
+    pub secret_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct User {
+    first_name: String,
+    username:   Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    message_id: i64,
+    chat:       Chat,
+    text:       Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    id:      String,
+    from:    User,
+    message: Option<Message>,
+    data:    Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatMember {
+    status: String,
+}
+
+/// Bot was added to the chat or removed from it
+#[derive(Deserialize)]
+struct ChatMemberUpdated {
+    chat:            Chat,
+    new_chat_member: ChatMember,
+}
+
+#[derive(Deserialize)]
+pub struct Update {
+    update_id:      i64,
+    message:        Option<Message>,
+    callback_query: Option<CallbackQuery>,
+    my_chat_member: Option<ChatMemberUpdated>,
+}
+
+#[derive(Deserialize)]
+struct GetUpdatesResponse {
+    ok:     bool,
+    #[serde(default)]
+    result: Vec<Update>,
+}
+
+async fn handle_update(
+    tg_client: &TgClient,
+    escalations: &Escalations,
+    topics: &Topics,
+    update: Update,
+) {
+    // Messages only come from chats the bot is in, users also write after unblocking it
+    let chat_id = match (&update.my_chat_member, &update.message) {
+        (Some(member), _)
+            if !matches!(member.new_chat_member.status.as_str(), "left" | "kicked") =>
+            Some(member.chat.id),
+        (None, Some(message)) => Some(message.chat.id),
+        _ => None,
+    };
+    for recipient in chat_id
+        .map(|chat_id| tg_client.defunct.restore_chat(chat_id))
+        .unwrap_or_default()
+    {
+        log::info!(
+            "Bot is back in the chat of {}, delivering to it again",
+            recipient
+        );
+    }
+
+    if let Some(Message {
+        chat,
+        text: Some(text),
+        ..
+    }) = &update.message
+    {
+        subscriptions::handle_command(tg_client, topics, chat.id, text).await;
+    }
+
+    let callback_query = match update.callback_query {
+        Some(callback_query) => callback_query,
+        None => return,
+    };
+
+    if let Some(key) = callback_query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(ORIGINAL_CALLBACK_PREFIX))
+    {
+        send_original(tg_client, topics, &callback_query, key).await;
+        return;
+    }
+
+    let id = match callback_query
+        .data
+        .as_deref()
+        .and_then(|data| data.strip_prefix(ACK_CALLBACK_PREFIX))
+        .and_then(|id| id.parse::<u64>().ok())
+    {
+        Some(id) => id,
+        None => return,
+    };
+
+    let acknowledged = escalations.acknowledge(id);
+    // Whoever pressed the button of a forgotten alert gets the default locale
+    let locale = acknowledged
+        .as_ref()
+        .map(|acknowledged| tg_client.locale(&acknowledged.topic))
+        .unwrap_or_default();
+    let acknowledged_by = match &callback_query.from.username {
+        Some(username) => format!("@{}", username),
+        None => callback_query.from.first_name.clone(),
+    };
+    if let Some(acknowledged) = &acknowledged {
+        escalations.log_ack(id, acknowledged, &acknowledged_by);
+        let labels = [("topic", acknowledged.topic.as_str())];
+        tg_client
+            .metrics
+            .increment("microphone_acks_total", &labels);
+        tg_client.metrics.add(
+            "microphone_ack_latency_seconds_total",
+            &labels,
+            acknowledged.latency().as_secs(),
+        );
+    }
+
+    let answer = tg_client
+        .call_method(
+            TELEGRAM_ANSWER_CALLBACK_QUERY_METHOD,
+            &json!({
+                "callback_query_id": callback_query.id,
+                "text": locale.text(if acknowledged.is_some() {
+                    Phrase::Acknowledged
+                } else {
+                    Phrase::AlreadyAcknowledged
+                }),
+            }),
+        )
+        .await;
+    if let Err(err) = answer {
+        log::warn!("Failed to answer callback query: {}", err);
+    }
+
+    if let (Some(_), Some(message)) = (&acknowledged, &callback_query.message) {
+        let edit = tg_client
+            .call_method(
+                TELEGRAM_EDIT_MESSAGE_REPLY_MARKUP_METHOD,
+                &json!({
+                    "chat_id": message.chat.id,
+                    "message_id": message.message_id,
+                    "reply_markup": acknowledged_markup(id, &acknowledged_by, locale),
+                }),
+            )
+            .await;
+        if let Err(err) = edit {
+            log::warn!("Failed to mark message as acknowledged: {}", err);
+        }
+    }
+
+    for sent in acknowledged
+        .map(|acknowledged| acknowledged.pinned)
+        .unwrap_or_default()
+    {
+        tg_client.unpin(&sent).await;
+    }
+}
+
+/// Original of a downscaled photo goes to the chat its button was pressed in
+async fn send_original(
+    tg_client: &TgClient,
+    topics: &Topics,
+    callback_query: &CallbackQuery,
+    key: &str,
+) {
+    let original = tg_client.originals.get(key);
+    let mut answer = json!({ "callback_query_id": callback_query.id });
+    // Whoever pressed the button of a forgotten photo gets the default locale
+    if original.is_none() {
+        answer["text"] = Locale::default().text(Phrase::OriginalGone).into();
+    }
+    let answer = tg_client
+        .call_method(TELEGRAM_ANSWER_CALLBACK_QUERY_METHOD, &answer)
+        .await;
+    if let Err(err) = answer {
+        log::warn!("Failed to answer callback query: {}", err);
+    }
+
+    let (original, message) = match (original, &callback_query.message) {
+        (Some(original), Some(message)) => (original, message),
+        _ => return,
+    };
+    // Topic could be gone since, then its settings are too
+    let options = topics
+        .get(&original.topic)
+        .map(SendOptions::of_topic)
+        .unwrap_or_default();
+    match tg_client
+        .send_original(message.chat.id, &original, options)
+        .await
+    {
+        Ok(response) if response.status().is_success() => (),
+        Ok(response) => log::warn!(
+            "Failed to send original photo, Telegram answered {}",
+            response.status()
+        ),
+        Err(err) => log::warn!("Failed to send original photo: {}", err),
+    }
+}
+
+async fn get_updates(tg_client: &TgClient, offset: i64) -> Result<Vec<Update>, String> {
+    let response = tg_client
+        .http_client
+        .post(format!(
+            "{}/{}",
+            tg_client.base_request_url, TELEGRAM_GET_UPDATES_METHOD
+        ))
+        .json(&json!({
+            "offset": offset,
+            "timeout": LONG_POLLING_TIMEOUT_SECONDS,
+            "allowed_updates": ["callback_query", "message", "my_chat_member"],
+        }))
+        .timeout(Duration::from_secs(LONG_POLLING_TIMEOUT_SECONDS + 10))
+        .send()
+        .await
+        .map_err(|err| err.to_string())?
+        .json::<GetUpdatesResponse>()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    if response.ok {
+        Ok(response.result)
+    } else {
+        Err("Telegram refused to return updates".to_owned())
+    }
+}
+
+/// Updates are only needed for Ack buttons of escalated messages, Original buttons of downscaled
+/// photos and for subscription commands
+pub fn poller_runs(topics: &Topics, config: &UpdatesConfig) -> bool {
+    config.mode == UpdatesMode::Polling
+        && (topics.values().any(|topic_info| {
+            topic_info.escalation.is_some() || topic_info.max_photo_dimension.is_some()
+        }) || subscriptions::any_open(topics))
+}
+
+/// Only one instance may poll, Telegram rejects concurrent getUpdates calls
+pub fn spawn_poller(
+    tg_client: Arc<TgClient>,
+    escalations: Arc<Escalations>,
+    topics: Arc<LiveTopics>,
+    coordinator: Arc<Coordinator>,
+) {
+    actix_web::rt::spawn(async move {
+        let mut offset = 0;
+
+        loop {
+            if !coordinator.is_leader() {
+                sleep(RETRY_DELAY).await;
+                continue;
+            }
+
+            match get_updates(&tg_client, offset).await {
+                Ok(updates) =>
+                    for update in updates {
+                        offset = offset.max(update.update_id + 1);
+                        handle_update(&tg_client, &escalations, &topics.current(), update).await;
+                    },
+                Err(err) => {
+                    log::warn!("Failed to get Telegram updates: {}", err);
+                    sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    });
+}
+
+pub async fn post_update(
+    request: HttpRequest,
+    updates_config: web::Data<UpdatesConfig>,
+    tg_client: web::Data<Arc<TgClient>>,
+    escalations: web::Data<Arc<Escalations>>,
+    topics: web::Data<Arc<LiveTopics>>,
+    update: web::Json<Update>,
+) -> impl Responder {
+    if updates_config.mode != UpdatesMode::Webhook {
+        return HttpResponse::from(ApiError::new(
+            ErrorCode::WebhookDisabled,
+            "Telegram webhook is disabled",
+        ));
+    }
+
+    if let Some(secret_token) = &updates_config.secret_token {
+        let provided_token = request
+            .headers()
+            .get("X-Telegram-Bot-Api-Secret-Token")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        if !secrets_match(secret_token, provided_token) {
+            return HttpResponse::from(ApiError::new(
+                ErrorCode::InvalidToken,
+                "Invalid secret token",
+            ));
+        }
+    }
+
+    handle_update(
+        &tg_client,
+        &escalations,
+        &topics.current(),
+        update.into_inner(),
+    )
+    .await;
+
+    HttpResponse::Ok().finish()
+}
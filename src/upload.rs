@@ -0,0 +1,194 @@
+use std::{
+    borrow::Cow,
+    io,
+    path::PathBuf,
+    time::Duration,
+};
+
+use futures::{
+    stream,
+    StreamExt,
+    TryStreamExt,
+};
+use reqwest::{
+    multipart::Part,
+    Body,
+};
+use serde::Deserialize;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+use crate::errors::{
+    ApiError,
+    ErrorCode,
+};
+
+const DEFAULT_MAX_FIELDS: usize = 20;
+const DEFAULT_MAX_FIELD_SIZE: usize = 50 * 1000 * 1000;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Bounds of multipart requests, ones of the topic take precedence over ones of the server
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UploadLimits {
+    /// Fields of any kind, files as well as message
+    max_fields:     Option<usize>,
+    max_field_size: Option<usize>,
+    /// Reading the whole request has to take less, so clients trickling bytes don't hold
+    /// connections forever
+    #[serde(default, with = "humantime_serde")]
+    timeout:        Option<Duration>,
+}
+
+/// Limits applying to a request
+#[derive(Clone)]
+#[derive(Copy)]
+pub struct Limits {
+    pub max_fields:     usize,
+    pub max_field_size: usize,
+    pub timeout:        Duration,
+}
+
+/// Where posted files are kept until they are sent
+pub struct Uploads {
+    /// Files over this many bytes are written to disk as they arrive
+    memory_limit: usize,
+    /// System temporary directory if not set
+    dir:          Option<PathBuf>,
+    limits:       UploadLimits,
+}
+
+/// Content of a posted file, in memory or in a temporary file deleted once it is dropped
+pub enum Content {
+    Memory(Vec<u8>),
+    Disk { file: NamedTempFile, len: usize },
+}
+
+impl Uploads {
+    pub fn new(memory_limit: usize, dir: Option<PathBuf>, limits: UploadLimits) -> Self {
+        Self {
+            memory_limit,
+            dir,
+            limits,
+        }
+    }
+
+    pub fn limits_of(&self, topic: &UploadLimits) -> Limits {
+        let server = &self.limits;
+        Limits {
+            max_fields:     topic
+                .max_fields
+                .or(server.max_fields)
+                .unwrap_or(DEFAULT_MAX_FIELDS),
+            max_field_size: topic
+                .max_field_size
+                .or(server.max_field_size)
+                .unwrap_or(DEFAULT_MAX_FIELD_SIZE),
+            timeout:        topic.timeout.or(server.timeout).unwrap_or(DEFAULT_TIMEOUT),
+        }
+    }
+
+    fn spill(&self, buffer: &[u8]) -> io::Result<(NamedTempFile, tokio::fs::File)> {
+        let file = match &self.dir {
+            Some(dir) => NamedTempFile::new_in(dir)?,
+            None => NamedTempFile::new()?,
+        };
+        let mut writer = file.reopen()?;
+        io::Write::write_all(&mut writer, buffer)?;
+
+        Ok((file, tokio::fs::File::from_std(writer)))
+    }
+
+    /// Reads the field, moving it to disk once it grows over the memory limit
+    pub async fn read(
+        &self,
+        field: &mut actix_multipart::Field,
+        max_size: usize,
+    ) -> Result<Content, ApiError> {
+        let write_error = |err: io::Error| {
+            log::error!("Failed to write upload to disk: {}", err);
+            ApiError::new(ErrorCode::InternalError, "Failed to store the file")
+        };
+
+        let mut buffer = Vec::new();
+        let mut spilled: Option<(NamedTempFile, tokio::fs::File)> = None;
+        let mut len = 0;
+        while let Some(chunk) = field.next().await {
+            let chunk =
+                chunk.map_err(|err| ApiError::new(ErrorCode::InvalidMultipart, err.to_string()))?;
+            len += chunk.len();
+            if len > max_size {
+                return Err(field_too_large(field.name(), max_size));
+            }
+
+            match &mut spilled {
+                Some((_, writer)) => writer.write_all(&chunk).await.map_err(write_error)?,
+                None if len > self.memory_limit => {
+                    buffer.extend(chunk);
+                    spilled = Some(self.spill(&buffer).map_err(write_error)?);
+                    buffer = Vec::new();
+                }
+                None => buffer.extend(chunk),
+            }
+        }
+
+        match spilled {
+            Some((file, mut writer)) => {
+                writer.flush().await.map_err(write_error)?;
+                Ok(Content::Disk { file, len })
+            }
+            None => Ok(Content::Memory(buffer)),
+        }
+    }
+}
+
+pub fn field_too_large(name: &str, max_size: usize) -> ApiError {
+    ApiError::new(
+        ErrorCode::MultipartTooLarge,
+        format!("Field \"{}\" is over {} bytes", name, max_size),
+    )
+}
+
+impl Content {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Memory(bytes) => bytes.len(),
+            Self::Disk { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whole content in memory, for diffs, archives and compression that need all of it
+    pub fn load(&self) -> Result<Cow<'_, [u8]>, ApiError> {
+        match self {
+            Self::Memory(bytes) => Ok(Cow::Borrowed(bytes)),
+            Self::Disk { file, .. } => std::fs::read(file.path()).map(Cow::Owned).map_err(|err| {
+                log::error!("Failed to read upload from disk: {}", err);
+                ApiError::new(ErrorCode::InternalError, "Failed to read the file")
+            }),
+        }
+    }
+
+    /// Part of Bot API request, files on disk are streamed from there
+    pub fn part(&self, filename: &str) -> Part {
+        let part = match self {
+            Self::Memory(bytes) => Part::bytes(bytes.clone()),
+            Self::Disk { file, len } => {
+                // Every request opens the file again, so retries start from the beginning
+                let content = stream::once(tokio::fs::File::open(file.path().to_owned()))
+                    .map_ok(ReaderStream::new)
+                    .try_flatten();
+                Part::stream_with_length(Body::wrap_stream(content), *len as u64)
+            }
+        };
+
+        part.file_name(filename.to_owned())
+    }
+}
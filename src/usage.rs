@@ -0,0 +1,207 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+
+use actix_web::{
+    web,
+    HttpResponse,
+    Responder,
+};
+use chrono::{
+    Days,
+    NaiveDate,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    access::AllowedTopic,
+    admin::Viewer,
+    Delivery,
+};
+
+/// Days usage is kept for, longer periods are cut to it
+const RETENTION_DAYS: u64 = 400;
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(Default)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Counts {
+    messages: u64,
+    /// Bodies of requests, with attached files
+    bytes:    u64,
+    /// Messages that didn't reach every recipient
+    failures: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: Counts) {
+        self.messages += other.messages;
+        self.bytes += other.bytes;
+        self.failures += other.failures;
+    }
+}
+
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+struct Day {
+    #[serde(default)]
+    topics:   BTreeMap<String, Counts>,
+    #[serde(default)]
+    api_keys: BTreeMap<String, Counts>,
+}
+
+/// Usage of one UTC day, as `GET /admin/state` exports it
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct Snapshot {
+    date: NaiveDate,
+    #[serde(flatten)]
+    day:  Day,
+}
+
+/// Messages sent per topic and API key, by UTC day
+#[derive(Default)]
+pub struct Usage {
+    days: Mutex<BTreeMap<NaiveDate, Day>>,
+}
+
+/// Usage of a message to be counted once it's delivered
+pub struct Meter {
+    usage:   Arc<Usage>,
+    topic:   String,
+    api_key: Option<String>,
+    bytes:   usize,
+}
+
+impl Usage {
+    /// Meter of a message posted to the topic, counted under the topic it was posted to even if
+    /// it's rerouted
+    pub fn meter(self: &Arc<Self>, topic: &AllowedTopic, bytes: usize) -> Meter {
+        Meter {
+            usage: self.clone(),
+            topic: topic.name.clone(),
+            api_key: topic.api_key.clone(),
+            bytes,
+        }
+    }
+
+    fn record(&self, meter: &Meter, counts: Counts) {
+        let today = Utc::now().date_naive();
+        let mut days = self.days.lock().expect("Usage lock is poisoned");
+
+        let day = days.entry(today).or_default();
+        day.topics
+            .entry(meter.topic.clone())
+            .or_default()
+            .add(counts);
+        if let Some(api_key) = &meter.api_key {
+            day.api_keys.entry(api_key.clone()).or_default().add(counts);
+        }
+
+        if let Some(oldest) = today.checked_sub_days(Days::new(RETENTION_DAYS)) {
+            days.retain(|date, _| *date > oldest);
+        }
+    }
+
+    /// Sums of days since the date
+    fn since(&self, since: NaiveDate) -> Day {
+        let days = self.days.lock().expect("Usage lock is poisoned");
+        let mut total = Day::default();
+
+        for (_, day) in days.range(since..) {
+            for (topic, counts) in &day.topics {
+                total.topics.entry(topic.clone()).or_default().add(*counts);
+            }
+            for (api_key, counts) in &day.api_keys {
+                total
+                    .api_keys
+                    .entry(api_key.clone())
+                    .or_default()
+                    .add(*counts);
+            }
+        }
+
+        total
+    }
+
+    pub fn export(&self) -> Vec<Snapshot> {
+        self.days
+            .lock()
+            .expect("Usage lock is poisoned")
+            .iter()
+            .map(|(date, day)| Snapshot {
+                date: *date,
+                day:  day.clone(),
+            })
+            .collect()
+    }
+
+    pub fn import(&self, snapshots: Vec<Snapshot>) {
+        let mut days = self.days.lock().expect("Usage lock is poisoned");
+        for snapshot in snapshots {
+            days.insert(snapshot.date, snapshot.day);
+        }
+    }
+}
+
+impl Meter {
+    pub fn record(self, deliveries: &[Delivery]) {
+        let failed = deliveries.iter().any(|delivery| !delivery.is_delivered());
+        let counts = Counts {
+            messages: 1,
+            bytes:    self.bytes as u64,
+            failures: u64::from(failed),
+        };
+
+        self.usage.record(&self, counts);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    #[serde(default = "default_period", with = "humantime_serde")]
+    period: Duration,
+}
+
+fn default_period() -> Duration {
+    30 * DAY
+}
+
+/// Usage per topic and API key within the period, counted by whole UTC days including today
+pub async fn get_usage(
+    _: Viewer,
+    usage: web::Data<Arc<Usage>>,
+    query: web::Query<UsageQuery>,
+) -> impl Responder {
+    let days = query
+        .period
+        .as_secs()
+        .div_ceil(DAY.as_secs())
+        .clamp(1, RETENTION_DAYS);
+    let today = Utc::now().date_naive();
+    let since = today
+        .checked_sub_days(Days::new(days - 1))
+        .unwrap_or(NaiveDate::MIN);
+    let total = usage.since(since);
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "since": since,
+        "until": today,
+        "topics": total.topics,
+        "api_keys": total.api_keys,
+    }))
+}
@@ -0,0 +1,46 @@
+use actix_web::{
+    HttpResponse,
+    Responder,
+};
+use chrono::DateTime;
+use serde::Serialize;
+
+use crate::admin::Viewer;
+
+/// Optional Cargo features, backends among them
+const FEATURES: [(&str, bool); 5] = [
+    ("smtp", cfg!(feature = "smtp")),
+    ("redis", cfg!(feature = "redis")),
+    ("chaos", cfg!(feature = "chaos")),
+    ("scripting", cfg!(feature = "scripting")),
+    ("coap", cfg!(feature = "coap")),
+];
+
+#[derive(Serialize)]
+struct Version {
+    version:  &'static str,
+    commit:   &'static str,
+    built_at: String,
+    features: Vec<&'static str>,
+}
+
+/// What this binary was built from, for checking what runs across instances
+pub async fn get_version(_: Viewer) -> impl Responder {
+    let built_at = env!("MICROPHONE_BUILT_AT")
+        .parse()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .map(|built_at| built_at.to_rfc3339())
+        .unwrap_or_default();
+
+    HttpResponse::Ok().json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("MICROPHONE_COMMIT"),
+        built_at,
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(feature, _)| *feature)
+            .collect(),
+    })
+}
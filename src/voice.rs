@@ -0,0 +1,103 @@
+use std::{
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+
+use actix_web::rt::time::timeout;
+use serde::Deserialize;
+use tokio::{
+    io::AsyncWriteExt,
+    process::Command,
+};
+
+use crate::TgClient;
+
+/// Voice notes of messages, made by a text to speech program
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VoiceConfig {
+    /// Program and its arguments, it gets text on stdin and writes OGG Opus or MP3 to stdout
+    command: Vec<String>,
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    timeout: Duration,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+async fn synthesize(config: &VoiceConfig, text: &str) -> Result<Vec<u8>, String> {
+    let (program, arguments) = config
+        .command
+        .split_first()
+        .ok_or("Voice command is empty")?;
+
+    let mut child = Command::new(program)
+        .args(arguments)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| format!("Failed to run {}: {}", program, err))?;
+
+    let mut stdin = child.stdin.take().expect("Stdin is piped");
+    let text = text.to_owned();
+    // Written while output is read, so neither side waits on a full pipe
+    let write = async move {
+        // Programs that don't read all of the text aren't a failure
+        let _ = stdin.write_all(text.as_bytes()).await;
+    };
+    let output = async move { futures::join!(write, child.wait_with_output()).1 };
+
+    let output = timeout(config.timeout, output)
+        .await
+        .map_err(|_| format!("{} timed out", program))?
+        .map_err(|err| format!("Failed to run {}: {}", program, err))?;
+    if !output.status.success() {
+        return Err(format!("{} exited with {}", program, output.status));
+    }
+    if output.stdout.is_empty() {
+        return Err(format!("{} produced no audio", program));
+    }
+
+    Ok(output.stdout)
+}
+
+/// Sends voice note of the text in background, failures don't affect delivery of the text
+pub fn speak(
+    config: VoiceConfig,
+    tg_client: Arc<TgClient>,
+    recipients: Vec<String>,
+    topic: String,
+    text: String,
+) {
+    actix_web::rt::spawn(async move {
+        let voice = match synthesize(&config, &text).await {
+            Ok(voice) => voice,
+            Err(err) => {
+                log::warn!("Failed to make voice note for \"{}\": {}", topic, err);
+                return;
+            }
+        };
+
+        for recipient in &recipients {
+            match tg_client.send_voice(recipient, &topic, &voice).await {
+                Ok(response) if response.status().is_success() => (),
+                Ok(response) => log::warn!(
+                    "Telegram responded with {} to voice note for {}",
+                    response.status(),
+                    recipient
+                ),
+                Err(err) => log::warn!(
+                    "Failed to send voice note to {}: {}",
+                    recipient,
+                    err.without_url()
+                ),
+            }
+        }
+    });
+}
@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use actix_web::{
+    body,
+    web,
+    FromRequest,
+    HttpRequest,
+    HttpResponse,
+    Responder,
+};
+use actix_ws::{
+    AggregatedMessage,
+    Session,
+};
+use futures::StreamExt;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::{
+    access::AllowedTopic,
+    alerts::{
+        AlertUpdate,
+        Alerts,
+    },
+    callbacks::Callback,
+    errors::{
+        ApiError,
+        ErrorCode,
+    },
+    escalation::Escalations,
+    maintenance::Maintenance,
+    messages::Messages,
+    metrics::Metrics,
+    post_message,
+    quotas::Quotas,
+    send_options::RequestOptions,
+    severity::Severity,
+    PostPathData,
+    TgClient,
+};
+
+/// Frames over this size close the connection, like bodies over the payload limit
+const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Frame of `/ws`, which names topic and sender in every message
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Frame {
+    /// Echoed in the reply, to match replies with frames
+    #[serde(default)]
+    id:       Option<serde_json::Value>,
+    topic:    String,
+    sender:   String,
+    message:  String,
+    #[serde(default)]
+    severity: Severity,
+    alert_id: Option<String>,
+    #[serde(default)]
+    resolved: bool,
+}
+
+/// Reply to every text frame, with status and body the same message posted over HTTP gets
+#[derive(Serialize)]
+struct Reply {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id:     Option<serde_json::Value>,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body:   Option<serde_json::Value>,
+}
+
+impl Reply {
+    async fn of(id: Option<serde_json::Value>, response: HttpResponse) -> Self {
+        let status = response.status().as_u16();
+        let body = body::to_bytes(response.into_body())
+            .await
+            .ok()
+            .filter(|bytes| !bytes.is_empty())
+            .map(|bytes| {
+                serde_json::from_slice(&bytes).unwrap_or_else(|_| {
+                    serde_json::Value::String(String::from_utf8_lossy(&bytes).into_owned())
+                })
+            });
+
+        Self { id, status, body }
+    }
+}
+
+/// Upgrades to WebSocket whose text frames are posted as messages. At `/ws/{topic}/{sender}`
+/// frames are messages as they are, at `/ws` they are JSON naming topic and sender. Headers of
+/// the request, like `X-Callback-Url`, apply to every message
+pub async fn connect(request: HttpRequest, payload: web::Payload) -> impl Responder {
+    let path = match (
+        request.match_info().get("topic_name"),
+        request.match_info().get("sender"),
+    ) {
+        (Some(topic_name), Some(sender)) => {
+            // Clients that can't post to the topic don't get to open connection either
+            if let Err(err) = AllowedTopic::of(&request, topic_name.to_owned()).await {
+                return err.error_response();
+            }
+            Some(PostPathData {
+                topic_name: topic_name.to_owned(),
+                sender:     sender.to_owned(),
+            })
+        }
+        _ => None,
+    };
+
+    let (response, session, stream) = match actix_ws::handle(&request, payload) {
+        Ok(handled) => handled,
+        Err(err) => return err.error_response(),
+    };
+    let stream = stream
+        .max_frame_size(MAX_FRAME_SIZE)
+        .aggregate_continuations()
+        .max_continuation_size(MAX_FRAME_SIZE);
+
+    actix_web::rt::spawn(async move {
+        let mut session = session;
+        let mut stream = stream;
+
+        while let Some(message) = stream.next().await {
+            let reply = match message {
+                Ok(AggregatedMessage::Text(text)) => reply_to(&request, path.as_ref(), &text).await,
+                Ok(AggregatedMessage::Binary(_)) => malformed(None, "Frames have to be text").await,
+                Ok(AggregatedMessage::Ping(bytes)) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                Ok(AggregatedMessage::Pong(_)) => continue,
+                Ok(AggregatedMessage::Close(reason)) => {
+                    let _ = session.close(reason).await;
+                    return;
+                }
+                Err(err) => {
+                    log::debug!("WebSocket connection failed: {}", err);
+                    break;
+                }
+            };
+
+            if !send(&mut session, &reply).await {
+                return;
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    response
+}
+
+async fn reply_to(request: &HttpRequest, path: Option<&PostPathData>, text: &str) -> Reply {
+    if let Some(path) = path {
+        let response = post(
+            request,
+            path.topic_name.clone(),
+            path.sender.clone(),
+            Severity::default(),
+            AlertUpdate::default(),
+            text.to_owned(),
+        )
+        .await;
+        return Reply::of(None, response).await;
+    }
+
+    let frame = match serde_json::from_str::<Frame>(text) {
+        Ok(frame) => frame,
+        Err(err) => return malformed(None, format!("Malformed frame: {}", err)).await,
+    };
+    if frame.resolved && frame.alert_id.is_none() {
+        return malformed(frame.id, "Resolved frame needs alert_id").await;
+    }
+
+    let response = post(
+        request,
+        frame.topic,
+        frame.sender,
+        frame.severity,
+        AlertUpdate::new(frame.alert_id, frame.resolved),
+        frame.message,
+    )
+    .await;
+    Reply::of(frame.id, response).await
+}
+
+async fn malformed(id: Option<serde_json::Value>, message: impl Into<String>) -> Reply {
+    Reply::of(
+        id,
+        HttpResponse::from(ApiError::new(ErrorCode::MalformedPayload, message)),
+    )
+    .await
+}
+
+async fn send(session: &mut Session, reply: &Reply) -> bool {
+    let text = serde_json::to_string(reply).expect("Failed to serialize reply");
+    session.text(text).await.is_ok()
+}
+
+/// Goes through the same handler as messages posted over HTTP, with access to the topic checked
+/// for every frame
+async fn post(
+    request: &HttpRequest,
+    topic_name: String,
+    sender: String,
+    severity: Severity,
+    alert: AlertUpdate,
+    message: String,
+) -> HttpResponse {
+    let topic = match AllowedTopic::of(request, topic_name.clone()).await {
+        Ok(topic) => topic,
+        Err(err) => return err.error_response(),
+    };
+    let callback = match Callback::extract(request).await {
+        Ok(callback) => callback,
+        Err(err) => return err.error_response(),
+    };
+    let request_options = match RequestOptions::extract(request).await {
+        Ok(request_options) => request_options,
+        Err(err) => return err.error_response(),
+    };
+
+    post_message(
+        topic,
+        data::<Arc<TgClient>>(request),
+        data::<Arc<Maintenance>>(request),
+        data::<Arc<Quotas>>(request),
+        data::<Arc<Metrics>>(request),
+        data::<Arc<Escalations>>(request),
+        data::<Arc<Alerts>>(request),
+        data::<Arc<Messages>>(request),
+        (severity, alert, callback, request_options),
+        web::Path::from(PostPathData { topic_name, sender }),
+        request.clone(),
+        message,
+    )
+    .await
+    .respond_to(request)
+    .map_into_boxed_body()
+}
+
+fn data<T: 'static>(request: &HttpRequest) -> web::Data<T> {
+    request
+        .app_data::<web::Data<T>>()
+        .expect("Handler data is in app data")
+        .clone()
+}